@@ -4,12 +4,19 @@ pragma solidity ^0.8.17;
 import "openzeppelin-contracts/token/ERC20/IERC20.sol";
 import "openzeppelin-contracts/token/ERC20/extensions/IERC20Metadata.sol";
 import "./AuroraSdk.sol";
+import "./WrappedToken.sol";
 
 string constant ERR_METHOD_NOT_IMPLEMENTED = "ERR_METHOD_NOT_IMPLEMENTED";
 // TODO: Determine proper values for gas.
 uint64 constant ON_DEPOSIT_NEAR_GAS = 3_000_000_000_000;
 // TODO: Determine proper values for gas.
+uint64 constant ON_DEPOSIT_CALL_NEAR_GAS = 100_000_000_000_000;
+// TODO: Determine proper values for gas.
 uint64 constant DEPOSIT_CALLBACK_NEAR_GAS = 3_000_000_000_000;
+// TODO: Determine proper values for gas.
+uint64 constant ON_RELEASE_NEAR_GAS = 20_000_000_000_000;
+/// Pseudo-address identifying ETH, the base token, on the factory.
+address constant ETH_ADDRESS = 0xEeeeeEeeeEeEeeEeEeEeeEEEeeeeEeeeeeeeEEeE;
 
 // TODO: Implement Pause mechanics.
 // TODO: Implement Upgradable mechanics.
@@ -35,6 +42,14 @@ contract Locker {
     NEAR public near;
     /// Implicit address for representative NEAR account of this contract.
     address public immutable selfReprsentativeImplicitAddress;
+    /// Wrapped ERC20 of each NEP141 token bridged into Aurora, by NEAR Account Id.
+    mapping(string => WrappedToken) public wrappedTokens;
+    /// Transfer id of the next deposit. The factory ignores deposits whose id
+    /// was already processed, so a duplicated message can't mint twice.
+    uint64 public depositNonce;
+    /// ERC20 tokens detached from the factory, which can't be deposited
+    /// anymore. The tokens held for them stay locked in this contract.
+    mapping(IERC20 => bool) public lockedTokens;
 
     constructor(string memory factoryAccountId_, IERC20 wNEAR) {
         factoryAccountId = factoryAccountId_;
@@ -53,6 +68,8 @@ contract Locker {
     /// If the transaction fails, the tokens are automatically returned to
     /// the sender of the transaction.
     function deposit(IERC20 token, string memory receiverId, uint128 amount) public {
+        require(!lockedTokens[token], "ERR_TOKEN_LOCKED");
+
         // First transfer the tokens from the caller to the locker contract.
         token.transferFrom(msg.sender, address(this), amount);
 
@@ -61,7 +78,20 @@ contract Locker {
         PromiseCreateArgs memory mintOnNear = near.call(
             factoryAccountId,
             "on_deposit",
-            abi.encodePacked(token, bytes(receiverId).encode(), amount.encodeU128()),
+            // The hash of the current transaction is not available in the EVM, so
+            // the borsh encoded `origin_tx_hash` argument is always `None`. The
+            // `metadata` argument is `None` as well, so tokens without metadata
+            // can be deposited, and the factory uses the default metadata. The
+            // `transfer_id` argument is `Some(depositNonce)`.
+            abi.encodePacked(
+                token,
+                bytes(receiverId).encode(),
+                amount.encodeU128(),
+                uint8(0),
+                uint8(0),
+                uint8(1),
+                Codec.encodeU64(depositNonce++)
+            ),
             0,
             ON_DEPOSIT_NEAR_GAS
         );
@@ -79,6 +109,50 @@ contract Locker {
         mintOnNear.then(callback).transact();
     }
 
+    /// ETH sent with the call is locked in this contract, while the
+    /// equivalent amount is minted on NEAR, in the NEP141 representative
+    /// of `ETH_ADDRESS`. Users don't need to wrap ETH first. Check comments
+    /// and considerations for `deposit` function.
+    function depositEth(string memory receiverId) public payable {
+        require(msg.value <= type(uint128).max, "ERR_AMOUNT_TOO_LARGE");
+        uint128 amount = uint128(msg.value);
+
+        PromiseCreateArgs memory mintOnNear = near.call(
+            factoryAccountId,
+            "on_deposit",
+            abi.encodePacked(
+                ETH_ADDRESS,
+                bytes(receiverId).encode(),
+                amount.encodeU128(),
+                uint8(0),
+                uint8(0),
+                uint8(1),
+                Codec.encodeU64(depositNonce++)
+            ),
+            0,
+            ON_DEPOSIT_NEAR_GAS
+        );
+        PromiseCreateArgs memory callback = near.auroraCall(
+            address(this),
+            abi.encodeWithSelector(this.depositEthCallback.selector, msg.sender, amount),
+            0,
+            DEPOSIT_CALLBACK_NEAR_GAS
+        );
+        mintOnNear.then(callback).transact();
+    }
+
+    /// Callback to return ETH to the sender if the call to the factory
+    /// fails. This method can only be called by the representative NEAR
+    /// account of this contract.
+    function depositEthCallback(address payable sender, uint128 amount) public {
+        require(msg.sender == selfReprsentativeImplicitAddress, "ERR_ACCESS_DENIED");
+
+        if (!(AuroraSdk.promiseResult(0).status == PromiseResultStatus.Successful)) {
+            (bool success,) = sender.call{value: amount}("");
+            require(success, "ERR_REFUND_FAILED");
+        }
+    }
+
     /// Callback to return tokens to the sender if the call to the factory
     /// fails. This method can only be called by the representative NEAR
     /// account of this contract.
@@ -109,6 +183,105 @@ contract Locker {
         token.transfer(receiver, amount);
     }
 
+    /// Finish the transfer of ETH from NEAR to Aurora.
+    ///
+    /// Similar to `withdraw`, but the base token is transferred. This
+    /// function CAN only be called from the factory contract.
+    function withdrawEth(address payable receiver, uint256 amount) public {
+        // Only the factory contract can call this method.
+        require(msg.sender == factoryImplicitAddress, "ERR_ACCESS_DENIED");
+
+        (bool success,) = receiver.call{value: amount}("");
+        require(success, "ERR_WITHDRAW_FAILED");
+    }
+
+    /// Finish the transfer of tokens from NEAR to Aurora and call the
+    /// receiver.
+    ///
+    /// Similar to `withdraw`, but after the tokens are transferred to
+    /// `receiver`, it is called with `data`. This function CAN only be
+    /// called from the factory contract. If the call fails, the whole
+    /// transaction is reverted, and the factory mints the tokens back on
+    /// NEAR side.
+    function withdrawAndCall(IERC20 token, address receiver, uint256 amount, bytes memory data) public {
+        // Only the factory contract can call this method.
+        require(msg.sender == factoryImplicitAddress, "ERR_ACCESS_DENIED");
+
+        // Transfer the tokens to the receiver, then call it.
+        token.transfer(receiver, amount);
+        (bool success,) = receiver.call(data);
+        require(success, "ERR_WITHDRAW_CALL_FAILED");
+    }
+
+    /// Mint the wrapped ERC20 of a NEP141 token locked on NEAR.
+    ///
+    /// This function CAN only be called from the factory contract, after
+    /// it locked `amount` of the NEP141 at `nep141`. The wrapped ERC20 is
+    /// deployed on the first mint. If this function fails, the factory
+    /// refunds the NEP141 tokens.
+    function mintWrapped(bytes memory nep141, address receiver, uint256 amount) public {
+        // Only the factory contract can call this method.
+        require(msg.sender == factoryImplicitAddress, "ERR_ACCESS_DENIED");
+
+        string memory nep141Id = string(nep141);
+        WrappedToken token = wrappedTokens[nep141Id];
+        if (address(token) == address(0)) {
+            token = new WrappedToken(nep141Id);
+            wrappedTokens[nep141Id] = token;
+        }
+        token.mint(receiver, amount);
+    }
+
+    /// Refuse further deposits of `token`, permanently.
+    ///
+    /// This function CAN only be called from the factory contract, after
+    /// the NEP141 of `token` was detached from the factory. Deposits would
+    /// be refused by the factory anyway, this avoids locking them first.
+    function lockToken(IERC20 token) public {
+        // Only the factory contract can call this method.
+        require(msg.sender == factoryImplicitAddress, "ERR_ACCESS_DENIED");
+
+        lockedTokens[token] = true;
+    }
+
+    /// Transfer NEP141 tokens bridged into Aurora back to NEAR.
+    ///
+    /// The wrapped ERC20 of `nep141` is burnt from the caller, and the
+    /// factory releases the locked NEP141 tokens to `receiverId`, which
+    /// MUST be registered on the NEP141. If the call to the factory fails,
+    /// the wrapped tokens are minted back to the caller.
+    function release(string memory nep141, string memory receiverId, uint128 amount) public {
+        WrappedToken token = wrappedTokens[nep141];
+        require(address(token) != address(0), "ERR_UNKNOWN_TOKEN");
+        token.burn(msg.sender, amount);
+
+        PromiseCreateArgs memory releaseOnNear = near.call(
+            factoryAccountId,
+            "on_release",
+            abi.encodePacked(bytes(nep141).encode(), bytes(receiverId).encode(), amount.encodeU128()),
+            0,
+            ON_RELEASE_NEAR_GAS
+        );
+        PromiseCreateArgs memory callback = near.auroraCall(
+            address(this),
+            abi.encodeWithSelector(this.releaseCallback.selector, token, msg.sender, amount),
+            0,
+            DEPOSIT_CALLBACK_NEAR_GAS
+        );
+        releaseOnNear.then(callback).transact();
+    }
+
+    /// Callback to mint the wrapped tokens back to the sender if the call
+    /// to the factory fails. This method can only be called by the
+    /// representative NEAR account of this contract.
+    function releaseCallback(WrappedToken token, address sender, uint128 amount) public {
+        require(msg.sender == selfReprsentativeImplicitAddress, "ERR_ACCESS_DENIED");
+
+        if (!(AuroraSdk.promiseResult(0).status == PromiseResultStatus.Successful)) {
+            token.mint(sender, amount);
+        }
+    }
+
     /// Create NEP141 compatible contract on NEAR for any ERC20 token.
     ///
     /// This function CAN be called at most once per token. Subsequent
@@ -123,17 +296,45 @@ contract Locker {
     /// Transfer ERC20 tokens from Aurora to NEAR chain and execute a
     /// function call.
     ///
-    /// Similar to `transfer`, but also executes a function call on the
-    /// NEAR side. Check comments and considerations for `transfer` function.
+    /// Similar to `deposit`, but the minted tokens are transferred to
+    /// `receiverId` with `ft_transfer_call` and `message`. The token MUST
+    /// be deployed on NEAR already. The part of the amount not used by the
+    /// receiver is unlocked back to the sender by the factory. Check comments
+    /// and considerations for `deposit` function.
     ///
     /// Insipired by `ft_transfer_call` on NEP141:
     /// https://nomicon.io/Standards/Tokens/FungibleToken/Core.
-    function depositCall(
-        IERC20, // token
-        uint256, // amount
-        bytes calldata // payload
-    ) internal pure {
-        require(false, ERR_METHOD_NOT_IMPLEMENTED);
+    function depositCall(IERC20 token, string memory receiverId, uint128 amount, string memory message)
+        public
+    {
+        require(!lockedTokens[token], "ERR_TOKEN_LOCKED");
+
+        // First transfer the tokens from the caller to the locker contract.
+        token.transferFrom(msg.sender, address(this), amount);
+
+        // Issue a call to the factory to mint the tokens and transfer them to
+        // the receiverId. Unused tokens are refunded to the sender.
+        PromiseCreateArgs memory mintOnNear = near.call(
+            factoryAccountId,
+            "on_deposit_call",
+            abi.encodePacked(
+                token, bytes(receiverId).encode(), amount.encodeU128(), bytes(message).encode(), msg.sender
+            ),
+            0,
+            ON_DEPOSIT_CALL_NEAR_GAS
+        );
+
+        // Prepare callback to return tokens to the sender if the call to
+        // the factory fails.
+        PromiseCreateArgs memory callback = near.auroraCall(
+            address(this),
+            abi.encodeWithSelector(this.depositCallback.selector, token, msg.sender, amount),
+            0,
+            DEPOSIT_CALLBACK_NEAR_GAS
+        );
+
+        // Combine the two promises into a single promise and schedule it.
+        mintOnNear.then(callback).transact();
     }
 
     /// Sends new metadata from ERC20 token to the representative token on NEAR.
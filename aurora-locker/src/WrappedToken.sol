@@ -0,0 +1,32 @@
+// SPDX-License-Identifier: CC-BY-1.0
+pragma solidity ^0.8.17;
+
+import "openzeppelin-contracts/token/ERC20/ERC20.sol";
+
+/// ERC20 representative of a NEP141 token bridged into Aurora. It is
+/// deployed by the locker, which is the only account allowed to mint and
+/// burn it.
+contract WrappedToken is ERC20 {
+    /// Locker that deployed this token.
+    address public immutable locker;
+    /// NEAR Account Id of the NEP141 token.
+    string public nep141;
+
+    constructor(string memory nep141_) ERC20(nep141_, nep141_) {
+        locker = msg.sender;
+        nep141 = nep141_;
+    }
+
+    modifier onlyLocker() {
+        require(msg.sender == locker, "ERR_ACCESS_DENIED");
+        _;
+    }
+
+    function mint(address account, uint256 amount) public onlyLocker {
+        _mint(account, amount);
+    }
+
+    function burn(address account, uint256 amount) public onlyLocker {
+        _burn(account, amount);
+    }
+}
@@ -0,0 +1,317 @@
+//! Operator workflows of the connector, each one a few calls to the factory or the
+//! engine.
+use crate::rpc::Rpc;
+use anyhow::{anyhow, Context};
+use borsh::BorshDeserialize;
+use near_primitives::transaction::{Action, DeployContractAction, FunctionCallAction};
+use near_primitives::types::AccountId;
+use near_token_common::{abi, Address, SubmitResult, TransactionStatus};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+
+/// Default size of the chunks of `upload-binary`, well below the transaction size
+/// limit once base64 encoded.
+pub const DEFAULT_CHUNK_SIZE: usize = 512 * 1024;
+/// Default number of tokens listed by `token-versions`.
+pub const DEFAULT_TOKENS_LIMIT: u64 = 100;
+
+/// Deploy the factory to the signer account and initialize it in the same
+/// transaction.
+pub async fn deploy_factory(
+    rpc: &Rpc,
+    wasm: &Path,
+    aurora: AccountId,
+    locker: Address,
+    owner: Option<AccountId>,
+) -> anyhow::Result<()> {
+    let code = read(wasm).await?;
+    let args = serde_json::json!({
+        "aurora": aurora,
+        "locker": locker,
+        "owner": owner,
+    });
+    let factory = rpc.signer()?.account_id.clone();
+    rpc.transact(
+        &factory,
+        vec![
+            Action::DeployContract(DeployContractAction { code }),
+            Action::FunctionCall(FunctionCallAction {
+                method_name: "new".to_string(),
+                args: serde_json::to_vec(&args)?,
+                gas: crate::rpc::MAX_GAS,
+                deposit: 0,
+            }),
+        ],
+    )
+    .await?;
+    println!("Factory deployed to {}", factory);
+    Ok(())
+}
+
+/// Upload the token binary in chunks of `chunk_size` bytes, then make it the token
+/// binary. The factory checks the sha256 checksum of the chunks. A binary replacing
+/// the current one MUST have been proposed with `propose-binary`, and the timelock
+/// MUST have expired.
+pub async fn upload_binary(
+    rpc: &Rpc,
+    factory: &AccountId,
+    wasm: &Path,
+    chunk_size: usize,
+) -> anyhow::Result<()> {
+    let binary = read(wasm).await?;
+    call_json(rpc, factory, "start_binary_upload", serde_json::json!({})).await?;
+    let chunks = binary.chunks(chunk_size).collect::<Vec<_>>();
+    for (index, chunk) in chunks.iter().enumerate() {
+        call_json(
+            rpc,
+            factory,
+            "append_binary_chunk",
+            serde_json::json!({ "chunk": base64::encode(chunk) }),
+        )
+        .await?;
+        println!("Uploaded chunk {}/{}", index + 1, chunks.len());
+    }
+    let checksum = checksum(&binary);
+    call_json(
+        rpc,
+        factory,
+        "finalize_binary_upload",
+        serde_json::json!({ "checksum": checksum }),
+    )
+    .await?;
+    println!("Token binary {} installed", checksum);
+    Ok(())
+}
+
+/// Propose the token binary in `wasm`. It can be installed with `upload-binary` once
+/// the timelock expired.
+pub async fn propose_binary(rpc: &Rpc, factory: &AccountId, wasm: &Path) -> anyhow::Result<()> {
+    let checksum = checksum(&read(wasm).await?);
+    call_json(
+        rpc,
+        factory,
+        "propose_token_binary",
+        serde_json::json!({ "hash": checksum }),
+    )
+    .await?;
+    println!("Token binary {} proposed", checksum);
+    Ok(())
+}
+
+/// Deploy the locker on the engine `aurora`. `bytecode` is the linked bytecode of
+/// the locker, either hex or the forge artifact containing it.
+pub async fn deploy_locker(
+    rpc: &Rpc,
+    aurora: &AccountId,
+    factory: &AccountId,
+    bytecode: &Path,
+    wnear: Address,
+) -> anyhow::Result<Address> {
+    let bytecode = String::from_utf8(read(bytecode).await?)?;
+    let mut code = parse_bytecode(&bytecode)?;
+    code.extend(abi::encode(&[
+        abi::AbiValue::Bytes(factory.as_bytes().to_vec()),
+        abi::AbiValue::Address(wnear),
+    ]));
+
+    let output = rpc.call(aurora, "deploy_code", code, 0).await?;
+    let result = SubmitResult::try_from_slice(&output)?;
+    let address = match result.status {
+        TransactionStatus::Succeed(bytes) if bytes.len() == 20 => {
+            let mut address = [0u8; 20];
+            address.copy_from_slice(&bytes);
+            Address(address)
+        }
+        status => return Err(anyhow!("Locker deployment failed: {:?}", status)),
+    };
+    println!("Locker deployed at {}", address);
+    Ok(address)
+}
+
+/// Propose `locker` as the new locker of the factory. It takes effect with
+/// `confirm-locker` once the timelock expired.
+pub async fn propose_locker(rpc: &Rpc, factory: &AccountId, locker: Address) -> anyhow::Result<()> {
+    call_json(
+        rpc,
+        factory,
+        "propose_locker",
+        serde_json::json!({ "locker": locker }),
+    )
+    .await?;
+    let rotation = rpc
+        .view(factory, "locker_rotation", serde_json::json!({}))
+        .await?;
+    println!("Locker {} proposed: {}", locker, rotation);
+    Ok(())
+}
+
+pub async fn confirm_locker(rpc: &Rpc, factory: &AccountId) -> anyhow::Result<()> {
+    call_json(rpc, factory, "confirm_locker", serde_json::json!({})).await?;
+    let locker = rpc
+        .view(factory, "locker_account_id", serde_json::json!({}))
+        .await?;
+    println!("Locker is now {}", locker);
+    Ok(())
+}
+
+/// Deploy the NEP-141 representative of the ERC-20 at `token`, registering
+/// `accounts` on it.
+pub async fn create_token(
+    rpc: &Rpc,
+    factory: &AccountId,
+    token: Address,
+    accounts: Vec<AccountId>,
+) -> anyhow::Result<()> {
+    call_json(
+        rpc,
+        factory,
+        "create_token_with_registrations",
+        serde_json::json!({ "token_address": token, "accounts": accounts }),
+    )
+    .await?;
+    let account_id = rpc
+        .view(
+            factory,
+            "get_token_account_id",
+            serde_json::json!({ "erc20_address": token }),
+        )
+        .await?;
+    println!("Token {} deployed to {}", token, account_id);
+    Ok(())
+}
+
+/// Print the binary version of the deployed tokens, after the number of tokens at
+/// each version.
+pub async fn token_versions(
+    rpc: &Rpc,
+    factory: &AccountId,
+    from_index: u64,
+    limit: u64,
+) -> anyhow::Result<()> {
+    let distribution = rpc
+        .view(factory, "version_distribution", serde_json::json!({}))
+        .await?;
+    for entry in distribution.as_array().into_iter().flatten() {
+        println!("Version {}: {} tokens", entry[0], entry[1]);
+    }
+    let tokens = rpc
+        .view(
+            factory,
+            "get_tokens",
+            serde_json::json!({ "from_index": from_index, "limit": limit }),
+        )
+        .await?;
+    for token in tokens.as_array().into_iter().flatten() {
+        println!("{} {}", token[0], token[1]);
+    }
+    Ok(())
+}
+
+/// Pause, or unpause, the flows selected by `flags`, see the factory `pause`.
+pub async fn set_paused(
+    rpc: &Rpc,
+    factory: &AccountId,
+    flags: u8,
+    paused: bool,
+) -> anyhow::Result<()> {
+    let method = if paused { "pause" } else { "unpause" };
+    call_json(rpc, factory, method, serde_json::json!({ "flags": flags })).await?;
+    let modes = rpc.view(factory, "modes", serde_json::json!({})).await?;
+    println!("{}", modes);
+    Ok(())
+}
+
+/// Upgrade up to `limit` tokens running an outdated binary.
+pub async fn upgrade_tokens(rpc: &Rpc, factory: &AccountId, limit: u64) -> anyhow::Result<()> {
+    let output = call_json(
+        rpc,
+        factory,
+        "upgrade_tokens",
+        serde_json::json!({ "limit": limit }),
+    )
+    .await?;
+    println!(
+        "{} upgrades scheduled",
+        String::from_utf8_lossy(&output).trim()
+    );
+    Ok(())
+}
+
+async fn call_json(
+    rpc: &Rpc,
+    receiver_id: &AccountId,
+    method: &str,
+    args: serde_json::Value,
+) -> anyhow::Result<Vec<u8>> {
+    rpc.call(receiver_id, method, serde_json::to_vec(&args)?, 0)
+        .await
+}
+
+async fn read(path: &Path) -> anyhow::Result<Vec<u8>> {
+    tokio::fs::read(path)
+        .await
+        .with_context(|| format!("Failed to read {}", path.display()))
+}
+
+/// Base58 sha256 hash of `binary`, as expected by `propose_token_binary` and
+/// `finalize_binary_upload`.
+fn checksum(binary: &[u8]) -> String {
+    bs58::encode(Sha256::digest(binary)).into_string()
+}
+
+/// Bytecode in `input`, which is either hex, with an optional `0x` prefix, or a forge
+/// artifact with the hex in `bytecode.object`.
+fn parse_bytecode(input: &str) -> anyhow::Result<Vec<u8>> {
+    let hex = match serde_json::from_str::<serde_json::Value>(input) {
+        Ok(artifact) => artifact["bytecode"]["object"]
+            .as_str()
+            .ok_or_else(|| anyhow!("The artifact has no `bytecode.object`"))?
+            .to_string(),
+        Err(_) => input.trim().to_string(),
+    };
+    if hex.contains("__$") {
+        return Err(anyhow!(
+            "The bytecode has unlinked libraries, link them before deploying"
+        ));
+    }
+    Ok(hex::decode(hex.strip_prefix("0x").unwrap_or(&hex))?)
+}
+
+/// Aurora address, see `Address::decode`.
+pub fn parse_address(input: &str) -> anyhow::Result<Address> {
+    Address::decode(input).with_context(|| format!("{} is not an address", input))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{checksum, parse_address, parse_bytecode};
+    use near_token_common::Address;
+
+    #[test]
+    fn test_parse_bytecode() {
+        assert_eq!(parse_bytecode("0x6080").unwrap(), vec![0x60, 0x80]);
+        assert_eq!(
+            parse_bytecode(r#"{"bytecode": {"object": "0x6080"}}"#).unwrap(),
+            vec![0x60, 0x80]
+        );
+        assert!(parse_bytecode("0x60__$abc$__").is_err());
+    }
+
+    #[test]
+    fn test_parse_address() {
+        assert_eq!(
+            parse_address("0x0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a").unwrap(),
+            Address([0x0a; 20])
+        );
+        assert!(parse_address("0a0a").is_err());
+    }
+
+    #[test]
+    fn test_checksum() {
+        // sha256 of the empty binary.
+        assert_eq!(
+            checksum(&[]),
+            "GKot5hBsd81kMupNCXHaqbhv3huEbxAFMLnpcX2hniwn"
+        );
+    }
+}
@@ -0,0 +1,218 @@
+//! Command line tool wrapping the operator workflows of the connector: deploying the
+//! factory and the locker, uploading the token binary, creating, inspecting, pausing
+//! and upgrading tokens. Transactions are signed with a key of the near-cli keychain.
+use anyhow::{anyhow, Context};
+use near_primitives::types::AccountId;
+use std::path::PathBuf;
+
+mod commands;
+mod rpc;
+
+const USAGE: &str = "\
+Usage: connector-cli [OPTIONS] <COMMAND> [ARGS]
+
+Options:
+  --network <NETWORK>    testnet, mainnet or localnet [default: testnet]
+  --rpc-url <URL>        RPC endpoint [default: the endpoint of the network]
+  --signer <ACCOUNT_ID>  Account signing the transactions, with its key read from
+                         ~/.near-credentials/<NETWORK>/<ACCOUNT_ID>.json
+  --key-file <PATH>      Key file of the signer, instead of the keychain
+  --factory <ACCOUNT_ID> Factory account [default: the signer]
+
+Commands:
+  deploy-factory <WASM> <AURORA> <LOCKER> [OWNER]
+  propose-binary <WASM>
+  upload-binary <WASM> [CHUNK_SIZE]
+  deploy-locker <AURORA> <BYTECODE> <WNEAR>
+  propose-locker <LOCKER>
+  confirm-locker
+  create-token <TOKEN> [ACCOUNT_ID...]
+  token-versions [FROM_INDEX] [LIMIT]
+  pause <FLAGS>
+  unpause <FLAGS>
+  upgrade-tokens <LIMIT>
+";
+
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Options {
+    network: Option<String>,
+    rpc_url: Option<String>,
+    signer: Option<AccountId>,
+    key_file: Option<PathBuf>,
+    factory: Option<AccountId>,
+    command: Vec<String>,
+}
+
+impl Options {
+    fn parse(mut args: impl Iterator<Item = String>) -> anyhow::Result<Self> {
+        let mut options = Self::default();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| anyhow!("Missing value of {}", arg))
+            };
+            match arg.as_str() {
+                "--network" => options.network = Some(value()?),
+                "--rpc-url" => options.rpc_url = Some(value()?),
+                "--signer" => options.signer = Some(value()?.parse()?),
+                "--key-file" => options.key_file = Some(value()?.into()),
+                "--factory" => options.factory = Some(value()?.parse()?),
+                _ if arg.starts_with("--") => return Err(anyhow!("Unknown option {}", arg)),
+                _ => {
+                    options.command.push(arg);
+                    options.command.extend(args);
+                    break;
+                }
+            }
+        }
+        Ok(options)
+    }
+
+    fn network(&self) -> &str {
+        self.network.as_deref().unwrap_or("testnet")
+    }
+
+    fn rpc_url(&self) -> anyhow::Result<String> {
+        if let Some(url) = &self.rpc_url {
+            return Ok(url.clone());
+        }
+        match self.network() {
+            "testnet" => Ok("https://rpc.testnet.near.org".to_string()),
+            "mainnet" => Ok("https://rpc.mainnet.near.org".to_string()),
+            "localnet" => Ok("http://localhost:3030".to_string()),
+            network => Err(anyhow!("Unknown network {}, set --rpc-url", network)),
+        }
+    }
+
+    fn rpc(&self) -> anyhow::Result<rpc::Rpc> {
+        let key_file = match (&self.key_file, &self.signer) {
+            (Some(path), _) => Some(path.clone()),
+            (None, Some(signer)) => Some(rpc::keychain_path(self.network(), signer)?),
+            (None, None) => None,
+        };
+        let signer = key_file.map(|path| rpc::load_signer(&path)).transpose()?;
+        Ok(rpc::Rpc::new(&self.rpc_url()?, signer))
+    }
+
+    fn factory(&self, rpc: &rpc::Rpc) -> anyhow::Result<AccountId> {
+        match &self.factory {
+            Some(factory) => Ok(factory.clone()),
+            None => Ok(rpc.signer().context("Set --factory")?.account_id.clone()),
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    if let Err(err) = run().await {
+        eprintln!("Error: {:#}", err);
+        std::process::exit(1);
+    }
+}
+
+async fn run() -> anyhow::Result<()> {
+    let options = Options::parse(std::env::args().skip(1))?;
+    let rpc = options.rpc()?;
+    let args: Vec<&str> = options.command.iter().map(String::as_str).collect();
+    match args.as_slice() {
+        ["deploy-factory", wasm, aurora, locker, owner @ ..] if owner.len() <= 1 => {
+            let owner = owner.first().map(|owner| owner.parse()).transpose()?;
+            commands::deploy_factory(
+                &rpc,
+                std::path::Path::new(wasm),
+                aurora.parse()?,
+                commands::parse_address(locker)?,
+                owner,
+            )
+            .await
+        }
+        ["propose-binary", wasm] => {
+            let factory = options.factory(&rpc)?;
+            commands::propose_binary(&rpc, &factory, std::path::Path::new(wasm)).await
+        }
+        ["upload-binary", wasm, chunk_size @ ..] if chunk_size.len() <= 1 => {
+            let chunk_size = match chunk_size.first() {
+                Some(chunk_size) => chunk_size.parse()?,
+                None => commands::DEFAULT_CHUNK_SIZE,
+            };
+            let factory = options.factory(&rpc)?;
+            commands::upload_binary(&rpc, &factory, std::path::Path::new(wasm), chunk_size).await
+        }
+        ["deploy-locker", aurora, bytecode, wnear] => {
+            let factory = options.factory(&rpc)?;
+            commands::deploy_locker(
+                &rpc,
+                &aurora.parse()?,
+                &factory,
+                std::path::Path::new(bytecode),
+                commands::parse_address(wnear)?,
+            )
+            .await
+            .map(|_| ())
+        }
+        ["propose-locker", locker] => {
+            let factory = options.factory(&rpc)?;
+            commands::propose_locker(&rpc, &factory, commands::parse_address(locker)?).await
+        }
+        ["confirm-locker"] => commands::confirm_locker(&rpc, &options.factory(&rpc)?).await,
+        ["create-token", token, accounts @ ..] => {
+            let accounts = accounts
+                .iter()
+                .map(|account_id| account_id.parse())
+                .collect::<Result<Vec<_>, _>>()?;
+            let factory = options.factory(&rpc)?;
+            commands::create_token(&rpc, &factory, commands::parse_address(token)?, accounts).await
+        }
+        ["token-versions", range @ ..] if range.len() <= 2 => {
+            let from_index = range.first().map_or(Ok(0), |index| index.parse())?;
+            let limit = range
+                .get(1)
+                .map_or(Ok(commands::DEFAULT_TOKENS_LIMIT), |limit| limit.parse())?;
+            let factory = options.factory(&rpc)?;
+            commands::token_versions(&rpc, &factory, from_index, limit).await
+        }
+        ["pause", flags] => {
+            commands::set_paused(&rpc, &options.factory(&rpc)?, flags.parse()?, true).await
+        }
+        ["unpause", flags] => {
+            commands::set_paused(&rpc, &options.factory(&rpc)?, flags.parse()?, false).await
+        }
+        ["upgrade-tokens", limit] => {
+            commands::upgrade_tokens(&rpc, &options.factory(&rpc)?, limit.parse()?).await
+        }
+        _ => Err(anyhow!("{}", USAGE)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Options;
+
+    fn parse(args: &[&str]) -> anyhow::Result<Options> {
+        Options::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_parse_options() {
+        let options = parse(&[
+            "--network",
+            "mainnet",
+            "--signer",
+            "owner.near",
+            "pause",
+            "--flags",
+        ])
+        .unwrap();
+        assert_eq!(options.network(), "mainnet");
+        assert_eq!(options.signer, Some("owner.near".parse().unwrap()));
+        // Everything after the command is an argument of the command.
+        assert_eq!(options.command, vec!["pause", "--flags"]);
+        assert_eq!(options.rpc_url().unwrap(), "https://rpc.mainnet.near.org");
+    }
+
+    #[test]
+    fn test_parse_options_rejects_unknown_option() {
+        assert!(parse(&["--sign", "owner.near"]).is_err());
+        assert!(parse(&["--signer"]).is_err());
+    }
+}
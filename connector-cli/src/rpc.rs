@@ -0,0 +1,142 @@
+//! Thin wrapper of the NEAR JSON RPC, signing transactions with a key of the local
+//! keychain.
+use anyhow::{anyhow, Context};
+use near_crypto::InMemorySigner;
+use near_jsonrpc_client::{methods, JsonRpcClient};
+use near_jsonrpc_primitives::types::query::QueryResponseKind;
+use near_primitives::transaction::{Action, FunctionCallAction, Transaction};
+use near_primitives::types::{AccountId, BlockReference, Finality, FunctionArgs};
+use near_primitives::views::{FinalExecutionStatus, QueryRequest};
+use std::path::{Path, PathBuf};
+
+/// Gas attached to function calls, the maximum of a transaction.
+pub const MAX_GAS: u64 = 300_000_000_000_000;
+
+pub struct Rpc {
+    client: JsonRpcClient,
+    signer: Option<InMemorySigner>,
+}
+
+impl Rpc {
+    pub fn new(url: &str, signer: Option<InMemorySigner>) -> Self {
+        Self {
+            client: JsonRpcClient::connect(url),
+            signer,
+        }
+    }
+
+    /// Call the view `method` of `account_id` with JSON `args`.
+    pub async fn view(
+        &self,
+        account_id: &AccountId,
+        method: &str,
+        args: serde_json::Value,
+    ) -> anyhow::Result<serde_json::Value> {
+        let request = methods::query::RpcQueryRequest {
+            block_reference: BlockReference::Finality(Finality::Final),
+            request: QueryRequest::CallFunction {
+                account_id: account_id.clone(),
+                method_name: method.to_string(),
+                args: FunctionArgs::from(serde_json::to_vec(&args)?),
+            },
+        };
+        let response = self
+            .client
+            .call(request)
+            .await
+            .map_err(|err| anyhow!("View of {} failed: {:?}", method, err))?;
+        match response.kind {
+            QueryResponseKind::CallResult(result) => Ok(serde_json::from_slice(&result.result)?),
+            kind => Err(anyhow!("Unexpected response to view: {:?}", kind)),
+        }
+    }
+
+    /// Call `method` of `receiver_id` with `args`, and return the result once the
+    /// transaction and its receipts are final.
+    pub async fn call(
+        &self,
+        receiver_id: &AccountId,
+        method: &str,
+        args: Vec<u8>,
+        deposit: u128,
+    ) -> anyhow::Result<Vec<u8>> {
+        let action = Action::FunctionCall(FunctionCallAction {
+            method_name: method.to_string(),
+            args,
+            gas: MAX_GAS,
+            deposit,
+        });
+        self.transact(receiver_id, vec![action]).await
+    }
+
+    /// Sign and send a transaction of `actions` to `receiver_id`.
+    pub async fn transact(
+        &self,
+        receiver_id: &AccountId,
+        actions: Vec<Action>,
+    ) -> anyhow::Result<Vec<u8>> {
+        let signer = self.signer()?;
+        let request = methods::query::RpcQueryRequest {
+            block_reference: BlockReference::Finality(Finality::Final),
+            request: QueryRequest::ViewAccessKey {
+                account_id: signer.account_id.clone(),
+                public_key: signer.public_key.clone(),
+            },
+        };
+        let response = self
+            .client
+            .call(request)
+            .await
+            .map_err(|err| anyhow!("Access key query failed: {:?}", err))?;
+        let nonce = match response.kind {
+            QueryResponseKind::AccessKey(access_key) => access_key.nonce,
+            kind => return Err(anyhow!("Unexpected response to access key: {:?}", kind)),
+        };
+
+        let transaction = Transaction {
+            signer_id: signer.account_id.clone(),
+            public_key: signer.public_key.clone(),
+            nonce: nonce + 1,
+            receiver_id: receiver_id.clone(),
+            block_hash: response.block_hash,
+            actions,
+        };
+        let request = methods::broadcast_tx_commit::RpcBroadcastTxCommitRequest {
+            signed_transaction: transaction.sign(signer),
+        };
+        let outcome = self
+            .client
+            .call(request)
+            .await
+            .map_err(|err| anyhow!("Transaction failed: {:?}", err))?;
+        match outcome.status {
+            FinalExecutionStatus::SuccessValue(value) => Ok(value),
+            status => Err(anyhow!(
+                "Transaction {} failed: {:?}",
+                outcome.transaction.hash,
+                status
+            )),
+        }
+    }
+
+    pub fn signer(&self) -> anyhow::Result<&InMemorySigner> {
+        self.signer
+            .as_ref()
+            .ok_or_else(|| anyhow!("The command needs a signer, see `--signer`"))
+    }
+}
+
+/// Key of `account_id` in the keychain of near-cli, i.e.
+/// `~/.near-credentials/{network}/{account_id}.json`.
+pub fn keychain_path(network: &str, account_id: &AccountId) -> anyhow::Result<PathBuf> {
+    let home = std::env::var("HOME").context("HOME is not set")?;
+    Ok(Path::new(&home)
+        .join(".near-credentials")
+        .join(network)
+        .join(format!("{}.json", account_id)))
+}
+
+pub fn load_signer(path: &Path) -> anyhow::Result<InMemorySigner> {
+    InMemorySigner::from_file(path)
+        .with_context(|| format!("Failed to read the key file {}", path.display()))
+}
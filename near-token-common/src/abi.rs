@@ -0,0 +1,512 @@
+//! Solidity ABI encoding and decoding of the values exchanged with Aurora contracts:
+//! addresses, unsigned integers, booleans, `bytes` and dynamic arrays. Selectors are
+//! computed at compile time with `selector`. Only `core` and `alloc` are used, so the
+//! module doesn't depend on the NEAR environment.
+use crate::{Address, RawU256};
+use near_sdk::json_types::U128;
+use serde::{Deserialize, Serialize};
+
+/// Size of a single encoded ABI word.
+pub const WORD_SIZE: usize = 32;
+
+/// ABI value. Static values take a single word, `Bytes` and `Array` are dynamic.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type", content = "value", rename_all = "snake_case")]
+pub enum AbiValue {
+    Address(Address),
+    Uint(U128),
+    /// Unsigned integer of up to 256 bits, big-endian.
+    Uint256(RawU256),
+    Bool(bool),
+    Bytes(Vec<u8>),
+    /// Dynamic array of values of the same type.
+    Array(Vec<AbiValue>),
+}
+
+/// ABI type of a value to decode.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AbiType {
+    Address,
+    /// Unsigned integer of the given number of bits. It is decoded as `AbiValue::Uint`
+    /// up to 128 bits, and as `AbiValue::Uint256` above.
+    Uint(u16),
+    Bool,
+    Bytes,
+    Array(Box<AbiType>),
+}
+
+/// Error decoding ABI encoded data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AbiError {
+    /// The data ends before the value.
+    UnexpectedEnd,
+    /// An offset or a length doesn't fit in the data.
+    InvalidOffset,
+    /// A word is not a valid value of its type, e.g. an address with dirty upper bytes.
+    InvalidValue,
+}
+
+impl AbiValue {
+    fn is_dynamic(&self) -> bool {
+        matches!(self, AbiValue::Bytes(_) | AbiValue::Array(_))
+    }
+
+    /// Word of a static value.
+    fn encode_word(&self) -> [u8; WORD_SIZE] {
+        let mut word = [0u8; WORD_SIZE];
+        match self {
+            AbiValue::Address(address) => word[12..].copy_from_slice(&address.0),
+            AbiValue::Uint(value) => word = uint_word(value.0),
+            AbiValue::Uint256(value) => word = *value,
+            AbiValue::Bool(value) => word[WORD_SIZE - 1] = *value as u8,
+            AbiValue::Bytes(_) | AbiValue::Array(_) => unreachable!("dynamic value"),
+        }
+        word
+    }
+
+    /// Tail of a dynamic value: its length followed by its content.
+    fn encode_tail(&self) -> Vec<u8> {
+        match self {
+            AbiValue::Bytes(data) => {
+                let mut tail = uint_word(data.len() as u128).to_vec();
+                tail.extend_from_slice(data);
+                tail.resize(WORD_SIZE + padded_len(data.len()), 0);
+                tail
+            }
+            AbiValue::Array(values) => {
+                let mut tail = uint_word(values.len() as u128).to_vec();
+                tail.extend(encode(values));
+                tail
+            }
+            _ => unreachable!("static value"),
+        }
+    }
+}
+
+/// Encode `values` as a tuple, e.g. the arguments of a call.
+pub fn encode(values: &[AbiValue]) -> Vec<u8> {
+    let head_size = WORD_SIZE * values.len();
+    let mut head = Vec::with_capacity(head_size);
+    let mut tail = Vec::new();
+    for value in values {
+        if value.is_dynamic() {
+            head.extend_from_slice(&uint_word((head_size + tail.len()) as u128));
+            tail.extend(value.encode_tail());
+        } else {
+            head.extend_from_slice(&value.encode_word());
+        }
+    }
+    head.extend(tail);
+    head
+}
+
+/// Encode a call of the function with `selector` and `args`.
+pub fn encode_call(selector: [u8; 4], args: &[AbiValue]) -> Vec<u8> {
+    let mut input = selector.to_vec();
+    input.extend(encode(args));
+    input
+}
+
+/// Decode `data` as a tuple of values of `types`.
+pub fn decode(types: &[AbiType], data: &[u8]) -> Result<Vec<AbiValue>, AbiError> {
+    types
+        .iter()
+        .enumerate()
+        .map(|(index, ty)| {
+            let word = read_word(data, index * WORD_SIZE)?;
+            match ty {
+                AbiType::Bytes | AbiType::Array(_) => decode_tail(ty, data, read_usize(&word)?),
+                _ => decode_word(ty, &word),
+            }
+        })
+        .collect()
+}
+
+/// Selector of the function with `signature`: the first 4 bytes of its keccak256 hash.
+/// It can be evaluated at compile time, e.g. for constants.
+pub const fn selector(signature: &str) -> [u8; 4] {
+    let hash = keccak256(signature.as_bytes());
+    [hash[0], hash[1], hash[2], hash[3]]
+}
+
+fn decode_word(ty: &AbiType, word: &[u8; WORD_SIZE]) -> Result<AbiValue, AbiError> {
+    match ty {
+        AbiType::Address => {
+            require_zero(&word[..12])?;
+            let mut address = [0u8; 20];
+            address.copy_from_slice(&word[12..]);
+            Ok(AbiValue::Address(Address(address)))
+        }
+        AbiType::Uint(bits) if *bits <= 128 => {
+            require_zero(&word[..16])?;
+            let mut bytes = [0u8; 16];
+            bytes.copy_from_slice(&word[16..]);
+            let value = u128::from_be_bytes(bytes);
+            if *bits < 128 && value >> bits != 0 {
+                return Err(AbiError::InvalidValue);
+            }
+            Ok(AbiValue::Uint(U128(value)))
+        }
+        AbiType::Uint(_) => Ok(AbiValue::Uint256(*word)),
+        AbiType::Bool => {
+            require_zero(&word[..WORD_SIZE - 1])?;
+            match word[WORD_SIZE - 1] {
+                0 => Ok(AbiValue::Bool(false)),
+                1 => Ok(AbiValue::Bool(true)),
+                _ => Err(AbiError::InvalidValue),
+            }
+        }
+        AbiType::Bytes | AbiType::Array(_) => unreachable!("dynamic type"),
+    }
+}
+
+fn decode_tail(ty: &AbiType, data: &[u8], offset: usize) -> Result<AbiValue, AbiError> {
+    let len = read_usize(&read_word(data, offset)?)?;
+    let content = data
+        .get(offset + WORD_SIZE..)
+        .ok_or(AbiError::InvalidOffset)?;
+    match ty {
+        AbiType::Bytes => content
+            .get(..len)
+            .map(|bytes| AbiValue::Bytes(bytes.to_vec()))
+            .ok_or(AbiError::InvalidOffset),
+        AbiType::Array(item) => {
+            let types = vec![item.as_ref().clone(); len];
+            decode(&types, content).map(AbiValue::Array)
+        }
+        _ => unreachable!("static type"),
+    }
+}
+
+fn read_word(data: &[u8], offset: usize) -> Result<[u8; WORD_SIZE], AbiError> {
+    let bytes = data
+        .get(offset..offset.saturating_add(WORD_SIZE))
+        .ok_or(AbiError::UnexpectedEnd)?;
+    let mut word = [0u8; WORD_SIZE];
+    word.copy_from_slice(bytes);
+    Ok(word)
+}
+
+/// Offset or length held by `word`.
+fn read_usize(word: &[u8; WORD_SIZE]) -> Result<usize, AbiError> {
+    require_zero(&word[..WORD_SIZE - 8]).map_err(|_| AbiError::InvalidOffset)?;
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&word[WORD_SIZE - 8..]);
+    usize::try_from(u64::from_be_bytes(bytes)).map_err(|_| AbiError::InvalidOffset)
+}
+
+fn require_zero(bytes: &[u8]) -> Result<(), AbiError> {
+    if bytes.iter().all(|byte| *byte == 0) {
+        Ok(())
+    } else {
+        Err(AbiError::InvalidValue)
+    }
+}
+
+fn uint_word(value: u128) -> [u8; WORD_SIZE] {
+    let mut word = [0u8; WORD_SIZE];
+    word[16..].copy_from_slice(&value.to_be_bytes());
+    word
+}
+
+/// `len` rounded up to whole words.
+fn padded_len(len: usize) -> usize {
+    (len + WORD_SIZE - 1) / WORD_SIZE * WORD_SIZE
+}
+
+const KECCAK_RATE: usize = 136;
+const KECCAK_ROUND_CONSTANTS: [u64; 24] = [
+    0x0000000000000001,
+    0x0000000000008082,
+    0x800000000000808a,
+    0x8000000080008000,
+    0x000000000000808b,
+    0x0000000080000001,
+    0x8000000080008081,
+    0x8000000000008009,
+    0x000000000000008a,
+    0x0000000000000088,
+    0x0000000080008009,
+    0x000000008000000a,
+    0x000000008000808b,
+    0x800000000000008b,
+    0x8000000000008089,
+    0x8000000000008003,
+    0x8000000000008002,
+    0x8000000000000080,
+    0x000000000000800a,
+    0x800000008000000a,
+    0x8000000080008081,
+    0x8000000000008080,
+    0x0000000080000001,
+    0x8000000080008008,
+];
+const KECCAK_ROTATIONS: [u32; 24] = [
+    1, 3, 6, 10, 15, 21, 28, 36, 45, 55, 2, 14, 27, 41, 56, 8, 25, 43, 62, 18, 39, 61, 20, 44,
+];
+const KECCAK_LANES: [usize; 24] = [
+    10, 7, 11, 17, 18, 3, 5, 16, 8, 21, 24, 4, 15, 23, 19, 13, 12, 2, 20, 14, 22, 9, 6, 1,
+];
+
+/// Keccak256 hash of `input`, usable in constants. At runtime, prefer
+/// `near_sdk::env::keccak256`, which is cheaper.
+pub const fn keccak256(input: &[u8]) -> [u8; 32] {
+    let mut state = [0u64; 25];
+    let mut offset = 0;
+    while offset + KECCAK_RATE <= input.len() {
+        state = keccak_absorb(state, input, offset);
+        state = keccak_f(state);
+        offset += KECCAK_RATE;
+    }
+
+    // The last block holds the rest of the input and the padding.
+    let mut block = [0u8; KECCAK_RATE];
+    let mut i = 0;
+    while offset + i < input.len() {
+        block[i] = input[offset + i];
+        i += 1;
+    }
+    block[i] ^= 0x01;
+    block[KECCAK_RATE - 1] ^= 0x80;
+    state = keccak_absorb(state, &block, 0);
+    state = keccak_f(state);
+
+    let mut hash = [0u8; 32];
+    let mut i = 0;
+    while i < 32 {
+        hash[i] = (state[i / 8] >> (8 * (i % 8))) as u8;
+        i += 1;
+    }
+    hash
+}
+
+const fn keccak_absorb(mut state: [u64; 25], data: &[u8], offset: usize) -> [u64; 25] {
+    let mut i = 0;
+    while i < KECCAK_RATE {
+        state[i / 8] ^= (data[offset + i] as u64) << (8 * (i % 8));
+        i += 1;
+    }
+    state
+}
+
+const fn keccak_f(mut state: [u64; 25]) -> [u64; 25] {
+    let mut round = 0;
+    while round < 24 {
+        // Theta.
+        let mut columns = [0u64; 5];
+        let mut x = 0;
+        while x < 5 {
+            columns[x] = state[x] ^ state[x + 5] ^ state[x + 10] ^ state[x + 15] ^ state[x + 20];
+            x += 1;
+        }
+        x = 0;
+        while x < 5 {
+            let t = columns[(x + 4) % 5] ^ columns[(x + 1) % 5].rotate_left(1);
+            let mut y = 0;
+            while y < 25 {
+                state[y + x] ^= t;
+                y += 5;
+            }
+            x += 1;
+        }
+
+        // Rho and pi.
+        let mut last = state[1];
+        let mut i = 0;
+        while i < 24 {
+            let lane = KECCAK_LANES[i];
+            let next = state[lane];
+            state[lane] = last.rotate_left(KECCAK_ROTATIONS[i]);
+            last = next;
+            i += 1;
+        }
+
+        // Chi.
+        let mut y = 0;
+        while y < 25 {
+            let mut row = [0u64; 5];
+            let mut x = 0;
+            while x < 5 {
+                row[x] = state[y + x];
+                x += 1;
+            }
+            x = 0;
+            while x < 5 {
+                state[y + x] ^= !row[(x + 1) % 5] & row[(x + 2) % 5];
+                x += 1;
+            }
+            y += 5;
+        }
+
+        // Iota.
+        state[0] ^= KECCAK_ROUND_CONSTANTS[round];
+        round += 1;
+    }
+    state
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, encode, encode_call, keccak256, selector, AbiError, AbiType, AbiValue};
+    use crate::Address;
+    use near_sdk::json_types::U128;
+    use proptest::prelude::*;
+
+    fn to_ethabi(value: &AbiValue) -> ethabi::Token {
+        match value {
+            AbiValue::Address(address) => ethabi::Token::Address(address.0.into()),
+            AbiValue::Uint(value) => ethabi::Token::Uint(value.0.into()),
+            AbiValue::Uint256(value) => ethabi::Token::Uint(ethabi::Uint::from_big_endian(value)),
+            AbiValue::Bool(value) => ethabi::Token::Bool(*value),
+            AbiValue::Bytes(data) => ethabi::Token::Bytes(data.clone()),
+            AbiValue::Array(values) => ethabi::Token::Array(values.iter().map(to_ethabi).collect()),
+        }
+    }
+
+    fn sample() -> Vec<AbiValue> {
+        vec![
+            AbiValue::Address(Address([7; 20])),
+            AbiValue::Bytes(vec![5; 33]),
+            AbiValue::Uint(U128(0x998877665544332211)),
+            AbiValue::Array(vec![
+                AbiValue::Bytes(vec![1, 2, 3]),
+                AbiValue::Bytes(Vec::new()),
+            ]),
+            AbiValue::Bool(true),
+            AbiValue::Uint256([9; 32]),
+        ]
+    }
+
+    fn sample_types() -> Vec<AbiType> {
+        vec![
+            AbiType::Address,
+            AbiType::Bytes,
+            AbiType::Uint(128),
+            AbiType::Array(Box::new(AbiType::Bytes)),
+            AbiType::Bool,
+            AbiType::Uint(256),
+        ]
+    }
+
+    #[test]
+    fn test_encode_matches_ethabi() {
+        let values = sample();
+        let tokens: Vec<ethabi::Token> = values.iter().map(to_ethabi).collect();
+        assert_eq!(encode(&values), ethabi::encode(&tokens));
+
+        let call = encode_call([1, 2, 3, 4], &values);
+        assert_eq!(&call[..4], &[1, 2, 3, 4]);
+        assert_eq!(&call[4..], &ethabi::encode(&tokens));
+    }
+
+    #[test]
+    fn test_decode_round_trip() {
+        let values = sample();
+        assert_eq!(decode(&sample_types(), &encode(&values)), Ok(values));
+    }
+
+    #[test]
+    fn test_decode_rejects_invalid_data() {
+        let encoded = encode(&sample());
+        assert_eq!(
+            decode(&sample_types(), &encoded[..encoded.len() - 1]),
+            Err(AbiError::UnexpectedEnd)
+        );
+        // A value doesn't fit its type.
+        assert_eq!(
+            decode(&[AbiType::Uint(8)], &encode(&[AbiValue::Uint(U128(256))])),
+            Err(AbiError::InvalidValue)
+        );
+        // Upper bytes of an address are not zero.
+        assert_eq!(
+            decode(&[AbiType::Address], &[1; 32]),
+            Err(AbiError::InvalidValue)
+        );
+    }
+
+    #[test]
+    fn test_selector() {
+        const WITHDRAW: [u8; 4] = selector("withdraw(address,address,uint256)");
+        assert_eq!(WITHDRAW, [0xd9, 0xca, 0xed, 0x12]);
+        assert_eq!(
+            WITHDRAW,
+            ethabi::short_signature(
+                "withdraw",
+                &[
+                    ethabi::ParamType::Address,
+                    ethabi::ParamType::Address,
+                    ethabi::ParamType::Uint(256)
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn test_keccak256_across_blocks() {
+        for len in [0, 1, 135, 136, 137, 300] {
+            let input = vec![0x61; len];
+            assert_eq!(
+                keccak256(&input).to_vec(),
+                near_sdk::env::keccak256(&input),
+                "length {}",
+                len
+            );
+        }
+    }
+
+    /// Types of up to two levels of nested arrays.
+    fn abi_type() -> impl Strategy<Value = AbiType> {
+        let leaf = prop_oneof![
+            Just(AbiType::Address),
+            Just(AbiType::Uint(128)),
+            Just(AbiType::Uint(256)),
+            Just(AbiType::Bool),
+            Just(AbiType::Bytes),
+        ];
+        leaf.prop_recursive(2, 8, 1, |inner| {
+            inner.prop_map(|ty| AbiType::Array(Box::new(ty)))
+        })
+    }
+
+    /// Values of type `ty`, with dynamic values crossing word boundaries.
+    fn abi_value(ty: AbiType) -> BoxedStrategy<AbiValue> {
+        match ty {
+            AbiType::Address => any::<[u8; 20]>()
+                .prop_map(|address| AbiValue::Address(Address(address)))
+                .boxed(),
+            AbiType::Uint(256) => any::<[u8; 32]>().prop_map(AbiValue::Uint256).boxed(),
+            AbiType::Uint(_) => any::<u128>()
+                .prop_map(|value| AbiValue::Uint(U128(value)))
+                .boxed(),
+            AbiType::Bool => any::<bool>().prop_map(AbiValue::Bool).boxed(),
+            AbiType::Bytes => proptest::collection::vec(any::<u8>(), 0..100)
+                .prop_map(AbiValue::Bytes)
+                .boxed(),
+            AbiType::Array(ty) => proptest::collection::vec(abi_value(*ty), 0..4)
+                .prop_map(AbiValue::Array)
+                .boxed(),
+        }
+    }
+
+    fn typed_values() -> impl Strategy<Value = Vec<(AbiType, AbiValue)>> {
+        proptest::collection::vec(
+            abi_type().prop_flat_map(|ty| (Just(ty.clone()), abi_value(ty))),
+            0..6,
+        )
+    }
+
+    proptest! {
+        #[test]
+        fn prop_encode_matches_ethabi(values in typed_values()) {
+            let values: Vec<AbiValue> = values.into_iter().map(|(_, value)| value).collect();
+            let tokens: Vec<ethabi::Token> = values.iter().map(to_ethabi).collect();
+            prop_assert_eq!(encode(&values), ethabi::encode(&tokens));
+        }
+
+        #[test]
+        fn prop_decode_round_trip(values in typed_values()) {
+            let (types, values): (Vec<AbiType>, Vec<AbiValue>) = values.into_iter().unzip();
+            prop_assert_eq!(decode(&types, &encode(&values)), Ok(values));
+        }
+    }
+}
@@ -1,5 +1,6 @@
 use near_sdk::borsh;
 use near_sdk::ext_contract;
+use near_sdk::{AccountId, Balance, Gas, Promise};
 
 use crate::types::CallArgs;
 
@@ -15,3 +16,13 @@ pub fn call_args(to: crate::Address, input: Vec<u8>) -> CallArgs {
         input,
     })
 }
+
+/// `call` on the engine `aurora` with `fee` yoctoNEAR attached. Engines charging for
+/// cross-contract calls back to NEAR wrap the attached NEAR into wNEAR to pay for their
+/// execution. A zero fee attaches nothing.
+pub fn call_with_fee(aurora: AccountId, args: CallArgs, gas: Gas, fee: Balance) -> Promise {
+    ext_aurora::ext(aurora)
+        .with_static_gas(gas)
+        .with_attached_deposit(fee)
+        .call(args)
+}
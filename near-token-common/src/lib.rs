@@ -1,3 +1,4 @@
+pub mod abi;
 pub mod aurora;
 pub mod types;
 
@@ -1,6 +1,7 @@
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
 use serde::{Deserialize, Serialize};
 use std::fmt::Display;
+use std::str::FromStr;
 
 #[derive(BorshSerialize, BorshDeserialize, Debug, Clone)]
 pub enum CallArgs {
@@ -33,12 +34,97 @@ impl From<CallArgs> for FunctionCallArgs {
 
 pub type WeiU256 = [u8; 32];
 
-#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Debug, Clone)]
+/// Address of an Aurora account. It is a hex string in JSON, see `Address::decode`,
+/// and the 20 raw bytes in borsh.
+#[derive(BorshDeserialize, BorshSerialize, Debug, Clone, PartialEq, Eq)]
 pub struct Address(pub [u8; 20]);
 
+/// Error of the parsing of an `Address`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressError {
+    /// The address is not 20 bytes long.
+    InvalidLength,
+    /// The address is not hex.
+    InvalidHex,
+    /// The address is mixed case, and it doesn't match its EIP-55 checksum.
+    InvalidChecksum,
+}
+
+impl Display for AddressError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let message = match self {
+            Self::InvalidLength => "ERR_INVALID_ADDRESS: Address must be 20 bytes long.",
+            Self::InvalidHex => "ERR_INVALID_ADDRESS: Address must be hex.",
+            Self::InvalidChecksum => "ERR_INVALID_ADDRESS: Address checksum doesn't match.",
+        };
+        f.write_str(message)
+    }
+}
+
+impl std::error::Error for AddressError {}
+
+impl Address {
+    pub const ZERO: Address = Address([0u8; 20]);
+
+    pub fn is_zero(&self) -> bool {
+        self == &Self::ZERO
+    }
+
+    /// Hex encoding without the `0x` prefix, as used in NEAR account ids.
+    pub fn encode(&self) -> String {
+        hex::encode(self.0)
+    }
+
+    /// Parse 40 hex chars, with an optional `0x` prefix. Mixed case addresses MUST
+    /// match their EIP-55 checksum, lower and upper case addresses are not checked.
+    pub fn decode(input: &str) -> Result<Self, AddressError> {
+        let hex = input.strip_prefix("0x").unwrap_or(input);
+        if hex.len() != 40 {
+            return Err(AddressError::InvalidLength);
+        }
+        let mut address = [0u8; 20];
+        hex::decode_to_slice(hex, &mut address).map_err(|_| AddressError::InvalidHex)?;
+        let address = Self(address);
+
+        let is_mixed_case = hex.chars().any(|c| c.is_ascii_lowercase())
+            && hex.chars().any(|c| c.is_ascii_uppercase());
+        if is_mixed_case && address.to_checksum()[2..] != *hex {
+            return Err(AddressError::InvalidChecksum);
+        }
+        Ok(address)
+    }
+
+    /// EIP-55 checksummed hex encoding, with the `0x` prefix.
+    pub fn to_checksum(&self) -> String {
+        let lower = self.encode();
+        let hash = near_sdk::env::keccak256(lower.as_bytes());
+        let checksummed: String = lower
+            .chars()
+            .enumerate()
+            .map(|(i, c)| {
+                let nibble = (hash[i / 2] >> (4 * (1 - i % 2))) & 0x0f;
+                if nibble >= 8 {
+                    c.to_ascii_uppercase()
+                } else {
+                    c
+                }
+            })
+            .collect();
+        format!("0x{}", checksummed)
+    }
+}
+
 impl Display for Address {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "0x{}", hex::encode(&self.0))
+        f.write_str(&self.to_checksum())
+    }
+}
+
+impl FromStr for Address {
+    type Err = AddressError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        Self::decode(input)
     }
 }
 
@@ -47,3 +133,151 @@ impl From<[u8; 20]> for Address {
         Self(address)
     }
 }
+
+impl TryFrom<&[u8]> for Address {
+    type Error = AddressError;
+
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        <[u8; 20]>::try_from(bytes)
+            .map(Self)
+            .map_err(|_| AddressError::InvalidLength)
+    }
+}
+
+impl Serialize for Address {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Address {
+    /// Accepts a hex string, and the array of 20 bytes used by the JSON of the
+    /// addresses before they were strings.
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct AddressVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for AddressVisitor {
+            type Value = Address;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("a hex encoded address")
+            }
+
+            fn visit_str<E: serde::de::Error>(self, value: &str) -> Result<Address, E> {
+                Address::decode(value).map_err(E::custom)
+            }
+
+            fn visit_seq<A: serde::de::SeqAccess<'de>>(self, seq: A) -> Result<Address, A::Error> {
+                <[u8; 20]>::deserialize(serde::de::value::SeqAccessDeserializer::new(seq))
+                    .map(Address)
+            }
+        }
+
+        deserializer.deserialize_any(AddressVisitor)
+    }
+}
+
+pub type RawU256 = [u8; 32];
+
+/// Metadata of an ERC-20 forwarded to its NEP-141 representative when it is deployed.
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Debug, Clone, PartialEq, Eq)]
+pub struct TokenMetadata {
+    pub name: String,
+    pub symbol: String,
+    pub decimals: u8,
+}
+
+/// Result of an EVM transaction returned by the engine `call` method. Mirrors
+/// the borsh layout of `SubmitResult` in `aurora-engine`.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SubmitResult {
+    pub version: u8,
+    pub status: TransactionStatus,
+    pub gas_used: u64,
+    pub logs: Vec<ResultLog>,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub enum TransactionStatus {
+    Succeed(Vec<u8>),
+    Revert(Vec<u8>),
+    OutOfGas,
+    OutOfFund,
+    OutOfOffset,
+    CallTooDeep,
+}
+
+impl TransactionStatus {
+    pub fn is_ok(&self) -> bool {
+        matches!(self, TransactionStatus::Succeed(_))
+    }
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ResultLog {
+    pub address: Address,
+    pub topics: Vec<RawU256>,
+    pub data: Vec<u8>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Address, AddressError};
+
+    // Test vectors of EIP-55.
+    const CHECKSUMMED: [&str; 4] = [
+        "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed",
+        "0xfB6916095ca1df60bB79Ce92cE3Ea74c37c5d359",
+        "0xdbF03B407c01E7cD3CBea99509d93f8DDDC8C6FB",
+        "0xD1220A0cf47c7B9Be7A2E6BA89F429762e7b9aDb",
+    ];
+
+    #[test]
+    fn test_checksum() {
+        for checksummed in CHECKSUMMED {
+            let address: Address = checksummed.parse().unwrap();
+            assert_eq!(address.to_string(), checksummed);
+            assert_eq!(
+                checksummed.to_lowercase().parse::<Address>().unwrap(),
+                address
+            );
+        }
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        assert_eq!(
+            "0x5aaeb6053f3e94c9b9a09f33669435e7ef1beae".parse::<Address>(),
+            Err(AddressError::InvalidLength)
+        );
+        assert_eq!(
+            "0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaeg".parse::<Address>(),
+            Err(AddressError::InvalidHex)
+        );
+        assert_eq!(
+            "0x5AAeb6053F3E94C9b9A09f33669435E7Ef1BeAed".parse::<Address>(),
+            Err(AddressError::InvalidChecksum)
+        );
+        assert_eq!(
+            Address::try_from(&[0u8; 19][..]),
+            Err(AddressError::InvalidLength)
+        );
+    }
+
+    #[test]
+    fn test_serde() {
+        let address: Address = CHECKSUMMED[0].parse().unwrap();
+        let json = near_sdk::serde_json::to_string(&address).unwrap();
+        assert_eq!(json, format!("\"{}\"", CHECKSUMMED[0]));
+        assert_eq!(
+            near_sdk::serde_json::from_str::<Address>(&json).unwrap(),
+            address
+        );
+        // The array of bytes of the former encoding is still accepted.
+        let legacy = near_sdk::serde_json::to_string(&address.0).unwrap();
+        assert_eq!(
+            near_sdk::serde_json::from_str::<Address>(&legacy).unwrap(),
+            address
+        );
+    }
+}
@@ -6,5 +6,8 @@ pub trait ExtNearTokenFactory {
         &mut self,
         receiver_id: near_token_common::Address,
         amount: near_sdk::json_types::U128,
+        sender_id: Option<near_sdk::AccountId>,
+        memo: Option<String>,
+        msg: Option<String>,
     );
 }
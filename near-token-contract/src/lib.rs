@@ -1,21 +1,38 @@
 use ext::ext_near_token_factory;
+use near_contract_standards::fungible_token::core::FungibleTokenCore;
 use near_contract_standards::fungible_token::events::{FtBurn, FtMint};
 use near_contract_standards::fungible_token::metadata::{
     FungibleTokenMetadata, FungibleTokenMetadataProvider,
 };
+use near_contract_standards::fungible_token::resolver::FungibleTokenResolver;
 use near_contract_standards::fungible_token::FungibleToken;
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
 use near_sdk::json_types::U128;
 use near_sdk::{
     assert_self, env, near_bindgen, BorshStorageKey, PanicOnDefault, Promise, PromiseOrValue,
 };
-use near_sdk::{require, AccountId, Gas};
+use near_sdk::{require, AccountId, Balance, Gas};
 
 mod ext;
 
 // TODO: Determine properly what are good gas constants for both of these steps.
+/// Must match the gas the factory attaches to the Aurora call that unlocks the tokens.
 const GAS_FOR_UNLOCKING_TOKENS: Gas = Gas(10_000_000_000_000);
-const GAS_FOR_ON_WITHDRAW: Gas = Gas(10_000_000_000_000 + GAS_FOR_UNLOCKING_TOKENS.0);
+/// Gas reserved for `on_withdraw_complete`. The factory caps its callback gas to it, see
+/// `TOKEN_WITHDRAW_CALLBACK_GAS` of the factory.
+const GAS_FOR_WITHDRAW_CALLBACK: Gas = Gas(7_000_000_000_000);
+const GAS_FOR_ON_WITHDRAW: Gas =
+    Gas(10_000_000_000_000 + GAS_FOR_UNLOCKING_TOKENS.0 + GAS_FOR_WITHDRAW_CALLBACK.0);
+/// Must match the gas the factory attaches to the Aurora call that unlocks the tokens
+/// and calls the receiver.
+const GAS_FOR_UNLOCKING_TOKENS_AND_CALLING: Gas = Gas(30_000_000_000_000);
+const GAS_FOR_ON_WITHDRAW_CALL: Gas =
+    Gas(10_000_000_000_000 + GAS_FOR_UNLOCKING_TOKENS_AND_CALLING.0 + GAS_FOR_WITHDRAW_CALLBACK.0);
+const GAS_FOR_MIGRATE: Gas = Gas(5_000_000_000_000);
+/// Balance `sweep_near` keeps on top of the balance locked for storage.
+const SWEEP_RESERVE: Balance = 100_000_000_000_000_000_000_000;
+/// Storage key of the contract state, see `near_sdk::env::state_read`.
+const STATE_KEY: &[u8] = b"STATE";
 
 #[derive(BorshDeserialize, BorshSerialize, BorshStorageKey)]
 enum StorageKeys {
@@ -31,6 +48,28 @@ pub struct Contract {
     token: FungibleToken,
     /// Metadata for the token.
     metadata: FungibleTokenMetadata,
+    /// Whether `ft_transfer` and `ft_transfer_call` are enabled.
+    transfers_enabled: bool,
+}
+
+/// Layout of the contract state before transfers could be disabled. The field order
+/// MUST NOT change, as it is the order of the borsh encoded state.
+#[derive(BorshDeserialize)]
+struct ContractV1 {
+    factory: AccountId,
+    token: FungibleToken,
+    metadata: FungibleTokenMetadata,
+}
+
+impl From<ContractV1> for Contract {
+    fn from(old: ContractV1) -> Self {
+        Self {
+            factory: old.factory,
+            token: old.token,
+            metadata: old.metadata,
+            transfers_enabled: true,
+        }
+    }
 }
 
 // TODO: Pausable methods.
@@ -44,18 +83,23 @@ impl Contract {
     /// to make sure the factory and other input arguments are properly set.
     ///
     /// Method is payable since the factory needs to pay the storage to be
-    /// registered automatically.
+    /// registered automatically. `metadata` is the metadata of the ERC-20 on
+    /// Aurora. If it is not set, the metadata is empty.
     #[init]
     #[payable]
-    pub fn new() -> Self {
-        // TODO: Load metadata automatically during creation.
-
+    pub fn new(metadata: Option<near_token_common::TokenMetadata>) -> Self {
         let factory = env::predecessor_account_id();
 
         let mut contract = Self {
             factory: factory.clone(),
             token: FungibleToken::new(StorageKeys::FungibleToken),
-            metadata: default_metadata(),
+            metadata: metadata.map_or_else(default_metadata, |metadata| FungibleTokenMetadata {
+                name: metadata.name,
+                symbol: metadata.symbol,
+                decimals: metadata.decimals,
+                ..default_metadata()
+            }),
+            transfers_enabled: true,
         };
 
         // Automatically register the factory as a minter.
@@ -172,9 +216,14 @@ impl Contract {
 
     /// Burn tokens owned by the predecessor account id, and unlock the equivalent
     /// amount on Aurora for `receiver_id`. It decreases the total supply. Anyone
-    /// can call this method, including other contracts.
+    /// can call this method, including other contracts. If the unlock fails, the
+    /// factory mints the tokens back to the predecessor account id. The promise
+    /// resolves to the `WithdrawOutcome` returned by the factory. The attached NEAR is
+    /// forwarded to the factory, which attaches it to the Aurora call as the XCC fee
+    /// instead of its default fee.
     ///
     /// Emit `FtBurn` event.
+    #[payable]
     pub fn withdraw(
         &mut self,
         receiver_id: near_token_common::Address,
@@ -195,31 +244,142 @@ impl Contract {
 
         ext_near_token_factory::ext(self.factory.clone())
             .with_static_gas(GAS_FOR_ON_WITHDRAW)
-            .on_withdraw(receiver_id, amount)
+            .with_attached_deposit(env::attached_deposit())
+            .on_withdraw(
+                receiver_id,
+                amount,
+                Some(env::predecessor_account_id()),
+                memo,
+                None,
+            )
+    }
+
+    /// Like `withdraw`, but after the tokens are unlocked on Aurora, `receiver_id` is
+    /// called with `msg`, the hex encoded calldata. If the call fails, the factory mints
+    /// the tokens back to the predecessor account id. The attached NEAR is forwarded like
+    /// in `withdraw`.
+    ///
+    /// Emit `FtBurn` event.
+    #[payable]
+    pub fn withdraw_call(
+        &mut self,
+        receiver_id: near_token_common::Address,
+        amount: U128,
+        msg: String,
+        memo: Option<String>,
+    ) -> Promise {
+        self.token
+            .internal_withdraw(&env::predecessor_account_id(), amount.into());
+
+        FtBurn {
+            owner_id: &env::predecessor_account_id(),
+            amount: &amount,
+            memo: memo.as_deref(),
+        }
+        .emit();
+
+        ext_near_token_factory::ext(self.factory.clone())
+            .with_static_gas(GAS_FOR_ON_WITHDRAW_CALL)
+            .with_attached_deposit(env::attached_deposit())
+            .on_withdraw(
+                receiver_id,
+                amount,
+                Some(env::predecessor_account_id()),
+                memo,
+                Some(msg),
+            )
     }
 
     /// Upgrade the contract to a newer version. This method MUST be
-    /// executed only if the predecessor account id is the factory.
+    /// executed only if the predecessor account id is the factory. The
+    /// new binary MUST provide `migrate`, which is called in the same
+    /// batched transaction.
     pub fn upgrade_contract(&mut self, binary: near_sdk::json_types::Base64VecU8) -> Promise {
         // Only the factory can upgrade the contract
         self.assert_factory();
 
-        // Deploy the new contract
-        Promise::new(env::current_account_id()).deploy_contract(binary.into())
+        // Deploy the new contract and migrate its state
+        Promise::new(env::current_account_id())
+            .deploy_contract(binary.into())
+            .function_call("migrate".to_string(), vec![], 0, GAS_FOR_MIGRATE)
     }
 
-    /// Update the metadata for the token. ONLY accounts with `ControlMetadata`
-    /// role can call this method. In particular it is expected that the factory
-    /// has this role. This allows a trustless workflow where metadata can be
-    /// updated by any user starting the call from the locker in Aurora.
-    ///
-    ///
-    pub fn update_metadata(&mut self, metadata: FungibleTokenMetadata) {
-        // TODO: Make sure accounts with the proper role can call this function.
-        // TODO: Allow updating parts of the metadata, and not require update the whole struct at once.
-        // Update the metadata with the new information.
-        self.metadata = metadata;
-        todo!();
+    /// Hand over the control of the token to `controller`, e.g. a new factory. From
+    /// then on only `controller` can mint and upgrade the token, and withdrawals are
+    /// forwarded to it. It is registered so tokens can be minted for it. This method
+    /// MUST be executed only if the predecessor account id is the factory.
+    pub fn set_controller(&mut self, controller: AccountId) {
+        // Only the factory can hand over the control of the token
+        self.assert_factory();
+
+        if !self.token.accounts.contains_key(&controller) {
+            self.token.internal_register_account(&controller);
+        }
+        self.factory = controller;
+    }
+
+    /// Migrate the state after the contract is upgraded. States of
+    /// tokens deployed before transfers could be disabled are migrated
+    /// with transfers enabled, and the current layout is kept as it is.
+    /// This method can only be called by the contract itself.
+    #[private]
+    #[init(ignore_state)]
+    pub fn migrate() -> Self {
+        let state = env::storage_read(STATE_KEY)
+            .unwrap_or_else(|| env::panic_str("Contract state is not initialized"));
+        Self::try_from_slice(&state)
+            .or_else(|_| ContractV1::try_from_slice(&state).map(Self::from))
+            .unwrap_or_else(|_| env::panic_str("Cannot deserialize the contract state"))
+    }
+
+    /// Enable or disable `ft_transfer` and `ft_transfer_call`, e.g. to
+    /// halt transfers during an incident. Deposits and withdrawals are
+    /// not affected. This method MUST be executed only if the
+    /// predecessor account id is the factory.
+    pub fn set_transfers_enabled(&mut self, enabled: bool) {
+        // Only the factory can enable or disable transfers
+        self.assert_factory();
+
+        self.transfers_enabled = enabled;
+    }
+
+    /// Whether `ft_transfer` and `ft_transfer_call` are enabled.
+    pub fn transfers_enabled(&self) -> bool {
+        self.transfers_enabled
+    }
+
+    /// Account that controls the token: it mints and upgrades the
+    /// token, and withdrawals are forwarded to it.
+    pub fn controller(&self) -> AccountId {
+        self.factory.clone()
+    }
+
+    /// Update the name, symbol and decimals of the token, e.g. once the metadata of
+    /// the ERC-20 is known. The rest of the metadata is kept. This method MUST be
+    /// executed only if the predecessor account id is the factory.
+    pub fn update_metadata(&mut self, metadata: near_token_common::TokenMetadata) {
+        // Only the factory can update the metadata
+        self.assert_factory();
+
+        self.metadata.name = metadata.name;
+        self.metadata.symbol = metadata.symbol;
+        self.metadata.decimals = metadata.decimals;
+    }
+
+    /// Transfer the balance of the token above the balance locked for
+    /// storage and `SWEEP_RESERVE` to `receiver_id`, and return the amount
+    /// transferred. This method MUST be executed only if the predecessor
+    /// account id is the factory.
+    pub fn sweep_near(&mut self, receiver_id: AccountId) -> U128 {
+        // Only the factory can sweep the balance
+        self.assert_factory();
+
+        let locked = Balance::from(env::storage_usage()) * env::storage_byte_cost();
+        let amount = env::account_balance().saturating_sub(locked + SWEEP_RESERVE);
+        if amount > 0 {
+            Promise::new(receiver_id).transfer(amount);
+        }
+        amount.into()
     }
 }
 
@@ -238,6 +398,10 @@ impl Contract {
             "Only factory can call this method"
         );
     }
+
+    fn assert_transfers_enabled(&self) {
+        require!(self.transfers_enabled, "Transfers are disabled");
+    }
 }
 
 fn unwrap_promise<T>(promise_or_value: PromiseOrValue<T>) -> near_sdk::Promise {
@@ -247,7 +411,53 @@ fn unwrap_promise<T>(promise_or_value: PromiseOrValue<T>) -> near_sdk::Promise {
     }
 }
 
-near_contract_standards::impl_fungible_token_core!(Contract, token);
+#[near_bindgen]
+impl FungibleTokenCore for Contract {
+    /// NEP-141 transfer. It fails while transfers are disabled.
+    #[payable]
+    fn ft_transfer(&mut self, receiver_id: AccountId, amount: U128, memo: Option<String>) {
+        self.assert_transfers_enabled();
+        self.token.ft_transfer(receiver_id, amount, memo)
+    }
+
+    /// NEP-141 transfer and call. It fails while transfers are disabled.
+    #[payable]
+    fn ft_transfer_call(
+        &mut self,
+        receiver_id: AccountId,
+        amount: U128,
+        memo: Option<String>,
+        msg: String,
+    ) -> PromiseOrValue<U128> {
+        self.assert_transfers_enabled();
+        self.token.ft_transfer_call(receiver_id, amount, memo, msg)
+    }
+
+    fn ft_total_supply(&self) -> U128 {
+        self.token.ft_total_supply()
+    }
+
+    fn ft_balance_of(&self, account_id: AccountId) -> U128 {
+        self.token.ft_balance_of(account_id)
+    }
+}
+
+#[near_bindgen]
+impl FungibleTokenResolver for Contract {
+    #[private]
+    fn ft_resolve_transfer(
+        &mut self,
+        sender_id: AccountId,
+        receiver_id: AccountId,
+        amount: U128,
+    ) -> U128 {
+        let (used_amount, _) =
+            self.token
+                .internal_ft_resolve_transfer(&sender_id, receiver_id, amount);
+        used_amount.into()
+    }
+}
+
 near_contract_standards::impl_fungible_token_storage!(Contract, token);
 
 fn default_metadata() -> FungibleTokenMetadata {
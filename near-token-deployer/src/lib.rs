@@ -0,0 +1,55 @@
+//! Deployer sub-account of the token factory. It creates the token accounts under its
+//! own account id and deploys the token binary on them, so the factory only pays for
+//! a function call when a token is deployed through it. The factory initializes the
+//! tokens itself afterwards, so it stays their controller.
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::{env, near_bindgen, require, AccountId, PanicOnDefault, Promise};
+
+const ERR_ONLY_FACTORY: &str = "ERR_ONLY_FACTORY: Only the factory can call this method.";
+const ERR_INVALID_LABEL: &str = "ERR_INVALID_LABEL: Label doesn't form a valid account id.";
+
+#[near_bindgen]
+#[derive(BorshDeserialize, BorshSerialize, PanicOnDefault)]
+pub struct Contract {
+    /// Account id of the factory determined at deployment time.
+    factory: AccountId,
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Initializes the contract. The account creating the deployer becomes its factory.
+    #[init]
+    pub fn new() -> Self {
+        Self {
+            factory: env::predecessor_account_id(),
+        }
+    }
+
+    /// Create the account `{label}.{deployer}`, transfer the attached deposit to it and
+    /// deploy `binary` on it. The returned promise fails if the account already exists.
+    /// This method MUST be executed only if the predecessor account id is the factory.
+    #[payable]
+    pub fn deploy_token(
+        &mut self,
+        #[serializer(borsh)] label: String,
+        #[serializer(borsh)] binary: Vec<u8>,
+    ) -> Promise {
+        require!(
+            env::predecessor_account_id() == self.factory,
+            ERR_ONLY_FACTORY
+        );
+        let token_account_id: AccountId = format!("{}.{}", label, env::current_account_id())
+            .parse()
+            .unwrap_or_else(|_| env::panic_str(ERR_INVALID_LABEL));
+
+        Promise::new(token_account_id)
+            .create_account()
+            .transfer(env::attached_deposit())
+            .deploy_contract(binary)
+    }
+
+    /// Account id of the factory.
+    pub fn factory(&self) -> AccountId {
+        self.factory.clone()
+    }
+}
@@ -0,0 +1,80 @@
+//! Selectors of the Aurora functions called by the factory. The ABI encoding itself
+//! lives in `near_token_common::abi`.
+use near_sdk::{env, require};
+
+pub use crate::aurora_sdk::abi::{decode, encode_call, selector, AbiType, AbiValue, WORD_SIZE};
+
+const ERR_SELECTOR_MISMATCH: &str =
+    "ERR_SELECTOR_MISMATCH: Selector doesn't match its function signature.";
+
+/// Selectors of the Aurora functions called by the factory, next to their signatures.
+/// They are computed at compile time, and `assert_selectors` checks them against the
+/// keccak256 of the runtime.
+pub const SELECTORS: &[(&str, [u8; 4])] = &[
+    (
+        "withdraw(address,address,uint256)",
+        crate::WITHDRAW_SELECTOR,
+    ),
+    (
+        "withdrawAndCall(address,address,uint256,bytes)",
+        crate::WITHDRAW_AND_CALL_SELECTOR,
+    ),
+    ("withdrawEth(address,uint256)", crate::WITHDRAW_ETH_SELECTOR),
+    (
+        "mintWrapped(bytes,address,uint256)",
+        crate::nep141_bridge::MINT_WRAPPED_SELECTOR,
+    ),
+    ("lockToken(address)", crate::detach::LOCK_TOKEN_SELECTOR),
+];
+
+/// Panic if a selector of `selectors` doesn't match its signature.
+pub fn assert_selectors(selectors: &[(&str, [u8; 4])]) {
+    for (signature, expected) in selectors {
+        let hash = env::keccak256(signature.as_bytes());
+        require!(hash[..4] == expected[..], ERR_SELECTOR_MISMATCH);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{assert_selectors, encode_call, AbiValue, SELECTORS};
+    use crate::aurora_sdk::Address;
+
+    #[test]
+    fn test_encode_withdraw_and_call() {
+        let address = [7u8; 20];
+        for data in [vec![], vec![1, 2, 3], vec![9; 32], vec![5; 33]] {
+            let encoded = encode_call(
+                crate::WITHDRAW_AND_CALL_SELECTOR,
+                &[
+                    AbiValue::Address(Address(address)),
+                    AbiValue::Address(Address(address)),
+                    AbiValue::Uint(100.into()),
+                    AbiValue::Bytes(data.clone()),
+                ],
+            );
+
+            assert_eq!(&encoded[..4], &crate::WITHDRAW_AND_CALL_SELECTOR);
+            assert_eq!(
+                &encoded[4..],
+                &ethabi::encode(&[
+                    ethabi::Token::Address(ethabi::Address::from(address)),
+                    ethabi::Token::Address(ethabi::Address::from(address)),
+                    ethabi::Token::Uint(ethabi::Uint::from(100)),
+                    ethabi::Token::Bytes(data),
+                ])
+            );
+        }
+    }
+
+    #[test]
+    fn test_selectors_match_signatures() {
+        assert_selectors(SELECTORS);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_SELECTOR_MISMATCH")]
+    fn test_assert_selectors_rejects_mismatch() {
+        assert_selectors(&[("withdraw(address,uint256)", crate::WITHDRAW_SELECTOR)]);
+    }
+}
@@ -0,0 +1,239 @@
+//! Queryable authorization rules of the privileged methods.
+use crate::roles::{Role, PAUSER_ACTIONS, TOKEN_UPGRADER_ACTIONS};
+use crate::{Contract, ContractExt};
+use near_sdk::serde::Serialize;
+use near_sdk::{near_bindgen, AccountId};
+
+/// Maximum number of accounts listed per role by `roles`.
+const MAX_LISTED_ACCOUNTS: usize = 100;
+
+/// Accounts holding the administrative roles of the factory.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RolesView {
+    /// Account allowed to call the privileged methods.
+    pub owner: AccountId,
+    /// Locker account, which can always deposit and create tokens.
+    pub locker: AccountId,
+    /// Secondary minters, up to `MAX_LISTED_ACCOUNTS` of them.
+    pub minters: Vec<AccountId>,
+    /// Total number of secondary minters.
+    pub minters_count: u64,
+    /// Accounts granted the `TokenUpgrader` role, up to `MAX_LISTED_ACCOUNTS` of them.
+    pub token_upgraders: Vec<AccountId>,
+    /// Accounts granted the `Pauser` role, up to `MAX_LISTED_ACCOUNTS` of them.
+    pub pausers: Vec<AccountId>,
+    /// Account notified of every new token, if any.
+    pub creation_notifier: Option<AccountId>,
+}
+
+/// Methods that ONLY the `Owner` role can call. Methods that delegated roles can call
+/// as well are listed in `roles`.
+pub(crate) const OWNER_ACTIONS: &[&str] = &[
+    "acknowledge_incident",
+    "add_allowed_receiver",
+    "add_allowed_token",
+    "add_minter",
+    "add_silo",
+    "audit_sweep",
+    "cancel_deferred",
+    "cancel_pending_change",
+    "clear_allowed_receivers",
+    "clear_failed_withdraws",
+    "clear_pending_deploy",
+    "confirm_default_fees",
+    "confirm_locker",
+    "confirm_timelock_delay",
+    "confirm_token_fees",
+    "create_deployer",
+    "create_token_with_registrations",
+    "deregister_token",
+    "disable_holder_tracking",
+    "enable_holder_tracking",
+    "freeze_upgrades",
+    "grant_role",
+    "process_pending_deposits",
+    "propose_default_fees",
+    "propose_deployer_binary",
+    "propose_locker",
+    "propose_timelock_delay",
+    "propose_token_detach",
+    "propose_token_fees",
+    "prune_token",
+    "rebuild_reverse_index",
+    "register_receivers",
+    "reinitialize_token",
+    "remove_allowed_receiver",
+    "remove_allowed_token",
+    "remove_minter",
+    "remove_silo",
+    "rescue_ft",
+    "revoke_role",
+    "set_auto_pause_threshold",
+    "set_batch_limits",
+    "set_connector_metadata",
+    "set_creation_notifier",
+    "set_creation_transfer",
+    "set_deploy_balance_margin",
+    "set_deployer_enabled",
+    "set_deposit_refund_address",
+    "set_dry_run",
+    "set_event_context",
+    "set_event_format",
+    "set_excess_withdraw_policy",
+    "set_failed_unlock_policy",
+    "set_fee_collector",
+    "set_gas_config",
+    "set_lazy_upgrade_opt_out",
+    "set_lazy_upgrades",
+    "set_locker_grace_period",
+    "set_log_level",
+    "set_max_decimals",
+    "set_min_deploy_attached_deposit",
+    "set_owner",
+    "set_paused_deposit_policy",
+    "set_pending_deploy_policy",
+    "set_rate_limit",
+    "set_receiver_storage_deposit",
+    "set_remainder_policy",
+    "set_reserve_balance",
+    "set_spec_version",
+    "set_storage_payer",
+    "set_supply_cap",
+    "set_token_allowlist_enabled",
+    "set_token_metadata",
+    "set_token_scaling",
+    "set_token_transfers_enabled",
+    "set_treasury",
+    "set_unknown_token_policy",
+    "set_xcc_fee",
+    "sweep_to_treasury",
+    "sweep_token",
+    "transfer_token_control",
+    "unfreeze_upgrades",
+    "unpause",
+    "unpause_all_tokens",
+    "unpause_deploys",
+    "unpause_token",
+    "upgrade",
+    "withdraw_near",
+    "withdraw_storage_pool",
+];
+
+#[near_bindgen]
+impl Contract {
+    /// Whether `account` is allowed to call the method named `action`, e.g. to gray
+    /// out actions in a user interface. It only checks the caller, calls can still
+    /// fail for other reasons. Unknown methods return `false`.
+    pub fn can_call(&self, action: String, account: AccountId) -> bool {
+        match action.as_str() {
+            "on_deposit" | "on_deposit_batch" | "on_deposit_u256" | "on_deposit_call"
+            | "on_deposit_entries" => self.is_depositor(&account),
+            "create_token" => self.is_locker(&account) || self.silo_of_locker(&account).is_some(),
+            "on_withdraw" => self.try_address_from_token_account_id(&account).is_some(),
+            action if OWNER_ACTIONS.contains(&action) => self.is_owner(&account),
+            action if TOKEN_UPGRADER_ACTIONS.contains(&action) => {
+                self.has_role(Role::TokenUpgrader, account)
+            }
+            action if PAUSER_ACTIONS.contains(&action) => self.has_role(Role::Pauser, account),
+            _ => false,
+        }
+    }
+
+    /// Snapshot of the accounts holding each administrative role, for audits.
+    pub fn roles(&self) -> RolesView {
+        RolesView {
+            owner: self.owner.clone(),
+            locker: self.locker_account_id(),
+            minters: self.minters.iter().take(MAX_LISTED_ACCOUNTS).collect(),
+            minters_count: self.minters.len(),
+            token_upgraders: self
+                .token_upgraders
+                .iter()
+                .take(MAX_LISTED_ACCOUNTS)
+                .collect(),
+            pausers: self.pausers.iter().take(MAX_LISTED_ACCOUNTS).collect(),
+            creation_notifier: self.creation_notifier.clone(),
+        }
+    }
+}
+
+impl Contract {
+    pub(crate) fn is_owner(&self, account_id: &AccountId) -> bool {
+        account_id == &self.owner
+    }
+
+    /// The locker can always deposit, regardless of the minter set, so the bridge
+    /// can't be broken by a misconfigured minter set. So can the lockers of the silos.
+    pub(crate) fn is_depositor(&self, account_id: &AccountId) -> bool {
+        self.is_locker(account_id)
+            || self.minters.contains(account_id)
+            || self.silo_of_locker(account_id).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RolesView;
+    use crate::aurora_sdk::Address;
+    use crate::roles::Role;
+    use crate::test_utils;
+    use near_sdk::AccountId;
+
+    #[test]
+    fn test_roles() {
+        let mut contract = test_utils::new_contract();
+        let minter: AccountId = "minter.near".parse().unwrap();
+        let notifier: AccountId = "notifier.near".parse().unwrap();
+        let dao: AccountId = "dao.near".parse().unwrap();
+        contract.add_minter(minter.clone());
+        contract.set_creation_notifier(Some(notifier.clone()));
+        contract.grant_role(Role::TokenUpgrader, dao.clone());
+
+        assert_eq!(
+            contract.roles(),
+            RolesView {
+                owner: test_utils::factory(),
+                locker: test_utils::locker_account_id(),
+                minters: vec![minter],
+                minters_count: 1,
+                token_upgraders: vec![dao],
+                pausers: Vec::new(),
+                creation_notifier: Some(notifier),
+            }
+        );
+    }
+
+    #[test]
+    fn test_can_call() {
+        let contract = test_utils::new_contract();
+        let owner = test_utils::factory();
+        let locker = test_utils::locker_account_id();
+        let token = test_utils::token_account_id(&Address([1; 20]));
+        let mallory: AccountId = "mallory.near".parse().unwrap();
+
+        for action in ["set_token_binary", "pause_all_tokens"] {
+            assert!(contract.can_call(action.to_string(), owner.clone()));
+            assert!(!contract.can_call(action.to_string(), locker.clone()));
+            assert!(!contract.can_call(action.to_string(), mallory.clone()));
+        }
+        for action in ["on_deposit", "create_token"] {
+            assert!(contract.can_call(action.to_string(), locker.clone()));
+            assert!(!contract.can_call(action.to_string(), mallory.clone()));
+        }
+        assert!(contract.can_call("on_withdraw".to_string(), token));
+        assert!(!contract.can_call("on_withdraw".to_string(), mallory.clone()));
+        assert!(!contract.can_call("unknown".to_string(), owner));
+    }
+
+    #[test]
+    fn test_can_call_with_role() {
+        let mut contract = test_utils::new_contract();
+        let pauser: AccountId = "pauser.near".parse().unwrap();
+        contract.grant_role(Role::Pauser, pauser.clone());
+
+        assert!(contract.can_call("pause_token".to_string(), pauser.clone()));
+        assert!(!contract.can_call("unpause_token".to_string(), pauser.clone()));
+        assert!(!contract.can_call("upgrade_tokens".to_string(), pauser));
+    }
+}
@@ -0,0 +1,787 @@
+//! Bookkeeping of the amount of tokens bridged through the factory.
+use crate::{aurora_sdk, events, ext, Contract, ContractExt, StorageKey};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::collections::UnorderedSet;
+use near_sdk::json_types::{U128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{env, near_bindgen, require, AccountId, Balance, Gas, Promise, PromiseResult};
+
+/// Number of entries kept in the top tokens by supply list.
+pub const TOP_TOKENS_CAPACITY: usize = 10;
+const FT_BALANCE_OF_GAS: Gas = Gas(5_000_000_000_000);
+const SUPPLY_VS_BALANCE_CALLBACK_GAS: Gas = Gas(5_000_000_000_000);
+
+const ERR_WITHDRAW_EXCEEDS_SUPPLY: &str =
+    "ERR_WITHDRAW_EXCEEDS_SUPPLY: Withdraw amount exceeds the mirrored supply.";
+
+/// What happens when a token withdraws more than its mirrored supply, which indicates a
+/// bug or an attack on the token.
+#[derive(
+    BorshSerialize, BorshDeserialize, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq,
+)]
+#[serde(crate = "near_sdk::serde")]
+pub enum ExcessWithdrawPolicy {
+    /// The whole amount is unlocked. Tokens deployed before the mirrored supply was
+    /// tracked can withdraw more than their mirrored supply.
+    Allow,
+    /// The call panics.
+    Reject,
+    /// Only the mirrored supply is unlocked, and `WithdrawClamped` is emitted with the
+    /// shortfall.
+    ClampToSupply,
+}
+
+impl Default for ExcessWithdrawPolicy {
+    fn default() -> Self {
+        Self::Allow
+    }
+}
+
+/// Mirrored supply of a token next to the balance of an account on the token.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SupplyVsBalance {
+    /// Mirrored supply of the token according to the factory.
+    pub mirrored_supply: U128,
+    /// Balance of the account returned by `ft_balance_of`, or `None` if the query
+    /// failed.
+    pub balance: Option<U128>,
+}
+
+/// Deposit recorded before its mints are confirmed, with what is needed to undo it if
+/// they fail, see `revert_deposit`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RecordedDeposit {
+    /// Amount recorded as minted, fees included.
+    pub amount: U128,
+    /// Number of calls of `record_deposit`, one per receiver and fee.
+    pub mints: u64,
+    /// Amount consumed from the deposit limit of the token.
+    pub volume: U128,
+    /// Receivers that became holders of the token with the deposit.
+    pub new_holders: Vec<AccountId>,
+    /// Deferred remainders above the supply cap created by the deposit.
+    pub deferred_ids: Vec<u64>,
+    /// Depositor and transfer id of each deposit that had one.
+    pub transfers: Vec<(AccountId, U64)>,
+    /// Dust of a scaled token left on Aurora by the deposit, see `on_deposit_u256`.
+    pub dust: U128,
+}
+
+/// Cumulative amounts bridged for a token.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GrossFlows {
+    /// Amount minted on NEAR by deposits.
+    pub deposited: Balance,
+    /// Amount burnt on NEAR by withdrawals. Withdrawals reverted because the unlock
+    /// on Aurora failed are not included.
+    pub withdrawn: Balance,
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Amount of tokens minted on NEAR minus the amount withdrawn back to Aurora.
+    pub fn mirrored_supply(&self, token_address: aurora_sdk::Address) -> U128 {
+        let token_account_id = self.account_id_from_token_address(token_address);
+        self.token_supply.get(&token_account_id).unwrap_or(0).into()
+    }
+
+    /// Cumulative amounts deposited and withdrawn for the token, in this order. Both
+    /// amounts only grow, except when a withdraw is reverted.
+    pub fn gross_flows(&self, token_address: aurora_sdk::Address) -> (U128, U128) {
+        let flows = self
+            .gross_flows
+            .get(&self.account_id_from_token_address(token_address))
+            .unwrap_or_default();
+        (flows.deposited.into(), flows.withdrawn.into())
+    }
+
+    /// Whether the mirrored supply of the token equals its gross deposits minus its
+    /// gross withdrawals. Both are updated together, so `false` indicates a bug in the
+    /// bookkeeping of the factory.
+    pub fn supply_invariant_holds(&self, token_address: aurora_sdk::Address) -> bool {
+        let token_account_id = self.account_id_from_token_address(token_address);
+        self.check_supply_invariant(&token_account_id).is_none()
+    }
+
+    /// Check `supply_invariant_holds` for each registered token of the page, and emit
+    /// `InvariantViolation` for each token where it doesn't hold. Returns the number of
+    /// violations. ONLY the `Owner` role can call this method.
+    pub fn audit_sweep(&mut self, from: u64, limit: u64) -> u64 {
+        self.assert_owner();
+        self.record_admin_action("audit_sweep");
+
+        let violations: Vec<events::ConnectorEvent> = self
+            .tokens
+            .keys()
+            .skip(from as usize)
+            .take(limit as usize)
+            .filter_map(|token_account_id| self.check_supply_invariant(&token_account_id))
+            .collect();
+        let count = violations.len() as u64;
+        for violation in violations {
+            self.emit_event(violation);
+        }
+        count
+    }
+
+    /// Set how withdraws exceeding the mirrored supply of their token are handled. ONLY
+    /// the `Owner` role can call this method.
+    pub fn set_excess_withdraw_policy(&mut self, policy: ExcessWithdrawPolicy) {
+        self.assert_owner();
+        self.record_admin_action("set_excess_withdraw_policy");
+
+        self.excess_withdraw_policy = policy;
+    }
+
+    /// Policy applied to withdraws exceeding the mirrored supply of their token.
+    pub fn excess_withdraw_policy(&self) -> ExcessWithdrawPolicy {
+        self.excess_withdraw_policy
+    }
+
+    /// Start tracking the distinct receivers of deposits of the token. Only deposits
+    /// made afterwards are tracked. Every receiver uses storage of the factory, so it
+    /// is opt-in. ONLY the `Owner` role can call this method.
+    pub fn enable_holder_tracking(&mut self, token_address: aurora_sdk::Address) {
+        self.assert_owner();
+        self.record_admin_action("enable_holder_tracking");
+
+        let token_account_id = self.account_id_from_token_address(token_address);
+        if self.token_holders.get(&token_account_id).is_none() {
+            let holders = UnorderedSet::new(StorageKey::TokenHolders {
+                token_account_id: token_account_id.clone(),
+            });
+            self.token_holders.insert(&token_account_id, &holders);
+        }
+    }
+
+    /// Stop tracking the receivers of the token and release their storage. ONLY the
+    /// `Owner` role can call this method.
+    pub fn disable_holder_tracking(&mut self, token_address: aurora_sdk::Address) {
+        self.assert_owner();
+        self.record_admin_action("disable_holder_tracking");
+
+        let token_account_id = self.account_id_from_token_address(token_address);
+        if let Some(mut holders) = self.token_holders.remove(&token_account_id) {
+            holders.clear();
+        }
+    }
+
+    /// Number of distinct accounts that received deposits of the token since holder
+    /// tracking was enabled. It is 0 if tracking is disabled. Accounts are still
+    /// counted after transferring or withdrawing all their tokens.
+    pub fn token_holder_count(&self, token_address: aurora_sdk::Address) -> u64 {
+        self.token_holders
+            .get(&self.account_id_from_token_address(token_address))
+            .map_or(0, |holders| holders.len())
+    }
+
+    /// Registered tokens that `account_id` received deposits of, among the tokens with
+    /// holder tracking enabled. Pagination is applied to the registered tokens before
+    /// filtering, so a page can hold less than `limit` tokens even if the account holds
+    /// more tokens.
+    pub fn tokens_of(&self, account_id: AccountId, from: u64, limit: u64) -> Vec<AccountId> {
+        self.tokens
+            .keys()
+            .skip(from as usize)
+            .take(limit as usize)
+            .filter(|token_account_id| {
+                self.token_holders
+                    .get(token_account_id)
+                    .map_or(false, |holders| holders.contains(&account_id))
+            })
+            .collect()
+    }
+
+    /// Sum of the mirrored supply of each token times its price in `prices`. Tokens
+    /// without a price are not included. The result saturates at `u128::MAX`.
+    pub fn total_liability(&self, prices: Vec<(aurora_sdk::Address, U128)>) -> U128 {
+        prices
+            .into_iter()
+            .fold(0u128, |total, (token_address, price)| {
+                let supply = self.mirrored_supply(token_address).0;
+                total.saturating_add(supply.saturating_mul(price.0))
+            })
+            .into()
+    }
+
+    /// Mirrored supply of the token and the live balance of `account_id` on the token,
+    /// so auditors can compare the accounting of the factory to the state of the token
+    /// in a single call.
+    pub fn supply_vs_balance(
+        &self,
+        token_address: aurora_sdk::Address,
+        account_id: AccountId,
+    ) -> Promise {
+        let token_account_id = self.account_id_from_token_address(token_address);
+        ext::ext_fungible_token::ext(token_account_id.clone())
+            .with_static_gas(FT_BALANCE_OF_GAS)
+            .ft_balance_of(account_id)
+            .then(
+                Contract::ext(env::current_account_id())
+                    .with_static_gas(SUPPLY_VS_BALANCE_CALLBACK_GAS)
+                    .on_supply_vs_balance(token_account_id),
+            )
+    }
+
+    /// Callback of `supply_vs_balance`. The mirrored supply is read when the balance
+    /// is received. This is a callback function that can be only executed from the
+    /// contract itself.
+    #[private]
+    pub fn on_supply_vs_balance(&self, token_account_id: AccountId) -> SupplyVsBalance {
+        let balance = match env::promise_result(0) {
+            PromiseResult::Successful(output) => near_sdk::serde_json::from_slice(&output).ok(),
+            _ => None,
+        };
+        SupplyVsBalance {
+            mirrored_supply: self.token_supply.get(&token_account_id).unwrap_or(0).into(),
+            balance,
+        }
+    }
+
+    /// Tokens with the highest mirrored supply, in decreasing order of supply.
+    ///
+    /// Sorting all tokens on chain is too expensive, so the factory keeps a list of at
+    /// most `TOP_TOKENS_CAPACITY` tokens which is updated whenever the supply of a
+    /// token changes. A token enters the list when its supply grows above the
+    /// smallest entry. When the supply of an entry shrinks, its position is updated,
+    /// but a token outside of the list only takes its place after its own supply
+    /// changes, so the tail of the list is approximate.
+    pub fn top_tokens_by_supply(&self, limit: u64) -> Vec<(AccountId, U128)> {
+        self.top_tokens
+            .iter()
+            .take(limit as usize)
+            .map(|(account_id, supply)| (account_id.clone(), (*supply).into()))
+            .collect()
+    }
+}
+
+impl Contract {
+    /// Record tokens minted on NEAR for `receiver_id`.
+    pub(crate) fn record_deposit(
+        &mut self,
+        token_account_id: &AccountId,
+        receiver_id: &AccountId,
+        amount: Balance,
+    ) {
+        self.increase_supply(token_account_id, amount);
+        self.update_gross_flows(token_account_id, |flows| {
+            flows.deposited = flows.deposited.saturating_add(amount)
+        });
+        self.deposits_count += 1;
+        self.record_activity(token_account_id);
+        if let Some(mut holders) = self.token_holders.get(token_account_id) {
+            if holders.insert(receiver_id) {
+                self.token_holders.insert(token_account_id, &holders);
+            }
+        }
+    }
+
+    /// Undo `mints` calls of `record_deposit`, of `amount` in total, for tokens whose
+    /// mint failed on NEAR.
+    pub(crate) fn record_deposit_failed(
+        &mut self,
+        token_account_id: &AccountId,
+        amount: Balance,
+        mints: u64,
+    ) {
+        let supply = self
+            .token_supply
+            .get(token_account_id)
+            .unwrap_or(0)
+            .saturating_sub(amount);
+        self.set_token_supply(token_account_id, supply);
+        self.update_gross_flows(token_account_id, |flows| {
+            flows.deposited = flows.deposited.saturating_sub(amount)
+        });
+        self.deposits_count = self.deposits_count.saturating_sub(mints);
+    }
+
+    /// Undo everything a deposit recorded, for deposits that were refunded on Aurora,
+    /// e.g. because the token deployment failed. The transfer id is no longer
+    /// processed, so the deposit can be relayed again.
+    pub(crate) fn revert_deposit(
+        &mut self,
+        token_account_id: &AccountId,
+        deposit: RecordedDeposit,
+    ) {
+        self.record_deposit_failed(token_account_id, deposit.amount.0, deposit.mints);
+        self.release_deposit_limit(token_account_id, deposit.volume.0);
+        if let Some(mut holders) = self.token_holders.get(token_account_id) {
+            for account_id in deposit.new_holders.iter() {
+                holders.remove(account_id);
+            }
+            self.token_holders.insert(token_account_id, &holders);
+        }
+        for id in deposit.deferred_ids {
+            self.deferred_deposits.remove(&id);
+        }
+        for (depositor_id, transfer_id) in deposit.transfers {
+            self.unmark_transfer_processed(&depositor_id, transfer_id.0);
+        }
+        self.release_dust(token_account_id, deposit.dust.0);
+    }
+
+    /// Accounts of `account_ids` that are not holders of the token yet, while holder
+    /// tracking is enabled, see `RecordedDeposit`.
+    pub(crate) fn new_holders<'a>(
+        &self,
+        token_account_id: &AccountId,
+        account_ids: impl IntoIterator<Item = &'a AccountId>,
+    ) -> Vec<AccountId> {
+        let holders = match self.token_holders.get(token_account_id) {
+            Some(holders) => holders,
+            None => return Vec::new(),
+        };
+        let mut new_holders: Vec<AccountId> = Vec::new();
+        for account_id in account_ids {
+            if !holders.contains(account_id) && !new_holders.contains(account_id) {
+                new_holders.push(account_id.clone());
+            }
+        }
+        new_holders
+    }
+
+    /// `InvariantViolation` event if the mirrored supply of the token doesn't equal its
+    /// gross deposits minus its gross withdrawals.
+    fn check_supply_invariant(
+        &self,
+        token_account_id: &AccountId,
+    ) -> Option<events::ConnectorEvent> {
+        let supply = self.token_supply.get(token_account_id).unwrap_or(0);
+        let flows = self.gross_flows.get(token_account_id).unwrap_or_default();
+        if flows.deposited.checked_sub(flows.withdrawn) == Some(supply) {
+            return None;
+        }
+        Some(events::ConnectorEvent::InvariantViolation {
+            token_account_id: token_account_id.clone(),
+            mirrored_supply: supply.into(),
+            deposited: flows.deposited.into(),
+            withdrawn: flows.withdrawn.into(),
+        })
+    }
+
+    /// Apply the excess withdraw policy to a withdraw of `amount` from the token.
+    /// Returns the amount to unlock on Aurora.
+    pub(crate) fn check_withdraw_amount(
+        &self,
+        token_account_id: &AccountId,
+        token_id: &aurora_sdk::Address,
+        amount: Balance,
+    ) -> Balance {
+        let supply = self.token_supply.get(token_account_id).unwrap_or(0);
+        if amount <= supply {
+            return amount;
+        }
+
+        match self.excess_withdraw_policy {
+            ExcessWithdrawPolicy::Allow => amount,
+            ExcessWithdrawPolicy::Reject => env::panic_str(ERR_WITHDRAW_EXCEEDS_SUPPLY),
+            ExcessWithdrawPolicy::ClampToSupply => {
+                self.emit_event(events::ConnectorEvent::WithdrawClamped {
+                    token: token_id.to_string(),
+                    amount: amount.into(),
+                    unlocked: supply.into(),
+                });
+                supply
+            }
+        }
+    }
+
+    /// Record tokens burnt on NEAR that are unlocked on Aurora.
+    pub(crate) fn record_withdraw(&mut self, token_account_id: &AccountId, amount: Balance) {
+        let supply = self
+            .token_supply
+            .get(token_account_id)
+            .unwrap_or(0)
+            .saturating_sub(amount);
+        self.set_token_supply(token_account_id, supply);
+        self.update_gross_flows(token_account_id, |flows| {
+            flows.withdrawn = flows.withdrawn.saturating_add(amount)
+        });
+        self.withdrawals_count += 1;
+        self.record_activity(token_account_id);
+    }
+
+    /// Undo `record_withdraw` for tokens minted back after the unlock on Aurora failed.
+    pub(crate) fn record_withdraw_reverted(
+        &mut self,
+        token_account_id: &AccountId,
+        amount: Balance,
+    ) {
+        self.increase_supply(token_account_id, amount);
+        self.update_gross_flows(token_account_id, |flows| {
+            flows.withdrawn = flows.withdrawn.saturating_sub(amount)
+        });
+        self.withdrawals_count = self.withdrawals_count.saturating_sub(1);
+    }
+
+    fn record_activity(&mut self, token_account_id: &AccountId) {
+        self.token_last_activity
+            .insert(token_account_id, &env::block_height());
+    }
+
+    fn increase_supply(&mut self, token_account_id: &AccountId, amount: Balance) {
+        let supply = self
+            .token_supply
+            .get(token_account_id)
+            .unwrap_or(0)
+            .saturating_add(amount);
+        self.set_token_supply(token_account_id, supply);
+    }
+
+    fn update_gross_flows<F: FnOnce(&mut GrossFlows)>(
+        &mut self,
+        token_account_id: &AccountId,
+        f: F,
+    ) {
+        let mut flows = self.gross_flows.get(token_account_id).unwrap_or_default();
+        f(&mut flows);
+        self.gross_flows.insert(token_account_id, &flows);
+    }
+
+    fn set_token_supply(&mut self, token_account_id: &AccountId, supply: Balance) {
+        self.token_supply.insert(token_account_id, &supply);
+
+        self.top_tokens
+            .retain(|(account_id, _)| account_id != token_account_id);
+        if supply > 0 {
+            self.top_tokens.push((token_account_id.clone(), supply));
+        }
+        self.top_tokens.sort_by(|a, b| b.1.cmp(&a.1));
+        self.top_tokens.truncate(TOP_TOKENS_CAPACITY);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ExcessWithdrawPolicy, SupplyVsBalance};
+    use crate::aurora_sdk::Address;
+    use crate::test_utils;
+    use near_sdk::json_types::U128;
+    use near_sdk::PromiseResult;
+
+    #[test]
+    fn test_top_tokens_by_supply() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let tokens = [Address([1; 20]), Address([2; 20]), Address([3; 20])];
+
+        test_utils::set_context(test_utils::locker_account_id());
+        for (token, amount) in tokens.iter().zip([100, 300, 200]) {
+            let _ = contract.on_deposit(
+                token.clone(),
+                "alice.near".parse().unwrap(),
+                amount,
+                None,
+                None,
+                None,
+            );
+        }
+
+        test_utils::set_context(test_utils::token_account_id(&tokens[1]));
+        let _ = contract.on_withdraw(Address([9; 20]), 250.into(), None, None, None);
+
+        assert_eq!(contract.mirrored_supply(tokens[1].clone()), U128(50));
+        assert_eq!(
+            contract.top_tokens_by_supply(10),
+            vec![
+                (test_utils::token_account_id(&tokens[2]), U128(200)),
+                (test_utils::token_account_id(&tokens[0]), U128(100)),
+                (test_utils::token_account_id(&tokens[1]), U128(50)),
+            ]
+        );
+        assert_eq!(contract.top_tokens_by_supply(1).len(), 1);
+    }
+
+    #[test]
+    fn test_gross_flows() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        let token_account_id = test_utils::token_account_id(&token);
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(
+            token.clone(),
+            "alice.near".parse().unwrap(),
+            100,
+            None,
+            None,
+            None,
+        );
+        let _ = contract.on_deposit(
+            token.clone(),
+            "bob.near".parse().unwrap(),
+            50,
+            None,
+            None,
+            None,
+        );
+
+        test_utils::set_context(token_account_id.clone());
+        let _ = contract.on_withdraw(Address([9; 20]), 30.into(), None, None, None);
+        let _ = contract.on_withdraw(Address([9; 20]), 20.into(), None, None, None);
+
+        assert_eq!(contract.gross_flows(token.clone()), (U128(150), U128(50)));
+        assert_eq!(contract.mirrored_supply(token.clone()), U128(100));
+
+        // A reverted withdraw is not accounted as withdrawn.
+        contract.record_withdraw_reverted(&token_account_id, 20);
+        assert_eq!(contract.gross_flows(token.clone()), (U128(150), U128(30)));
+        assert_eq!(contract.mirrored_supply(token), U128(120));
+    }
+
+    #[test]
+    fn test_supply_invariant_holds() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        let token_account_id = test_utils::token_account_id(&token);
+        assert!(contract.supply_invariant_holds(token.clone()));
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(
+            token.clone(),
+            "alice.near".parse().unwrap(),
+            100,
+            None,
+            None,
+            None,
+        );
+        test_utils::set_context(token_account_id.clone());
+        let _ = contract.on_withdraw(Address([9; 20]), 30.into(), None, None, None);
+        contract.record_withdraw_reverted(&token_account_id, 10);
+        assert!(contract.supply_invariant_holds(token.clone()));
+
+        // Desync the supply from the flows.
+        contract.token_supply.insert(&token_account_id, &71);
+        assert!(!contract.supply_invariant_holds(token));
+    }
+
+    /// Deploy a token with a mirrored supply of 100 and withdraw 150 from it.
+    fn withdraw_over_supply(policy: ExcessWithdrawPolicy) -> crate::Contract {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        contract.set_excess_withdraw_policy(policy);
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(
+            token.clone(),
+            "alice.near".parse().unwrap(),
+            100,
+            None,
+            None,
+            None,
+        );
+        test_utils::confirm_deploy(&mut contract, &token);
+
+        test_utils::set_context(test_utils::token_account_id(&token));
+        let _ = contract.on_withdraw(Address([9; 20]), 150.into(), None, None, None);
+        contract
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_WITHDRAW_EXCEEDS_SUPPLY")]
+    fn test_withdraw_over_supply_rejected() {
+        withdraw_over_supply(ExcessWithdrawPolicy::Reject);
+    }
+
+    #[test]
+    fn test_withdraw_over_supply_clamped() {
+        let contract = withdraw_over_supply(ExcessWithdrawPolicy::ClampToSupply);
+        let token = Address([1; 20]);
+
+        assert_eq!(contract.mirrored_supply(token.clone()), U128(0));
+        assert_eq!(contract.gross_flows(token.clone()), (U128(100), U128(100)));
+
+        let logs = near_sdk::test_utils::get_logs();
+        let event: near_sdk::serde_json::Value =
+            near_sdk::serde_json::from_str(logs[0].strip_prefix("EVENT_JSON:").unwrap()).unwrap();
+        assert_eq!(event["event"], "withdraw_clamped");
+        assert_eq!(event["data"]["amount"], "150");
+        assert_eq!(event["data"]["unlocked"], "100");
+
+        // Only the mirrored supply is unlocked, and minted back if the unlock fails.
+        let callbacks = test_utils::function_calls(&test_utils::factory());
+        assert_eq!(callbacks[0].method, "on_withdraw_complete");
+        let args: near_sdk::serde_json::Value =
+            near_sdk::serde_json::from_slice(&callbacks[0].args).unwrap();
+        assert_eq!(args["amount"], "100");
+    }
+
+    #[test]
+    fn test_audit_sweep() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let tokens = [Address([1; 20]), Address([2; 20])];
+        for token in tokens.iter() {
+            test_utils::deploy_token(&mut contract, token);
+        }
+        test_utils::set_context(test_utils::locker_account_id());
+        for token in tokens.iter() {
+            let _ = contract.on_deposit(
+                token.clone(),
+                "alice.near".parse().unwrap(),
+                50,
+                None,
+                None,
+                None,
+            );
+        }
+        let broken = test_utils::token_account_id(&tokens[1]);
+        contract.token_supply.insert(&broken, &60);
+
+        test_utils::set_context(test_utils::factory());
+        assert_eq!(contract.audit_sweep(0, 10), 1);
+
+        let logs = near_sdk::test_utils::get_logs();
+        assert_eq!(logs.len(), 1);
+        let event: near_sdk::serde_json::Value =
+            near_sdk::serde_json::from_str(logs[0].strip_prefix("EVENT_JSON:").unwrap()).unwrap();
+        assert_eq!(event["event"], "invariant_violation");
+        assert_eq!(event["data"]["token_account_id"], broken.as_str());
+        assert_eq!(event["data"]["mirrored_supply"], "60");
+        assert_eq!(event["data"]["deposited"], "50");
+        assert_eq!(event["data"]["withdrawn"], "0");
+
+        // The page only covers the consistent token.
+        test_utils::set_context(test_utils::factory());
+        assert_eq!(contract.audit_sweep(0, 1), 0);
+        assert!(near_sdk::test_utils::get_logs().is_empty());
+    }
+
+    #[test]
+    fn test_total_liability() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let tokens = [Address([1; 20]), Address([2; 20]), Address([3; 20])];
+
+        test_utils::set_context(test_utils::locker_account_id());
+        for (token, amount) in tokens.iter().zip([100, 300, 200]) {
+            let _ = contract.on_deposit(
+                token.clone(),
+                "alice.near".parse().unwrap(),
+                amount,
+                None,
+                None,
+                None,
+            );
+        }
+
+        // The third token has no price, so it is skipped.
+        let prices = vec![(tokens[0].clone(), U128(2)), (tokens[1].clone(), U128(5))];
+        assert_eq!(contract.total_liability(prices), U128(100 * 2 + 300 * 5));
+    }
+
+    #[test]
+    fn test_token_holder_count() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        contract.enable_holder_tracking(token.clone());
+
+        test_utils::set_context(test_utils::locker_account_id());
+        for receiver_id in ["alice.near", "bob.near", "alice.near"] {
+            let _ = contract.on_deposit(
+                token.clone(),
+                receiver_id.parse().unwrap(),
+                10,
+                None,
+                None,
+                None,
+            );
+        }
+        assert_eq!(contract.token_holder_count(token.clone()), 2);
+
+        // Untracked tokens have no holders.
+        let other = Address([2; 20]);
+        let _ = contract.on_deposit(
+            other.clone(),
+            "alice.near".parse().unwrap(),
+            10,
+            None,
+            None,
+            None,
+        );
+        assert_eq!(contract.token_holder_count(other), 0);
+    }
+
+    #[test]
+    fn test_tokens_of() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let tokens = [Address([1; 20]), Address([2; 20]), Address([3; 20])];
+        for token in tokens.iter() {
+            contract.enable_holder_tracking(token.clone());
+        }
+
+        test_utils::set_context(test_utils::locker_account_id());
+        for (token, receiver_id) in tokens.iter().zip(["alice.near", "bob.near", "alice.near"]) {
+            let _ = contract.on_deposit(
+                token.clone(),
+                receiver_id.parse().unwrap(),
+                10,
+                None,
+                None,
+                None,
+            );
+            test_utils::confirm_deploy(&mut contract, token);
+            test_utils::set_context(test_utils::locker_account_id());
+        }
+
+        assert_eq!(
+            contract.tokens_of("alice.near".parse().unwrap(), 0, 10),
+            vec![
+                test_utils::token_account_id(&tokens[0]),
+                test_utils::token_account_id(&tokens[2]),
+            ]
+        );
+        assert_eq!(
+            contract.tokens_of("bob.near".parse().unwrap(), 0, 10),
+            vec![test_utils::token_account_id(&tokens[1])]
+        );
+        assert!(contract
+            .tokens_of("carol.near".parse().unwrap(), 0, 10)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_supply_vs_balance() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        let token_account_id = test_utils::token_account_id(&token);
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(
+            token.clone(),
+            "alice.near".parse().unwrap(),
+            30,
+            None,
+            None,
+            None,
+        );
+
+        test_utils::set_context("auditor.near".parse().unwrap());
+        let _ = contract.supply_vs_balance(token, "alice.near".parse().unwrap());
+
+        let calls = test_utils::function_calls(&token_account_id);
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].method, "ft_balance_of");
+        let args: near_sdk::serde_json::Value =
+            near_sdk::serde_json::from_slice(&calls[0].args).unwrap();
+        assert_eq!(args["account_id"], "alice.near");
+        assert_eq!(
+            test_utils::function_calls(&test_utils::factory())[0].method,
+            "on_supply_vs_balance"
+        );
+
+        test_utils::set_callback_context(vec![PromiseResult::Successful(b"\"25\"".to_vec())]);
+        assert_eq!(
+            contract.on_supply_vs_balance(token_account_id.clone()),
+            SupplyVsBalance {
+                mirrored_supply: U128(30),
+                balance: Some(U128(25)),
+            }
+        );
+
+        test_utils::set_callback_context(vec![PromiseResult::Failed]);
+        assert_eq!(
+            contract.on_supply_vs_balance(token_account_id).balance,
+            None
+        );
+    }
+}
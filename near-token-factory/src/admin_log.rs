@@ -0,0 +1,108 @@
+//! On-chain history of the privileged actions. Only the latest `ADMIN_LOG_CAPACITY`
+//! actions are kept.
+use crate::{Contract, ContractExt};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::U64;
+use near_sdk::serde::Serialize;
+use near_sdk::{env, near_bindgen, AccountId};
+
+/// Maximum number of actions kept in the log. Older actions are overwritten.
+pub(crate) const ADMIN_LOG_CAPACITY: u64 = 100;
+/// Maximum length in bytes of the summary of an action.
+const MAX_SUMMARY_LEN: usize = 256;
+
+/// Privileged action recorded in the admin log.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AdminLogEntry {
+    pub method: String,
+    pub caller: AccountId,
+    pub block_height: U64,
+    /// Arguments of the call, truncated to `MAX_SUMMARY_LEN` bytes.
+    pub summary: String,
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Recorded actions starting from the action number `from`, in the order they
+    /// were recorded. Actions that are no longer kept are skipped.
+    pub fn admin_log(&self, from: u64, limit: u64) -> Vec<AdminLogEntry> {
+        let oldest = self.admin_log_len.saturating_sub(ADMIN_LOG_CAPACITY);
+        (from.max(oldest)..self.admin_log_len)
+            .take(limit as usize)
+            .filter_map(|index| self.admin_log.get(&(index % ADMIN_LOG_CAPACITY)))
+            .collect()
+    }
+
+    /// Number of actions ever recorded, including the ones no longer kept.
+    pub fn admin_log_len(&self) -> u64 {
+        self.admin_log_len
+    }
+}
+
+impl Contract {
+    /// Record a call to the privileged `method` by the predecessor.
+    pub(crate) fn record_admin_action(&mut self, method: &str) {
+        let mut summary = String::from_utf8_lossy(&env::input().unwrap_or_default()).into_owned();
+        if summary.len() > MAX_SUMMARY_LEN {
+            let mut end = MAX_SUMMARY_LEN;
+            while !summary.is_char_boundary(end) {
+                end -= 1;
+            }
+            summary.truncate(end);
+        }
+
+        self.admin_log.insert(
+            &(self.admin_log_len % ADMIN_LOG_CAPACITY),
+            &AdminLogEntry {
+                method: method.to_string(),
+                caller: env::predecessor_account_id(),
+                block_height: env::block_height().into(),
+                summary,
+            },
+        );
+        self.admin_log_len += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ADMIN_LOG_CAPACITY;
+    use crate::test_utils;
+
+    #[test]
+    fn test_admin_actions_are_appended() {
+        let mut contract = test_utils::new_contract();
+
+        contract.pause_deploys();
+        contract.set_event_context(Some("mainnet-1".to_string()));
+        contract.unpause_deploys();
+
+        let methods: Vec<String> = contract
+            .admin_log(0, 10)
+            .into_iter()
+            .map(|entry| entry.method)
+            .collect();
+        assert_eq!(
+            methods,
+            ["pause_deploys", "set_event_context", "unpause_deploys"]
+        );
+        assert_eq!(contract.admin_log(1, 1)[0].method, "set_event_context");
+        assert_eq!(contract.admin_log(0, 1)[0].caller, test_utils::factory());
+    }
+
+    #[test]
+    fn test_admin_log_respects_capacity() {
+        let mut contract = test_utils::new_contract();
+
+        contract.set_event_context(None);
+        for _ in 0..ADMIN_LOG_CAPACITY {
+            contract.pause_deploys();
+        }
+
+        assert_eq!(contract.admin_log_len(), ADMIN_LOG_CAPACITY + 1);
+        let entries = contract.admin_log(0, ADMIN_LOG_CAPACITY + 1);
+        assert_eq!(entries.len() as u64, ADMIN_LOG_CAPACITY);
+        assert!(entries.iter().all(|entry| entry.method == "pause_deploys"));
+    }
+}
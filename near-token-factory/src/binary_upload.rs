@@ -0,0 +1,192 @@
+//! Staged upload of the token binary across several transactions, for binaries too
+//! large to be passed to `set_token_binary` or `confirm_token_binary` in a single call.
+//! Like them, an uploaded binary replaces the current one only through the timelock.
+use crate::{binary_hash, roles, Contract, ContractExt};
+use near_sdk::json_types::{Base58CryptoHash, Base64VecU8, U64};
+use near_sdk::serde::Serialize;
+use near_sdk::{env, near_bindgen, require};
+
+const ERR_NO_BINARY_UPLOAD: &str =
+    "ERR_NO_BINARY_UPLOAD: There is no upload of the token binary in progress.";
+const ERR_BINARY_CHECKSUM_MISMATCH: &str =
+    "ERR_BINARY_CHECKSUM_MISMATCH: Sha256 hash of the uploaded binary doesn't match the checksum.";
+
+/// Progress of the upload of the token binary.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct BinaryUploadStatus {
+    /// Number of chunks appended so far.
+    pub chunks: u64,
+    /// Size in bytes of the chunks appended so far.
+    pub size: U64,
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Start uploading a new token binary, discarding any upload in progress. The
+    /// binary is appended with `append_binary_chunk` and becomes the token binary with
+    /// `finalize_binary_upload`. ONLY the `Owner` and `TokenUpgrader` roles can call
+    /// this method.
+    pub fn start_binary_upload(&mut self) {
+        self.assert_role(roles::Role::TokenUpgrader);
+        self.record_admin_action("start_binary_upload");
+        self.assert_upgrades_not_frozen();
+
+        self.binary_upload_chunks.clear();
+        self.binary_upload_size = Some(0);
+    }
+
+    /// Append `chunk` to the token binary being uploaded. ONLY the `Owner` and
+    /// `TokenUpgrader` roles can call this method.
+    pub fn append_binary_chunk(&mut self, chunk: Base64VecU8) {
+        self.assert_role(roles::Role::TokenUpgrader);
+        self.record_admin_action("append_binary_chunk");
+        self.assert_upgrades_not_frozen();
+        let size = self
+            .binary_upload_size
+            .unwrap_or_else(|| env::panic_str(ERR_NO_BINARY_UPLOAD));
+
+        let chunk: Vec<u8> = chunk.into();
+        self.binary_upload_size = Some(size + chunk.len() as u64);
+        self.binary_upload_chunks.push(&chunk);
+    }
+
+    /// Make the uploaded binary the token binary. It fails with
+    /// `ERR_BINARY_CHECKSUM_MISMATCH`, keeping the upload, unless `checksum` is the
+    /// sha256 hash of the uploaded binary. Like `set_token_binary`, the first token
+    /// binary is installed at once. A later binary replaces the current one like
+    /// `confirm_token_binary`: its hash MUST have been proposed with
+    /// `propose_token_binary` and the timelock MUST have expired. ONLY the `Owner` and
+    /// `TokenUpgrader` roles can call this method.
+    pub fn finalize_binary_upload(&mut self, checksum: Base58CryptoHash) {
+        self.assert_role(roles::Role::TokenUpgrader);
+        self.record_admin_action("finalize_binary_upload");
+        self.assert_upgrades_not_frozen();
+        require!(self.binary_upload_size.is_some(), ERR_NO_BINARY_UPLOAD);
+
+        let binary = self
+            .binary_upload_chunks
+            .iter()
+            .collect::<Vec<_>>()
+            .concat();
+        require!(
+            Base58CryptoHash::from(binary_hash(&binary)) == checksum,
+            ERR_BINARY_CHECKSUM_MISMATCH
+        );
+        self.binary_upload_chunks.clear();
+        self.binary_upload_size = None;
+        if self.token_binary_version == 0 {
+            self.install_token_binary(binary);
+        } else {
+            self.install_proposed_token_binary(binary);
+        }
+    }
+
+    /// Progress of the upload of the token binary, or `None` if there is no upload in
+    /// progress.
+    pub fn binary_upload_status(&self) -> Option<BinaryUploadStatus> {
+        self.binary_upload_size.map(|size| BinaryUploadStatus {
+            chunks: self.binary_upload_chunks.len(),
+            size: size.into(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BinaryUploadStatus;
+    use crate::{binary_hash, test_utils};
+    use near_sdk::json_types::Base58CryptoHash;
+
+    #[test]
+    fn test_chunked_upload() {
+        let mut contract = test_utils::new_contract();
+        contract.start_binary_upload();
+        contract.append_binary_chunk(vec![0, 97].into());
+        contract.append_binary_chunk(vec![115, 109].into());
+        assert_eq!(
+            contract.binary_upload_status(),
+            Some(BinaryUploadStatus {
+                chunks: 2,
+                size: 4.into(),
+            })
+        );
+
+        let hash: Base58CryptoHash = binary_hash(&[0, 97, 115, 109]).into();
+        contract.finalize_binary_upload(hash);
+        assert_eq!(contract.token_binary_version, 1);
+        assert_eq!(contract.token_binary_hash(), Some(hash));
+        assert_eq!(contract.get_token_binary(), vec![0, 97, 115, 109]);
+        assert_eq!(contract.binary_upload_status(), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_BINARY_CHECKSUM_MISMATCH")]
+    fn test_checksum_mismatch() {
+        let mut contract = test_utils::new_contract();
+        contract.start_binary_upload();
+        contract.append_binary_chunk(vec![0, 97, 115, 109].into());
+
+        contract.finalize_binary_upload(binary_hash(&[0, 97, 115]).into());
+    }
+
+    /// Upload `binary` to a factory that already has a token binary.
+    fn upload_replacement(contract: &mut crate::Contract, binary: &[u8]) {
+        contract.start_binary_upload();
+        for chunk in binary.chunks(2) {
+            contract.append_binary_chunk(chunk.to_vec().into());
+        }
+    }
+
+    #[test]
+    fn test_replacing_upload_after_timelock() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let binary = [0, 97, 115, 109, 2];
+        contract.propose_token_binary(binary_hash(&binary).into());
+        upload_replacement(&mut contract, &binary);
+
+        test_utils::skip_timelock(&contract);
+        contract.finalize_binary_upload(binary_hash(&binary).into());
+        assert_eq!(contract.token_binary_version, 2);
+        assert_eq!(contract.get_token_binary(), binary);
+        assert!(contract.pending_changes().is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_TIMELOCK_NOT_EXPIRED")]
+    fn test_replacing_upload_before_timelock() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let binary = [0, 97, 115, 109, 2];
+        contract.propose_token_binary(binary_hash(&binary).into());
+        upload_replacement(&mut contract, &binary);
+
+        contract.finalize_binary_upload(binary_hash(&binary).into());
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_NO_PENDING_CHANGE")]
+    fn test_replacing_upload_without_proposal() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let binary = [0, 97, 115, 109, 2];
+        upload_replacement(&mut contract, &binary);
+
+        contract.finalize_binary_upload(binary_hash(&binary).into());
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_UPGRADES_FROZEN")]
+    fn test_append_while_frozen() {
+        let mut contract = test_utils::new_contract();
+        contract.start_binary_upload();
+        contract.freeze_upgrades();
+
+        contract.append_binary_chunk(vec![0, 97, 115, 109].into());
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_NO_BINARY_UPLOAD")]
+    fn test_append_without_upload() {
+        let mut contract = test_utils::new_contract();
+        contract.append_binary_chunk(vec![0, 97, 115, 109].into());
+    }
+}
@@ -0,0 +1,590 @@
+//! Deposits clamped to the supply cap of a token. The part of a deposit above the cap
+//! is not minted, and it is recorded as a deferred remainder instead. Deposits of
+//! paused tokens can be deferred as well, see `PausedDepositPolicy`.
+use crate::{
+    aurora_sdk, events, ext, Contract, ContractExt, ERR_TOKEN_PAUSED, WITHDRAW_AURORA_CALL_GAS,
+};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{U128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{
+    env, near_bindgen, require, AccountId, Balance, Gas, PromiseOrValue, PromiseResult,
+};
+
+const PENDING_DEPOSIT_CALLBACK_GAS: Gas = Gas(5_000_000_000_000);
+
+const ERR_MISSING_REFUND_RECIPIENT: &str =
+    "ERR_MISSING_REFUND_RECIPIENT: Refund policy requires a refund recipient.";
+const ERR_NO_DEFERRED_DEPOSIT: &str =
+    "ERR_NO_DEFERRED_DEPOSIT: There is no deferred remainder with this id.";
+
+/// What happens with the remainder of a clamped deposit.
+#[derive(
+    BorshSerialize, BorshDeserialize, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq,
+)]
+#[serde(crate = "near_sdk::serde")]
+pub enum RemainderPolicy {
+    /// The remainder is minted to the receiver once the cap allows it.
+    Mint,
+    /// The remainder is unlocked on Aurora to the refund recipient.
+    Refund,
+}
+
+impl Default for RemainderPolicy {
+    fn default() -> Self {
+        Self::Mint
+    }
+}
+
+/// What happens with deposits of a paused token.
+#[derive(
+    BorshSerialize, BorshDeserialize, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq,
+)]
+#[serde(crate = "near_sdk::serde")]
+pub enum PausedDepositPolicy {
+    /// The deposit panics, which fails the transaction of the locker.
+    Reject,
+    /// The whole deposit is deferred with the `Mint` policy, so it is minted by
+    /// `process_pending_deposits` once the token is unpaused.
+    Queue,
+}
+
+impl Default for PausedDepositPolicy {
+    fn default() -> Self {
+        Self::Reject
+    }
+}
+
+/// Part of a deposit that was not minted because it exceeded the supply cap, or a
+/// deposit of a paused token that was queued.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct DeferredDeposit {
+    pub token_account_id: AccountId,
+    /// Receiver of the original deposit.
+    pub receiver_id: AccountId,
+    pub amount: U128,
+    /// Policy at the time the deposit was clamped.
+    pub policy: RemainderPolicy,
+    /// Aurora address receiving the remainder if it is refunded.
+    pub refund_recipient: Option<aurora_sdk::Address>,
+    /// Aurora transaction that locked the tokens, if known.
+    pub origin_tx_hash: Option<aurora_sdk::RawU256>,
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Set the maximum mirrored supply of the token, or remove it. Deposits exceeding
+    /// the cap are clamped and the remainder is deferred. ONLY the `Owner` role can
+    /// call this method.
+    pub fn set_supply_cap(&mut self, token_address: aurora_sdk::Address, cap: Option<U128>) {
+        self.assert_owner();
+        self.record_admin_action("set_supply_cap");
+
+        let token_account_id = self.account_id_from_token_address(token_address);
+        match cap {
+            Some(cap) => self.supply_caps.insert(&token_account_id, &cap.into()),
+            None => self.supply_caps.remove(&token_account_id),
+        };
+    }
+
+    /// Maximum mirrored supply of the token, if any.
+    pub fn supply_cap(&self, token_address: aurora_sdk::Address) -> Option<U128> {
+        self.supply_caps
+            .get(&self.account_id_from_token_address(token_address))
+            .map(Into::into)
+    }
+
+    /// Set how remainders of clamped deposits are handled. `refund_recipient` is the
+    /// Aurora address receiving refunded remainders, and it is required by the
+    /// `Refund` policy. ONLY the `Owner` role can call this method.
+    pub fn set_remainder_policy(
+        &mut self,
+        policy: RemainderPolicy,
+        refund_recipient: Option<aurora_sdk::Address>,
+    ) {
+        self.assert_owner();
+        self.record_admin_action("set_remainder_policy");
+        require!(
+            policy != RemainderPolicy::Refund || refund_recipient.is_some(),
+            ERR_MISSING_REFUND_RECIPIENT
+        );
+
+        self.remainder_policy = policy;
+        self.refund_recipient = refund_recipient;
+    }
+
+    /// Policy and refund recipient applied to remainders of clamped deposits.
+    pub fn remainder_policy(&self) -> (RemainderPolicy, Option<aurora_sdk::Address>) {
+        (self.remainder_policy, self.refund_recipient.clone())
+    }
+
+    /// Set how deposits of paused tokens are handled. ONLY the `Owner` role can call
+    /// this method.
+    pub fn set_paused_deposit_policy(&mut self, policy: PausedDepositPolicy) {
+        self.assert_owner();
+        self.record_admin_action("set_paused_deposit_policy");
+
+        self.paused_deposit_policy = policy;
+    }
+
+    /// Policy applied to deposits of paused tokens.
+    pub fn paused_deposit_policy(&self) -> PausedDepositPolicy {
+        self.paused_deposit_policy
+    }
+
+    /// Deferred remainder with the given id, if any.
+    pub fn deferred_deposit(&self, id: u64) -> Option<DeferredDeposit> {
+        self.deferred_deposits.get(&id)
+    }
+
+    /// Cancel the deferred remainder `id`, so it is never minted. If `refund`, the
+    /// remainder is unlocked on Aurora to its refund recipient, or to the current one
+    /// if it was deferred without any. The unlock is not retried if it fails. ONLY the
+    /// `Owner` role can call this method.
+    pub fn cancel_deferred(&mut self, id: u64, refund: bool) -> PromiseOrValue<()> {
+        self.assert_owner();
+        self.record_admin_action("cancel_deferred");
+
+        let deferred = self
+            .deferred_deposits
+            .remove(&id)
+            .unwrap_or_else(|| env::panic_str(ERR_NO_DEFERRED_DEPOSIT));
+        let refund_recipient = if refund {
+            let recipient = deferred
+                .refund_recipient
+                .clone()
+                .or_else(|| self.refund_recipient.clone())
+                .unwrap_or_else(|| env::panic_str(ERR_MISSING_REFUND_RECIPIENT));
+            Some(recipient)
+        } else {
+            None
+        };
+
+        self.emit_event(events::ConnectorEvent::DeferredCancelled {
+            id: id.into(),
+            token_account_id: deferred.token_account_id.clone(),
+            receiver_id: deferred.receiver_id,
+            amount: deferred.amount,
+            refunded: refund,
+        });
+
+        match refund_recipient {
+            Some(recipient) => {
+                let token_id =
+                    self.address_from_token_account_id(deferred.token_account_id.clone());
+                let input = self.encode_unlock(
+                    &deferred.token_account_id,
+                    &token_id,
+                    &recipient,
+                    deferred.amount.into(),
+                );
+                aurora_sdk::aurora::ext_aurora::ext(self.engine_of(&deferred.token_account_id))
+                    .with_static_gas(WITHDRAW_AURORA_CALL_GAS)
+                    .call(self.unlock_call_args(token_id, input))
+                    .into()
+            }
+            None => PromiseOrValue::Value(()),
+        }
+    }
+
+    /// Mint the oldest `limit` deferred remainders of the `Mint` policy, as far as the
+    /// supply caps of their tokens allow it. Remainders are removed, or reduced if they
+    /// are only partially minted, once their mint succeeds, so a relayer can call this
+    /// method periodically. Remainders of tokens that are not registered, paused or
+    /// detached are skipped.
+    /// Returns the number of mints scheduled. ONLY the `Owner` role can call this
+    /// method.
+    pub fn process_pending_deposits(&mut self, limit: u64) -> u64 {
+        self.assert_owner();
+        self.record_admin_action("process_pending_deposits");
+
+        let mut pending: Vec<(u64, DeferredDeposit)> = self
+            .deferred_deposits
+            .iter()
+            .filter(|(_, deferred)| deferred.policy == RemainderPolicy::Mint)
+            .collect();
+        pending.sort_by_key(|(id, _)| *id);
+
+        let mut scheduled = 0;
+        for (id, deferred) in pending {
+            if scheduled == limit {
+                break;
+            }
+            let token_account_id = deferred.token_account_id;
+            if self.tokens.get(&token_account_id).is_none()
+                || self.is_token_account_paused(&token_account_id)
+                || self.detached_tokens.contains(&token_account_id)
+            {
+                continue;
+            }
+            let amount = self.mintable_amount(&token_account_id, deferred.amount.0);
+            if amount == 0 {
+                continue;
+            }
+
+            self.record_deposit(&token_account_id, &deferred.receiver_id, amount);
+            self.emit_event(events::ConnectorEvent::Deposit {
+                token: self
+                    .address_from_token_account_id(token_account_id.clone())
+                    .to_string(),
+                receiver_id: deferred.receiver_id.clone(),
+                amount: amount.into(),
+                origin_tx_hash: deferred
+                    .origin_tx_hash
+                    .map(|hash| format!("0x{}", hex::encode(hash))),
+            });
+            ext::ext_near_token::ext(token_account_id)
+                .with_static_gas(self.gas_config.deposit_gas)
+                .deposit(deferred.receiver_id, amount.into(), None)
+                .then(
+                    Contract::ext(env::current_account_id())
+                        .with_static_gas(PENDING_DEPOSIT_CALLBACK_GAS)
+                        .with_unused_gas_weight(0)
+                        .on_pending_deposit_minted(id, amount.into()),
+                );
+            scheduled += 1;
+        }
+        scheduled
+    }
+
+    /// Callback invoked after `process_pending_deposits` minted `amount` of the
+    /// deferred remainder `id`. If the mint succeeded the remainder is reduced by
+    /// `amount`, and removed once it is fully minted. Otherwise the deposit is undone
+    /// in the accounting, and the remainder is kept for the next attempt. This is a
+    /// callback function that can be only executed from the contract itself.
+    #[private]
+    pub fn on_pending_deposit_minted(&mut self, id: u64, amount: U128) {
+        let mut deferred = match self.deferred_deposits.get(&id) {
+            Some(deferred) => deferred,
+            None => return,
+        };
+        if !matches!(env::promise_result(0), PromiseResult::Successful(_)) {
+            self.record_deposit_failed(&deferred.token_account_id, amount.0, 1);
+            return;
+        }
+
+        deferred.amount = deferred.amount.0.saturating_sub(amount.0).into();
+        if deferred.amount.0 == 0 {
+            self.deferred_deposits.remove(&id);
+        } else {
+            self.deferred_deposits.insert(&id, &deferred);
+        }
+    }
+
+    /// Paginated list of deferred remainders and their ids.
+    pub fn deferred_deposits(&self, from_index: u64, limit: u64) -> Vec<(u64, DeferredDeposit)> {
+        self.deferred_deposits
+            .iter()
+            .skip(from_index as usize)
+            .take(limit as usize)
+            .collect()
+    }
+}
+
+impl Contract {
+    /// Part of `amount` that can be minted without exceeding the supply cap of the
+    /// token.
+    fn mintable_amount(&self, token_account_id: &AccountId, amount: Balance) -> Balance {
+        match self.supply_caps.get(token_account_id) {
+            Some(cap) => {
+                let supply = self.token_supply.get(token_account_id).unwrap_or(0);
+                cap.saturating_sub(supply).min(amount)
+            }
+            None => amount,
+        }
+    }
+
+    /// Apply the paused deposit policy to a deposit of a paused token. Under `Queue`
+    /// the whole deposit is deferred, otherwise the call panics.
+    pub(crate) fn queue_paused_deposit(
+        &mut self,
+        token_account_id: &AccountId,
+        receiver_id: &AccountId,
+        amount: Balance,
+        origin_tx_hash: Option<aurora_sdk::RawU256>,
+    ) {
+        require!(
+            self.paused_deposit_policy == PausedDepositPolicy::Queue,
+            ERR_TOKEN_PAUSED
+        );
+        self.defer_deposit(
+            token_account_id,
+            receiver_id,
+            amount,
+            RemainderPolicy::Mint,
+            origin_tx_hash,
+        );
+    }
+
+    /// Clamp a deposit to the supply cap of the token. The remainder is recorded as a
+    /// deferred deposit. Return the amount that can be minted, and the id of the
+    /// deferred remainder if there is one.
+    pub(crate) fn clamp_deposit(
+        &mut self,
+        token_account_id: &AccountId,
+        receiver_id: &AccountId,
+        amount: Balance,
+        origin_tx_hash: Option<aurora_sdk::RawU256>,
+    ) -> (Balance, Option<u64>) {
+        let allowed = self.mintable_amount(token_account_id, amount);
+
+        let deferred_id = (allowed < amount).then(|| {
+            self.defer_deposit(
+                token_account_id,
+                receiver_id,
+                amount - allowed,
+                self.remainder_policy,
+                origin_tx_hash,
+            )
+        });
+
+        (allowed, deferred_id)
+    }
+
+    /// Record a deferred deposit and return its id.
+    fn defer_deposit(
+        &mut self,
+        token_account_id: &AccountId,
+        receiver_id: &AccountId,
+        amount: Balance,
+        policy: RemainderPolicy,
+        origin_tx_hash: Option<aurora_sdk::RawU256>,
+    ) -> u64 {
+        let id = self.next_deferred_id;
+        self.next_deferred_id += 1;
+        self.deferred_deposits.insert(
+            &id,
+            &DeferredDeposit {
+                token_account_id: token_account_id.clone(),
+                receiver_id: receiver_id.clone(),
+                amount: amount.into(),
+                policy,
+                refund_recipient: self.refund_recipient.clone(),
+                origin_tx_hash,
+            },
+        );
+        id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DeferredDeposit, PausedDepositPolicy, RemainderPolicy};
+    use crate::aurora_sdk::Address;
+    use crate::test_utils;
+    use near_sdk::{AccountId, PromiseResult};
+
+    /// Deploy a token capped at 100 and deposit 130 to it, deferring 30.
+    fn contract_with_deferred_remainder(token: &Address) -> crate::Contract {
+        let mut contract = test_utils::new_contract_with_binary();
+        contract.set_supply_cap(token.clone(), Some(100.into()));
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(
+            token.clone(),
+            "alice.near".parse().unwrap(),
+            130,
+            None,
+            None,
+            None,
+        );
+        test_utils::confirm_deploy(&mut contract, token);
+        contract
+    }
+
+    #[test]
+    fn test_clamped_deposit_records_remainder() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        let receiver_id: AccountId = "alice.near".parse().unwrap();
+        let refund_recipient = Address([7; 20]);
+        contract.set_supply_cap(token.clone(), Some(100.into()));
+        contract.set_remainder_policy(RemainderPolicy::Refund, Some(refund_recipient.clone()));
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(token.clone(), receiver_id.clone(), 80, None, None, None);
+        let _ = contract.on_deposit(
+            token.clone(),
+            receiver_id.clone(),
+            50,
+            Some([1; 32]),
+            None,
+            None,
+        );
+
+        assert_eq!(contract.mirrored_supply(token.clone()).0, 100);
+        assert_eq!(
+            contract.deferred_deposits(0, 10),
+            vec![(
+                0,
+                DeferredDeposit {
+                    token_account_id: test_utils::token_account_id(&token),
+                    receiver_id,
+                    amount: 30.into(),
+                    policy: RemainderPolicy::Refund,
+                    refund_recipient: Some(refund_recipient),
+                    origin_tx_hash: Some([1; 32]),
+                }
+            )]
+        );
+    }
+
+    #[test]
+    fn test_deposit_below_cap_is_not_deferred() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        contract.set_supply_cap(token.clone(), Some(100.into()));
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(token, "alice.near".parse().unwrap(), 100, None, None, None);
+
+        assert!(contract.deferred_deposit(0).is_none());
+    }
+
+    #[test]
+    fn test_cancel_deferred() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        contract.set_supply_cap(token.clone(), Some(100.into()));
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(token, "alice.near".parse().unwrap(), 130, None, None, None);
+        assert!(contract.deferred_deposit(0).is_some());
+
+        test_utils::set_context(test_utils::factory());
+        let _ = contract.cancel_deferred(0, false);
+
+        assert!(contract.deferred_deposit(0).is_none());
+        let logs = near_sdk::test_utils::get_logs();
+        assert_eq!(logs.len(), 1);
+        assert!(logs[0].contains(r#""event":"deferred_cancelled""#));
+        assert!(logs[0].contains(r#""amount":"30""#));
+        assert!(test_utils::function_calls(&test_utils::aurora()).is_empty());
+    }
+
+    #[test]
+    fn test_cancel_deferred_with_refund() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        contract.set_supply_cap(token.clone(), Some(100.into()));
+        contract.set_remainder_policy(RemainderPolicy::Refund, Some(Address([7; 20])));
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(token, "alice.near".parse().unwrap(), 130, None, None, None);
+
+        test_utils::set_context(test_utils::factory());
+        let _ = contract.cancel_deferred(0, true);
+
+        assert!(contract.deferred_deposit(0).is_none());
+        assert_eq!(
+            test_utils::function_calls(&test_utils::aurora())[0].method,
+            "call"
+        );
+    }
+
+    #[test]
+    fn test_process_pending_deposits() {
+        let token = Address([1; 20]);
+        let mut contract = contract_with_deferred_remainder(&token);
+        contract.set_supply_cap(token.clone(), Some(120.into()));
+
+        // Only 20 fit under the new cap.
+        assert_eq!(contract.process_pending_deposits(10), 1);
+        let calls = test_utils::function_calls(&test_utils::token_account_id(&token));
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].method, "deposit");
+        assert_eq!(contract.mirrored_supply(token.clone()).0, 120);
+
+        test_utils::set_callback_context(vec![PromiseResult::Successful(Vec::new())]);
+        contract.on_pending_deposit_minted(0, 20.into());
+        assert_eq!(contract.deferred_deposit(0).unwrap().amount, 10.into());
+
+        test_utils::set_context(test_utils::factory());
+        contract.set_supply_cap(token.clone(), None);
+        assert_eq!(contract.process_pending_deposits(10), 1);
+        test_utils::set_callback_context(vec![PromiseResult::Successful(Vec::new())]);
+        contract.on_pending_deposit_minted(0, 10.into());
+
+        assert!(contract.deferred_deposit(0).is_none());
+        assert_eq!(contract.mirrored_supply(token).0, 130);
+    }
+
+    #[test]
+    fn test_failed_pending_deposit_is_kept() {
+        let token = Address([1; 20]);
+        let mut contract = contract_with_deferred_remainder(&token);
+        contract.set_supply_cap(token.clone(), None);
+        assert_eq!(contract.process_pending_deposits(10), 1);
+
+        test_utils::set_callback_context(vec![PromiseResult::Failed]);
+        contract.on_pending_deposit_minted(0, 30.into());
+
+        assert_eq!(contract.deferred_deposit(0).unwrap().amount, 30.into());
+        assert_eq!(contract.mirrored_supply(token).0, 100);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_NO_DEFERRED_DEPOSIT")]
+    fn test_cancel_unknown_deferred() {
+        let mut contract = test_utils::new_contract();
+        let _ = contract.cancel_deferred(0, false);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_MISSING_REFUND_RECIPIENT")]
+    fn test_refund_policy_requires_recipient() {
+        let mut contract = test_utils::new_contract();
+        contract.set_remainder_policy(RemainderPolicy::Refund, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_TOKEN_PAUSED")]
+    fn test_paused_token_deposit_rejected() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        assert_eq!(
+            contract.paused_deposit_policy(),
+            PausedDepositPolicy::Reject
+        );
+        contract.pause_token(token.clone());
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(token, "alice.near".parse().unwrap(), 10, None, None, None);
+    }
+
+    #[test]
+    fn test_paused_token_deposit_queued() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        let token_account_id = test_utils::token_account_id(&token);
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.create_token(token.clone(), None);
+        test_utils::confirm_deploy(&mut contract, &token);
+        contract.set_paused_deposit_policy(PausedDepositPolicy::Queue);
+        contract.pause_token(token.clone());
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(
+            token.clone(),
+            "alice.near".parse().unwrap(),
+            10,
+            None,
+            None,
+            None,
+        );
+
+        assert!(test_utils::function_calls(&token_account_id).is_empty());
+        assert_eq!(contract.mirrored_supply(token.clone()).0, 0);
+        let queued = contract.deferred_deposit(0).unwrap();
+        assert_eq!(queued.amount.0, 10);
+        assert_eq!(queued.policy, RemainderPolicy::Mint);
+
+        // Queued deposits are only processed once the token is unpaused.
+        test_utils::set_context(test_utils::factory());
+        assert_eq!(contract.process_pending_deposits(10), 0);
+        contract.unpause_token(token.clone());
+        assert_eq!(contract.process_pending_deposits(10), 1);
+        assert_eq!(contract.mirrored_supply(token).0, 10);
+    }
+}
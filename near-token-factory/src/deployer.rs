@@ -0,0 +1,256 @@
+//! Deployment of new tokens through a deployer sub-account of the factory, see the
+//! `near-token-deployer` contract. The deployer creates the token accounts under its own
+//! account id, i.e. `{label}.deployer.{factory}`, and deploys the token binary on them,
+//! so the factory only schedules a function call for it. The tokens are still
+//! initialized by the factory, which stays their controller. Tokens keep their account
+//! id once deployed, whether the deployer is enabled afterwards or not.
+use crate::naming::MAX_ACCOUNT_ID_LEN;
+use crate::{logging, Contract, ContractExt, ERR_INSUFFICIENT_FACTORY_BALANCE};
+use near_sdk::json_types::Base64VecU8;
+use near_sdk::{env, near_bindgen, require, AccountId, Balance, Gas, Promise, PromiseResult};
+
+/// Label of the deployer account, i.e. the part before `.{factory}`.
+const DEPLOYER_LABEL: &str = "deployer";
+/// Storage of the deployer account on top of its binary, in bytes.
+const DEPLOYER_STORAGE_OVERHEAD: u64 = 1_000;
+/// Gas attached to the `new` call of the deployer.
+const DEPLOYER_NEW_GAS: Gas = Gas(5_000_000_000_000);
+/// Gas attached to `on_deployer_created`.
+const DEPLOYER_CREATED_CALLBACK_GAS: Gas = Gas(5_000_000_000_000);
+/// Gas of a `deploy_token` call of the deployer, besides the gas per byte of the binary.
+const DEPLOY_TOKEN_BASE_GAS: Gas = Gas(10_000_000_000_000);
+/// Gas per byte of the binary passed to `deploy_token`, covering both the argument and
+/// the deployment of the binary by the deployer.
+const DEPLOY_TOKEN_GAS_PER_BYTE: u64 = 100_000_000;
+
+const ERR_DEPLOYER_EXISTS: &str = "ERR_DEPLOYER_EXISTS: Deployer is already created.";
+const ERR_NO_DEPLOYER: &str = "ERR_NO_DEPLOYER: Deployer is not created.";
+const ERR_INVALID_DEPLOYER: &str =
+    "ERR_INVALID_DEPLOYER: Factory account id is too long for token accounts of a deployer.";
+
+#[near_bindgen]
+impl Contract {
+    /// Create the deployer sub-account `deployer.{factory}` running `binary`, which MUST
+    /// match the binary proposed with `propose_deployer_binary` once its timelock
+    /// expired. New tokens are deployed through the deployer once it is created, see
+    /// `set_deployer_enabled`. ONLY the `Owner` role can call this method.
+    pub fn create_deployer(&mut self, binary: Base64VecU8) -> Promise {
+        self.assert_owner();
+        self.record_admin_action("create_deployer");
+        require!(self.deployer.is_none(), ERR_DEPLOYER_EXISTS);
+        let deployer: AccountId = format!("{}.{}", DEPLOYER_LABEL, env::current_account_id())
+            .parse()
+            .unwrap_or_else(|_| env::panic_str(ERR_INVALID_DEPLOYER));
+        require!(
+            self.naming_scheme.label_len() + 1 + deployer.as_str().len() <= MAX_ACCOUNT_ID_LEN,
+            ERR_INVALID_DEPLOYER
+        );
+
+        let binary: Vec<u8> = binary.into();
+        self.take_proposed_deployer_binary(&binary);
+        let storage = Balance::from(binary.len() as u64 + DEPLOYER_STORAGE_OVERHEAD)
+            * env::storage_byte_cost();
+        require!(
+            self.available_balance().0 >= storage + self.deploy_balance_margin,
+            ERR_INSUFFICIENT_FACTORY_BALANCE
+        );
+        Promise::new(deployer.clone())
+            .create_account()
+            .transfer(storage)
+            .deploy_contract(binary)
+            .function_call("new".to_string(), Vec::new(), 0, DEPLOYER_NEW_GAS)
+            .then(
+                Contract::ext(env::current_account_id())
+                    .with_static_gas(DEPLOYER_CREATED_CALLBACK_GAS)
+                    .on_deployer_created(deployer),
+            )
+    }
+
+    /// Record the deployer and enable it if its creation succeeded.
+    /// This is a callback function that can be only executed from the contract itself.
+    #[private]
+    pub fn on_deployer_created(&mut self, deployer: AccountId) {
+        if !matches!(env::promise_result(0), PromiseResult::Successful(_)) {
+            self.log_at(
+                logging::LogLevel::Errors,
+                &format!("Creation of {} failed", deployer),
+            );
+            return;
+        }
+        self.deployer = Some(deployer);
+        self.deployer_enabled = true;
+    }
+
+    /// Set whether new tokens are deployed through the deployer. Tokens deployed before
+    /// keep their account id. ONLY the `Owner` role can call this method.
+    pub fn set_deployer_enabled(&mut self, enabled: bool) {
+        self.assert_owner();
+        self.record_admin_action("set_deployer_enabled");
+        require!(self.deployer.is_some(), ERR_NO_DEPLOYER);
+
+        self.deployer_enabled = enabled;
+    }
+
+    /// Deployer sub-account, if it was created.
+    pub fn deployer(&self) -> Option<AccountId> {
+        self.deployer.clone()
+    }
+
+    /// Whether new tokens are deployed through the deployer.
+    pub fn deployer_enabled(&self) -> bool {
+        self.deployer_enabled
+    }
+}
+
+impl Contract {
+    /// Account the account ids of new tokens are derived under, i.e. the deployer if it
+    /// is enabled, or the factory itself.
+    pub(crate) fn token_parent_account_id(&self) -> AccountId {
+        match &self.deployer {
+            Some(deployer) if self.deployer_enabled => deployer.clone(),
+            _ => env::current_account_id(),
+        }
+    }
+
+    /// Deployer and label of `token_account_id`, if it is a sub-account of the deployer.
+    pub(crate) fn deployer_label(
+        &self,
+        token_account_id: &AccountId,
+    ) -> Option<(AccountId, String)> {
+        let deployer = self.deployer.clone()?;
+        let label = token_account_id
+            .as_str()
+            .strip_suffix(deployer.as_str())?
+            .strip_suffix('.')?
+            .to_string();
+        Some((deployer, label))
+    }
+}
+
+/// Gas attached to the `deploy_token` call of the deployer for a binary of `len` bytes.
+pub(crate) fn deploy_token_gas(len: usize) -> Gas {
+    Gas(DEPLOY_TOKEN_BASE_GAS.0 + len as u64 * DEPLOY_TOKEN_GAS_PER_BYTE)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::aurora_sdk::Address;
+    use crate::{binary_hash, test_utils};
+    use near_sdk::borsh::BorshDeserialize;
+    use near_sdk::{AccountId, PromiseResult};
+
+    const DEPLOYER_BINARY: [u8; 4] = [0, 97, 115, 109];
+
+    fn deployer() -> AccountId {
+        "deployer.factory.near".parse().unwrap()
+    }
+
+    /// Create the deployer through the timelock and run the callback of its creation.
+    fn create_deployer(contract: &mut crate::Contract, result: PromiseResult) {
+        contract.propose_deployer_binary(binary_hash(&DEPLOYER_BINARY).into());
+        test_utils::skip_timelock(contract);
+        let _ = contract.create_deployer(DEPLOYER_BINARY.to_vec().into());
+        let calls = test_utils::function_calls(&deployer());
+        assert_eq!(calls[0].method, "new");
+
+        test_utils::set_callback_context(vec![result]);
+        contract.on_deployer_created(deployer());
+        test_utils::set_context(test_utils::factory());
+    }
+
+    #[test]
+    fn test_deploys_route_through_deployer() {
+        let mut contract = test_utils::new_contract_with_binary();
+        create_deployer(&mut contract, PromiseResult::Successful(Vec::new()));
+        assert_eq!(contract.deployer(), Some(deployer()));
+        assert!(contract.deployer_enabled());
+
+        let token = Address([1; 20]);
+        let token_account_id: AccountId = format!("{}.{}", token.encode(), deployer())
+            .parse()
+            .unwrap();
+        assert_eq!(contract.token_account_id(token.clone()), token_account_id);
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(
+            token.clone(),
+            "alice.near".parse().unwrap(),
+            10,
+            None,
+            None,
+            None,
+        );
+
+        let calls = test_utils::function_calls(&deployer());
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].method, "deploy_token");
+        let (label, binary): (String, Vec<u8>) =
+            BorshDeserialize::try_from_slice(&calls[0].args).unwrap();
+        assert_eq!(label, token.encode());
+        assert_eq!(binary, contract.get_token_binary());
+        let methods: Vec<String> = test_utils::function_calls(&token_account_id)
+            .into_iter()
+            .map(|call| call.method)
+            .collect();
+        assert_eq!(methods, ["new", "storage_deposit", "deposit"]);
+        assert!(test_utils::function_calls(&test_utils::token_account_id(&token)).is_empty());
+
+        test_utils::set_callback_context(vec![PromiseResult::Successful(Vec::new())]);
+        let _ = contract.on_token_deployed(token.clone(), token_account_id.clone(), None, None);
+        assert!(contract.is_token_deployed(token.clone()));
+        assert_eq!(contract.token_address(token_account_id), token);
+    }
+
+    #[test]
+    fn test_disabled_deployer_keeps_account_ids() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        create_deployer(&mut contract, PromiseResult::Successful(Vec::new()));
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.create_token(token.clone(), None);
+
+        test_utils::set_context(test_utils::factory());
+        contract.set_deployer_enabled(false);
+        assert_eq!(
+            contract.token_account_id(Address([2; 20])),
+            test_utils::token_account_id(&Address([2; 20]))
+        );
+        assert_eq!(
+            contract.token_account_id(token.clone()).as_str(),
+            format!("{}.{}", token.encode(), deployer())
+        );
+    }
+
+    #[test]
+    fn test_failed_deployer_creation() {
+        let mut contract = test_utils::new_contract_with_binary();
+        create_deployer(&mut contract, PromiseResult::Failed);
+        assert_eq!(contract.deployer(), None);
+
+        test_utils::deploy_token(&mut contract, &Address([1; 20]));
+        assert!(test_utils::function_calls(&deployer()).is_empty());
+        assert!(contract.is_token_deployed(Address([1; 20])));
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_PROPOSED_BINARY_MISMATCH")]
+    fn test_create_deployer_with_other_binary() {
+        let mut contract = test_utils::new_contract_with_binary();
+        contract.propose_deployer_binary(binary_hash(&DEPLOYER_BINARY).into());
+        test_utils::skip_timelock(&contract);
+        let _ = contract.create_deployer(vec![1, 2, 3].into());
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_NO_PENDING_CHANGE")]
+    fn test_create_deployer_without_proposal() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let _ = contract.create_deployer(DEPLOYER_BINARY.to_vec().into());
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_NO_DEPLOYER")]
+    fn test_enable_missing_deployer() {
+        let mut contract = test_utils::new_contract_with_binary();
+        contract.set_deployer_enabled(true);
+    }
+}
@@ -0,0 +1,240 @@
+//! Deposits followed by an `ft_transfer_call` to the receiver, e.g. to bridge an ERC-20
+//! and deposit it into a NEAR contract in a single transaction. The token mints the
+//! deposit to the factory and transfers it with `deposit_call`. The part not used by
+//! the receiver is burnt by the token and unlocked on Aurora to the refund address.
+use crate::{
+    aurora_sdk, events, is_reserved_token_address, logging, receiver_storage_deposit_args,
+    Contract, ContractExt, ERR_TOKEN_NOT_DEPLOYED, ERR_TOKEN_PAUSED, ERR_ZERO_TOKEN,
+    PAUSE_DEPOSITS, STORAGE_DEPOSIT_GAS, WITHDRAW_AURORA_CALL_GAS,
+};
+use near_sdk::json_types::U128;
+use near_sdk::{
+    env, near_bindgen, require, AccountId, Gas, Promise, PromiseOrValue, PromiseResult,
+};
+
+/// Gas attached to the `deposit_call` call of a token, including the `ft_on_transfer`
+/// call of the receiver and the resolution of the transfer.
+const DEPOSIT_CALL_GAS: Gas = Gas(60_000_000_000_000);
+/// Gas of `on_deposit_call_complete`, including the Aurora call unlocking the unused
+/// part of the deposit.
+const DEPOSIT_CALL_CALLBACK_GAS: Gas = Gas(5_000_000_000_000 + WITHDRAW_AURORA_CALL_GAS.0);
+
+#[near_bindgen]
+impl Contract {
+    /// Like `on_deposit`, but the minted tokens are transferred to `receiver_id` with
+    /// `ft_transfer_call` and `msg`. The part of the deposit not used by the receiver is
+    /// unlocked on Aurora to `refund_address`, see `on_deposit_call_complete`. The token
+    /// must be deployed, and deposits of paused tokens are rejected. This method can
+    /// only be called by the locker and the minters.
+    pub fn on_deposit_call(
+        &mut self,
+        #[serializer(borsh)] token: aurora_sdk::Address,
+        #[serializer(borsh)] receiver_id: AccountId,
+        #[serializer(borsh)] amount: u128,
+        #[serializer(borsh)] msg: String,
+        #[serializer(borsh)] refund_address: aurora_sdk::Address,
+    ) -> PromiseOrValue<()> {
+        self.assert_not_paused(PAUSE_DEPOSITS);
+        self.assert_can_deposit();
+        require!(!is_reserved_token_address(&token), ERR_ZERO_TOKEN);
+        self.assert_token_allowed(&token);
+
+        let token_account_id = self.route_deposit(&token);
+        require!(
+            self.tokens.get(&token_account_id).is_some(),
+            ERR_TOKEN_NOT_DEPLOYED
+        );
+        self.assert_not_detached(&token_account_id);
+        self.assert_receiver_allowed(&token_account_id, &receiver_id);
+        require!(
+            !self.is_token_account_paused(&token_account_id),
+            ERR_TOKEN_PAUSED
+        );
+        let args = deposit_call_args(&receiver_id, amount, &msg);
+        if self.dry_run {
+            self.emit_planned_call(&token_account_id, "deposit_call", args, DEPOSIT_CALL_GAS);
+            return PromiseOrValue::Value(());
+        }
+
+        self.consume_deposit_limit(&token_account_id, amount);
+        let fee = self.deposit_fee(&token_account_id, amount);
+        let transferred = amount - fee;
+        self.record_deposit(&token_account_id, &receiver_id, transferred);
+        let fee_collector = self.charge_fee(&token_account_id, &receiver_id, fee);
+        self.emit_event(events::ConnectorEvent::Deposit {
+            token: token.to_string(),
+            receiver_id: receiver_id.clone(),
+            amount: transferred.into(),
+            origin_tx_hash: None,
+        });
+
+        // The fee is minted separately, so it is kept even if the deposit call fails.
+        if let Some(fee_collector) = fee_collector {
+            let _ = self.append_mint(Promise::new(token_account_id.clone()), &fee_collector, fee);
+        }
+        // The tokens are minted to the factory before they are transferred, and the
+        // transfer fails unless the receiver is registered. The storage of the receiver
+        // is paid according to the storage payer.
+        let mut promise = Promise::new(token_account_id.clone()).function_call(
+            "storage_deposit".to_string(),
+            receiver_storage_deposit_args(&env::current_account_id()).into_bytes(),
+            self.gas_config.receiver_storage_deposit.0,
+            STORAGE_DEPOSIT_GAS,
+        );
+        if let Some(storage_deposit) = self.take_receiver_storage_deposit() {
+            promise = promise.function_call(
+                "storage_deposit".to_string(),
+                receiver_storage_deposit_args(&receiver_id).into_bytes(),
+                storage_deposit,
+                STORAGE_DEPOSIT_GAS,
+            );
+        }
+        promise
+            .function_call(
+                "deposit_call".to_string(),
+                args.into_bytes(),
+                0,
+                DEPOSIT_CALL_GAS,
+            )
+            .then(
+                Contract::ext(env::current_account_id())
+                    .with_static_gas(DEPOSIT_CALL_CALLBACK_GAS)
+                    .with_unused_gas_weight(0)
+                    .on_deposit_call_complete(token_account_id, refund_address, transferred.into()),
+            )
+            .into()
+    }
+
+    /// Callback invoked after the `deposit_call` of `on_deposit_call`. The unused amount
+    /// returned by the token, or the whole amount if the call failed, is removed from
+    /// the accounting and unlocked on Aurora to `refund_address`, and `DepositRefunded`
+    /// is emitted. Returns the refunded amount.
+    /// This is a callback function that can be only executed from the contract itself.
+    #[private]
+    pub fn on_deposit_call_complete(
+        &mut self,
+        token_account_id: AccountId,
+        refund_address: aurora_sdk::Address,
+        amount: U128,
+    ) -> U128 {
+        let refund = match env::promise_result(0) {
+            PromiseResult::Successful(output) => near_sdk::serde_json::from_slice::<U128>(&output)
+                .map_or(0, |unused| unused.0.min(amount.0)),
+            _ => amount.0,
+        };
+        if refund == 0 {
+            return U128(0);
+        }
+
+        let token_id = self.address_from_token_account_id(token_account_id.clone());
+        self.log_at(
+            logging::LogLevel::Info,
+            &format!("Refunding {} {} unused by a deposit call", refund, token_id),
+        );
+        self.record_deposit_failed(&token_account_id, refund, 1);
+        self.emit_event(events::ConnectorEvent::DepositRefunded {
+            token: token_id.to_string(),
+            refund_address: refund_address.to_string(),
+            amount: refund.into(),
+        });
+
+        let input = self.encode_unlock(&token_account_id, &token_id, &refund_address, refund);
+        let _ = aurora_sdk::aurora::ext_aurora::ext(self.engine_of(&token_account_id))
+            .with_static_gas(WITHDRAW_AURORA_CALL_GAS)
+            .call(self.unlock_call_args(token_id, input));
+        U128(refund)
+    }
+}
+
+/// JSON arguments of the `deposit_call` call of a token.
+fn deposit_call_args(receiver_id: &AccountId, amount: u128, msg: &str) -> String {
+    near_sdk::serde_json::json!({
+        "receiver_id": receiver_id,
+        "amount": U128(amount),
+        "msg": msg,
+    })
+    .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::aurora_sdk::Address;
+    use crate::test_utils;
+    use near_sdk::json_types::U128;
+    use near_sdk::PromiseResult;
+
+    #[test]
+    fn test_deposit_call() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        test_utils::deploy_token(&mut contract, &token);
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit_call(
+            token.clone(),
+            "dex.near".parse().unwrap(),
+            100,
+            "swap".to_string(),
+            Address([3; 20]),
+        );
+
+        let calls = test_utils::function_calls(&test_utils::token_account_id(&token));
+        let methods: Vec<&str> = calls.iter().map(|call| call.method.as_str()).collect();
+        assert_eq!(
+            methods,
+            ["storage_deposit", "storage_deposit", "deposit_call"]
+        );
+        let args: near_sdk::serde_json::Value =
+            near_sdk::serde_json::from_slice(&calls[2].args).unwrap();
+        assert_eq!(args["receiver_id"], "dex.near");
+        assert_eq!(args["amount"], "100");
+        assert_eq!(args["msg"], "swap");
+        assert_eq!(contract.mirrored_supply(token).0, 100);
+    }
+
+    #[test]
+    fn test_unused_deposit_is_refunded() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        test_utils::deploy_token(&mut contract, &token);
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit_call(
+            token.clone(),
+            "dex.near".parse().unwrap(),
+            100,
+            "swap".to_string(),
+            Address([3; 20]),
+        );
+
+        test_utils::set_callback_context(vec![PromiseResult::Successful(
+            near_sdk::serde_json::to_vec(&U128(40)).unwrap(),
+        )]);
+        let refund = contract.on_deposit_call_complete(
+            test_utils::token_account_id(&token),
+            Address([3; 20]),
+            U128(100),
+        );
+
+        assert_eq!(refund, U128(40));
+        assert_eq!(contract.mirrored_supply(token).0, 60);
+        assert_eq!(
+            test_utils::function_calls(&test_utils::aurora())[0].method,
+            "call"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_TOKEN_NOT_DEPLOYED")]
+    fn test_deposit_call_of_undeployed_token() {
+        let mut contract = test_utils::new_contract_with_binary();
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit_call(
+            Address([1; 20]),
+            "dex.near".parse().unwrap(),
+            100,
+            "swap".to_string(),
+            Address([3; 20]),
+        );
+    }
+}
@@ -0,0 +1,247 @@
+//! Detachment of tokens graduating to a natively managed NEP-141. The owner proposes
+//! the new controller of a token and the account of the token project approving it,
+//! e.g. its multisig, and the token is detached once that account approves it. Like
+//! `transfer_token_control`, the token is handed over to the controller, but it stays
+//! registered as detached: the factory refuses to mint or upgrade it, and the locker
+//! is told to lock the ERC-20 side permanently.
+use crate::{
+    abi, aurora_sdk, events, ext, logging, Contract, ContractExt, ERR_TOKEN_NOT_DEPLOYED,
+    SET_CONTROLLER_GAS,
+};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{env, near_bindgen, require, AccountId, Gas, Promise, PromiseResult};
+
+/// Selector of the locker `lockToken`, which refuses further deposits and withdrawals
+/// of the ERC-20.
+pub const LOCK_TOKEN_SELECTOR: [u8; 4] = abi::selector("lockToken(address)");
+/// Gas attached to the Aurora call locking the ERC-20.
+const LOCK_TOKEN_AURORA_CALL_GAS: Gas = Gas(10_000_000_000_000);
+/// Gas attached to `on_token_detached`, including the Aurora call it issues.
+const TOKEN_DETACHED_CALLBACK_GAS: Gas = Gas(5_000_000_000_000 + LOCK_TOKEN_AURORA_CALL_GAS.0);
+
+pub(crate) const ERR_TOKEN_DETACHED: &str =
+    "ERR_TOKEN_DETACHED: Token was detached from the factory.";
+const ERR_DETACH_NOT_PROPOSED: &str =
+    "ERR_DETACH_NOT_PROPOSED: Detachment of this token was not proposed.";
+const ERR_ONLY_DETACH_APPROVER: &str =
+    "ERR_ONLY_DETACH_APPROVER: Only the approver of the detachment can call this method.";
+
+/// Detachment of a token waiting for the approval of the token project.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TokenDetach {
+    /// Account taking over the control of the token.
+    pub controller: AccountId,
+    /// Account of the token project that must approve the detachment.
+    pub approver: AccountId,
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Propose to hand over the control of a deployed token to `controller`, once
+    /// `approver` calls `approve_token_detach`. A new proposal replaces the previous
+    /// one. ONLY the `Owner` role can call this method.
+    pub fn propose_token_detach(
+        &mut self,
+        token_address: aurora_sdk::Address,
+        controller: AccountId,
+        approver: AccountId,
+    ) {
+        self.assert_owner();
+        self.record_admin_action("propose_token_detach");
+
+        let token_account_id = self.account_id_from_token_address(token_address);
+        require!(
+            self.tokens.get(&token_account_id).is_some(),
+            ERR_TOKEN_NOT_DEPLOYED
+        );
+        self.assert_not_detached(&token_account_id);
+        self.token_detaches.insert(
+            &token_account_id,
+            &TokenDetach {
+                controller,
+                approver,
+            },
+        );
+    }
+
+    /// Detachment of the token waiting for approval, if any.
+    pub fn token_detach(&self, token_address: aurora_sdk::Address) -> Option<TokenDetach> {
+        self.token_detaches
+            .get(&self.account_id_from_token_address(token_address))
+    }
+
+    /// Approve the proposed detachment of the token. Deposits of the token are refused
+    /// right away, and the token is handed over to the controller. Once the handover
+    /// succeeds, the locker is told to lock the ERC-20, see `on_token_detached`. ONLY
+    /// the approver of the proposal can call this method.
+    pub fn approve_token_detach(&mut self, token_address: aurora_sdk::Address) -> Promise {
+        let token_account_id = self.account_id_from_token_address(token_address.clone());
+        let detach = self
+            .token_detaches
+            .get(&token_account_id)
+            .unwrap_or_else(|| env::panic_str(ERR_DETACH_NOT_PROPOSED));
+        require!(
+            env::predecessor_account_id() == detach.approver,
+            ERR_ONLY_DETACH_APPROVER
+        );
+
+        self.token_detaches.remove(&token_account_id);
+        self.detached_tokens.insert(&token_account_id);
+        ext::ext_near_token::ext(token_account_id.clone())
+            .with_static_gas(SET_CONTROLLER_GAS)
+            .set_controller(detach.controller.clone())
+            .then(
+                Contract::ext(env::current_account_id())
+                    .with_static_gas(TOKEN_DETACHED_CALLBACK_GAS)
+                    .with_unused_gas_weight(0)
+                    .on_token_detached(token_address, token_account_id, detach.controller),
+            )
+    }
+
+    /// Callback invoked after the token was handed over by `approve_token_detach`. If
+    /// the handover succeeded, `TokenDetached` is emitted and the locker is told to lock
+    /// the ERC-20. Otherwise the token is attached again, and the detachment must be
+    /// proposed again. Returns whether the token was detached.
+    /// This is a callback function that can be only executed from the contract itself.
+    #[private]
+    pub fn on_token_detached(
+        &mut self,
+        token_address: aurora_sdk::Address,
+        token_account_id: AccountId,
+        controller: AccountId,
+    ) -> bool {
+        if !matches!(env::promise_result(0), PromiseResult::Successful(_)) {
+            self.detached_tokens.remove(&token_account_id);
+            self.log_at(
+                logging::LogLevel::Errors,
+                &format!("Detachment of {} failed", token_account_id),
+            );
+            return false;
+        }
+
+        self.emit_event(events::ConnectorEvent::TokenDetached {
+            token: token_address.to_string(),
+            token_account_id: token_account_id.clone(),
+            controller,
+        });
+        let input = abi::encode_call(
+            LOCK_TOKEN_SELECTOR,
+            &[abi::AbiValue::Address(token_address)],
+        );
+        let _ = aurora_sdk::aurora::ext_aurora::ext(self.engine_of(&token_account_id))
+            .with_static_gas(LOCK_TOKEN_AURORA_CALL_GAS)
+            .call(aurora_sdk::aurora::call_args(
+                self.locker_of(&token_account_id),
+                input,
+            ));
+        true
+    }
+
+    /// Whether the token was detached, see `approve_token_detach`.
+    pub fn is_token_detached(&self, token_address: aurora_sdk::Address) -> bool {
+        self.detached_tokens
+            .contains(&self.account_id_from_token_address(token_address))
+    }
+}
+
+impl Contract {
+    pub(crate) fn assert_not_detached(&self, token_account_id: &AccountId) {
+        require!(
+            !self.detached_tokens.contains(token_account_id),
+            ERR_TOKEN_DETACHED
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::aurora_sdk::Address;
+    use crate::test_utils;
+    use near_sdk::{AccountId, PromiseResult};
+
+    fn controller() -> AccountId {
+        "controller.near".parse().unwrap()
+    }
+
+    fn multisig() -> AccountId {
+        "multisig.near".parse().unwrap()
+    }
+
+    /// Contract with `Address([1; 20])` deployed and its detachment approved.
+    fn contract_with_approved_detach() -> crate::Contract {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        test_utils::deploy_token(&mut contract, &token);
+        contract.propose_token_detach(token.clone(), controller(), multisig());
+
+        test_utils::set_context(multisig());
+        let _ = contract.approve_token_detach(token);
+        contract
+    }
+
+    #[test]
+    fn test_detach_token() {
+        let mut contract = contract_with_approved_detach();
+        let token = Address([1; 20]);
+        let calls = test_utils::function_calls(&test_utils::token_account_id(&token));
+        assert_eq!(calls[0].method, "set_controller");
+        let args: near_sdk::serde_json::Value =
+            near_sdk::serde_json::from_slice(&calls[0].args).unwrap();
+        assert_eq!(args["controller"], "controller.near");
+        assert!(contract.is_token_detached(token.clone()));
+        assert_eq!(contract.token_detach(token.clone()), None);
+
+        test_utils::set_callback_context(vec![PromiseResult::Successful(vec![])]);
+        assert!(contract.on_token_detached(
+            token.clone(),
+            test_utils::token_account_id(&token),
+            controller()
+        ));
+        let calls = test_utils::function_calls(&test_utils::aurora());
+        assert_eq!(calls[0].method, "call");
+    }
+
+    #[test]
+    fn test_failed_detach_attaches_token_again() {
+        let mut contract = contract_with_approved_detach();
+        let token = Address([1; 20]);
+
+        test_utils::set_callback_context(vec![PromiseResult::Failed]);
+        assert!(!contract.on_token_detached(
+            token.clone(),
+            test_utils::token_account_id(&token),
+            controller()
+        ));
+        assert!(!contract.is_token_detached(token));
+        assert!(test_utils::function_calls(&test_utils::aurora()).is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_TOKEN_DETACHED")]
+    fn test_deposit_of_detached_token() {
+        let mut contract = contract_with_approved_detach();
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(
+            Address([1; 20]),
+            "alice.near".parse().unwrap(),
+            10,
+            None,
+            None,
+            None,
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_ONLY_DETACH_APPROVER")]
+    fn test_detach_approved_by_other_account() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        test_utils::deploy_token(&mut contract, &token);
+        contract.propose_token_detach(token.clone(), controller(), multisig());
+
+        let _ = contract.approve_token_detach(token);
+    }
+}
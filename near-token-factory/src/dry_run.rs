@@ -0,0 +1,154 @@
+//! Dry-run mode for staging environments. Bridging methods validate their input and
+//! log the calls they would issue, without changing the state or calling other
+//! contracts.
+use crate::{events, Contract, ContractExt};
+use near_sdk::json_types::U64;
+use near_sdk::{near_bindgen, AccountId, Gas};
+
+#[near_bindgen]
+impl Contract {
+    /// Enable or disable the dry-run mode. While enabled, `on_deposit`, `on_withdraw`
+    /// and `create_token` emit a `PlannedCall` event for each call they would issue
+    /// instead of issuing it, and they return without a promise. The tokens burnt by a
+    /// withdraw are minted back to the sender. ONLY the `Owner` role can call this
+    /// method.
+    pub fn set_dry_run(&mut self, dry_run: bool) {
+        self.assert_owner();
+        self.record_admin_action("set_dry_run");
+
+        self.dry_run = dry_run;
+    }
+}
+
+impl Contract {
+    /// Emit the call that would be issued to `receiver_id` outside of the dry-run mode.
+    pub(crate) fn emit_planned_call(
+        &self,
+        receiver_id: &AccountId,
+        method: &str,
+        args: String,
+        gas: Gas,
+    ) {
+        self.emit_event(events::ConnectorEvent::PlannedCall {
+            receiver_id: receiver_id.clone(),
+            method: method.to_string(),
+            args,
+            gas: U64(gas.0),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::aurora_sdk::Address;
+    use crate::test_utils;
+    use near_sdk::test_utils::get_logs;
+    use near_sdk::PromiseOrValue;
+
+    fn planned_calls() -> Vec<(String, String)> {
+        get_logs()
+            .iter()
+            .filter_map(|log| log.strip_prefix("EVENT_JSON:"))
+            .map(|log| near_sdk::serde_json::from_str::<near_sdk::serde_json::Value>(log).unwrap())
+            .filter(|event| event["event"] == "planned_call")
+            .map(|event| {
+                (
+                    event["data"]["receiver_id"].as_str().unwrap().to_string(),
+                    event["data"]["method"].as_str().unwrap().to_string(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_dry_run_deposit() {
+        let mut contract = test_utils::new_contract_with_binary();
+        contract.set_dry_run(true);
+        assert!(contract.modes().dry_run);
+        let token = Address([1; 20]);
+        let token_account_id = test_utils::token_account_id(&token);
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let result = contract.on_deposit(
+            token.clone(),
+            "alice.near".parse().unwrap(),
+            10,
+            None,
+            None,
+            None,
+        );
+
+        assert!(matches!(result, PromiseOrValue::Value(())));
+        assert_eq!(
+            planned_calls(),
+            [
+                (token_account_id.to_string(), "new".to_string()),
+                (token_account_id.to_string(), "storage_deposit".to_string()),
+                (token_account_id.to_string(), "deposit".to_string()),
+            ]
+        );
+        assert!(test_utils::function_calls(&token_account_id).is_empty());
+        assert_eq!(contract.mirrored_supply(token).0, 0);
+        assert_eq!(contract.tokens_count(), 0);
+    }
+
+    #[test]
+    fn test_dry_run_create_token() {
+        let mut contract = test_utils::new_contract_with_binary();
+        contract.set_dry_run(true);
+        let token_account_id = test_utils::token_account_id(&Address([1; 20]));
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.create_token(Address([1; 20]), None);
+
+        assert_eq!(
+            planned_calls(),
+            [(token_account_id.to_string(), "new".to_string())]
+        );
+        assert!(test_utils::function_calls(&token_account_id).is_empty());
+    }
+
+    #[test]
+    fn test_dry_run_withdraw() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(
+            token.clone(),
+            "alice.near".parse().unwrap(),
+            10,
+            None,
+            None,
+            None,
+        );
+
+        test_utils::set_context(test_utils::factory());
+        contract.set_dry_run(true);
+        let token_account_id = test_utils::token_account_id(&token);
+        test_utils::set_context(token_account_id.clone());
+        let result = contract.on_withdraw(
+            Address([3; 20]),
+            5.into(),
+            Some("alice.near".parse().unwrap()),
+            None,
+            None,
+        );
+
+        assert!(matches!(result, PromiseOrValue::Value(outcome) if !outcome.success));
+        assert_eq!(
+            planned_calls(),
+            [(test_utils::aurora().to_string(), "call".to_string())]
+        );
+        assert!(test_utils::function_calls(&test_utils::aurora()).is_empty());
+        assert_eq!(contract.mirrored_supply(token).0, 10);
+
+        // The burnt tokens are minted back to the sender.
+        let calls = test_utils::function_calls(&token_account_id);
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].method, "deposit");
+        let args: near_sdk::serde_json::Value =
+            near_sdk::serde_json::from_slice(&calls[0].args).unwrap();
+        assert_eq!(args["receiver_id"], "alice.near");
+        assert_eq!(args["amount"], "5");
+    }
+}
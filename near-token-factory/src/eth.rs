@@ -0,0 +1,91 @@
+//! Bridging of ETH, the base token of Aurora. ETH has no ERC-20 contract, so it is
+//! identified by the reserved pseudo-address `ETH_ADDRESS`, which the locker passes to
+//! `on_deposit` for deposits of ETH. Its NEP-141 representative is deployed like the
+//! representative of any ERC-20, with the metadata of ETH. Unlocks of ETH call the
+//! locker `withdrawEth`, which transfers the base token instead of an ERC-20.
+use crate::{aurora_sdk, Contract};
+
+/// Pseudo-address of ETH.
+pub const ETH_ADDRESS: aurora_sdk::Address = aurora_sdk::Address([0xee; 20]);
+
+/// Metadata of the NEP-141 representative of ETH.
+pub(crate) fn eth_metadata() -> aurora_sdk::TokenMetadata {
+    aurora_sdk::TokenMetadata {
+        name: "Ether".to_string(),
+        symbol: "ETH".to_string(),
+        decimals: 18,
+    }
+}
+
+impl Contract {
+    /// Arguments of the Aurora call of an unlock of the token, built by `encode_unlock`.
+    /// Unlocks of ETH are sent to the locker, since ETH has no contract.
+    pub(crate) fn unlock_call_args(
+        &self,
+        token_id: aurora_sdk::Address,
+        input: Vec<u8>,
+    ) -> aurora_sdk::CallArgs {
+        let to = if token_id == ETH_ADDRESS {
+            self.locker.clone()
+        } else {
+            token_id
+        };
+        aurora_sdk::aurora::call_args(to, input)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ETH_ADDRESS;
+    use crate::aurora_sdk::{self, Address};
+    use crate::test_utils;
+    use near_sdk::borsh::BorshSerialize;
+
+    #[test]
+    fn test_deposit_of_eth_uses_eth_metadata() {
+        let mut contract = test_utils::new_contract_with_binary();
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(
+            ETH_ADDRESS,
+            "alice.near".parse().unwrap(),
+            10,
+            None,
+            None,
+            None,
+        );
+
+        let calls = test_utils::function_calls(&test_utils::token_account_id(&ETH_ADDRESS));
+        let new = calls.iter().find(|call| call.method == "new").unwrap();
+        let args: near_sdk::serde_json::Value =
+            near_sdk::serde_json::from_slice(&new.args).unwrap();
+        assert_eq!(args["metadata"]["symbol"], "ETH");
+    }
+
+    #[test]
+    fn test_withdraw_of_eth_unlocks_with_the_locker() {
+        let mut contract = test_utils::new_contract_with_binary();
+        test_utils::deploy_token(&mut contract, &ETH_ADDRESS);
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(
+            ETH_ADDRESS,
+            "alice.near".parse().unwrap(),
+            10,
+            None,
+            None,
+            None,
+        );
+
+        let token_account_id = test_utils::token_account_id(&ETH_ADDRESS);
+        test_utils::set_context(token_account_id.clone());
+        let _ = contract.on_withdraw(Address([3; 20]), 10.into(), None, None, None);
+
+        let input = contract.encode_unlock(&token_account_id, &ETH_ADDRESS, &Address([3; 20]), 10);
+        assert_eq!(&input[..4], &crate::WITHDRAW_ETH_SELECTOR);
+        let expected = aurora_sdk::aurora::call_args(test_utils::locker(), input);
+        assert_eq!(
+            test_utils::function_calls(&test_utils::aurora())[0].args,
+            expected.try_to_vec().unwrap()
+        );
+    }
+}
@@ -0,0 +1,364 @@
+//! Events emitted by the factory following the NEP-297 standard, or in a compact form.
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{Base58CryptoHash, U128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::serde_json::Value;
+use near_sdk::{env, AccountId};
+
+const STANDARD: &str = "nep141-connector";
+const VERSION: &str = "1.0.0";
+
+/// Format of the logs of the events.
+#[derive(
+    BorshSerialize, BorshDeserialize, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq,
+)]
+#[serde(crate = "near_sdk::serde")]
+pub enum EventFormat {
+    /// `EVENT_JSON:{...}` following NEP-297.
+    Json,
+    /// `EVENT:{event} {key}={value} ...` with the fields of the event data sorted by key.
+    /// It is shorter, but it doesn't follow NEP-297.
+    Compact,
+}
+
+impl Default for EventFormat {
+    fn default() -> Self {
+        Self::Json
+    }
+}
+
+/// Events emitted by the factory. Token addresses are serialized as hex strings. The
+/// fields of each variant are the event data.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(untagged)]
+pub enum ConnectorEvent {
+    /// Tokens locked on Aurora were minted to `receiver_id`. `origin_tx_hash` is the
+    /// Aurora transaction that locked them, if it is known.
+    Deposit {
+        token: String,
+        receiver_id: AccountId,
+        amount: U128,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        origin_tx_hash: Option<String>,
+    },
+    /// Tokens burnt on NEAR by `sender_id` are being unlocked on Aurora for
+    /// `receiver_id`. If the unlock fails, `WithdrawReverted` follows.
+    Withdraw {
+        token: String,
+        sender_id: AccountId,
+        receiver_id: String,
+        amount: U128,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        memo: Option<String>,
+    },
+    /// A token was deployed at `token_account_id` with the token binary
+    /// `token_binary_version`.
+    TokenDeployed {
+        token: String,
+        token_account_id: AccountId,
+        token_binary_version: u32,
+    },
+    /// The token at `token_account_id` was removed from the registered tokens after
+    /// its total supply was checked to be zero.
+    TokenDeregistered {
+        token: String,
+        token_account_id: AccountId,
+    },
+    /// The token at `token_account_id` was handed over to `controller`, and the factory
+    /// doesn't mint it anymore.
+    TokenDetached {
+        token: String,
+        token_account_id: AccountId,
+        controller: AccountId,
+    },
+    /// The Aurora unlock of a withdraw failed, and the burnt tokens were minted back
+    /// to the account that withdrew them. This is not a new deposit.
+    WithdrawReverted {
+        token: String,
+        receiver_id: AccountId,
+        amount: U128,
+    },
+    /// The mint of a deposit failed, so the deposit was removed from the accounting.
+    /// The tokens locked on Aurora are unlocked to `refund_address` if it is set.
+    MintFailed {
+        token: String,
+        receiver_id: AccountId,
+        amount: U128,
+        refund_address: Option<String>,
+    },
+    /// A withdraw of `amount` exceeded the mirrored supply of the token, so only
+    /// `unlocked` is unlocked on Aurora. The rest of the burnt tokens is lost.
+    WithdrawClamped {
+        token: String,
+        amount: U128,
+        unlocked: U128,
+    },
+    /// A token that was not deployed by the factory called `on_withdraw`, and no
+    /// tokens were unlocked on Aurora.
+    UnknownTokenWithdraw {
+        token_account_id: AccountId,
+        receiver_id: String,
+        amount: U128,
+    },
+    /// The deferred remainder `id` was cancelled, so it will never be minted. If
+    /// `refunded`, the remainder is being unlocked on Aurora.
+    DeferredCancelled {
+        id: U64,
+        token_account_id: AccountId,
+        receiver_id: AccountId,
+        amount: U128,
+        refunded: bool,
+    },
+    /// The unlock of a withdraw failed on Aurora, and it was queued with `id` to be
+    /// retried with `retry_withdraw`.
+    UnlockQueued {
+        id: U64,
+        token: String,
+        receiver_id: String,
+        amount: U128,
+    },
+    /// `amount` of a deposit call was not used by its receiver, so it was removed from
+    /// the accounting and unlocked on Aurora to `refund_address`.
+    DepositRefunded {
+        token: String,
+        refund_address: String,
+        amount: U128,
+    },
+    /// A deposit or withdrawal of `account_id` paid `fee`, which was minted to
+    /// `fee_collector`.
+    FeeCharged {
+        token: String,
+        account_id: AccountId,
+        fee: U128,
+        fee_collector: AccountId,
+    },
+    /// The token binary was replaced, so tokens SHOULD be upgraded to
+    /// `token_binary_version`.
+    TokenBinaryUpdated {
+        token_binary_version: u32,
+        hash: Base58CryptoHash,
+    },
+    /// The token at `token_account_id` was upgraded to `token_binary_version`.
+    TokenUpgraded {
+        token_account_id: AccountId,
+        token_binary_version: u32,
+    },
+    /// Bridging of `scope` was paused. `scope` is a token address, `all_tokens`,
+    /// `deploys`, `deposits`, `withdrawals` or `create_token`.
+    Paused { scope: String },
+    /// Bridging of `scope` was resumed, see `Paused`.
+    Unpaused { scope: String },
+    /// `role` (`minter`, `token_upgrader` or `pauser`) was granted to `account_id`.
+    RoleGranted { role: String, account_id: AccountId },
+    /// `role` was revoked from `account_id`, see `RoleGranted`.
+    RoleRevoked { role: String, account_id: AccountId },
+    /// The owner was changed by `set_owner`.
+    OwnerChanged {
+        old_owner: AccountId,
+        new_owner: AccountId,
+    },
+    /// The locker address was changed by `confirm_locker`.
+    LockerChanged {
+        old_locker: String,
+        new_locker: String,
+    },
+    /// The mirrored supply of the token doesn't equal its gross deposits minus its gross
+    /// withdrawals, which indicates a bug in the bookkeeping of the factory.
+    InvariantViolation {
+        token_account_id: AccountId,
+        mirrored_supply: U128,
+        deposited: U128,
+        withdrawn: U128,
+    },
+    /// Call that would be issued outside of the dry-run mode. `gas` is the static gas
+    /// attached to the call.
+    PlannedCall {
+        receiver_id: AccountId,
+        method: String,
+        args: String,
+        gas: U64,
+    },
+    /// `amount` of the NEP-141 at `token_account_id` was locked by `sender_id`, and its
+    /// wrapped ERC-20 is being minted to `receiver` on Aurora.
+    Nep141Locked {
+        token_account_id: AccountId,
+        sender_id: AccountId,
+        receiver: String,
+        amount: U128,
+    },
+    /// `amount` of the NEP-141 at `token_account_id` was released to `receiver_id` after
+    /// its wrapped ERC-20 was burnt on Aurora.
+    Nep141Released {
+        token_account_id: AccountId,
+        receiver_id: AccountId,
+        amount: U128,
+    },
+    /// Liveness signal emitted by `heartbeat`. `version` is the version of the factory.
+    Heartbeat {
+        block_height: U64,
+        version: String,
+        token_binary_version: u32,
+    },
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct EventLog<'a> {
+    standard: &'static str,
+    version: &'static str,
+    event: &'static str,
+    data: EventData<'a>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct EventData<'a> {
+    #[serde(flatten)]
+    event: &'a ConnectorEvent,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    context: Option<&'a str>,
+}
+
+impl ConnectorEvent {
+    /// Name of the event in snake case.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ConnectorEvent::Deposit { .. } => "deposit",
+            ConnectorEvent::Withdraw { .. } => "withdraw",
+            ConnectorEvent::TokenDeployed { .. } => "token_deployed",
+            ConnectorEvent::TokenDeregistered { .. } => "token_deregistered",
+            ConnectorEvent::TokenDetached { .. } => "token_detached",
+            ConnectorEvent::WithdrawReverted { .. } => "withdraw_reverted",
+            ConnectorEvent::MintFailed { .. } => "mint_failed",
+            ConnectorEvent::WithdrawClamped { .. } => "withdraw_clamped",
+            ConnectorEvent::UnknownTokenWithdraw { .. } => "unknown_token_withdraw",
+            ConnectorEvent::DeferredCancelled { .. } => "deferred_cancelled",
+            ConnectorEvent::UnlockQueued { .. } => "unlock_queued",
+            ConnectorEvent::DepositRefunded { .. } => "deposit_refunded",
+            ConnectorEvent::FeeCharged { .. } => "fee_charged",
+            ConnectorEvent::TokenBinaryUpdated { .. } => "token_binary_updated",
+            ConnectorEvent::TokenUpgraded { .. } => "token_upgraded",
+            ConnectorEvent::Paused { .. } => "paused",
+            ConnectorEvent::Unpaused { .. } => "unpaused",
+            ConnectorEvent::RoleGranted { .. } => "role_granted",
+            ConnectorEvent::RoleRevoked { .. } => "role_revoked",
+            ConnectorEvent::OwnerChanged { .. } => "owner_changed",
+            ConnectorEvent::LockerChanged { .. } => "locker_changed",
+            ConnectorEvent::InvariantViolation { .. } => "invariant_violation",
+            ConnectorEvent::PlannedCall { .. } => "planned_call",
+            ConnectorEvent::Nep141Locked { .. } => "nep141_locked",
+            ConnectorEvent::Nep141Released { .. } => "nep141_released",
+            ConnectorEvent::Heartbeat { .. } => "heartbeat",
+        }
+    }
+
+    /// Log the event in `format`. If `context` is set, it is included in the event data
+    /// as `context`.
+    pub fn emit(&self, context: Option<&str>, format: EventFormat) {
+        let data = EventData {
+            event: self,
+            context,
+        };
+        let log = match format {
+            EventFormat::Json => format!(
+                "EVENT_JSON:{}",
+                near_sdk::serde_json::to_string(&EventLog {
+                    standard: STANDARD,
+                    version: VERSION,
+                    event: self.name(),
+                    data,
+                })
+                .unwrap()
+            ),
+            EventFormat::Compact => {
+                let mut log = format!("EVENT:{}", self.name());
+                if let Value::Object(fields) = near_sdk::serde_json::to_value(&data).unwrap() {
+                    for (key, value) in fields {
+                        let value = match value {
+                            Value::String(value) => value,
+                            value => value.to_string(),
+                        };
+                        log.push_str(&format!(" {}={}", key, value));
+                    }
+                }
+                log
+            }
+        };
+        env::log_str(&log);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ConnectorEvent, EventFormat};
+    use crate::test_utils;
+
+    #[test]
+    fn test_emit() {
+        test_utils::set_context(test_utils::factory());
+
+        ConnectorEvent::WithdrawReverted {
+            token: "0x0101010101010101010101010101010101010101".to_string(),
+            receiver_id: "alice.near".parse().unwrap(),
+            amount: 10.into(),
+        }
+        .emit(None, EventFormat::Json);
+
+        assert_eq!(
+            near_sdk::test_utils::get_logs(),
+            vec![concat!(
+                r#"EVENT_JSON:{"standard":"nep141-connector","version":"1.0.0","#,
+                r#""event":"withdraw_reverted","data":{"#,
+                r#""token":"0x0101010101010101010101010101010101010101","#,
+                r#""receiver_id":"alice.near","amount":"10"}}"#
+            )]
+        );
+    }
+
+    #[test]
+    fn test_emit_with_context() {
+        test_utils::set_context(test_utils::factory());
+
+        ConnectorEvent::WithdrawReverted {
+            token: "0x0101010101010101010101010101010101010101".to_string(),
+            receiver_id: "alice.near".parse().unwrap(),
+            amount: 10.into(),
+        }
+        .emit(Some("mainnet-1"), EventFormat::Json);
+
+        let logs = near_sdk::test_utils::get_logs();
+        let event: near_sdk::serde_json::Value =
+            near_sdk::serde_json::from_str(logs[0].strip_prefix("EVENT_JSON:").unwrap()).unwrap();
+        assert_eq!(event["data"]["context"], "mainnet-1");
+        assert_eq!(event["data"]["amount"], "10");
+    }
+
+    #[test]
+    fn test_emit_in_both_formats() {
+        let event = ConnectorEvent::Deposit {
+            token: "0x0101010101010101010101010101010101010101".to_string(),
+            receiver_id: "alice.near".parse().unwrap(),
+            amount: 10.into(),
+            origin_tx_hash: None,
+        };
+        test_utils::set_context(test_utils::factory());
+
+        event.emit(Some("mainnet-1"), EventFormat::Json);
+        event.emit(Some("mainnet-1"), EventFormat::Compact);
+
+        let logs = near_sdk::test_utils::get_logs();
+        let json: near_sdk::serde_json::Value =
+            near_sdk::serde_json::from_str(logs[0].strip_prefix("EVENT_JSON:").unwrap()).unwrap();
+        assert_eq!(json["event"], "deposit");
+        assert_eq!(json["data"]["amount"], "10");
+        assert_eq!(
+            logs[1],
+            concat!(
+                "EVENT:deposit amount=10 context=mainnet-1 receiver_id=alice.near ",
+                "token=0x0101010101010101010101010101010101010101"
+            )
+        );
+        assert!(logs[1].len() < logs[0].len());
+    }
+}
@@ -4,6 +4,14 @@ use near_sdk::ext_contract;
 pub trait ExtNearToken {
     fn upgrade_contract(&mut self, binary: near_sdk::json_types::Base64VecU8);
 
+    fn set_transfers_enabled(&mut self, enabled: bool);
+
+    fn set_controller(&mut self, controller: near_sdk::AccountId);
+
+    fn update_metadata(&mut self, metadata: near_token_common::TokenMetadata);
+
+    fn sweep_near(&mut self, receiver_id: near_sdk::AccountId) -> near_sdk::json_types::U128;
+
     fn deposit(
         &mut self,
         receiver_id: near_sdk::AccountId,
@@ -11,3 +19,35 @@ pub trait ExtNearToken {
         memo: Option<String>,
     );
 }
+
+#[ext_contract(ext_fungible_token)]
+pub trait ExtFungibleToken {
+    fn ft_transfer(
+        &mut self,
+        receiver_id: near_sdk::AccountId,
+        amount: near_sdk::json_types::U128,
+        memo: Option<String>,
+    );
+
+    fn ft_balance_of(&self, account_id: near_sdk::AccountId) -> near_sdk::json_types::U128;
+
+    fn ft_total_supply(&self) -> near_sdk::json_types::U128;
+}
+
+#[ext_contract(ext_creation_notifier)]
+pub trait ExtCreationNotifier {
+    fn on_token_created(
+        &mut self,
+        token_address: near_token_common::Address,
+        account_id: near_sdk::AccountId,
+    );
+}
+
+#[ext_contract(ext_token_deployer)]
+pub trait ExtTokenDeployer {
+    fn deploy_token(
+        &mut self,
+        #[serializer(borsh)] label: String,
+        #[serializer(borsh)] binary: Vec<u8>,
+    );
+}
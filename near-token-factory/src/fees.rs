@@ -0,0 +1,320 @@
+//! Bridging fees. Deposits and withdrawals pay a fee in basis points of their amount,
+//! at least the minimum fee, which is minted to the fee collector. Tokens can have
+//! their own fees, otherwise the default fees apply. No fee is charged while there is
+//! no fee collector. Fees are changed through the timelock, see `propose_default_fees`
+//! and `propose_token_fees`.
+use crate::{aurora_sdk, events, Contract, ContractExt};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::U128;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{near_bindgen, require, AccountId, Balance};
+
+/// Basis points of the whole amount.
+const MAX_FEE_BPS: u16 = 10_000;
+
+const ERR_INVALID_FEE: &str = "ERR_INVALID_FEE: Fees must be at most 10000 basis points.";
+
+/// Fees of deposits and withdrawals.
+#[derive(
+    BorshSerialize, BorshDeserialize, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq,
+)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FeeConfig {
+    /// Fee of deposits, in basis points of the deposited amount.
+    pub deposit_bps: u16,
+    /// Fee of withdrawals, in basis points of the withdrawn amount.
+    pub withdraw_bps: u16,
+    /// Minimum fee of a deposit or withdrawal, capped to its amount.
+    pub min_fee: U128,
+}
+
+impl Default for FeeConfig {
+    fn default() -> Self {
+        Self {
+            deposit_bps: 0,
+            withdraw_bps: 0,
+            min_fee: U128(0),
+        }
+    }
+}
+
+impl FeeConfig {
+    fn fee(&self, bps: u16, amount: Balance) -> Balance {
+        // Split the amount so the product can't overflow.
+        let bps = bps as Balance;
+        let max = MAX_FEE_BPS as Balance;
+        let fee = amount / max * bps + amount % max * bps / max;
+        fee.max(self.min_fee.0).min(amount)
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Set the account receiving the fees. Fees are not charged while it is `None`.
+    /// ONLY the `Owner` role can call this method.
+    pub fn set_fee_collector(&mut self, fee_collector: Option<AccountId>) {
+        self.assert_owner();
+        self.record_admin_action("set_fee_collector");
+
+        self.fee_collector = fee_collector;
+    }
+
+    /// Fees of tokens without their own fees.
+    pub fn default_fees(&self) -> FeeConfig {
+        self.default_fees
+    }
+
+    /// Fees applied to the token, either its own fees or the default fees.
+    pub fn token_fees(&self, token_address: aurora_sdk::Address) -> FeeConfig {
+        self.fees_of(&self.account_id_from_token_address(token_address))
+    }
+
+    /// Account receiving the fees, if any.
+    pub fn fee_collector(&self) -> Option<AccountId> {
+        self.fee_collector.clone()
+    }
+
+    /// Fee charged for a deposit of `amount` of the token.
+    pub fn quote_deposit_fee(&self, token_address: aurora_sdk::Address, amount: U128) -> U128 {
+        self.deposit_fee(&self.account_id_from_token_address(token_address), amount.0)
+            .into()
+    }
+
+    /// Fee charged for a withdrawal of `amount` of the token.
+    pub fn quote_withdraw_fee(&self, token_address: aurora_sdk::Address, amount: U128) -> U128 {
+        self.withdraw_fee(&self.account_id_from_token_address(token_address), amount.0)
+            .into()
+    }
+}
+
+impl Contract {
+    /// Fee charged for a deposit of `amount`, zero if there is no fee collector.
+    pub(crate) fn deposit_fee(&self, token_account_id: &AccountId, amount: Balance) -> Balance {
+        if self.fee_collector.is_none() {
+            return 0;
+        }
+        let fees = self.fees_of(token_account_id);
+        fees.fee(fees.deposit_bps, amount)
+    }
+
+    /// Fee charged for a withdrawal of `amount`, zero if there is no fee collector.
+    pub(crate) fn withdraw_fee(&self, token_account_id: &AccountId, amount: Balance) -> Balance {
+        if self.fee_collector.is_none() {
+            return 0;
+        }
+        let fees = self.fees_of(token_account_id);
+        fees.fee(fees.withdraw_bps, amount)
+    }
+
+    /// Record `fee` as minted to the fee collector and emit `FeeCharged`, unless the fee
+    /// is zero. Returns the fee collector, which the caller mints `fee` to.
+    pub(crate) fn charge_fee(
+        &mut self,
+        token_account_id: &AccountId,
+        account_id: &AccountId,
+        fee: Balance,
+    ) -> Option<AccountId> {
+        let fee_collector = self.fee_collector.clone().filter(|_| fee > 0)?;
+        self.record_deposit(token_account_id, &fee_collector, fee);
+        self.emit_event(events::ConnectorEvent::FeeCharged {
+            token: self
+                .address_from_token_account_id(token_account_id.clone())
+                .to_string(),
+            account_id: account_id.clone(),
+            fee: fee.into(),
+            fee_collector: fee_collector.clone(),
+        });
+        Some(fee_collector)
+    }
+
+    fn fees_of(&self, token_account_id: &AccountId) -> FeeConfig {
+        self.token_fees
+            .get(token_account_id)
+            .unwrap_or(self.default_fees)
+    }
+}
+
+pub(crate) fn assert_valid_fees(fees: &FeeConfig) {
+    require!(
+        fees.deposit_bps <= MAX_FEE_BPS && fees.withdraw_bps <= MAX_FEE_BPS,
+        ERR_INVALID_FEE
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FeeConfig;
+    use crate::aurora_sdk::Address;
+    use crate::test_utils;
+    use near_sdk::json_types::U128;
+    use near_sdk::AccountId;
+
+    fn collector() -> AccountId {
+        "fees.near".parse().unwrap()
+    }
+
+    fn deposit_receivers(token: &Address) -> Vec<(String, String)> {
+        test_utils::function_calls(&test_utils::token_account_id(token))
+            .into_iter()
+            .filter(|call| call.method == "deposit")
+            .map(|call| {
+                let args: near_sdk::serde_json::Value =
+                    near_sdk::serde_json::from_slice(&call.args).unwrap();
+                (
+                    args["receiver_id"].as_str().unwrap().to_string(),
+                    args["amount"].as_str().unwrap().to_string(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_quote_fees() {
+        let mut contract = test_utils::new_contract();
+        let token = Address([1; 20]);
+        test_utils::set_default_fees(
+            &mut contract,
+            FeeConfig {
+                deposit_bps: 30,
+                withdraw_bps: 10,
+                min_fee: U128(5),
+            },
+        );
+        // No fee is charged without a fee collector.
+        assert_eq!(
+            contract.quote_deposit_fee(token.clone(), U128(10_000)),
+            U128(0)
+        );
+
+        contract.set_fee_collector(Some(collector()));
+        assert_eq!(
+            contract.quote_deposit_fee(token.clone(), U128(10_000)),
+            U128(30)
+        );
+        assert_eq!(
+            contract.quote_withdraw_fee(token.clone(), U128(10_000)),
+            U128(10)
+        );
+        // The minimum fee applies, capped to the amount.
+        assert_eq!(
+            contract.quote_deposit_fee(token.clone(), U128(100)),
+            U128(5)
+        );
+        assert_eq!(contract.quote_deposit_fee(token.clone(), U128(3)), U128(3));
+        // The product doesn't overflow.
+        assert_eq!(
+            contract.quote_deposit_fee(token.clone(), U128(u128::MAX)),
+            U128(u128::MAX / 10_000 * 30 + u128::MAX % 10_000 * 30 / 10_000)
+        );
+
+        test_utils::set_token_fees(
+            &mut contract,
+            &token,
+            Some(FeeConfig {
+                deposit_bps: 0,
+                withdraw_bps: 0,
+                min_fee: U128(0),
+            }),
+        );
+        assert_eq!(
+            contract.quote_deposit_fee(token.clone(), U128(10_000)),
+            U128(0)
+        );
+        test_utils::set_token_fees(&mut contract, &token, None);
+        assert_eq!(contract.token_fees(token), contract.default_fees());
+    }
+
+    #[test]
+    fn test_deposit_fee_is_minted_to_collector() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        contract.set_fee_collector(Some(collector()));
+        test_utils::set_default_fees(
+            &mut contract,
+            FeeConfig {
+                deposit_bps: 100,
+                withdraw_bps: 0,
+                min_fee: U128(0),
+            },
+        );
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(
+            token.clone(),
+            "alice.near".parse().unwrap(),
+            1_000,
+            None,
+            None,
+            None,
+        );
+
+        assert_eq!(
+            deposit_receivers(&token),
+            vec![
+                ("alice.near".to_string(), "990".to_string()),
+                ("fees.near".to_string(), "10".to_string()),
+            ]
+        );
+        assert_eq!(contract.mirrored_supply(token).0, 1_000);
+    }
+
+    #[test]
+    fn test_withdraw_fee_is_deducted_from_unlock() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(
+            token.clone(),
+            "alice.near".parse().unwrap(),
+            1_000,
+            None,
+            None,
+            None,
+        );
+        test_utils::set_context(test_utils::factory());
+        contract.set_fee_collector(Some(collector()));
+        test_utils::set_default_fees(
+            &mut contract,
+            FeeConfig {
+                deposit_bps: 0,
+                withdraw_bps: 100,
+                min_fee: U128(0),
+            },
+        );
+
+        test_utils::set_context(test_utils::token_account_id(&token));
+        let _ = contract.on_withdraw(
+            Address([3; 20]),
+            U128(1_000),
+            Some("alice.near".parse().unwrap()),
+            None,
+            None,
+        );
+
+        assert_eq!(
+            deposit_receivers(&token),
+            vec![("fees.near".to_string(), "10".to_string())]
+        );
+        let input = contract.encode_unlock(
+            &test_utils::token_account_id(&token),
+            &token,
+            &Address([3; 20]),
+            990,
+        );
+        let call = &test_utils::function_calls(&test_utils::aurora())[0];
+        assert!(call.args.ends_with(&input));
+        // The fee stays in the mirrored supply.
+        assert_eq!(contract.mirrored_supply(token).0, 10);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_INVALID_FEE")]
+    fn test_invalid_fee() {
+        let mut contract = test_utils::new_contract();
+        contract.propose_default_fees(FeeConfig {
+            deposit_bps: 10_001,
+            withdraw_bps: 0,
+            min_fee: U128(0),
+        });
+    }
+}
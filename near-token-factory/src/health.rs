@@ -0,0 +1,231 @@
+//! Tracking of tokens with operations that need the attention of operators, and
+//! aggregate counters for monitoring services.
+use crate::{aurora_sdk, Contract, ContractExt, ModeFlags};
+use near_sdk::json_types::U128;
+use near_sdk::serde::Serialize;
+use near_sdk::{near_bindgen, AccountId};
+use std::collections::HashSet;
+
+/// Aggregate counters of the factory.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct BridgeStatus {
+    /// Number of registered tokens.
+    pub tokens_count: u64,
+    /// Number of tokens ever deployed, including pruned tokens.
+    pub total_deploys: u64,
+    /// Number of deposits minted, failed mints excluded.
+    pub deposits_count: u64,
+    /// Number of withdrawals unlocked on Aurora, reverted withdrawals excluded.
+    pub withdrawals_count: u64,
+    /// Number of failed unlocks waiting in the retry queue.
+    pub queued_unlocks: u64,
+    /// Paused flows, see `pause`.
+    pub paused: u8,
+    /// Boolean mode settings, see `modes`.
+    pub modes: ModeFlags,
+    /// Version of the current token binary.
+    pub token_binary_version: u32,
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Tokens with a deploy that is pending or failed, and registered tokens with a
+    /// deferred deposit, a failed withdraw, or that are paused. Pagination is applied
+    /// to the registered tokens followed by the unregistered pending deploys before
+    /// filtering, so a page can hold less than `limit` tokens even if more tokens need
+    /// attention.
+    pub fn tokens_needing_attention(&self, from: u64, limit: u64) -> Vec<AccountId> {
+        let deferred: HashSet<AccountId> = self
+            .deferred_deposits
+            .values()
+            .map(|deposit| deposit.token_account_id)
+            .collect();
+
+        self.tokens
+            .keys()
+            .chain(
+                self.pending_deploys
+                    .iter()
+                    .filter(|token_account_id| self.tokens.get(token_account_id).is_none()),
+            )
+            .skip(from as usize)
+            .take(limit as usize)
+            .filter(|token_account_id| {
+                self.pending_deploys.contains(token_account_id)
+                    || deferred.contains(token_account_id)
+                    || self.failed_withdraws.get(token_account_id).unwrap_or(0) > 0
+                    || self.is_token_account_paused(token_account_id)
+            })
+            .collect()
+    }
+
+    /// Aggregate counters of the factory. The volume of each token is listed by
+    /// `bridge_volumes`.
+    pub fn get_bridge_status(&self) -> BridgeStatus {
+        BridgeStatus {
+            tokens_count: self.tokens_count(),
+            total_deploys: self.total_deploys,
+            deposits_count: self.deposits_count,
+            withdrawals_count: self.withdrawals_count,
+            queued_unlocks: self.queued_unlocks.len(),
+            paused: self.paused,
+            modes: self.modes(),
+            token_binary_version: self.token_binary_version,
+        }
+    }
+
+    /// Cumulative amounts deposited and withdrawn of the registered tokens, starting
+    /// at `from_index`, see `gross_flows`.
+    pub fn bridge_volumes(&self, from_index: u64, limit: u64) -> Vec<(AccountId, U128, U128)> {
+        self.tokens
+            .keys()
+            .skip(from_index as usize)
+            .take(limit as usize)
+            .map(|token_account_id| {
+                let flows = self.gross_flows.get(&token_account_id).unwrap_or_default();
+                (
+                    token_account_id,
+                    flows.deposited.into(),
+                    flows.withdrawn.into(),
+                )
+            })
+            .collect()
+    }
+
+    /// Number of withdraws of the token that failed to unlock on Aurora since the
+    /// count was last cleared.
+    pub fn failed_withdraw_count(&self, token_address: aurora_sdk::Address) -> u32 {
+        self.failed_withdraws
+            .get(&self.account_id_from_token_address(token_address))
+            .unwrap_or(0)
+    }
+
+    /// Clear the failed withdraws of the token after they were investigated. ONLY the
+    /// `Owner` role can call this method.
+    pub fn clear_failed_withdraws(&mut self, token_address: aurora_sdk::Address) {
+        self.assert_owner();
+        self.record_admin_action("clear_failed_withdraws");
+
+        self.failed_withdraws
+            .remove(&self.account_id_from_token_address(token_address));
+    }
+}
+
+impl Contract {
+    pub(crate) fn record_failed_withdraw(&mut self, token_account_id: &AccountId) {
+        let count = self.failed_withdraws.get(token_account_id).unwrap_or(0);
+        self.failed_withdraws
+            .insert(token_account_id, &count.saturating_add(1));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::aurora_sdk::Address;
+    use crate::test_utils;
+    use near_sdk::json_types::U128;
+    use near_sdk::PromiseResult;
+
+    #[test]
+    fn test_token_with_failed_withdraw_needs_attention() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let healthy = Address([1; 20]);
+        let failing = Address([2; 20]);
+
+        test_utils::set_context(test_utils::locker_account_id());
+        for token in [&healthy, &failing] {
+            let _ = contract.on_deposit(
+                token.clone(),
+                "alice.near".parse().unwrap(),
+                10,
+                None,
+                None,
+                None,
+            );
+        }
+        for token in [&healthy, &failing] {
+            test_utils::set_callback_context(vec![PromiseResult::Successful(Vec::new())]);
+            let _ = contract.on_token_deployed(
+                token.clone(),
+                test_utils::token_account_id(token),
+                None,
+                None,
+            );
+        }
+        assert!(contract.tokens_needing_attention(0, 10).is_empty());
+
+        test_utils::set_callback_context(vec![PromiseResult::Failed]);
+        let _ = contract.on_withdraw_complete(
+            test_utils::token_account_id(&failing),
+            "alice.near".parse().unwrap(),
+            5.into(),
+            None,
+        );
+
+        assert_eq!(
+            contract.tokens_needing_attention(0, 10),
+            [test_utils::token_account_id(&failing)]
+        );
+        assert_eq!(contract.failed_withdraw_count(failing.clone()), 1);
+
+        contract.clear_failed_withdraws(failing);
+        assert!(contract.tokens_needing_attention(0, 10).is_empty());
+    }
+
+    #[test]
+    fn test_bridge_status() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        let token_account_id = test_utils::token_account_id(&token);
+
+        test_utils::set_context(test_utils::locker_account_id());
+        for amount in [100, 50] {
+            let _ = contract.on_deposit(
+                token.clone(),
+                "alice.near".parse().unwrap(),
+                amount,
+                None,
+                None,
+                None,
+            );
+        }
+        test_utils::confirm_deploy(&mut contract, &token);
+        test_utils::set_context(token_account_id.clone());
+        let _ = contract.on_withdraw(Address([9; 20]), 30.into(), None, None, None);
+
+        let status = contract.get_bridge_status();
+        assert_eq!(status.tokens_count, 1);
+        assert_eq!(status.total_deploys, 1);
+        assert_eq!(status.deposits_count, 2);
+        assert_eq!(status.withdrawals_count, 1);
+        assert_eq!(status.queued_unlocks, 0);
+        assert_eq!(status.paused, 0);
+        assert_eq!(status.token_binary_version, 1);
+        assert_eq!(
+            contract.bridge_volumes(0, 10),
+            [(token_account_id, U128(150), U128(30))]
+        );
+    }
+
+    #[test]
+    fn test_pending_deploy_needs_attention() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(
+            token.clone(),
+            "alice.near".parse().unwrap(),
+            10,
+            None,
+            None,
+            None,
+        );
+
+        assert_eq!(
+            contract.tokens_needing_attention(0, 10),
+            [test_utils::token_account_id(&token)]
+        );
+    }
+}
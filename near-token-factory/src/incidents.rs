@@ -0,0 +1,107 @@
+//! Automatic pause of every token after repeated withdraw failures. Once triggered,
+//! the incident must be acknowledged before tokens can be unpaused.
+use crate::{Contract, ContractExt};
+use near_sdk::{near_bindgen, require};
+
+pub(crate) const ERR_INCIDENT_NOT_ACKNOWLEDGED: &str =
+    "ERR_INCIDENT_NOT_ACKNOWLEDGED: Acknowledge the incident before unpausing.";
+
+#[near_bindgen]
+impl Contract {
+    /// Pause every token once `threshold` consecutive withdraws failed to unlock on
+    /// Aurora. `None` disables the automatic pause. ONLY the `Owner` role can call
+    /// this method.
+    pub fn set_auto_pause_threshold(&mut self, threshold: Option<u32>) {
+        self.assert_owner();
+        self.record_admin_action("set_auto_pause_threshold");
+
+        self.auto_pause_threshold = threshold;
+    }
+
+    /// Number of consecutive withdraws that failed to unlock on Aurora.
+    pub fn failure_count(&self) -> u32 {
+        self.failure_count
+    }
+
+    /// Acknowledge the incident that triggered the automatic pause and clear the
+    /// failure counter, so `unpause_all_tokens` can be called. ONLY the `Owner` role
+    /// can call this method.
+    pub fn acknowledge_incident(&mut self) {
+        self.assert_owner();
+        self.record_admin_action("acknowledge_incident");
+
+        self.failure_count = 0;
+        self.incident_pending = false;
+    }
+}
+
+impl Contract {
+    /// Record the result of a withdraw unlock, pausing every token if the threshold
+    /// of consecutive failures is reached.
+    pub(crate) fn record_unlock_result(&mut self, unlocked: bool) {
+        if unlocked {
+            self.failure_count = 0;
+            return;
+        }
+
+        self.failure_count = self.failure_count.saturating_add(1);
+        if let Some(threshold) = self.auto_pause_threshold {
+            if self.failure_count >= threshold {
+                self.all_tokens_paused = true;
+                self.incident_pending = true;
+                self.emit_pause_changed("all_tokens".to_string(), true);
+            }
+        }
+    }
+
+    pub(crate) fn assert_no_pending_incident(&self) {
+        require!(!self.incident_pending, ERR_INCIDENT_NOT_ACKNOWLEDGED);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::aurora_sdk::Address;
+    use crate::test_utils;
+    use crate::Contract;
+    use near_sdk::PromiseResult;
+
+    fn fail_withdraw(contract: &mut Contract) {
+        test_utils::set_callback_context(vec![PromiseResult::Failed]);
+        let _ = contract.on_withdraw_complete(
+            test_utils::token_account_id(&Address([1; 20])),
+            "alice.near".parse().unwrap(),
+            10.into(),
+            None,
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_INCIDENT_NOT_ACKNOWLEDGED")]
+    fn test_unpause_requires_acknowledgement() {
+        let mut contract = test_utils::new_contract();
+        contract.set_auto_pause_threshold(Some(2));
+
+        fail_withdraw(&mut contract);
+        assert!(!contract.modes().all_tokens_paused);
+        fail_withdraw(&mut contract);
+        assert!(contract.modes().all_tokens_paused);
+
+        contract.unpause_all_tokens();
+    }
+
+    #[test]
+    fn test_unpause_after_acknowledgement() {
+        let mut contract = test_utils::new_contract();
+        contract.set_auto_pause_threshold(Some(1));
+        fail_withdraw(&mut contract);
+        assert!(contract.modes().incident_pending);
+
+        contract.acknowledge_incident();
+        contract.unpause_all_tokens();
+
+        assert_eq!(contract.failure_count(), 0);
+        assert!(!contract.modes().all_tokens_paused);
+        assert!(!contract.modes().incident_pending);
+    }
+}
@@ -0,0 +1,133 @@
+//! Machine readable description of the interface of the factory, for tooling that
+//! generates clients.
+use crate::abi::SELECTORS;
+use crate::access::OWNER_ACTIONS;
+use crate::roles::{PAUSER_ACTIONS, TOKEN_UPGRADER_ACTIONS};
+use crate::{Contract, ContractExt};
+use near_sdk::near_bindgen;
+use near_sdk::serde::Serialize;
+
+/// Accounts allowed to call a method.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(crate = "near_sdk::serde")]
+pub enum Access {
+    /// Any account.
+    Public,
+    /// The locker or a minter.
+    Depositor,
+    /// Only the locker.
+    Locker,
+    /// A token account of the factory.
+    Token,
+    /// The `Owner` role.
+    Owner,
+    /// The `Owner` or `TokenUpgrader` roles.
+    TokenUpgrader,
+    /// The `Owner` or `Pauser` roles.
+    Pauser,
+}
+
+/// A method of the factory and the accounts allowed to call it.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct MethodDescriptor {
+    pub name: String,
+    pub access: Access,
+}
+
+/// Function of the locker on Aurora called by the factory.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SelectorDescriptor {
+    /// Solidity signature of the function.
+    pub signature: String,
+    /// Hex encoded selector, with the `0x` prefix.
+    pub selector: String,
+}
+
+/// Methods of the factory that change its state, and the Aurora selectors it uses.
+/// Views and callbacks are not listed.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct InterfaceDescriptor {
+    pub methods: Vec<MethodDescriptor>,
+    pub selectors: Vec<SelectorDescriptor>,
+}
+
+/// Bridging methods, which are not owner actions.
+const BRIDGING_METHODS: &[(&str, Access)] = &[
+    ("on_deposit", Access::Depositor),
+    ("on_deposit_batch", Access::Depositor),
+    ("on_deposit_u256", Access::Depositor),
+    ("on_deposit_call", Access::Depositor),
+    ("on_deposit_entries", Access::Depositor),
+    ("create_token", Access::Locker),
+    ("on_withdraw", Access::Token),
+    ("heartbeat", Access::Public),
+    ("retry_withdraw", Access::Public),
+    ("fund_storage_pool", Access::Public),
+    ("ft_on_transfer", Access::Public),
+    ("on_release", Access::Locker),
+    ("prune_processed_transfers", Access::Public),
+    ("approve_token_detach", Access::Public),
+];
+
+#[near_bindgen]
+impl Contract {
+    /// Description of the interface of the factory. Owner and role methods are the ones
+    /// checked by `can_call`, so both stay in sync.
+    pub fn interface(&self) -> InterfaceDescriptor {
+        let methods = BRIDGING_METHODS
+            .iter()
+            .map(|(name, access)| (*name, *access))
+            .chain(OWNER_ACTIONS.iter().map(|name| (*name, Access::Owner)))
+            .chain(
+                TOKEN_UPGRADER_ACTIONS
+                    .iter()
+                    .map(|name| (*name, Access::TokenUpgrader)),
+            )
+            .chain(PAUSER_ACTIONS.iter().map(|name| (*name, Access::Pauser)))
+            .map(|(name, access)| MethodDescriptor {
+                name: name.to_string(),
+                access,
+            })
+            .collect();
+
+        InterfaceDescriptor {
+            methods,
+            selectors: SELECTORS
+                .iter()
+                .map(|(signature, selector)| SelectorDescriptor {
+                    signature: signature.to_string(),
+                    selector: format!("0x{}", hex::encode(selector)),
+                })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Access, MethodDescriptor};
+    use crate::test_utils;
+
+    #[test]
+    fn test_interface_lists_core_methods() {
+        let contract = test_utils::new_contract();
+        let interface = contract.interface();
+
+        for (name, access) in [
+            ("on_deposit", Access::Depositor),
+            ("on_withdraw", Access::Token),
+            ("create_token", Access::Locker),
+            ("set_token_binary", Access::TokenUpgrader),
+            ("unpause", Access::Owner),
+        ] {
+            assert!(interface.methods.contains(&MethodDescriptor {
+                name: name.to_string(),
+                access,
+            }));
+        }
+        assert_eq!(interface.selectors[0].selector, "0xd9caed12");
+    }
+}
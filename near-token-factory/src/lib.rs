@@ -1,26 +1,443 @@
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
-use near_sdk::collections::{LazyOption, UnorderedMap};
+use near_sdk::collections::{LazyOption, LookupMap, UnorderedMap, UnorderedSet, Vector};
+use near_sdk::json_types::{Base58CryptoHash, U128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
 use near_sdk::{
-    env, near_bindgen, require, AccountId, Balance, BorshStorageKey, Gas, PanicOnDefault, Promise,
+    env, near_bindgen, require, AccountId, Balance, BorshStorageKey, CryptoHash, Gas,
+    PanicOnDefault, Promise, PromiseOrValue, PromiseResult,
 };
 use near_token_common as aurora_sdk;
+mod abi;
+mod access;
+mod accounting;
+mod admin_log;
+mod binary_upload;
+mod deferred;
+mod deployer;
+mod deposit_call;
+mod detach;
+mod dry_run;
+mod eth;
+mod events;
 mod ext;
+mod fees;
+mod health;
+mod incidents;
+mod interface;
+mod logging;
+mod migration;
+mod minters;
+mod multi_deposit;
+mod naming;
+mod nep141_bridge;
+mod rate_limits;
+mod receiver_allowlist;
+mod recovery;
+mod roles;
+mod scaling;
+mod silos;
+mod storage_pool;
+#[cfg(test)]
+mod test_utils;
+mod timelock;
+mod token_allowlist;
+mod transfer_ids;
+mod unknown_tokens;
+mod unlock_queue;
+mod upgrades;
 
-const TOKEN_STORAGE_DEPOSIT_COST: Balance = 1_000_000_000_000_000_000;
+/// Bytes of storage used by a new token account besides its binary, i.e. the account
+/// itself and the initial state of the token.
+const TOKEN_ACCOUNT_STORAGE_OVERHEAD: u64 = 1_000;
+/// Amount transferred to a new token account on creation, unless its binary version
+/// has its own amount configured.
+const DEFAULT_CREATION_TRANSFER: Balance = 3_000_000_000_000_000_000_000_000;
+/// Spec version of tokens deployed with a binary version without its own spec version.
+const DEFAULT_SPEC_VERSION: u32 = 1;
+/// Default balance the factory must keep on top of the cost of a deployment.
+const DEFAULT_DEPLOY_BALANCE_MARGIN: Balance = 1_000_000_000_000_000_000_000_000;
+/// Default maximum decimals of the metadata of deployed tokens, the decimals of NEAR.
+const DEFAULT_MAX_DECIMALS: u8 = 24;
+/// Default balance `withdraw_near` leaves on top of the balance locked for storage.
+const DEFAULT_RESERVE_BALANCE: Balance = 5_000_000_000_000_000_000_000_000;
+/// Default gas attached to the `new` call of a token.
 const TOKEN_DEPLOYMENT_COST: Gas = Gas(5_000_000_000_000);
+/// Default gas attached to the `deposit` call of a token.
 const DEPOSIT_COST: Gas = Gas(2_000_000_000_000);
+/// Gas for `on_withdraw_complete`, on top of the deposit gas used to mint the tokens
+/// back if the unlock fails.
+const WITHDRAW_CALLBACK_BASE_COST: Gas = Gas(5_000_000_000_000);
+/// Gas the tokens reserve for `on_withdraw_complete` in the gas they attach to
+/// `on_withdraw`. MUST match `GAS_FOR_WITHDRAW_CALLBACK` of the token.
+const TOKEN_WITHDRAW_CALLBACK_GAS: Gas = Gas(7_000_000_000_000);
+/// Minimum gas attached to the Aurora call that unlocks the tokens of a withdraw. The
+/// gas left after scheduling the callback is added to it.
+const WITHDRAW_AURORA_CALL_GAS: Gas = Gas(10_000_000_000_000);
+/// Minimum gas attached to the Aurora call of a withdraw with calldata, which also
+/// covers the call of the receiver.
+const WITHDRAW_AND_CALL_AURORA_CALL_GAS: Gas = Gas(30_000_000_000_000);
+/// Gas of `on_mint_complete`, including the Aurora call refunding a failed mint.
+const MINT_CALLBACK_GAS: Gas = Gas(5_000_000_000_000 + WITHDRAW_AURORA_CALL_GAS.0);
+const TOKEN_DEPLOYED_CALLBACK_COST: Gas = Gas(10_000_000_000_000);
+/// Gas for the callback of a deposit that deploys the token. It is separate from
+/// `TOKEN_DEPLOYED_CALLBACK_COST`, used when tokens are created without a deposit.
+const DEPOSIT_CALLBACK_GAS: Gas = Gas(10_000_000_000_000);
+/// Gas of `fail_token_deployment`, out of the gas of the callback of the deploy.
+const FAIL_TOKEN_DEPLOYMENT_GAS: Gas = Gas(2_000_000_000_000);
+/// Maximum gas attached to the promises scheduled by `on_deposit`. It leaves room
+/// under the transaction gas limit for the locker call and `on_deposit` itself.
+const MAX_DEPOSIT_GAS: Gas = Gas(200_000_000_000_000);
+const FT_TRANSFER_GAS: Gas = Gas(5_000_000_000_000);
+const SET_TRANSFERS_ENABLED_GAS: Gas = Gas(5_000_000_000_000);
+const SET_CONTROLLER_GAS: Gas = Gas(10_000_000_000_000);
+const UPDATE_METADATA_GAS: Gas = Gas(5_000_000_000_000);
+const FT_TOTAL_SUPPLY_GAS: Gas = Gas(5_000_000_000_000);
+const DEREGISTER_CALLBACK_GAS: Gas = Gas(10_000_000_000_000);
+const NOTIFY_TOKEN_CREATED_COST: Gas = Gas(5_000_000_000_000);
+/// Version of the factory contract.
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+const STORAGE_DEPOSIT_COST: Balance = 1_250_000_000_000_000_000_000;
+/// Maximum deposit attached to the `storage_deposit` calls registering accounts on the
+/// tokens, so a misconfiguration can't drain the factory.
+const MAX_STORAGE_DEPOSIT: Balance = 100_000_000_000_000_000_000_000;
+/// Maximum default XCC fee, 1 NEAR.
+const MAX_XCC_FEE: Balance = 1_000_000_000_000_000_000_000_000;
+const STORAGE_DEPOSIT_GAS: Gas = Gas(5_000_000_000_000);
+/// Flag of `pause` for `on_deposit` and `on_deposit_batch`.
+const PAUSE_DEPOSITS: u8 = 1 << 0;
+/// Flag of `pause` for `on_withdraw`.
+const PAUSE_WITHDRAWALS: u8 = 1 << 1;
+/// Flag of `pause` for `create_token`.
+const PAUSE_CREATE_TOKEN: u8 = 1 << 2;
+/// Every flag accepted by `pause`.
+const PAUSE_ALL: u8 = PAUSE_DEPOSITS | PAUSE_WITHDRAWALS | PAUSE_CREATE_TOKEN;
+/// Oldest token binary version the method names and gas of the factory apply to.
+const MIN_SUPPORTED_TOKEN_VERSION: u32 = 1;
+/// Format of the NEAR representative of the locker produced by the Cross Contract Call
+/// interface. `{address}` is replaced by the hex address of the locker without the
+/// `0x` prefix, and `{aurora}` by the account id of the engine.
+const DEFAULT_LOCKER_ACCOUNT_FORMAT: &str = "{address}.{aurora}";
+/// Default maximum number of storage registrations in a single batched transaction.
+const DEFAULT_MAX_REGISTRATIONS_PER_CALL: u32 = 20;
+/// Default maximum number of deposits minted by a call to `on_deposit_batch`.
+const DEFAULT_MAX_DEPOSITS_PER_BATCH: u32 = 20;
 
 const ERR_ONLY_LOCKER: &str = "ERR_ONLY_LOCKER: Only locker can call this method.";
-const ERR_INVALID_ACCOUNT: &str =
+const ERR_ONLY_OWNER: &str = "ERR_ONLY_OWNER: Only owner can call this method.";
+pub(crate) const ERR_INVALID_ACCOUNT: &str =
     "ERR_INVALID_ACCOUNT: Account ID too large. Impossible to create token subcontracts.";
+const ERR_INVALID_LOCKER_FORMAT: &str =
+    "ERR_INVALID_LOCKER_FORMAT: Locker account format doesn't produce a valid account id.";
+const ERR_ZERO_LOCKER: &str = "ERR_ZERO_LOCKER: Locker address can't be the zero address.";
 const ERR_BINARY_NOT_AVAILABLE: &str = "ERR_BINARY_NOT_AVAILABLE: Token binary is not set.";
+const ERR_TOKEN_BINARY_ALREADY_SET: &str =
+    "ERR_TOKEN_BINARY_ALREADY_SET: Token binary can only be replaced through the timelock.";
+pub(crate) const ERR_INVALID_TOKEN_ACCOUNT: &str =
+    "ERR_INVALID_TOKEN_ACCOUNT: Account ID is not a token deployed by the factory.";
+const ERR_NOT_A_TOKEN: &str = "ERR_NOT_A_TOKEN: Caller is not a token account of the factory.";
+const ERR_RESERVE_BREACHED: &str =
+    "ERR_RESERVE_BREACHED: Amount exceeds the balance available above the reserve.";
+const ERR_ZERO_GAS: &str = "ERR_ZERO_GAS: Gas must be greater than zero.";
+const ERR_INVALID_STORAGE_DEPOSIT: &str =
+    "ERR_INVALID_STORAGE_DEPOSIT: Storage deposit must be positive and at most 0.1 NEAR.";
+const ERR_INVALID_XCC_FEE: &str = "ERR_INVALID_XCC_FEE: XCC fee can't exceed 1 NEAR.";
+const ERR_DEPOSIT_GAS_TOO_HIGH: &str =
+    "ERR_DEPOSIT_GAS_TOO_HIGH: Gas required by deposits exceeds the budget.";
+const ERR_WITHDRAW_GAS_TOO_HIGH: &str =
+    "ERR_WITHDRAW_GAS_TOO_HIGH: Gas of the withdraw callback exceeds the gas the tokens reserve for it.";
+const ERR_INSUFFICIENT_FACTORY_BALANCE: &str =
+    "ERR_INSUFFICIENT_FACTORY_BALANCE: Factory balance is too low to deploy a token.";
+const ERR_ZERO_TOKEN: &str = "ERR_ZERO_TOKEN: Token address can't be the zero address.";
+const ERR_DECIMALS_OUT_OF_RANGE: &str =
+    "ERR_DECIMALS_OUT_OF_RANGE: Token decimals exceed the maximum decimals.";
+const ERR_DEPLOY_IN_PROGRESS: &str = "ERR_DEPLOY_IN_PROGRESS: Deployment of this token is pending.";
+const ERR_TOKEN_ALREADY_DEPLOYED: &str = "ERR_TOKEN_ALREADY_DEPLOYED: Token is already deployed.";
+const ERR_INVALID_BATCH_SIZE: &str =
+    "ERR_INVALID_BATCH_SIZE: Batch must contain between one and the maximum number of deposits.";
+const ERR_INVALID_BATCH_LIMITS: &str =
+    "ERR_INVALID_BATCH_LIMITS: Batch limits must be greater than zero.";
+const ERR_TOO_MANY_ACCOUNTS: &str = "ERR_TOO_MANY_ACCOUNTS: Too many accounts for a single call.";
+pub(crate) const ERR_TOKEN_PAUSED: &str = "ERR_TOKEN_PAUSED: Bridging of this token is paused.";
+const ERR_PAUSED: &str = "ERR_PAUSED: This method is paused.";
+const ERR_INVALID_PAUSE_FLAGS: &str = "ERR_INVALID_PAUSE_FLAGS: Unknown pause flags.";
+const ERR_INSUFFICIENT_ATTACHED: &str =
+    "ERR_INSUFFICIENT_ATTACHED: Attached deposit doesn't cover the token deployment.";
+const ERR_DEPLOY_PAUSED: &str = "ERR_DEPLOY_PAUSED: Deployment of new tokens is paused.";
+const ERR_TOKEN_NOT_DEPLOYED: &str = "ERR_TOKEN_NOT_DEPLOYED: Token is not deployed.";
+const ERR_TOKEN_HAS_SUPPLY: &str =
+    "ERR_TOKEN_HAS_SUPPLY: Token can't be pruned while it has a mirrored supply.";
+const ERR_TOKEN_DEPLOYMENT_FAILED: &str = "ERR_TOKEN_DEPLOYMENT_FAILED: Token deployment failed.";
 
-pub const WITHDRAW_SELECTOR: [u8; 4] = [0xd9, 0xca, 0xed, 0x12];
+pub const WITHDRAW_SELECTOR: [u8; 4] = abi::selector("withdraw(address,address,uint256)");
+pub const WITHDRAW_AND_CALL_SELECTOR: [u8; 4] =
+    abi::selector("withdrawAndCall(address,address,uint256,bytes)");
+pub const WITHDRAW_ETH_SELECTOR: [u8; 4] = abi::selector("withdrawEth(address,uint256)");
 
 #[derive(BorshDeserialize, BorshSerialize, BorshStorageKey)]
 enum StorageKey {
     TokenBinary,
     TokenMap,
+    PausedTokens,
+    TokenSupply,
+    ReceiverAllowlists,
+    ReceiverAllowlist { token_account_id: AccountId },
+    SupplyCaps,
+    DeferredDeposits,
+    GrossFlows,
+    CreationTransfers,
+    Minters,
+    TokenHoldersMap,
+    TokenHolders { token_account_id: AccountId },
+    BinaryVersions,
+    PendingDeploys,
+    FailedWithdraws,
+    TokenAccountIds,
+    TokenAddresses,
+    VersionCounts,
+    TokenCreatedAt,
+    AdminLog,
+    TokenLastActivity,
+    SpecVersions,
+    TokenSpecVersions,
+    TokenOrigins,
+    TokenUpgraders,
+    Pausers,
+    TokenScaling,
+    TokenDust,
+    QueuedUnlocks,
+    BinaryUploadChunks,
+    TokenFees,
+    AllowedTokens,
+    RateLimits,
+    TokenVolumes,
+    Silos,
+    TokenSilos,
+    LockedNep141,
+    ProcessedTransfers,
+    TransferLog,
+    TransferIdFloors,
+    TokenDetaches,
+    DetachedTokens,
+    LazyUpgradeOptOuts,
+}
+
+/// Boolean mode settings of the factory. Every new toggle SHOULD be added here.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ModeFlags {
+    /// Bridging of every token is paused.
+    pub all_tokens_paused: bool,
+    /// Deployment of new tokens is paused. Deposits of deployed tokens and withdrawals
+    /// still work.
+    pub deploy_paused: bool,
+    /// Changes of the token binary and upgrades of the tokens are blocked.
+    pub upgrades_frozen: bool,
+    /// Every token was paused automatically and the incident was not acknowledged
+    /// yet.
+    pub incident_pending: bool,
+    /// `on_deposit` and `on_deposit_batch` are paused.
+    pub deposits_paused: bool,
+    /// `on_withdraw` is paused. Tokens burnt while paused are minted back.
+    pub withdrawals_paused: bool,
+    /// `create_token` is paused.
+    pub create_token_paused: bool,
+    /// Bridging methods only log the calls they would issue.
+    pub dry_run: bool,
+}
+
+/// Result of a withdraw, returned to the token that called `on_withdraw`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct WithdrawOutcome {
+    /// Whether the tokens were unlocked on Aurora. If not, they were minted back to
+    /// the account that withdrew them.
+    pub success: bool,
+    pub token: aurora_sdk::Address,
+    pub amount: U128,
+}
+
+/// Parameters of the unlock the locker performs for a withdraw, to match the events
+/// emitted on Aurora.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct UnlockExpectation {
+    pub token: aurora_sdk::Address,
+    pub receiver: aurora_sdk::Address,
+    pub amount: U128,
+}
+
+/// Amounts used by the next token deployment.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct DeployConfig {
+    /// Gas attached to the `new` call of the token.
+    pub gas: Gas,
+    /// Deposit attached to the `new` call of the token.
+    pub storage_deposit: U128,
+    /// Amount transferred to the token account on creation.
+    pub creation_transfer: U128,
+}
+
+impl DeployConfig {
+    /// Balance transferred by the factory for each deployment.
+    fn cost(&self) -> Balance {
+        self.creation_transfer.0 + self.storage_deposit.0
+    }
+}
+
+/// Gas attached to the calls to the tokens. It can be tuned after a change of the
+/// protocol gas schedule without redeploying the factory.
+#[derive(
+    BorshSerialize, BorshDeserialize, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq,
+)]
+#[serde(crate = "near_sdk::serde")]
+pub struct GasConfig {
+    /// Gas attached to the `new` call of the token.
+    pub deploy_gas: Gas,
+    /// Gas attached to the `deposit` call of the token.
+    pub deposit_gas: Gas,
+    /// Deposit attached to the `storage_deposit` call registering the receiver of a
+    /// deposit on the token.
+    pub receiver_storage_deposit: U128,
+}
+
+impl GasConfig {
+    /// Gas of the `storage_deposit` and `deposit` calls minting tokens for a receiver.
+    fn mint_gas(&self) -> Gas {
+        Gas(STORAGE_DEPOSIT_GAS.0 + self.deposit_gas.0)
+    }
+
+    /// Gas for `on_withdraw_complete`, including minting the tokens back if the unlock
+    /// fails.
+    fn withdraw_callback_gas(&self) -> Gas {
+        Gas(WITHDRAW_CALLBACK_BASE_COST.0 + self.deposit_gas.0)
+    }
+}
+
+impl Default for GasConfig {
+    fn default() -> Self {
+        Self {
+            deploy_gas: TOKEN_DEPLOYMENT_COST,
+            deposit_gas: DEPOSIT_COST,
+            receiver_storage_deposit: U128(STORAGE_DEPOSIT_COST),
+        }
+    }
+}
+
+/// Maximum sizes of the batched methods, so clients can split their batches
+/// accordingly.
+#[derive(
+    BorshSerialize, BorshDeserialize, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq,
+)]
+#[serde(crate = "near_sdk::serde")]
+pub struct BatchLimits {
+    /// Maximum number of accounts registered by `register_receivers` and
+    /// `create_token_with_registrations`.
+    pub max_registrations: u32,
+    /// Maximum number of deposits minted by `on_deposit_batch`.
+    pub max_deposits: u32,
+}
+
+impl Default for BatchLimits {
+    fn default() -> Self {
+        Self {
+            max_registrations: DEFAULT_MAX_REGISTRATIONS_PER_CALL,
+            max_deposits: DEFAULT_MAX_DEPOSITS_PER_BATCH,
+        }
+    }
+}
+
+/// What happens when `create_token` is called for a token whose deploy is pending.
+#[derive(
+    BorshSerialize, BorshDeserialize, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq,
+)]
+#[serde(crate = "near_sdk::serde")]
+pub enum PendingDeployPolicy {
+    /// The token is deployed again. The second deploy fails if the first one
+    /// succeeded, but it retries a deploy that failed.
+    Redeploy,
+    /// The call panics with `ERR_DEPLOY_IN_PROGRESS`.
+    Reject,
+    /// The call does nothing.
+    Ignore,
+}
+
+impl Default for PendingDeployPolicy {
+    fn default() -> Self {
+        Self::Redeploy
+    }
+}
+
+/// Gas reserved by the callbacks of the bridging and deployment flows. Each amount is
+/// part of the gas attached by the main call of its flow.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CallbackGasConfig {
+    /// Gas of `on_token_deployed` after a deposit that deploys the token.
+    pub deposit: Gas,
+    /// Gas of `on_withdraw_complete`, including the deposit gas to mint the tokens
+    /// back if the unlock fails.
+    pub withdraw: Gas,
+    /// Gas of `on_token_deployed` after a token is created without a deposit.
+    pub deploy: Gas,
+    /// Gas of `on_token_upgraded`.
+    pub upgrade: Gas,
+    /// Gas of `on_mint_complete` after a deposit of a deployed token.
+    pub mint: Gas,
+}
+
+/// Provenance of a token binary version.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct BinaryInfo {
+    /// Block at which the binary was set.
+    pub uploaded_at: U64,
+    /// Account that set the binary.
+    pub uploader: AccountId,
+    /// Sha256 hash of the binary.
+    pub hash: Base58CryptoHash,
+}
+
+/// Provenance of the deploy of a token.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TokenOrigin {
+    /// Account whose call first deployed the token, e.g. the locker, the owner or a
+    /// minter.
+    pub deployer: AccountId,
+    /// Block at which the deploy was started.
+    pub deployed_at: U64,
+}
+
+/// Human readable description of the connector. It is only informational.
+#[derive(
+    BorshSerialize, BorshDeserialize, Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq,
+)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ConnectorMetadata {
+    pub name: String,
+    pub description: String,
+}
+
+/// Branch taken by `on_deposit` for a given token.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DepositPlan {
+    /// The token is not deployed yet, so it is deployed in the same batch as the mint.
+    DeployAndMint,
+    /// The token is already deployed, so tokens are only minted.
+    Mint,
+}
+
+impl DepositPlan {
+    /// Gas attached to the promises scheduled by `on_deposit` for this plan.
+    fn required_gas(self, config: &GasConfig) -> Gas {
+        match self {
+            DepositPlan::DeployAndMint => {
+                Gas(config.deploy_gas.0 + config.mint_gas().0 + DEPOSIT_CALLBACK_GAS.0)
+            }
+            DepositPlan::Mint => Gas(config.mint_gas().0 + MINT_CALLBACK_GAS.0),
+        }
+    }
 }
 
 #[near_bindgen]
@@ -32,44 +449,744 @@ pub struct Contract {
     token_binary: LazyOption<Vec<u8>>,
     /// Version of the token contract.
     token_binary_version: u32,
+    /// Sha256 hash of the WASM binary of the token contract.
+    token_binary_hash: Option<CryptoHash>,
+    /// Chunks of the token binary being uploaded with `append_binary_chunk`.
+    binary_upload_chunks: Vector<Vec<u8>>,
+    /// Size in bytes of the token binary being uploaded, or `None` if there is no
+    /// upload in progress.
+    binary_upload_size: Option<u64>,
     /// Iterable map of deployed contracts and their current version.
     tokens: UnorderedMap<AccountId, u32>,
     /// Address of the locker in aurora.
     locker: aurora_sdk::Address,
+    /// Account allowed to call the privileged methods of the factory.
+    owner: AccountId,
+    /// Format of the NEAR representative of the locker, see
+    /// `DEFAULT_LOCKER_ACCOUNT_FORMAT`.
+    locker_account_format: String,
+    /// EVM gas used by the Aurora call of the most recent withdraw.
+    last_withdraw_gas: Option<u64>,
+    /// Tokens whose bridging is paused individually.
+    paused_tokens: UnorderedSet<AccountId>,
+    /// If set, bridging of every token is paused, regardless of `paused_tokens`.
+    all_tokens_paused: bool,
+    /// Whether deployment of new tokens is paused.
+    deploy_paused: bool,
+    /// Changes of the token binary and upgrades of the tokens are blocked.
+    upgrades_frozen: bool,
+    /// Flows paused by `pause`, as a combination of `PAUSE_DEPOSITS` and
+    /// `PAUSE_WITHDRAWALS`.
+    paused: u8,
+    /// Whether bridging methods only log the calls they would issue.
+    dry_run: bool,
+    /// Verbosity of the plain text logs of the bridging methods.
+    log_level: logging::LogLevel,
+    /// Mirrored supply of each token, i.e. amount deposited minus amount withdrawn.
+    token_supply: LookupMap<AccountId, Balance>,
+    /// Tokens with the highest mirrored supply sorted in decreasing order.
+    top_tokens: Vec<(AccountId, Balance)>,
+    /// Contract notified with `on_token_created` every time a token is deployed.
+    creation_notifier: Option<AccountId>,
+    /// Aurora address the locked tokens of failed mints are unlocked to, if any.
+    deposit_refund_address: Option<aurora_sdk::Address>,
+    /// Fees of tokens without their own fees.
+    default_fees: fees::FeeConfig,
+    /// Fees of tokens that don't use the default fees.
+    token_fees: LookupMap<AccountId, fees::FeeConfig>,
+    /// Account receiving the fees. Fees are not charged while it is `None`.
+    fee_collector: Option<AccountId>,
+    /// Who pays the storage registering the receivers of deposits.
+    storage_payer: storage_pool::StoragePayer,
+    /// Balance funding the registration of receivers under the `Pool` payer.
+    storage_pool: Balance,
+    /// Whether only the tokens in `allowed_tokens` can be bridged.
+    token_allowlist_enabled: bool,
+    /// Tokens that can be bridged while the allowlist mode is enabled.
+    allowed_tokens: UnorderedSet<aurora_sdk::Address>,
+    /// Daily limits of the volume bridged, for tokens with limits.
+    rate_limits: LookupMap<AccountId, rate_limits::RateLimit>,
+    /// Volumes bridged over the last 24 hours.
+    token_volumes: LookupMap<AccountId, rate_limits::TokenVolumes>,
+    /// Silos served besides the default engine, by id.
+    silos: UnorderedMap<String, silos::Silo>,
+    /// Silo of each token routed to a silo.
+    token_silos: LookupMap<AccountId, String>,
+    /// Amount of each NEP-141 locked by the factory while bridged into Aurora.
+    locked_nep141: LookupMap<AccountId, Balance>,
+    /// Transfer ids of deposits already processed, by depositor.
+    processed_transfers: LookupMap<(AccountId, u64), ()>,
+    /// Log of the processed transfer ids in processing order, by position.
+    transfer_log: LookupMap<u64, transfer_ids::ProcessedTransfer>,
+    /// Position of the oldest entry of `transfer_log`.
+    transfer_log_head: u64,
+    /// Position of the next entry of `transfer_log`.
+    transfer_log_tail: u64,
+    /// Highest pruned transfer id of each depositor. Ids up to it are processed.
+    transfer_id_floors: LookupMap<AccountId, u64>,
+    /// Detachments of tokens waiting for the approval of their project.
+    token_detaches: LookupMap<AccountId, detach::TokenDetach>,
+    /// Tokens handed over to a standalone controller, which the factory doesn't mint.
+    detached_tokens: UnorderedSet<AccountId>,
+    /// NEAR attached by default to the Aurora calls of withdraws, see `set_xcc_fee`.
+    xcc_fee: Balance,
+    /// Whether deposits upgrade tokens running an older version first.
+    lazy_upgrades: bool,
+    /// Tokens excluded from lazy upgrades.
+    lazy_upgrade_opt_outs: UnorderedSet<AccountId>,
+    /// Account receiving the NEAR swept from the factory and the tokens.
+    treasury: Option<AccountId>,
+    /// Number of deposits minted, minus the failed mints.
+    deposits_count: u64,
+    /// Number of withdrawals unlocked on Aurora, minus the reverted withdrawals.
+    withdrawals_count: u64,
+    /// Accounts allowed to receive deposits, for tokens that restrict receivers.
+    receiver_allowlists: LookupMap<AccountId, UnorderedSet<AccountId>>,
+    /// Label of this connector deployment.
+    metadata: ConnectorMetadata,
+    /// Maximum mirrored supply of tokens with a cap.
+    supply_caps: LookupMap<AccountId, Balance>,
+    /// Policy applied to remainders of deposits clamped to the supply cap.
+    remainder_policy: deferred::RemainderPolicy,
+    /// Policy applied to deposits of paused tokens.
+    paused_deposit_policy: deferred::PausedDepositPolicy,
+    /// Aurora address receiving refunded remainders.
+    refund_recipient: Option<aurora_sdk::Address>,
+    /// Remainders of clamped deposits by id.
+    deferred_deposits: UnorderedMap<u64, deferred::DeferredDeposit>,
+    /// Id of the next deferred deposit.
+    next_deferred_id: u64,
+    /// Policy applied to withdraws whose unlock on Aurora failed.
+    failed_unlock_policy: unlock_queue::FailedUnlockPolicy,
+    /// Unlocks of failed withdraws waiting to be retried, by id.
+    queued_unlocks: UnorderedMap<u64, unlock_queue::QueuedUnlock>,
+    /// Id of the next queued unlock.
+    next_unlock_id: u64,
+    /// Cumulative amounts deposited and withdrawn for each token.
+    gross_flows: LookupMap<AccountId, accounting::GrossFlows>,
+    /// Amount transferred to new token accounts for each token binary version.
+    creation_transfers: LookupMap<u32, Balance>,
+    /// Spec version of the interface implemented by each token binary version.
+    spec_versions: LookupMap<u32, u32>,
+    /// Spec version of each registered token. Tokens registered before the first
+    /// migration have no entry.
+    token_spec_versions: LookupMap<AccountId, u32>,
+    /// Accounts other than the locker allowed to deposit.
+    minters: UnorderedSet<AccountId>,
+    /// Accounts with the `TokenUpgrader` role besides the owner.
+    token_upgraders: UnorderedSet<AccountId>,
+    /// Accounts with the `Pauser` role besides the owner.
+    pausers: UnorderedSet<AccountId>,
+    /// Distinct receivers of deposits for tokens with holder tracking enabled.
+    token_holders: LookupMap<AccountId, UnorderedSet<AccountId>>,
+    /// Number of registered tokens at each token binary version.
+    version_counts: LookupMap<u32, u64>,
+    /// Block at which each token was registered. Tokens registered before the first
+    /// migration have no entry.
+    token_created_at: LookupMap<AccountId, near_sdk::BlockHeight>,
+    /// Provenance of the first deploy of each token.
+    token_origins: LookupMap<AccountId, TokenOrigin>,
+    /// Decimal scaling of the tokens with more decimals than their NEP-141
+    /// representative.
+    token_scaling: LookupMap<AccountId, scaling::DecimalScaling>,
+    /// ERC-20 amount left locked on Aurora by the rounding of scaled deposits, as a
+    /// big-endian uint256.
+    token_dust: LookupMap<AccountId, aurora_sdk::RawU256>,
+    /// Block of the last deposit or withdraw of each token. Tokens without activity
+    /// since the first migration have no entry.
+    token_last_activity: LookupMap<AccountId, near_sdk::BlockHeight>,
+    /// Number of tokens ever registered. Unlike `tokens`, it doesn't shrink when a
+    /// token is pruned.
+    total_deploys: u64,
+    /// Provenance of each token binary version.
+    binary_versions: LookupMap<u32, BinaryInfo>,
+    /// Deployment identifier included in the data of every event.
+    event_context: Option<String>,
+    /// Format of the logs of every event.
+    event_format: events::EventFormat,
+    /// Tokens whose deployment is in flight or failed.
+    pending_deploys: UnorderedSet<AccountId>,
+    /// Number of withdraws of each token that failed to unlock on Aurora.
+    failed_withdraws: LookupMap<AccountId, u32>,
+    /// Balance the factory must keep on top of the cost of a deployment.
+    deploy_balance_margin: Balance,
+    /// Maximum decimals of the metadata of deployed tokens.
+    max_decimals: u8,
+    /// Gas attached to the calls to the tokens.
+    gas_config: GasConfig,
+    /// Maximum sizes of the batched methods.
+    batch_limits: BatchLimits,
+    /// Balance `withdraw_near` leaves on top of the balance locked for storage.
+    reserve_balance: Balance,
+    /// Minimum deposit attached to `on_deposit` when it deploys the token.
+    min_deploy_attached_deposit: Balance,
+    /// Scheme used to derive the account ids of the tokens.
+    naming_scheme: naming::NamingScheme,
+    /// Account id of each deployed token.
+    token_account_ids: LookupMap<aurora_sdk::Address, AccountId>,
+    /// Address of each deployed token. Required to resolve the address of a token under
+    /// `NamingScheme::HashedLabel`.
+    token_addresses: LookupMap<AccountId, aurora_sdk::Address>,
+    /// Number of consecutive withdraw failures that pauses every token, if any.
+    auto_pause_threshold: Option<u32>,
+    /// Number of consecutive withdraws that failed to unlock on Aurora.
+    failure_count: u32,
+    /// Whether every token was paused automatically and it was not acknowledged yet.
+    incident_pending: bool,
+    /// Number of blocks between proposing and confirming a change.
+    timelock_delay: near_sdk::BlockHeight,
+    /// Changes waiting for their timelock to expire. At most one per target.
+    pending_changes: Vec<timelock::PendingChangeRecord>,
+    /// Number of blocks the previous locker is still accepted after `confirm_locker`.
+    locker_grace_period: near_sdk::BlockHeight,
+    /// Previous locker and the last block at which it is accepted, after a rotation
+    /// with a grace period.
+    retired_locker: Option<(aurora_sdk::Address, near_sdk::BlockHeight)>,
+    /// How withdraws from unregistered tokens are handled.
+    unknown_token_policy: unknown_tokens::UnknownTokenPolicy,
+    /// Policy applied to withdraws exceeding the mirrored supply of their token.
+    excess_withdraw_policy: accounting::ExcessWithdrawPolicy,
+    /// Policy applied to `create_token` calls for tokens whose deploy is pending.
+    pending_deploy_policy: PendingDeployPolicy,
+    /// Latest privileged actions, indexed by their number modulo the log capacity.
+    admin_log: LookupMap<u64, admin_log::AdminLogEntry>,
+    /// Number of privileged actions ever recorded.
+    admin_log_len: u64,
+    /// Deployer sub-account created by `create_deployer`, if any.
+    deployer: Option<AccountId>,
+    /// Whether new tokens are deployed through the deployer.
+    deployer_enabled: bool,
 }
 
-// TODO: Add pausable
-// TODO: Add access control
 #[near_bindgen]
 impl Contract {
     /// Initializes the contract. The locker account id MUST be the NEAR
     /// representative of the Aurora address of the locker contract created
-    /// using the Cross Contract Call interface.
+    /// using the Cross Contract Call interface. The `Owner` role is given to `owner`,
+    /// or to the account deploying the factory if it is not set.
+    #[init]
+    pub fn new(aurora: AccountId, locker: aurora_sdk::Address, owner: Option<AccountId>) -> Self {
+        let mut contract = Self::new_with_naming_scheme(aurora, locker, naming::NamingScheme::Hex);
+        if let Some(owner) = owner {
+            contract.owner = owner;
+        }
+        contract
+    }
+
+    /// Initializes the contract like `new`, with the `Owner` role given to the account
+    /// deploying the factory and token account ids derived using `naming_scheme`.
+    /// `NamingScheme::HashedLabel` allows longer factory account ids.
+    #[init]
+    pub fn new_with_naming_scheme(
+        aurora: AccountId,
+        locker: aurora_sdk::Address,
+        naming_scheme: naming::NamingScheme,
+    ) -> Self {
+        Self::new_with_locker_format(
+            aurora,
+            locker,
+            naming_scheme,
+            DEFAULT_LOCKER_ACCOUNT_FORMAT.to_string(),
+        )
+    }
+
+    /// Initializes the contract like `new_with_naming_scheme`, with the NEAR
+    /// representative of the locker derived using `locker_account_format`. The format
+    /// MUST match the account ids produced by the Cross Contract Call interface, see
+    /// `DEFAULT_LOCKER_ACCOUNT_FORMAT` for the placeholders.
     #[init]
-    pub fn new(aurora: AccountId, locker: aurora_sdk::Address) -> Self {
+    pub fn new_with_locker_format(
+        aurora: AccountId,
+        locker: aurora_sdk::Address,
+        naming_scheme: naming::NamingScheme,
+        locker_account_format: String,
+    ) -> Self {
+        require!(!locker.is_zero(), ERR_ZERO_LOCKER);
+        require!(
+            locker_account_format.contains("{address}")
+                && format_locker_account_id(&locker_account_format, &locker, &aurora).is_some(),
+            ERR_INVALID_LOCKER_FORMAT
+        );
         require!(
-            env::current_account_id().as_str().len() + 1 + 40 <= 63,
+            env::current_account_id().as_str().len() + 1 + naming_scheme.label_len()
+                <= naming::MAX_ACCOUNT_ID_LEN,
             ERR_INVALID_ACCOUNT
         );
+        assert_deposit_gas_within_budget(&GasConfig::default());
+        assert_withdraw_gas_within_budget(&GasConfig::default());
+        abi::assert_selectors(abi::SELECTORS);
 
         Self {
             aurora,
             token_binary: LazyOption::new(StorageKey::TokenBinary, None),
             token_binary_version: 0,
+            token_binary_hash: None,
+            binary_upload_chunks: Vector::new(StorageKey::BinaryUploadChunks),
+            binary_upload_size: None,
             tokens: UnorderedMap::new(StorageKey::TokenMap),
             locker,
+            owner: env::predecessor_account_id(),
+            locker_account_format,
+            last_withdraw_gas: None,
+            paused_tokens: UnorderedSet::new(StorageKey::PausedTokens),
+            all_tokens_paused: false,
+            deploy_paused: false,
+            upgrades_frozen: false,
+            paused: 0,
+            dry_run: false,
+            log_level: Default::default(),
+            token_supply: LookupMap::new(StorageKey::TokenSupply),
+            top_tokens: Vec::new(),
+            creation_notifier: None,
+            deposit_refund_address: None,
+            default_fees: Default::default(),
+            token_fees: LookupMap::new(StorageKey::TokenFees),
+            fee_collector: None,
+            storage_payer: Default::default(),
+            storage_pool: 0,
+            token_allowlist_enabled: false,
+            allowed_tokens: UnorderedSet::new(StorageKey::AllowedTokens),
+            rate_limits: LookupMap::new(StorageKey::RateLimits),
+            token_volumes: LookupMap::new(StorageKey::TokenVolumes),
+            silos: UnorderedMap::new(StorageKey::Silos),
+            token_silos: LookupMap::new(StorageKey::TokenSilos),
+            locked_nep141: LookupMap::new(StorageKey::LockedNep141),
+            processed_transfers: LookupMap::new(StorageKey::ProcessedTransfers),
+            transfer_log: LookupMap::new(StorageKey::TransferLog),
+            transfer_log_head: 0,
+            transfer_log_tail: 0,
+            transfer_id_floors: LookupMap::new(StorageKey::TransferIdFloors),
+            token_detaches: LookupMap::new(StorageKey::TokenDetaches),
+            detached_tokens: UnorderedSet::new(StorageKey::DetachedTokens),
+            xcc_fee: 0,
+            lazy_upgrades: false,
+            lazy_upgrade_opt_outs: UnorderedSet::new(StorageKey::LazyUpgradeOptOuts),
+            treasury: None,
+            deposits_count: 0,
+            withdrawals_count: 0,
+            receiver_allowlists: LookupMap::new(StorageKey::ReceiverAllowlists),
+            metadata: ConnectorMetadata::default(),
+            supply_caps: LookupMap::new(StorageKey::SupplyCaps),
+            remainder_policy: Default::default(),
+            paused_deposit_policy: Default::default(),
+            refund_recipient: None,
+            deferred_deposits: UnorderedMap::new(StorageKey::DeferredDeposits),
+            next_deferred_id: 0,
+            failed_unlock_policy: Default::default(),
+            queued_unlocks: UnorderedMap::new(StorageKey::QueuedUnlocks),
+            next_unlock_id: 0,
+            gross_flows: LookupMap::new(StorageKey::GrossFlows),
+            creation_transfers: LookupMap::new(StorageKey::CreationTransfers),
+            spec_versions: LookupMap::new(StorageKey::SpecVersions),
+            token_spec_versions: LookupMap::new(StorageKey::TokenSpecVersions),
+            minters: UnorderedSet::new(StorageKey::Minters),
+            token_upgraders: UnorderedSet::new(StorageKey::TokenUpgraders),
+            pausers: UnorderedSet::new(StorageKey::Pausers),
+            token_holders: LookupMap::new(StorageKey::TokenHoldersMap),
+            version_counts: LookupMap::new(StorageKey::VersionCounts),
+            token_created_at: LookupMap::new(StorageKey::TokenCreatedAt),
+            token_origins: LookupMap::new(StorageKey::TokenOrigins),
+            token_scaling: LookupMap::new(StorageKey::TokenScaling),
+            token_dust: LookupMap::new(StorageKey::TokenDust),
+            token_last_activity: LookupMap::new(StorageKey::TokenLastActivity),
+            total_deploys: 0,
+            binary_versions: LookupMap::new(StorageKey::BinaryVersions),
+            event_context: None,
+            event_format: Default::default(),
+            pending_deploys: UnorderedSet::new(StorageKey::PendingDeploys),
+            failed_withdraws: LookupMap::new(StorageKey::FailedWithdraws),
+            deploy_balance_margin: DEFAULT_DEPLOY_BALANCE_MARGIN,
+            max_decimals: DEFAULT_MAX_DECIMALS,
+            gas_config: Default::default(),
+            batch_limits: Default::default(),
+            reserve_balance: DEFAULT_RESERVE_BALANCE,
+            min_deploy_attached_deposit: 0,
+            naming_scheme,
+            token_account_ids: LookupMap::new(StorageKey::TokenAccountIds),
+            token_addresses: LookupMap::new(StorageKey::TokenAddresses),
+            auto_pause_threshold: None,
+            failure_count: 0,
+            incident_pending: false,
+            timelock_delay: timelock::DEFAULT_TIMELOCK_DELAY,
+            pending_changes: Vec::new(),
+            locker_grace_period: 0,
+            retired_locker: None,
+            unknown_token_policy: Default::default(),
+            excess_withdraw_policy: Default::default(),
+            pending_deploy_policy: Default::default(),
+            admin_log: LookupMap::new(StorageKey::AdminLog),
+            admin_log_len: 0,
+            deployer: None,
+            deployer_enabled: false,
         }
     }
 
-    /// Set WASM binary for the token contracts. This increases the token binary version,
-    /// so all deployed contracts SHOULD be upgraded after calling this function. ONLY the
-    /// `Owner` role can call this method.
+    /// Transfer the `Owner` role to `new_owner`. ONLY the `Owner` role can call this
+    /// method.
+    pub fn set_owner(&mut self, new_owner: AccountId) {
+        self.assert_owner();
+        self.record_admin_action("set_owner");
+
+        let old_owner = std::mem::replace(&mut self.owner, new_owner);
+        self.emit_event(events::ConnectorEvent::OwnerChanged {
+            old_owner,
+            new_owner: self.owner.clone(),
+        });
+    }
+
+    /// Account with the `Owner` role.
+    pub fn get_owner(&self) -> AccountId {
+        self.owner.clone()
+    }
+
+    /// Set the first WASM binary for the token contracts. Later binaries replace it
+    /// through the timelock, see `propose_token_binary`, and deployed contracts SHOULD
+    /// then be upgraded, see `upgrade_tokens`. ONLY the `Owner` and `TokenUpgrader`
+    /// roles can call this method.
     pub fn set_token_binary(&mut self, binary: near_sdk::json_types::Base64VecU8) {
-        // TODO: Replace with Owner
-        near_sdk::assert_self();
+        self.assert_role(roles::Role::TokenUpgrader);
+        self.record_admin_action("set_token_binary");
+        self.assert_upgrades_not_frozen();
+        require!(self.token_binary_version == 0, ERR_TOKEN_BINARY_ALREADY_SET);
 
-        self.token_binary.set(&binary.into());
-        self.token_binary_version += 1;
+        self.install_token_binary(binary.into());
+    }
+
+    /// Provenance of the token binary `version`, if it was set through
+    /// `set_token_binary`.
+    pub fn binary_version_info(&self, version: u32) -> Option<BinaryInfo> {
+        self.binary_versions.get(&version)
+    }
+
+    /// Whether the factory can operate tokens of the binary `version`. The same method
+    /// names and gas are used for every version from `MIN_SUPPORTED_TOKEN_VERSION` up to
+    /// the current one, so versions outside of this range are not supported.
+    pub fn supports_version(&self, version: u32) -> bool {
+        (MIN_SUPPORTED_TOKEN_VERSION..=self.token_binary_version).contains(&version)
+    }
+
+    /// Sha256 hash of the current token binary, if any.
+    pub fn token_binary_hash(&self) -> Option<Base58CryptoHash> {
+        self.token_binary_hash.map(Into::into)
+    }
+
+    /// Whether `expected_hash` is the hash of the current token binary.
+    pub fn is_active_binary(&self, expected_hash: Base58CryptoHash) -> bool {
+        self.token_binary_hash() == Some(expected_hash)
+    }
+
+    /// Sha256 hash of the key state of the factory: the token binary hash and
+    /// version, the owner, the locker, the number of tokens and their total mirrored
+    /// supply. Light clients can detect changes of any of them by comparing digests
+    /// instead of querying each field. It iterates over all tokens.
+    pub fn state_digest(&self) -> Base58CryptoHash {
+        let total_supply = self.tokens.keys().fold(0u128, |total, token_account_id| {
+            total.saturating_add(self.token_supply.get(&token_account_id).unwrap_or(0))
+        });
+        let state = (
+            self.token_binary_hash,
+            self.token_binary_version,
+            &self.owner,
+            &self.locker,
+            self.tokens.len(),
+            total_supply,
+        );
+        binary_hash(&state.try_to_vec().unwrap()).into()
+    }
+
+    /// Set the amount transferred to new token accounts while `version` is the current
+    /// token binary version. Heavier binaries need more balance to cover their storage.
+    /// Passing `None` restores the default amount. ONLY the `Owner` role can call this
+    /// method.
+    pub fn set_creation_transfer(&mut self, version: u32, amount: Option<U128>) {
+        self.assert_owner();
+        self.record_admin_action("set_creation_transfer");
+
+        match amount {
+            Some(amount) => self.creation_transfers.insert(&version, &amount.0),
+            None => self.creation_transfers.remove(&version),
+        };
+    }
+
+    /// Amount transferred to new token accounts while `version` is the current token
+    /// binary version.
+    pub fn creation_transfer(&self, version: u32) -> U128 {
+        self.creation_transfers
+            .get(&version)
+            .unwrap_or(DEFAULT_CREATION_TRANSFER)
+            .into()
+    }
+
+    /// Set the spec version of the interface implemented by the token binary `version`,
+    /// so other contracts know how to interact with the tokens running it. It is
+    /// distinct from the binary version, as binaries can change without changing the
+    /// interface. Passing `None` restores the default spec version. ONLY the `Owner`
+    /// role can call this method.
+    pub fn set_spec_version(&mut self, version: u32, spec_version: Option<u32>) {
+        self.assert_owner();
+        self.record_admin_action("set_spec_version");
+
+        match spec_version {
+            Some(spec_version) => self.spec_versions.insert(&version, &spec_version),
+            None => self.spec_versions.remove(&version),
+        };
+    }
+
+    /// Spec version of the interface implemented by the token binary `version`.
+    pub fn spec_version(&self, version: u32) -> u32 {
+        self.spec_versions
+            .get(&version)
+            .unwrap_or(DEFAULT_SPEC_VERSION)
+    }
+
+    /// Spec version of the token, recorded when it was deployed and updated when it is
+    /// upgraded, if known.
+    pub fn token_spec_version(&self, token_address: aurora_sdk::Address) -> Option<u32> {
+        self.token_spec_versions
+            .get(&self.account_id_from_token_address(token_address))
+    }
+
+    /// Borsh encoded arguments of the Aurora `call` that invokes the function with
+    /// `selector` on `target` with `args`, as sent by the factory to the engine.
+    pub fn encode_aurora_call(
+        &self,
+        target: aurora_sdk::Address,
+        selector: [u8; 4],
+        args: Vec<abi::AbiValue>,
+    ) -> near_sdk::json_types::Base64VecU8 {
+        let input = abi::encode_call(selector, &args);
+        aurora_sdk::aurora::call_args(target, input)
+            .try_to_vec()
+            .unwrap()
+            .into()
+    }
+
+    /// Whether `expected` is the ABI encoding of the locker `withdraw` call built by
+    /// `on_withdraw`, to cross-check the Solidity and Rust encodings.
+    pub fn assert_withdraw_encoding(
+        &self,
+        token_id: aurora_sdk::Address,
+        receiver_id: aurora_sdk::Address,
+        amount: U128,
+        expected: near_sdk::json_types::Base64VecU8,
+    ) -> bool {
+        abi_encode_withdraw(&token_id, &receiver_id, amount.0) == Vec::<u8>::from(expected)
+    }
+
+    /// Parameters of the unlock requested by `on_withdraw` for a withdraw with the given
+    /// arguments. They are decoded from the call sent to the locker.
+    pub fn expected_unlock(
+        &self,
+        token_id: aurora_sdk::Address,
+        receiver: aurora_sdk::Address,
+        amount: U128,
+    ) -> UnlockExpectation {
+        let input = abi_encode_withdraw(&token_id, &receiver, amount.into());
+        let params = abi::decode(
+            &[
+                abi::AbiType::Address,
+                abi::AbiType::Address,
+                abi::AbiType::Uint(128),
+            ],
+            &input[4..],
+        )
+        .unwrap();
+        use abi::AbiValue::{Address, Uint};
+        match params.as_slice() {
+            [Address(token), Address(receiver), Uint(amount)] => UnlockExpectation {
+                token: token.clone(),
+                receiver: receiver.clone(),
+                amount: *amount,
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    /// Whether each of `addresses` can have a NEP-141 representative, i.e. it is not
+    /// reserved and its derived account ID is valid.
+    pub fn validate_addresses(&self, addresses: Vec<aurora_sdk::Address>) -> Vec<bool> {
+        addresses
+            .into_iter()
+            .map(|address| {
+                !is_reserved_token_address(&address)
+                    && self.try_account_id_from_token_address(address).is_some()
+            })
+            .collect()
+    }
+
+    /// Number of registered tokens at each token binary version, in increasing order
+    /// of version. Versions without tokens are not included.
+    pub fn version_distribution(&self) -> Vec<(u32, u64)> {
+        (0..=self.token_binary_version)
+            .filter_map(|version| {
+                let count = self.version_counts.get(&version).unwrap_or(0);
+                (count > 0).then_some((version, count))
+            })
+            .collect()
+    }
+
+    /// Gas and amounts the next token deployment uses with the current token binary
+    /// version.
+    pub fn effective_deploy_config(&self) -> DeployConfig {
+        DeployConfig {
+            gas: self.gas_config.deploy_gas,
+            storage_deposit: self.required_storage_deposit().into(),
+            creation_transfer: self.creation_transfer(self.token_binary_version),
+        }
+    }
+
+    /// Set the gas attached to the `new` and `deposit` calls of the tokens. Deposits
+    /// must still fit in the gas budget of `on_deposit`, and the withdraw callback,
+    /// which mints the tokens back if the unlock fails, in the gas the tokens reserve
+    /// for it, see `TOKEN_WITHDRAW_CALLBACK_GAS`. ONLY the `Owner` role can call this
+    /// method.
+    pub fn set_gas_config(&mut self, deploy_gas: Gas, deposit_gas: Gas) {
+        self.assert_owner();
+        self.record_admin_action("set_gas_config");
+        require!(deploy_gas.0 > 0 && deposit_gas.0 > 0, ERR_ZERO_GAS);
+
+        let config = GasConfig {
+            deploy_gas,
+            deposit_gas,
+            ..self.gas_config
+        };
+        assert_deposit_gas_within_budget(&config);
+        assert_withdraw_gas_within_budget(&config);
+        self.gas_config = config;
+    }
+
+    /// Set the deposit attached to the `storage_deposit` calls registering accounts on
+    /// the tokens, i.e. the receivers of deposits and the accounts of `register_receivers`.
+    /// It MUST cover the storage of an account on the tokens, and can't exceed
+    /// `MAX_STORAGE_DEPOSIT`. ONLY the `Owner` role can call this method.
+    pub fn set_receiver_storage_deposit(&mut self, amount: U128) {
+        self.assert_owner();
+        self.record_admin_action("set_receiver_storage_deposit");
+        require!(
+            amount.0 > 0 && amount.0 <= MAX_STORAGE_DEPOSIT,
+            ERR_INVALID_STORAGE_DEPOSIT
+        );
+
+        self.gas_config.receiver_storage_deposit = amount;
+    }
+
+    /// Gas attached to the calls to the tokens.
+    pub fn get_gas_config(&self) -> GasConfig {
+        self.gas_config
+    }
+
+    /// Set the maximum sizes of the batched methods. Deposit batches must still fit in
+    /// the gas budget of `on_deposit_batch`. ONLY the `Owner` role can call this method.
+    pub fn set_batch_limits(&mut self, limits: BatchLimits) {
+        self.assert_owner();
+        self.record_admin_action("set_batch_limits");
+        require!(
+            limits.max_registrations > 0 && limits.max_deposits > 0,
+            ERR_INVALID_BATCH_LIMITS
+        );
+
+        self.batch_limits = limits;
+    }
+
+    /// Maximum sizes of the batched methods.
+    pub fn batch_limits(&self) -> BatchLimits {
+        self.batch_limits
+    }
+
+    /// Gas reserved by each callback, to check it against the gas of the main calls.
+    pub fn callback_gas_config(&self) -> CallbackGasConfig {
+        CallbackGasConfig {
+            deposit: DEPOSIT_CALLBACK_GAS,
+            withdraw: self.withdraw_callback_gas(),
+            deploy: TOKEN_DEPLOYED_CALLBACK_COST,
+            upgrade: upgrades::TOKEN_UPGRADED_CALLBACK_GAS,
+            mint: MINT_CALLBACK_GAS,
+        }
+    }
+
+    /// Set the balance the factory must keep on top of the cost of a deployment. Deploys
+    /// are rejected early if the available balance is lower, instead of failing in the
+    /// middle of the batch. ONLY the `Owner` role can call this method.
+    pub fn set_deploy_balance_margin(&mut self, margin: U128) {
+        self.assert_owner();
+        self.record_admin_action("set_deploy_balance_margin");
+
+        self.deploy_balance_margin = margin.0;
+    }
+
+    /// Balance the factory must keep on top of the cost of a deployment.
+    pub fn deploy_balance_margin(&self) -> U128 {
+        self.deploy_balance_margin.into()
+    }
+
+    /// Set the NEAR the factory attaches to the Aurora call of each withdraw, for
+    /// engines charging wNEAR for the cross-contract calls back to NEAR. It is paid
+    /// from the balance of the factory, unless the withdraw attaches its own fee, see
+    /// `on_withdraw`. It can't exceed `MAX_XCC_FEE`. ONLY the `Owner` role can call this
+    /// method.
+    pub fn set_xcc_fee(&mut self, fee: U128) {
+        self.assert_owner();
+        self.record_admin_action("set_xcc_fee");
+        require!(fee.0 <= MAX_XCC_FEE, ERR_INVALID_XCC_FEE);
+
+        self.xcc_fee = fee.0;
+    }
+
+    /// NEAR attached to the Aurora call of a withdraw that doesn't attach its own fee.
+    pub fn required_xcc_fee(&self) -> U128 {
+        self.xcc_fee.into()
+    }
+
+    /// Set the maximum decimals of the metadata of deployed tokens. Deploys with more
+    /// decimals are rejected, as amounts of such tokens are unusable on NEAR. ONLY the
+    /// `Owner` role can call this method.
+    pub fn set_max_decimals(&mut self, max_decimals: u8) {
+        self.assert_owner();
+        self.record_admin_action("set_max_decimals");
+
+        self.max_decimals = max_decimals;
+    }
+
+    /// Maximum decimals of the metadata of deployed tokens.
+    pub fn max_decimals(&self) -> u8 {
+        self.max_decimals
+    }
+
+    /// Set the minimum deposit `on_deposit` requires when it deploys the token. It is
+    /// disabled by default, as the locker doesn't attach NEAR yet, and it SHOULD be set
+    /// to the storage deposit of `effective_deploy_config` once it does. ONLY the
+    /// `Owner` role can call this method.
+    pub fn set_min_deploy_attached_deposit(&mut self, amount: U128) {
+        self.assert_owner();
+        self.record_admin_action("set_min_deploy_attached_deposit");
+
+        self.min_deploy_attached_deposit = amount.0;
+    }
+
+    /// Minimum deposit `on_deposit` requires when it deploys the token.
+    pub fn min_deploy_attached_deposit(&self) -> U128 {
+        self.min_deploy_attached_deposit.into()
+    }
+
+    /// Balance of the factory not locked for storage.
+    pub fn available_balance(&self) -> U128 {
+        let locked = Balance::from(env::storage_usage()) * env::storage_byte_cost();
+        env::account_balance().saturating_sub(locked).into()
+    }
+
+    /// Number of tokens the factory can still deploy with its available balance, keeping
+    /// the deploy balance margin. It is an estimate, as the storage used by each new
+    /// token also locks part of the balance.
+    pub fn remaining_capacity(&self) -> u64 {
+        let available = self
+            .available_balance()
+            .0
+            .saturating_sub(self.deploy_balance_margin);
+        let cost = self.effective_deploy_config().cost();
+        if cost == 0 {
+            return u64::MAX;
+        }
+        u64::try_from(available / cost).unwrap_or(u64::MAX)
     }
 
     /// Get the most recent binary version or fails if no binary is available.
@@ -80,181 +1197,4777 @@ impl Contract {
         }
     }
 
-    /// Create a new token by deploying the current binary in a sub-account. This method
-    /// can only be called by the locker.
-    pub fn create_token(&mut self, token_address: aurora_sdk::Address) -> Promise {
+    /// Create a new token by deploying the current binary in a sub-account. `metadata`
+    /// is the metadata of the ERC-20, see `token_init_args` if it is not set. This
+    /// method can only be called by the locker.
+    pub fn create_token(
+        &mut self,
+        token_address: aurora_sdk::Address,
+        metadata: Option<aurora_sdk::TokenMetadata>,
+    ) -> PromiseOrValue<()> {
+        self.assert_not_paused(PAUSE_CREATE_TOKEN);
         self.assert_locker();
+        self.assert_deploy_not_paused();
+        self.assert_token_allowed(&token_address);
 
-        let token_account_id = account_id_from_token_address(token_address);
-        let binary = self.get_token_binary();
+        let token_account_id = self.route_deposit(&token_address);
+        if self.pending_deploys.contains(&token_account_id) {
+            match self.pending_deploy_policy {
+                PendingDeployPolicy::Redeploy => {}
+                PendingDeployPolicy::Reject => env::panic_str(ERR_DEPLOY_IN_PROGRESS),
+                PendingDeployPolicy::Ignore => return PromiseOrValue::Value(()),
+            }
+        }
+        if self.dry_run {
+            self.emit_planned_deploy(&token_address, &token_account_id, metadata);
+            return PromiseOrValue::Value(());
+        }
 
-        Promise::new(token_account_id)
-            .create_account()
-            .deploy_contract(binary)
-            .function_call(
-                "new".to_string(),
-                vec![],
-                TOKEN_STORAGE_DEPOSIT_COST,
-                TOKEN_DEPLOYMENT_COST,
-            )
+        self.log_at(
+            logging::LogLevel::Info,
+            &format!("Deploying {}", token_account_id),
+        );
+        self.deploy_token(&token_address, token_account_id.clone(), metadata)
+            .then(self.token_deployed_callback(
+                token_address,
+                token_account_id,
+                TOKEN_DEPLOYED_CALLBACK_COST,
+                None,
+            ))
+            .into()
+    }
+
+    /// Set how `create_token` handles tokens whose deploy is pending. A failed deploy
+    /// stays pending, so `Reject` and `Ignore` also prevent `create_token` from
+    /// retrying it. ONLY the `Owner` role can call this method.
+    pub fn set_pending_deploy_policy(&mut self, policy: PendingDeployPolicy) {
+        self.assert_owner();
+        self.record_admin_action("set_pending_deploy_policy");
+
+        self.pending_deploy_policy = policy;
+    }
+
+    /// Policy applied to `create_token` calls for tokens whose deploy is pending.
+    pub fn pending_deploy_policy(&self) -> PendingDeployPolicy {
+        self.pending_deploy_policy
+    }
+
+    /// Register the storage of `accounts` on a deployed token in a single batched
+    /// transaction. Storage is paid by the factory. At most `max_registrations` of the
+    /// batch limits can be registered. ONLY the `Owner` role can call this method.
+    pub fn register_receivers(
+        &mut self,
+        token_address: aurora_sdk::Address,
+        accounts: Vec<AccountId>,
+    ) -> Promise {
+        self.assert_owner();
+        self.record_admin_action("register_receivers");
+        require!(
+            accounts.len() <= self.batch_limits.max_registrations as usize,
+            ERR_TOO_MANY_ACCOUNTS
+        );
+
+        let token_account_id = self.account_id_from_token_address(token_address);
+        require!(
+            self.tokens.get(&token_account_id).is_some(),
+            ERR_TOKEN_NOT_DEPLOYED
+        );
+        register_accounts(
+            Promise::new(token_account_id),
+            accounts,
+            self.gas_config.receiver_storage_deposit.0,
+        )
+    }
+
+    /// Create a new token and register the storage of `accounts` on it in the same
+    /// batched transaction. Storage is paid by the factory. At most `max_registrations`
+    /// of the batch limits can be registered. Like for `create_token`, the token is only
+    /// registered by `on_token_deployed` once the batch succeeded. ONLY the `Owner` role
+    /// can call this method.
+    pub fn create_token_with_registrations(
+        &mut self,
+        token_address: aurora_sdk::Address,
+        accounts: Vec<AccountId>,
+    ) -> Promise {
+        self.assert_owner();
+        self.record_admin_action("create_token_with_registrations");
+        self.assert_deploy_not_paused();
+        require!(
+            accounts.len() <= self.batch_limits.max_registrations as usize,
+            ERR_TOO_MANY_ACCOUNTS
+        );
+
+        let token_account_id = self.account_id_from_token_address(token_address.clone());
+        require!(
+            self.tokens.get(&token_account_id).is_none(),
+            ERR_TOKEN_ALREADY_DEPLOYED
+        );
+
+        self.log_at(
+            logging::LogLevel::Info,
+            &format!("Deploying {}", token_account_id),
+        );
+        let promise = self.deploy_token(&token_address, token_account_id.clone(), None);
+        let deposit = self.gas_config.receiver_storage_deposit.0;
+        register_accounts(promise, accounts, deposit).then(self.token_deployed_callback(
+            token_address,
+            token_account_id,
+            TOKEN_DEPLOYED_CALLBACK_COST,
+            None,
+        ))
     }
 
-    /// Method called by the locker when new tokens were deposited. The same amount of
-    /// tokens is minted in the equivalent NEP-141 contract. If such contract doesn't
-    /// exist it is deployed.
+    /// Method called by the locker (or a minter) when new tokens were deposited. The
+    /// same amount of tokens is minted in the equivalent NEP-141 contract. If such
+    /// contract doesn't exist it is deployed. `origin_tx_hash` is the Aurora
+    /// transaction that locked the tokens, if known, and it is included in the deposit
+    /// event and in any deferred record of the deposit. `metadata` is the metadata of
+    /// the ERC-20, and it is only used if the token is deployed, see `token_init_args`
+    /// if it is not set. `transfer_id` identifies the deposit among the deposits of the
+    /// caller, and a deposit whose id was already processed is ignored, see
+    /// `is_transfer_processed`.
+    ///
+    /// If the token is deployed, the attached deposit MUST cover
+    /// `min_deploy_attached_deposit`, and the excess is refunded to the caller.
     #[payable]
     pub fn on_deposit(
         &mut self,
         #[serializer(borsh)] token: aurora_sdk::Address,
         #[serializer(borsh)] receiver_id: AccountId,
         #[serializer(borsh)] amount: u128,
-    ) -> Promise {
-        self.assert_locker();
+        #[serializer(borsh)] origin_tx_hash: Option<aurora_sdk::RawU256>,
+        #[serializer(borsh)] metadata: Option<aurora_sdk::TokenMetadata>,
+        #[serializer(borsh)] transfer_id: Option<u64>,
+    ) -> PromiseOrValue<()> {
+        self.process_deposit(
+            token,
+            receiver_id,
+            amount,
+            origin_tx_hash,
+            metadata,
+            transfer_id,
+            0,
+        )
+    }
+
+    /// Like `on_deposit`, for several deposits of the same token. The tokens are minted
+    /// by a single batched transaction on the token, following its deployment if it is
+    /// not deployed yet, so the token is deployed at most once. At most
+    /// `max_deposits` of the batch limits can be minted, and the gas they require must
+    /// fit in `MAX_DEPOSIT_GAS`. Failed mints of a deployed token are handled by
+    /// `on_batch_mint_complete`. `transfer_ids`, if set, has the transfer id of each
+    /// deposit, and deposits whose id was already processed are ignored, see
+    /// `is_transfer_processed`.
+    #[payable]
+    pub fn on_deposit_batch(
+        &mut self,
+        #[serializer(borsh)] token: aurora_sdk::Address,
+        #[serializer(borsh)] deposits: Vec<(AccountId, u128)>,
+        #[serializer(borsh)] transfer_ids: Option<Vec<u64>>,
+    ) -> PromiseOrValue<()> {
+        self.assert_not_paused(PAUSE_DEPOSITS);
+        self.assert_can_deposit();
+        require!(!is_reserved_token_address(&token), ERR_ZERO_TOKEN);
+        self.assert_token_allowed(&token);
+        require!(
+            !deposits.is_empty() && deposits.len() <= self.batch_limits.max_deposits as usize,
+            ERR_INVALID_BATCH_SIZE
+        );
+        require!(
+            transfer_ids
+                .as_ref()
+                .map_or(true, |transfer_ids| transfer_ids.len() == deposits.len()),
+            ERR_INVALID_BATCH_SIZE
+        );
+
+        let token_account_id = self.route_deposit(&token);
+        self.assert_not_detached(&token_account_id);
+        for (receiver_id, _) in deposits.iter() {
+            self.assert_receiver_allowed(&token_account_id, receiver_id);
+        }
+        // Replayed deposits are dropped before anything is planned or recorded.
+        let depositor_id = env::predecessor_account_id();
+        let transfer_ids: Vec<Option<u64>> = match transfer_ids {
+            Some(transfer_ids) => transfer_ids.into_iter().map(Some).collect(),
+            None => vec![None; deposits.len()],
+        };
+        let mut accepted: Vec<(AccountId, u128, Option<u64>)> = Vec::new();
+        for ((receiver_id, amount), transfer_id) in deposits.into_iter().zip(transfer_ids) {
+            if let Some(id) = transfer_id {
+                if self.transfer_processed(&depositor_id, id)
+                    || accepted.iter().any(|(_, _, other)| *other == Some(id))
+                {
+                    self.log_at(
+                        logging::LogLevel::Errors,
+                        &format!("Transfer {} of {} was already processed", id, depositor_id),
+                    );
+                    continue;
+                }
+            }
+            accepted.push((receiver_id, amount, transfer_id));
+        }
+        let deposits = accepted;
+        if deposits.is_empty() {
+            return PromiseOrValue::Value(());
+        }
+        // The transfer ids are not marked in the dry-run mode, so the deposits can still
+        // be relayed once it is disabled.
+        if self.dry_run && self.is_token_account_paused(&token_account_id) {
+            return PromiseOrValue::Value(());
+        }
+        if !self.dry_run {
+            for transfer_id in deposits
+                .iter()
+                .filter_map(|(_, _, transfer_id)| *transfer_id)
+            {
+                self.mark_transfer_processed(transfer_id);
+            }
+        }
+        if self.is_token_account_paused(&token_account_id) {
+            for (receiver_id, amount, _) in deposits {
+                self.queue_paused_deposit(&token_account_id, &receiver_id, amount, None);
+            }
+            return PromiseOrValue::Value(());
+        }
+        let plan = self.deposit_plan(&token_account_id);
+        let mints = deposits.len() as u64 + u64::from(self.fee_collector.is_some());
+        let deposits_gas = Gas(self.gas_config.mint_gas().0 * mints);
+        let required_gas = match plan {
+            DepositPlan::DeployAndMint => {
+                Gas(self.gas_config.deploy_gas.0 + deposits_gas.0 + DEPOSIT_CALLBACK_GAS.0)
+            }
+            DepositPlan::Mint => Gas(deposits_gas.0 + MINT_CALLBACK_GAS.0),
+        };
+        require!(required_gas <= MAX_DEPOSIT_GAS, ERR_DEPOSIT_GAS_TOO_HIGH);
+        if self.dry_run {
+            if plan == DepositPlan::DeployAndMint {
+                self.assert_deploy_not_paused();
+                self.emit_planned_deploy(&token, &token_account_id, None);
+            }
+            for (receiver_id, amount, _) in deposits {
+                self.emit_planned_mint(&token_account_id, &receiver_id, amount);
+            }
+            return PromiseOrValue::Value(());
+        }
+
+        let mut promise = if plan == DepositPlan::DeployAndMint {
+            self.assert_deploy_not_paused();
+            self.charge_deploy_deposit();
+            self.log_at(
+                logging::LogLevel::Info,
+                &format!("Deploying {}", token_account_id),
+            );
+            self.deploy_token(&token, token_account_id.clone(), None)
+        } else {
+            Promise::new(token_account_id.clone())
+        };
+        let mut recorded = accounting::RecordedDeposit {
+            new_holders: self.new_holders(
+                &token_account_id,
+                deposits
+                    .iter()
+                    .map(|(receiver_id, _, _)| receiver_id)
+                    .chain(self.fee_collector.as_ref()),
+            ),
+            transfers: deposits
+                .iter()
+                .filter_map(|(_, _, transfer_id)| *transfer_id)
+                .map(|transfer_id| (depositor_id.clone(), transfer_id.into()))
+                .collect(),
+            ..Default::default()
+        };
+        let mut receivers = Vec::with_capacity(deposits.len());
+        let mut fees = 0;
+        let mut fee_collector = None;
+        for (receiver_id, amount, _) in deposits {
+            recorded.volume.0 += amount;
+            self.consume_deposit_limit(&token_account_id, amount);
+            let (amount, deferred_id) =
+                self.clamp_deposit(&token_account_id, &receiver_id, amount, None);
+            recorded.deferred_ids.extend(deferred_id);
+            recorded.amount.0 += amount;
+            receivers.push((receiver_id.clone(), U128(amount)));
+            let fee = self.deposit_fee(&token_account_id, amount);
+            let minted = amount - fee;
+            self.record_deposit(&token_account_id, &receiver_id, minted);
+            let charged = self.charge_fee(&token_account_id, &receiver_id, fee);
+            recorded.mints += 1 + u64::from(charged.is_some());
+            fee_collector = charged.or(fee_collector);
+            fees += fee;
+            self.emit_event(events::ConnectorEvent::Deposit {
+                token: token.to_string(),
+                receiver_id: receiver_id.clone(),
+                amount: minted.into(),
+                origin_tx_hash: None,
+            });
+            promise = self.append_mint(promise, &receiver_id, minted);
+        }
+        // The fees of the whole batch are minted at once.
+        if let Some(fee_collector) = fee_collector {
+            promise = self.append_mint(promise, &fee_collector, fees);
+        }
 
-        let token_account_id = account_id_from_token_address(token);
+        match plan {
+            DepositPlan::DeployAndMint => promise
+                .then(self.token_deployed_callback(
+                    token,
+                    token_account_id,
+                    DEPOSIT_CALLBACK_GAS,
+                    Some(recorded),
+                ))
+                .into(),
+            DepositPlan::Mint => promise
+                .then(
+                    Contract::ext(env::current_account_id())
+                        .with_static_gas(MINT_CALLBACK_GAS)
+                        .with_unused_gas_weight(0)
+                        .on_batch_mint_complete(token_account_id, receivers, recorded.mints),
+                )
+                .into(),
+        }
+    }
 
+    /// Callback invoked after a token is deployed. If the deployment failed this method
+    /// fails as well, so the failure is propagated to the caller (e.g. the locker
+    /// refunds the deposit), and the token is not registered, so the next deposit
+    /// deploys it again. The `deposit` minted by the deployment, if any, is undone
+    /// first, and the failure is returned by `fail_token_deployment` so the undoing is
+    /// kept. Otherwise the token is registered if it is not yet, at
+    /// `token_binary_version`, the version of the deployed binary, and the creation
+    /// notifier is called if it is set. Callbacks scheduled before the version was
+    /// passed register the token at the current version.
+    /// This is a callback function that can be only executed from the contract itself.
+    #[private]
+    pub fn on_token_deployed(
+        &mut self,
+        token_address: aurora_sdk::Address,
+        token_account_id: AccountId,
+        token_binary_version: Option<u32>,
+        deposit: Option<accounting::RecordedDeposit>,
+    ) -> PromiseOrValue<()> {
+        if !matches!(env::promise_result(0), PromiseResult::Successful(_)) {
+            self.log_at(
+                logging::LogLevel::Errors,
+                &format!("Deployment of {} failed", token_account_id),
+            );
+            let deposit = deposit.unwrap_or_else(|| env::panic_str(ERR_TOKEN_DEPLOYMENT_FAILED));
+            self.revert_deposit(&token_account_id, deposit);
+            return Contract::ext(env::current_account_id())
+                .with_static_gas(FAIL_TOKEN_DEPLOYMENT_GAS)
+                .fail_token_deployment()
+                .into();
+        }
+        self.pending_deploys.remove(&token_account_id);
         if self.tokens.get(&token_account_id).is_none() {
-            let binary = self.get_token_binary();
+            let version = token_binary_version.unwrap_or(self.token_binary_version);
+            self.register_token(&token_account_id, version);
+        }
+        self.emit_event(events::ConnectorEvent::TokenDeployed {
+            token: token_address.to_string(),
+            token_account_id: token_account_id.clone(),
+            token_binary_version: self.tokens.get(&token_account_id).unwrap_or_default(),
+        });
 
-            // Register new token.
-            self.tokens
-                .insert(&token_account_id, &self.token_binary_version);
+        if let Some(notifier) = self.creation_notifier.clone() {
+            // The notification is not returned, so its failure doesn't affect the deploy.
+            ext::ext_creation_notifier::ext(notifier)
+                .with_static_gas(NOTIFY_TOKEN_CREATED_COST)
+                .on_token_created(token_address, token_account_id);
+        }
+        PromiseOrValue::Value(())
+    }
 
-            // The token doesn't exist yet, so we deploy it and initialize it and deposit in a single
-            // batched transaction.
-            Promise::new(token_account_id)
-                .create_account()
-                .deploy_contract(binary)
-                .function_call(
-                    "new".to_string(),
-                    vec![],
-                    TOKEN_STORAGE_DEPOSIT_COST,
-                    TOKEN_DEPLOYMENT_COST,
-                )
-                .function_call(
-                    "deposit".to_string(),
-                    near_sdk::serde_json::json!({
-                        "receiver_id": receiver_id,
-                        "amount": amount,
-                    })
-                    .to_string()
-                    .into_bytes(),
-                    0,
-                    DEPOSIT_COST,
-                )
-        } else {
-            ext::ext_near_token::ext(token_account_id)
-                .with_static_gas(DEPOSIT_COST)
-                .deposit(receiver_id, amount.into(), None)
+    /// Fail with `ERR_TOKEN_DEPLOYMENT_FAILED`, see `on_token_deployed`.
+    /// This function can be only executed from the contract itself.
+    #[private]
+    pub fn fail_token_deployment(&self) {
+        env::panic_str(ERR_TOKEN_DEPLOYMENT_FAILED);
+    }
+
+    /// Callback invoked after the mint of a deposit of a deployed token. If the mint
+    /// failed, the deposit is removed from the accounting and the deposit limit,
+    /// `MintFailed` is emitted, and the tokens locked on Aurora are unlocked to the
+    /// deposit refund address if it is set. `mints` is the number of mints of the
+    /// deposit, i.e. 2 if a fee was minted as well. Callbacks scheduled before it was
+    /// passed count a single mint. Failed mints of tokens deployed by the same deposit
+    /// make the deployment fail instead, see `on_token_deployed`.
+    /// This is a callback function that can be only executed from the contract itself.
+    #[private]
+    pub fn on_mint_complete(
+        &mut self,
+        token_account_id: AccountId,
+        receiver_id: AccountId,
+        amount: U128,
+        mints: Option<u64>,
+    ) -> PromiseOrValue<()> {
+        if matches!(env::promise_result(0), PromiseResult::Successful(_)) {
+            return PromiseOrValue::Value(());
+        }
+
+        self.refund_failed_mints(
+            token_account_id,
+            vec![(receiver_id, amount)],
+            mints.unwrap_or(1),
+        )
+    }
+
+    /// Like `on_mint_complete`, for the mints of `on_deposit_batch` of a deployed token.
+    /// `deposits` are the amounts deposited to each receiver, fees included, and `mints`
+    /// the number of mints of the batch. The mints of a batch fail together, so they
+    /// are all undone, and their total is unlocked to the deposit refund address.
+    /// This is a callback function that can be only executed from the contract itself.
+    #[private]
+    pub fn on_batch_mint_complete(
+        &mut self,
+        token_account_id: AccountId,
+        deposits: Vec<(AccountId, U128)>,
+        mints: u64,
+    ) -> PromiseOrValue<()> {
+        if matches!(env::promise_result(0), PromiseResult::Successful(_)) {
+            return PromiseOrValue::Value(());
         }
+
+        self.refund_failed_mints(token_account_id, deposits, mints)
+    }
+
+    /// Set the Aurora address the tokens of failed mints are unlocked to, or remove
+    /// it. Without it, the tokens of failed mints stay locked on Aurora. ONLY the
+    /// `Owner` role can call this method.
+    pub fn set_deposit_refund_address(&mut self, address: Option<aurora_sdk::Address>) {
+        self.assert_owner();
+        self.record_admin_action("set_deposit_refund_address");
+
+        self.deposit_refund_address = address;
+    }
+
+    /// Aurora address the tokens of failed mints are unlocked to.
+    pub fn deposit_refund_address(&self) -> Option<aurora_sdk::Address> {
+        self.deposit_refund_address.clone()
+    }
+
+    /// Set the contract that is notified every time a token is deployed, or remove it.
+    /// ONLY the `Owner` role can call this method.
+    pub fn set_creation_notifier(&mut self, notifier: Option<AccountId>) {
+        self.assert_owner();
+        self.record_admin_action("set_creation_notifier");
+
+        self.creation_notifier = notifier;
+    }
+
+    /// Contract that is notified every time a token is deployed.
+    pub fn creation_notifier(&self) -> Option<AccountId> {
+        self.creation_notifier.clone()
     }
 
     /// Method invoked by each individual token when an account id calls `withdraw`.
     /// This method is called when tokens are already burned from the token contracts.
     /// The locker in Aurora is called to unlock the equivalent amount of tokens on
-    /// the receiver_id account.
+    /// the receiver_id account. If the unlock fails, the tokens are minted back to
+    /// `sender_id`, the account that burnt them. Tokens that don't provide `sender_id`
+    /// are refunded to the signer of the transaction. `memo` is only included in the
+    /// withdraw event, it is not sent to Aurora.
+    ///
+    /// If `msg` is given, it is the hex encoded calldata of a call of `receiver_id` made
+    /// by the locker right after the tokens are unlocked, with `withdrawAndCall`. The
+    /// Aurora call gets more gas in this case. If the call of the receiver fails, the
+    /// tokens are minted back to `sender_id`, regardless of the failed unlock policy.
+    /// Withdraws with a `msg` that is not valid hex are rejected and minted back.
     ///
     /// It is important that this method and the next method don't fail, otherwise this
     /// might result in the loss of tokens (in case the tokens are burnt but not unlocked).
+    /// If the gas left doesn't cover both the Aurora call and `on_withdraw_complete`, the
+    /// tokens are minted back right away instead of calling Aurora. For the same reason,
+    /// withdrawals of paused tokens, or while withdrawals are paused, don't panic: the
+    /// tokens are minted back to `sender_id` and the outcome is unsuccessful. The same
+    /// applies to withdrawals exceeding the daily limit of the token, see `rate_limits`.
     ///
     /// This is a public method with no access control. However calling will only grant
     /// withdraw privileges to the token associated with the caller if any. If the caller
-    /// is not a previously deployed token, this method will fail.
+    /// is not a token account of the factory, this method fails with `ERR_NOT_A_TOKEN`
+    /// before anything else is checked. Callers that were not deployed by the factory
+    /// are handled according to `unknown_token_policy`, and withdraws exceeding the
+    /// mirrored supply according to `excess_withdraw_policy`.
+    ///
+    /// The NEAR attached to this method, forwarded by the token from its `withdraw`, is
+    /// attached to the Aurora call instead of the default XCC fee, see `set_xcc_fee`.
+    /// It is refunded to `sender_id` if the withdraw doesn't reach Aurora, and kept by
+    /// the factory if the Aurora call fails.
+    #[payable]
     pub fn on_withdraw(
         &mut self,
         receiver_id: aurora_sdk::Address,
-        amount: near_sdk::json_types::U128,
-    ) -> Promise {
-        let token_id = address_from_token_account_id(env::predecessor_account_id());
-
-        let input = abi_encode_withdraw(&token_id, &receiver_id, amount.into());
-
-        aurora_sdk::aurora::ext_aurora::ext(self.aurora.clone())
-            .call(aurora_sdk::aurora::call_args(token_id, input))
-    }
-
-    /// Representative account id of the locker in Aurora.
-    pub fn locker_account_id(&self) -> AccountId {
-        format!("{}.{}", self.locker, self.aurora).parse().unwrap()
+        amount: U128,
+        sender_id: Option<AccountId>,
+        memo: Option<String>,
+        msg: Option<String>,
+    ) -> PromiseOrValue<WithdrawOutcome> {
+        let refund_id = sender_id.clone().unwrap_or_else(env::signer_account_id);
+        let result = self.unlock_withdraw(receiver_id, amount, sender_id, memo, msg);
+        let attached_fee = env::attached_deposit();
+        if attached_fee > 0 && matches!(result, PromiseOrValue::Value(_)) {
+            let _ = Promise::new(refund_id).transfer(attached_fee);
+        }
+        result
     }
-}
 
-impl Contract {
-    fn assert_locker(&self) {
+    /// Unlock of a withdraw on Aurora, see `on_withdraw`.
+    fn unlock_withdraw(
+        &mut self,
+        receiver_id: aurora_sdk::Address,
+        amount: U128,
+        sender_id: Option<AccountId>,
+        memo: Option<String>,
+        msg: Option<String>,
+    ) -> PromiseOrValue<WithdrawOutcome> {
+        let token_account_id = env::predecessor_account_id();
+        let token_id = self
+            .try_address_from_token_account_id(&token_account_id)
+            .unwrap_or_else(|| env::panic_str(ERR_NOT_A_TOKEN));
+        if !self.check_withdraw_token(&token_account_id, &receiver_id, amount) {
+            return PromiseOrValue::Value(WithdrawOutcome {
+                success: false,
+                token: token_id,
+                amount,
+            });
+        }
+        if self.paused & PAUSE_WITHDRAWALS != 0 || self.is_token_account_paused(&token_account_id) {
+            let sender_id = sender_id.unwrap_or_else(env::signer_account_id);
+            self.log_at(
+                logging::LogLevel::Errors,
+                &format!("Withdraw of {} {} is paused", amount.0, token_id),
+            );
+            self.refund_rejected_withdraw(token_account_id, sender_id, amount, "Withdraw paused");
+            return PromiseOrValue::Value(WithdrawOutcome {
+                success: false,
+                token: token_id,
+                amount,
+            });
+        }
+        if !self.is_withdraw_within_limit(&token_account_id, amount.0) {
+            let sender_id = sender_id.unwrap_or_else(env::signer_account_id);
+            self.log_at(
+                logging::LogLevel::Errors,
+                &format!(
+                    "Withdraw of {} {} exceeds the daily limit",
+                    amount.0, token_id
+                ),
+            );
+            self.refund_rejected_withdraw(
+                token_account_id,
+                sender_id,
+                amount,
+                "Withdraw exceeds the daily limit",
+            );
+            return PromiseOrValue::Value(WithdrawOutcome {
+                success: false,
+                token: token_id,
+                amount,
+            });
+        }
+        let data = match msg.as_deref().map(parse_withdraw_msg) {
+            Some(Some(_)) if token_id == eth::ETH_ADDRESS => {
+                let sender_id = sender_id.unwrap_or_else(env::signer_account_id);
+                self.log_at(
+                    logging::LogLevel::Errors,
+                    &format!("Withdraw of {} ETH can't call the receiver", amount.0),
+                );
+                self.refund_rejected_withdraw(
+                    token_account_id,
+                    sender_id,
+                    amount,
+                    "Withdraw calls are not supported for ETH",
+                );
+                return PromiseOrValue::Value(WithdrawOutcome {
+                    success: false,
+                    token: token_id,
+                    amount,
+                });
+            }
+            Some(None) => {
+                let sender_id = sender_id.unwrap_or_else(env::signer_account_id);
+                self.log_at(
+                    logging::LogLevel::Errors,
+                    &format!("Withdraw of {} {} has an invalid msg", amount.0, token_id),
+                );
+                self.refund_rejected_withdraw(
+                    token_account_id,
+                    sender_id,
+                    amount,
+                    "Withdraw msg is not valid hex",
+                );
+                return PromiseOrValue::Value(WithdrawOutcome {
+                    success: false,
+                    token: token_id,
+                    amount,
+                });
+            }
+            data => data.flatten(),
+        };
+        let amount = self.check_withdraw_amount(&token_account_id, &token_id, amount.0);
+        let fee = self.withdraw_fee(&token_account_id, amount);
+        let unlocked = U128(amount - fee);
+        let amount = U128(amount);
+        let (input, aurora_call_gas) = match &data {
+            Some(data) => (
+                self.encode_unlock_and_call(
+                    &token_account_id,
+                    &token_id,
+                    &receiver_id,
+                    unlocked.0,
+                    data,
+                ),
+                WITHDRAW_AND_CALL_AURORA_CALL_GAS,
+            ),
+            None => (
+                self.encode_unlock(&token_account_id, &token_id, &receiver_id, unlocked.0),
+                WITHDRAW_AURORA_CALL_GAS,
+            ),
+        };
+        if self.dry_run {
+            self.emit_planned_call(
+                &self.engine_of(&token_account_id),
+                "call",
+                format!("0x{}", hex::encode(&input)),
+                aurora_call_gas,
+            );
+            let sender_id = sender_id.unwrap_or_else(env::signer_account_id);
+            self.refund_rejected_withdraw(token_account_id, sender_id, amount, "Dry run");
+            return PromiseOrValue::Value(WithdrawOutcome {
+                success: false,
+                token: token_id,
+                amount,
+            });
+        }
+
+        self.record_withdraw(&token_account_id, amount.into());
+        self.record_withdraw_volume(&token_account_id, amount.0);
+        let sender_id = sender_id.unwrap_or_else(env::signer_account_id);
+        self.emit_event(events::ConnectorEvent::Withdraw {
+            token: token_id.to_string(),
+            sender_id: sender_id.clone(),
+            receiver_id: receiver_id.to_string(),
+            amount: unlocked,
+            memo,
+        });
+        self.log_at(
+            logging::LogLevel::Debug,
+            &format!("Withdraw of {} {} to {}", unlocked.0, token_id, receiver_id),
+        );
+
+        let fee_gas = if fee > 0 {
+            self.gas_config.mint_gas().0
+        } else {
+            0
+        };
+        let required_gas = aurora_call_gas.0 + self.withdraw_callback_gas().0 + fee_gas;
+        if env::prepaid_gas().0.saturating_sub(env::used_gas().0) < required_gas {
+            self.log_at(
+                logging::LogLevel::Errors,
+                &format!("Not enough gas to unlock {} {}", amount.0, token_id),
+            );
+            self.revert_withdraw(token_account_id, sender_id, amount);
+            return PromiseOrValue::Value(WithdrawOutcome {
+                success: false,
+                token: token_id,
+                amount,
+            });
+        }
+        let xcc_fee = match env::attached_deposit() {
+            0 => self.xcc_fee,
+            attached_fee => attached_fee,
+        };
+        if env::attached_deposit() == 0 && self.available_balance().0 < xcc_fee {
+            self.log_at(
+                logging::LogLevel::Errors,
+                &format!("Not enough balance to pay the XCC fee of {}", token_id),
+            );
+            self.revert_withdraw(token_account_id, sender_id, amount);
+            return PromiseOrValue::Value(WithdrawOutcome {
+                success: false,
+                token: token_id,
+                amount,
+            });
+        }
+
+        // The fee is minted to the fee collector. It is kept even if the unlock fails, in
+        // which case only the unlocked amount is minted back.
+        if let Some(fee_collector) = self.charge_fee(&token_account_id, &sender_id, fee) {
+            let _ = self.append_mint(Promise::new(token_account_id.clone()), &fee_collector, fee);
+        }
+
+        // Unlocks with a call are not queued if they fail, since the call can't be
+        // retried, see `on_withdraw_complete`.
+        let queued_receiver_id = data.is_none().then_some(receiver_id);
+        aurora_sdk::aurora::call_with_fee(
+            self.engine_of(&token_account_id),
+            self.unlock_call_args(token_id, input),
+            aurora_call_gas,
+            xcc_fee,
+        )
+        .then(
+            Contract::ext(env::current_account_id())
+                .with_static_gas(self.withdraw_callback_gas())
+                .with_unused_gas_weight(0)
+                .on_withdraw_complete(token_account_id, sender_id, unlocked, queued_receiver_id),
+        )
+        .into()
+    }
+
+    /// Callback invoked after the Aurora call issued by `on_withdraw`. It records the
+    /// EVM gas used by the call, which is used to size the gas for unlocking tokens.
+    ///
+    /// If the call failed, or the EVM transaction didn't succeed, the tokens were not
+    /// unlocked, so they are minted back to `sender_id` and `WithdrawReverted` is
+    /// emitted. Under the `Queue` failed unlock policy, the unlock to `receiver_id` is
+    /// queued instead, to be retried with `retry_withdraw`, unless `receiver_id` is
+    /// `None`. The returned outcome is the result of the withdraw for the token.
+    /// This is a callback function that can be only executed from the contract itself.
+    #[private]
+    pub fn on_withdraw_complete(
+        &mut self,
+        token_account_id: AccountId,
+        sender_id: AccountId,
+        amount: U128,
+        receiver_id: Option<aurora_sdk::Address>,
+    ) -> WithdrawOutcome {
+        let unlocked = match env::promise_result(0) {
+            PromiseResult::Successful(output) => {
+                match aurora_sdk::SubmitResult::try_from_slice(&output) {
+                    Ok(result) => {
+                        self.last_withdraw_gas = Some(result.gas_used);
+                        result.status.is_ok()
+                    }
+                    Err(_) => false,
+                }
+            }
+            _ => false,
+        };
+
+        let token = self.address_from_token_account_id(token_account_id.clone());
+        self.record_unlock_result(unlocked);
+        if !unlocked {
+            self.log_at(
+                logging::LogLevel::Errors,
+                &format!("Unlock of {} {} failed", amount.0, token),
+            );
+            match receiver_id {
+                Some(receiver_id)
+                    if self.failed_unlock_policy == unlock_queue::FailedUnlockPolicy::Queue =>
+                {
+                    self.queue_unlock(unlock_queue::QueuedUnlock {
+                        token_account_id,
+                        sender_id,
+                        receiver_id,
+                        amount,
+                    })
+                }
+                _ => self.revert_withdraw(token_account_id, sender_id, amount),
+            }
+        }
+
+        WithdrawOutcome {
+            success: unlocked,
+            token,
+            amount,
+        }
+    }
+
+    /// EVM gas used by the Aurora call of the most recent withdraw, if any.
+    pub fn last_withdraw_gas(&self) -> Option<U64> {
+        self.last_withdraw_gas.map(U64)
+    }
+
+    /// Representative account id of the locker in Aurora.
+    pub fn locker_account_id(&self) -> AccountId {
+        format_locker_account_id(&self.locker_account_format, &self.locker, &self.aurora)
+            .unwrap_or_else(|| env::panic_str(ERR_INVALID_LOCKER_FORMAT))
+    }
+
+    /// Pause deposits and withdrawals of a single token. ONLY the `Owner` and `Pauser`
+    /// roles can call this method.
+    pub fn pause_token(&mut self, token_address: aurora_sdk::Address) {
+        self.assert_role(roles::Role::Pauser);
+        self.record_admin_action("pause_token");
+
+        self.paused_tokens
+            .insert(&self.account_id_from_token_address(token_address.clone()));
+        self.emit_pause_changed(token_address.to_string(), true);
+    }
+
+    /// Resume deposits and withdrawals of a single token. Tokens remain paused while
+    /// all tokens are paused. ONLY the `Owner` role can call this method.
+    pub fn unpause_token(&mut self, token_address: aurora_sdk::Address) {
+        self.assert_owner();
+        self.record_admin_action("unpause_token");
+
+        self.paused_tokens
+            .remove(&self.account_id_from_token_address(token_address.clone()));
+        self.emit_pause_changed(token_address.to_string(), false);
+    }
+
+    /// Enable or disable NEP-141 transfers on a deployed token that supports it, e.g.
+    /// to halt transfers during an incident. Deposits and withdrawals through the
+    /// factory and their accounting are not affected, see `pause_token` for that. ONLY
+    /// the `Owner` role can call this method.
+    pub fn set_token_transfers_enabled(
+        &mut self,
+        token_address: aurora_sdk::Address,
+        enabled: bool,
+    ) -> Promise {
+        self.assert_owner();
+        self.record_admin_action("set_token_transfers_enabled");
+
+        let token_account_id = self.account_id_from_token_address(token_address);
         require!(
-            env::predecessor_account_id() == self.locker_account_id(),
-            ERR_ONLY_LOCKER
+            self.tokens.get(&token_account_id).is_some(),
+            ERR_TOKEN_NOT_DEPLOYED
+        );
+        ext::ext_near_token::ext(token_account_id)
+            .with_static_gas(SET_TRANSFERS_ENABLED_GAS)
+            .set_transfers_enabled(enabled)
+    }
+
+    /// Hand over the control of a deployed token to `new_factory`, to migrate to a new
+    /// factory without redeploying the token. Afterwards the token only accepts
+    /// deposits and upgrades from `new_factory` and sends its withdrawals there, so it
+    /// SHOULD be pruned from this factory. ONLY the `Owner` role can call this method.
+    pub fn transfer_token_control(
+        &mut self,
+        token_address: aurora_sdk::Address,
+        new_factory: AccountId,
+    ) -> Promise {
+        self.assert_owner();
+        self.record_admin_action("transfer_token_control");
+
+        let token_account_id = self.account_id_from_token_address(token_address);
+        require!(
+            self.tokens.get(&token_account_id).is_some(),
+            ERR_TOKEN_NOT_DEPLOYED
+        );
+        ext::ext_near_token::ext(token_account_id)
+            .with_static_gas(SET_CONTROLLER_GAS)
+            .set_controller(new_factory)
+    }
+
+    /// Replace the name, symbol and decimals of a deployed token, e.g. to fix the
+    /// metadata of a token deployed before the ERC-20 metadata was known. The icon and
+    /// reference of the token are kept. ONLY the `Owner` role can call this method.
+    pub fn set_token_metadata(
+        &mut self,
+        token_address: aurora_sdk::Address,
+        metadata: aurora_sdk::TokenMetadata,
+    ) -> Promise {
+        self.assert_owner();
+        self.record_admin_action("set_token_metadata");
+        self.assert_valid_metadata(Some(&metadata));
+
+        let token_account_id = self.account_id_from_token_address(token_address);
+        require!(
+            self.tokens.get(&token_account_id).is_some(),
+            ERR_TOKEN_NOT_DEPLOYED
+        );
+        ext::ext_near_token::ext(token_account_id)
+            .with_static_gas(UPDATE_METADATA_GAS)
+            .update_metadata(metadata)
+    }
+
+    /// Pause deposits and withdrawals of every token at once. Tokens paused
+    /// individually stay paused after `unpause_all_tokens`. ONLY the `Owner` and
+    /// `Pauser` roles can call this method.
+    pub fn pause_all_tokens(&mut self) {
+        self.assert_role(roles::Role::Pauser);
+        self.record_admin_action("pause_all_tokens");
+
+        self.all_tokens_paused = true;
+        self.emit_pause_changed("all_tokens".to_string(), true);
+    }
+
+    /// Revert `pause_all_tokens`. If every token was paused automatically, the
+    /// incident must be acknowledged first. ONLY the `Owner` role can call this method.
+    pub fn unpause_all_tokens(&mut self) {
+        self.assert_owner();
+        self.record_admin_action("unpause_all_tokens");
+        self.assert_no_pending_incident();
+
+        self.all_tokens_paused = false;
+        self.emit_pause_changed("all_tokens".to_string(), false);
+    }
+
+    /// Pause deployment of new tokens, e.g. under storage pressure. Deposits of tokens
+    /// already deployed and withdrawals keep working. ONLY the `Owner` and `Pauser`
+    /// roles can call this method.
+    pub fn pause_deploys(&mut self) {
+        self.assert_role(roles::Role::Pauser);
+        self.record_admin_action("pause_deploys");
+
+        self.deploy_paused = true;
+        self.emit_pause_changed("deploys".to_string(), true);
+    }
+
+    /// Revert `pause_deploys`. ONLY the `Owner` role can call this method.
+    pub fn unpause_deploys(&mut self) {
+        self.assert_owner();
+        self.record_admin_action("unpause_deploys");
+
+        self.deploy_paused = false;
+        self.emit_pause_changed("deploys".to_string(), false);
+    }
+
+    /// Pause the flows selected by `flags`: `1` for deposits (`on_deposit` and
+    /// `on_deposit_batch`), `2` for withdrawals (`on_withdraw`) and `4` for
+    /// `create_token`. Flags are combined with a bitwise or, and flows already paused
+    /// stay paused. ONLY the `Owner` and `Pauser` roles can call this method.
+    pub fn pause(&mut self, flags: u8) {
+        self.assert_role(roles::Role::Pauser);
+        self.record_admin_action("pause");
+        require!(flags & !PAUSE_ALL == 0, ERR_INVALID_PAUSE_FLAGS);
+
+        self.paused |= flags;
+        self.emit_pause_flags(flags, true);
+    }
+
+    /// Resume the flows selected by `flags`, see `pause`. ONLY the `Owner` role can call
+    /// this method.
+    pub fn unpause(&mut self, flags: u8) {
+        self.assert_owner();
+        self.record_admin_action("unpause");
+        require!(flags & !PAUSE_ALL == 0, ERR_INVALID_PAUSE_FLAGS);
+
+        self.paused &= !flags;
+        self.emit_pause_flags(flags, false);
+    }
+
+    /// Gas attached to the promises scheduled by `on_deposit` for the token. It depends
+    /// on whether the token must be deployed first. Relayers SHOULD attach this amount
+    /// plus the gas needed to execute `on_deposit` itself.
+    pub fn required_deposit_gas(&self, token_address: aurora_sdk::Address) -> Gas {
+        let token_account_id = self.account_id_from_token_address(token_address);
+        let gas = self
+            .deposit_plan(&token_account_id)
+            .required_gas(&self.gas_config);
+        // Fees are minted to the fee collector by the same batch.
+        match self.fee_collector {
+            Some(_) => Gas(gas.0 + self.gas_config.mint_gas().0),
+            None => gas,
+        }
+    }
+
+    /// Number of tokens currently registered.
+    pub fn tokens_count(&self) -> u64 {
+        self.tokens.len()
+    }
+
+    /// Registered tokens and their binary version, starting at `from_index`. The order
+    /// is the insertion order and it only changes when a token is pruned, which moves
+    /// the last token into the place of the pruned one.
+    pub fn get_tokens(&self, from_index: u64, limit: u64) -> Vec<(AccountId, u32)> {
+        self.tokens
+            .keys()
+            .zip(self.tokens.values())
+            .skip(from_index as usize)
+            .take(limit as usize)
+            .collect()
+    }
+
+    /// Binary version of a registered token, or `None` if `token_account_id` is not a
+    /// registered token.
+    pub fn get_token_version(&self, token_account_id: AccountId) -> Option<u32> {
+        self.tokens.get(&token_account_id)
+    }
+
+    /// Account of the registered NEP-141 representative of the ERC-20 at
+    /// `erc20_address`, or `None` if it is not registered. Unlike `token_account_id`,
+    /// it doesn't return the account of tokens that are not deployed yet.
+    pub fn get_token_account_id(&self, erc20_address: aurora_sdk::Address) -> Option<AccountId> {
+        self.try_account_id_from_token_address(erc20_address)
+            .filter(|account_id| self.tokens.get(account_id).is_some())
+    }
+
+    /// Aurora address of the ERC-20 mirrored by the registered token
+    /// `token_account_id`, or `None` if it is not a registered token. Unlike
+    /// `token_address`, it doesn't fail.
+    pub fn get_erc20_address(&self, token_account_id: AccountId) -> Option<aurora_sdk::Address> {
+        if self.tokens.get(&token_account_id).is_none() {
+            return None;
+        }
+        self.try_address_from_token_account_id(&token_account_id)
+    }
+
+    /// Block at which the token was registered, if known.
+    pub fn token_created_at(&self, token_address: aurora_sdk::Address) -> Option<U64> {
+        self.token_created_at
+            .get(&self.account_id_from_token_address(token_address))
+            .map(U64)
+    }
+
+    /// Account and block of the call that first deployed the token, if it was deployed
+    /// by the factory.
+    pub fn token_origin(&self, token_address: aurora_sdk::Address) -> Option<TokenOrigin> {
+        self.token_origins
+            .get(&self.account_id_from_token_address(token_address))
+    }
+
+    /// Block of the last deposit or withdraw of the token, if any. Together with a zero
+    /// mirrored supply, it helps to find dormant tokens to prune.
+    pub fn token_last_activity(&self, token_address: aurora_sdk::Address) -> Option<U64> {
+        self.token_last_activity
+            .get(&self.account_id_from_token_address(token_address))
+            .map(U64)
+    }
+
+    /// Registered tokens created from `start_block` to `end_block`, both included.
+    /// Pagination is applied to the registered tokens before filtering, so a page can
+    /// hold less than `limit` tokens even if more tokens were created in the range.
+    pub fn tokens_created_between(
+        &self,
+        start_block: U64,
+        end_block: U64,
+        from_index: u64,
+        limit: u64,
+    ) -> Vec<AccountId> {
+        self.tokens
+            .keys()
+            .skip(from_index as usize)
+            .take(limit as usize)
+            .filter(|token_account_id| {
+                self.token_created_at
+                    .get(token_account_id)
+                    .map_or(false, |block| {
+                        (start_block.0..=end_block.0).contains(&block)
+                    })
+            })
+            .collect()
+    }
+
+    /// Number of tokens ever deployed by the factory, including pruned tokens.
+    pub fn total_deploys(&self) -> u64 {
+        self.total_deploys
+    }
+
+    /// Remove a token without mirrored supply from the registered tokens. The token
+    /// account itself is not deleted. ONLY the `Owner` role can call this method.
+    pub fn prune_token(&mut self, token_address: aurora_sdk::Address) {
+        self.assert_owner();
+        self.record_admin_action("prune_token");
+
+        let token_account_id = self.account_id_from_token_address(token_address);
+        require!(
+            self.tokens.get(&token_account_id).is_some(),
+            ERR_TOKEN_NOT_DEPLOYED
+        );
+        require!(
+            self.token_supply.get(&token_account_id).unwrap_or(0) == 0,
+            ERR_TOKEN_HAS_SUPPLY
+        );
+        self.remove_token(&token_account_id);
+    }
+
+    /// Remove a token from the registered tokens, e.g. if it was deployed by mistake,
+    /// so the next deposit deploys it again. The total supply of the token is queried
+    /// first, and the token is only removed by `on_deregister_token` if it is zero, so
+    /// no user funds are stranded. ONLY the `Owner` role can call this method.
+    pub fn deregister_token(&mut self, token_address: aurora_sdk::Address) -> Promise {
+        self.assert_owner();
+        self.record_admin_action("deregister_token");
+
+        let token_account_id = self.account_id_from_token_address(token_address.clone());
+        require!(
+            self.tokens.get(&token_account_id).is_some(),
+            ERR_TOKEN_NOT_DEPLOYED
+        );
+        ext::ext_fungible_token::ext(token_account_id.clone())
+            .with_static_gas(FT_TOTAL_SUPPLY_GAS)
+            .ft_total_supply()
+            .then(
+                Contract::ext(env::current_account_id())
+                    .with_static_gas(DEREGISTER_CALLBACK_GAS)
+                    .with_unused_gas_weight(0)
+                    .on_deregister_token(token_address, token_account_id),
+            )
+    }
+
+    /// Callback invoked with the total supply of a token being deregistered. If the
+    /// query failed or the supply is not zero this method fails, and the token stays
+    /// registered. This is a callback function that can be only executed from the
+    /// contract itself.
+    #[private]
+    pub fn on_deregister_token(
+        &mut self,
+        token_address: aurora_sdk::Address,
+        token_account_id: AccountId,
+    ) {
+        let total_supply: Option<U128> = match env::promise_result(0) {
+            PromiseResult::Successful(output) => near_sdk::serde_json::from_slice(&output).ok(),
+            _ => None,
+        };
+        require!(total_supply == Some(U128(0)), ERR_TOKEN_HAS_SUPPLY);
+
+        if self.tokens.get(&token_account_id).is_some() {
+            self.remove_token(&token_account_id);
+            self.emit_event(events::ConnectorEvent::TokenDeregistered {
+                token: token_address.to_string(),
+                token_account_id,
+            });
+        }
+    }
+
+    /// Borsh serialized value stored in the tokens map for the token, if deployed.
+    /// Intended for debugging the storage layout.
+    pub fn raw_token_entry(
+        &self,
+        token_address: aurora_sdk::Address,
+    ) -> Option<near_sdk::json_types::Base64VecU8> {
+        self.tokens
+            .get(&self.account_id_from_token_address(token_address))
+            .map(|entry| entry.try_to_vec().unwrap().into())
+    }
+
+    /// Transfer `amount` of the NEP-141 `token` held by the factory to `to`. It recovers
+    /// tokens sent to the factory by mistake. ONLY the `Owner` role can call this
+    /// method.
+    pub fn rescue_ft(&mut self, token: AccountId, amount: U128, to: AccountId) -> Promise {
+        self.assert_owner();
+        self.record_admin_action("rescue_ft");
+
+        ext::ext_fungible_token::ext(token)
+            .with_static_gas(FT_TRANSFER_GAS)
+            .with_attached_deposit(1)
+            .ft_transfer(to, amount, Some("Rescued from the factory".to_string()))
+    }
+
+    /// Set the balance `withdraw_near` leaves on top of the balance locked for storage,
+    /// as a buffer for gas and future storage. ONLY the `Owner` role can call this
+    /// method.
+    pub fn set_reserve_balance(&mut self, reserve: U128) {
+        self.assert_owner();
+        self.record_admin_action("set_reserve_balance");
+
+        self.reserve_balance = reserve.0;
+    }
+
+    /// Balance `withdraw_near` leaves on top of the balance locked for storage.
+    pub fn reserve_balance(&self) -> U128 {
+        self.reserve_balance.into()
+    }
+
+    /// Balance `withdraw_near` can transfer, i.e. the available balance minus the
+    /// reserve and the storage pool.
+    pub fn sweepable_balance(&self) -> U128 {
+        self.available_balance()
+            .0
+            .saturating_sub(self.reserve_balance)
+            .saturating_sub(self.storage_pool)
+            .into()
+    }
+
+    /// Transfer `amount` of NEAR held by the factory to `to`. It fails if the factory
+    /// would be left with less than the storage requirement plus the reserve. ONLY the
+    /// `Owner` role can call this method.
+    pub fn withdraw_near(&mut self, amount: U128, to: AccountId) -> Promise {
+        self.assert_owner();
+        self.record_admin_action("withdraw_near");
+        require!(amount.0 <= self.sweepable_balance().0, ERR_RESERVE_BREACHED);
+
+        Promise::new(to).transfer(amount.0)
+    }
+
+    /// Set the label of this connector deployment. ONLY the `Owner` role can call this
+    /// method.
+    pub fn set_connector_metadata(&mut self, metadata: ConnectorMetadata) {
+        self.assert_owner();
+        self.record_admin_action("set_connector_metadata");
+
+        self.metadata = metadata;
+    }
+
+    /// Label of this connector deployment.
+    pub fn connector_metadata(&self) -> ConnectorMetadata {
+        self.metadata.clone()
+    }
+
+    /// Set the deployment identifier included in the data of every event, so indexers
+    /// can distinguish multiple factory deployments. ONLY the `Owner` role can call
+    /// this method.
+    pub fn set_event_context(&mut self, context: Option<String>) {
+        self.assert_owner();
+        self.record_admin_action("set_event_context");
+
+        self.event_context = context;
+    }
+
+    /// Deployment identifier included in the data of every event.
+    pub fn event_context(&self) -> Option<String> {
+        self.event_context.clone()
+    }
+
+    /// Set the format of the logs of every event. The compact format uses less gas on
+    /// busy bridges, but indexers MUST be configured for it, as it doesn't follow
+    /// NEP-297. ONLY the `Owner` role can call this method.
+    pub fn set_event_format(&mut self, format: events::EventFormat) {
+        self.assert_owner();
+        self.record_admin_action("set_event_format");
+
+        self.event_format = format;
+    }
+
+    /// Format of the logs of every event.
+    pub fn event_format(&self) -> events::EventFormat {
+        self.event_format
+    }
+
+    /// Emit a `Heartbeat` event and do nothing else. Watchdogs can call it periodically
+    /// to check the factory is still callable.
+    pub fn heartbeat(&self) {
+        self.emit_event(events::ConnectorEvent::Heartbeat {
+            block_height: env::block_height().into(),
+            version: VERSION.to_string(),
+            token_binary_version: self.token_binary_version,
+        });
+    }
+
+    /// All boolean mode settings of the factory.
+    pub fn modes(&self) -> ModeFlags {
+        ModeFlags {
+            all_tokens_paused: self.all_tokens_paused,
+            deploy_paused: self.deploy_paused,
+            upgrades_frozen: self.upgrades_frozen,
+            incident_pending: self.incident_pending,
+            deposits_paused: self.paused & PAUSE_DEPOSITS != 0,
+            withdrawals_paused: self.paused & PAUSE_WITHDRAWALS != 0,
+            create_token_paused: self.paused & PAUSE_CREATE_TOKEN != 0,
+            dry_run: self.dry_run,
+        }
+    }
+
+    /// Whether deposits and withdrawals of the token are paused.
+    pub fn is_token_paused(&self, token_address: aurora_sdk::Address) -> bool {
+        self.is_token_account_paused(&self.account_id_from_token_address(token_address))
+    }
+
+    /// Tokens paused individually with `pause_token`, starting at `from_index`. Tokens
+    /// only paused by `pause_all_tokens` are not listed.
+    pub fn paused_tokens(&self, from_index: u64, limit: u64) -> Vec<aurora_sdk::Address> {
+        self.paused_tokens
+            .iter()
+            .skip(from_index as usize)
+            .take(limit as usize)
+            .filter_map(|token_account_id| {
+                self.try_address_from_token_account_id(&token_account_id)
+            })
+            .collect()
+    }
+
+    /// Number of tokens paused individually with `pause_token`.
+    pub fn paused_tokens_count(&self) -> u64 {
+        self.paused_tokens.len()
+    }
+}
+
+impl Contract {
+    /// Make `binary` the current token binary and increase the token binary version.
+    pub(crate) fn install_token_binary(&mut self, binary: Vec<u8>) {
+        let hash = binary_hash(&binary);
+        self.token_binary_hash = Some(hash);
+        self.token_binary.set(&binary);
+        self.token_binary_version += 1;
+        self.binary_versions.insert(
+            &self.token_binary_version,
+            &BinaryInfo {
+                uploaded_at: env::block_height().into(),
+                uploader: env::predecessor_account_id(),
+                hash: hash.into(),
+            },
+        );
+        self.emit_event(events::ConnectorEvent::TokenBinaryUpdated {
+            token_binary_version: self.token_binary_version,
+            hash: hash.into(),
+        });
+    }
+
+    /// Batched promise creating the token account, deploying the current binary and
+    /// initializing it with `metadata`. More actions on the token can be appended to the
+    /// batch. The token is pending until `on_token_deployed` confirms the deployment.
+    /// Sub-accounts of the deployer are created by the deployer first, see
+    /// `create_deployer`.
+    fn deploy_token(
+        &mut self,
+        token_address: &aurora_sdk::Address,
+        token_account_id: AccountId,
+        metadata: Option<aurora_sdk::TokenMetadata>,
+    ) -> Promise {
+        self.assert_valid_metadata(metadata.as_ref());
+        let binary = self.get_token_binary();
+        let config = self.effective_deploy_config();
+        require!(
+            self.available_balance().0 >= config.cost() + self.deploy_balance_margin,
+            ERR_INSUFFICIENT_FACTORY_BALANCE
+        );
+        self.pending_deploys.insert(&token_account_id);
+        self.index_token_account(token_address, &token_account_id);
+        if self.token_origins.get(&token_account_id).is_none() {
+            self.token_origins.insert(
+                &token_account_id,
+                &TokenOrigin {
+                    deployer: env::predecessor_account_id(),
+                    deployed_at: env::block_height().into(),
+                },
+            );
+        }
+
+        // The account is created by the deployer if it is one of its sub-accounts, but the
+        // token is initialized by the factory in both cases, so it is its controller.
+        let promise = match self.deployer_label(&token_account_id) {
+            Some((deployer_id, label)) => ext::ext_token_deployer::ext(deployer_id)
+                .with_attached_deposit(config.creation_transfer.0)
+                .with_static_gas(deployer::deploy_token_gas(binary.len()))
+                .deploy_token(label, binary)
+                .then(Promise::new(token_account_id)),
+            None => Promise::new(token_account_id)
+                .create_account()
+                .transfer(config.creation_transfer.0)
+                .deploy_contract(binary),
+        };
+        promise.function_call(
+            "new".to_string(),
+            token_init_args(token_address, metadata).into_bytes(),
+            config.storage_deposit.0,
+            config.gas,
+        )
+    }
+
+    /// Remove a registered token and its per-token records.
+    fn remove_token(&mut self, token_account_id: &AccountId) {
+        if let Some(version) = self.tokens.remove(token_account_id) {
+            let count = self.version_counts.get(&version).unwrap_or(0);
+            self.version_counts
+                .insert(&version, &count.saturating_sub(1));
+        }
+        self.token_created_at.remove(token_account_id);
+        self.token_origins.remove(token_account_id);
+        self.token_scaling.remove(token_account_id);
+        self.token_dust.remove(token_account_id);
+        self.token_fees.remove(token_account_id);
+        self.rate_limits.remove(token_account_id);
+        self.token_volumes.remove(token_account_id);
+        self.unroute_token(token_account_id);
+        self.token_last_activity.remove(token_account_id);
+        self.token_spec_versions.remove(token_account_id);
+    }
+
+    /// Append to `promise` the registration of `receiver_id` on the token, so the mint
+    /// can't fail for lack of storage, followed by the mint of `amount` for it. Storage
+    /// is paid according to the storage payer, see `storage_pool`, and refunded by the
+    /// token if the receiver is already registered.
+    fn append_mint(
+        &mut self,
+        promise: Promise,
+        receiver_id: &AccountId,
+        amount: Balance,
+    ) -> Promise {
+        let promise = match self.take_receiver_storage_deposit() {
+            Some(storage_deposit) => promise.function_call(
+                "storage_deposit".to_string(),
+                receiver_storage_deposit_args(receiver_id).into_bytes(),
+                storage_deposit,
+                STORAGE_DEPOSIT_GAS,
+            ),
+            None => promise,
+        };
+        promise.function_call(
+            "deposit".to_string(),
+            deposit_args(receiver_id, amount).into_bytes(),
+            0,
+            self.gas_config.deposit_gas,
+        )
+    }
+
+    /// Append to `promise` the mint of a deposit of `minted` to `receiver_id`, and of its
+    /// `fee` to `fee_collector` if there is one.
+    fn append_deposit_mints(
+        &mut self,
+        promise: Promise,
+        receiver_id: &AccountId,
+        minted: Balance,
+        fee_collector: Option<AccountId>,
+        fee: Balance,
+    ) -> Promise {
+        let promise = self.append_mint(promise, receiver_id, minted);
+        match fee_collector {
+            Some(fee_collector) => self.append_mint(promise, &fee_collector, fee),
+            None => promise,
+        }
+    }
+
+    /// Emit the calls `append_mint` would issue outside of the dry-run mode.
+    fn emit_planned_mint(
+        &self,
+        token_account_id: &AccountId,
+        receiver_id: &AccountId,
+        amount: Balance,
+    ) {
+        self.emit_planned_call(
+            token_account_id,
+            "storage_deposit",
+            receiver_storage_deposit_args(receiver_id),
+            STORAGE_DEPOSIT_GAS,
+        );
+        self.emit_planned_call(
+            token_account_id,
+            "deposit",
+            deposit_args(receiver_id, amount),
+            self.gas_config.deposit_gas,
+        );
+    }
+
+    /// Require the attached deposit to cover `min_deploy_attached_deposit`, and refund
+    /// the excess to the caller.
+    fn charge_deploy_deposit(&self) {
+        let attached = env::attached_deposit();
+        require!(
+            attached >= self.min_deploy_attached_deposit,
+            ERR_INSUFFICIENT_ATTACHED
+        );
+        if attached > self.min_deploy_attached_deposit {
+            Promise::new(env::predecessor_account_id())
+                .transfer(attached - self.min_deploy_attached_deposit);
+        }
+    }
+
+    /// Deposit attached to the `new` call of a token. It covers the storage of the current
+    /// token binary and `TOKEN_ACCOUNT_STORAGE_OVERHEAD`, so it grows with the binary.
+    fn required_storage_deposit(&self) -> Balance {
+        let binary_len = self.token_binary.get().map_or(0, |binary| binary.len()) as u64;
+        Balance::from(binary_len + TOKEN_ACCOUNT_STORAGE_OVERHEAD) * env::storage_byte_cost()
+    }
+
+    /// Callback of a deploy of the current binary, see `on_token_deployed`.
+    fn token_deployed_callback(
+        &self,
+        token_address: aurora_sdk::Address,
+        token_account_id: AccountId,
+        gas: Gas,
+        deposit: Option<accounting::RecordedDeposit>,
+    ) -> Promise {
+        Contract::ext(env::current_account_id())
+            .with_static_gas(gas)
+            .with_unused_gas_weight(0)
+            .on_token_deployed(
+                token_address,
+                token_account_id,
+                Some(self.token_binary_version),
+                deposit,
+            )
+    }
+
+    /// Emit the `new` call that would deploy the token outside of the dry-run mode.
+    fn emit_planned_deploy(
+        &self,
+        token_address: &aurora_sdk::Address,
+        token_account_id: &AccountId,
+        metadata: Option<aurora_sdk::TokenMetadata>,
+    ) {
+        self.assert_valid_metadata(metadata.as_ref());
+        let config = self.effective_deploy_config();
+        self.emit_planned_call(
+            token_account_id,
+            "new",
+            token_init_args(token_address, metadata),
+            config.gas,
+        );
+    }
+
+    /// Panic if the decimals of `metadata` exceed the maximum decimals.
+    fn assert_valid_metadata(&self, metadata: Option<&aurora_sdk::TokenMetadata>) {
+        if let Some(metadata) = metadata {
+            require!(
+                metadata.decimals <= self.max_decimals,
+                ERR_DECIMALS_OUT_OF_RANGE
+            );
+        }
+    }
+
+    /// Register a new token deployed with the binary at `version`.
+    fn register_token(&mut self, token_account_id: &AccountId, version: u32) {
+        self.tokens.insert(token_account_id, &version);
+        self.add_to_version_count(version, 1);
+        self.token_created_at
+            .insert(token_account_id, &env::block_height());
+        self.token_spec_versions
+            .insert(token_account_id, &self.spec_version(version));
+        self.total_deploys += 1;
+    }
+
+    fn add_to_version_count(&mut self, version: u32, count: u64) {
+        let total = self.version_counts.get(&version).unwrap_or(0) + count;
+        self.version_counts.insert(&version, &total);
+    }
+
+    fn emit_event(&self, event: events::ConnectorEvent) {
+        event.emit(self.event_context.as_deref(), self.event_format);
+    }
+
+    /// `on_deposit` of a deposit that left `dust` locked on Aurora, in the decimals of
+    /// the ERC-20, see `on_deposit_u256`. The dust is added to the dust of the token once
+    /// the deposit is accepted, i.e. unless it is a dry run or a replay, and it is
+    /// removed again if the deployment of the token fails, see `revert_deposit`.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn process_deposit(
+        &mut self,
+        token: aurora_sdk::Address,
+        receiver_id: AccountId,
+        amount: u128,
+        origin_tx_hash: Option<aurora_sdk::RawU256>,
+        metadata: Option<aurora_sdk::TokenMetadata>,
+        transfer_id: Option<u64>,
+        dust: u128,
+    ) -> PromiseOrValue<()> {
+        self.assert_not_paused(PAUSE_DEPOSITS);
+        self.assert_can_deposit();
+        require!(!is_reserved_token_address(&token), ERR_ZERO_TOKEN);
+        self.assert_token_allowed(&token);
+
+        let token_account_id = self.route_deposit(&token);
+        self.assert_not_detached(&token_account_id);
+        self.assert_receiver_allowed(&token_account_id, &receiver_id);
+        // The transfer id is not marked in the dry-run mode, so the deposit can still be
+        // relayed once it is disabled.
+        if self.dry_run {
+            if self.is_token_account_paused(&token_account_id) {
+                return PromiseOrValue::Value(());
+            }
+            if self.deposit_plan(&token_account_id) == DepositPlan::DeployAndMint {
+                self.assert_deploy_not_paused();
+                self.emit_planned_deploy(&token, &token_account_id, metadata);
+            }
+            self.emit_planned_mint(&token_account_id, &receiver_id, amount);
+            return PromiseOrValue::Value(());
+        }
+        if let Some(transfer_id) = transfer_id {
+            if !self.mark_transfer_processed(transfer_id) {
+                return PromiseOrValue::Value(());
+            }
+        }
+        // The dust of a queued deposit is kept, since only its scaled amount is ever
+        // minted or unlocked.
+        self.record_dust(&token_account_id, dust);
+        if self.is_token_account_paused(&token_account_id) {
+            self.queue_paused_deposit(&token_account_id, &receiver_id, amount, origin_tx_hash);
+            return PromiseOrValue::Value(());
+        }
+        let volume = amount;
+        self.consume_deposit_limit(&token_account_id, amount);
+        let (amount, deferred_id) =
+            self.clamp_deposit(&token_account_id, &receiver_id, amount, origin_tx_hash);
+        let fee = self.deposit_fee(&token_account_id, amount);
+        let minted = amount - fee;
+        let new_holders = self.new_holders(
+            &token_account_id,
+            std::iter::once(&receiver_id).chain(self.fee_collector.as_ref()),
+        );
+        self.record_deposit(&token_account_id, &receiver_id, minted);
+        let fee_collector = self.charge_fee(&token_account_id, &receiver_id, fee);
+        let mints = 1 + u64::from(fee_collector.is_some());
+
+        self.emit_event(events::ConnectorEvent::Deposit {
+            token: token.to_string(),
+            receiver_id: receiver_id.clone(),
+            amount: minted.into(),
+            origin_tx_hash: origin_tx_hash.map(|hash| format!("0x{}", hex::encode(hash))),
+        });
+        self.log_at(
+            logging::LogLevel::Debug,
+            &format!("Deposit of {} {} to {}", minted, token, receiver_id),
+        );
+
+        if self.deposit_plan(&token_account_id) == DepositPlan::DeployAndMint {
+            self.assert_deploy_not_paused();
+            self.charge_deploy_deposit();
+
+            self.log_at(
+                logging::LogLevel::Info,
+                &format!("Deploying {}", token_account_id),
+            );
+            // The token doesn't exist yet, so we deploy it and initialize it and deposit in a single
+            // batched transaction. It is registered by `on_token_deployed` once the batch succeeds.
+            let deposit = accounting::RecordedDeposit {
+                amount: amount.into(),
+                mints,
+                volume: volume.into(),
+                new_holders,
+                deferred_ids: deferred_id.into_iter().collect(),
+                transfers: transfer_id
+                    .map(|id| (env::predecessor_account_id(), id.into()))
+                    .into_iter()
+                    .collect(),
+                dust: dust.into(),
+            };
+            let promise = self.deploy_token(&token, token_account_id.clone(), metadata);
+            self.append_deposit_mints(promise, &receiver_id, minted, fee_collector, fee)
+                .then(self.token_deployed_callback(
+                    token,
+                    token_account_id,
+                    DEPOSIT_CALLBACK_GAS,
+                    Some(deposit),
+                ))
+                .into()
+        } else {
+            let reserved_gas = DepositPlan::Mint.required_gas(&self.gas_config);
+            let promise = match self.lazy_upgrade(&token_account_id, reserved_gas) {
+                Some(upgrade) => upgrade.then(Promise::new(token_account_id.clone())),
+                None => Promise::new(token_account_id.clone()),
+            };
+            self.append_deposit_mints(promise, &receiver_id, minted, fee_collector, fee)
+                .then(
+                    Contract::ext(env::current_account_id())
+                        .with_static_gas(MINT_CALLBACK_GAS)
+                        .with_unused_gas_weight(0)
+                        .on_mint_complete(
+                            token_account_id,
+                            receiver_id,
+                            amount.into(),
+                            Some(mints),
+                        ),
+                )
+                .into()
+        }
+    }
+
+    /// Remove `deposits`, recorded by `mints` calls of `record_deposit`, from the
+    /// accounting and the deposit limit, emit `MintFailed` for each of them, and unlock
+    /// their total to the deposit refund address if it is set.
+    fn refund_failed_mints(
+        &mut self,
+        token_account_id: AccountId,
+        deposits: Vec<(AccountId, U128)>,
+        mints: u64,
+    ) -> PromiseOrValue<()> {
+        let token_id = self.address_from_token_account_id(token_account_id.clone());
+        let total: Balance = deposits.iter().map(|(_, amount)| amount.0).sum();
+        self.record_deposit_failed(&token_account_id, total, mints);
+        self.release_deposit_limit(&token_account_id, total);
+        for (receiver_id, amount) in deposits {
+            self.log_at(
+                logging::LogLevel::Errors,
+                &format!(
+                    "Mint of {} {} to {} failed",
+                    amount.0, token_id, receiver_id
+                ),
+            );
+            self.emit_event(events::ConnectorEvent::MintFailed {
+                token: token_id.to_string(),
+                receiver_id,
+                amount,
+                refund_address: self
+                    .deposit_refund_address
+                    .as_ref()
+                    .map(|address| address.to_string()),
+            });
+        }
+
+        match self.deposit_refund_address.clone() {
+            Some(refund_address) => {
+                let input =
+                    self.encode_unlock(&token_account_id, &token_id, &refund_address, total);
+                aurora_sdk::aurora::ext_aurora::ext(self.engine_of(&token_account_id))
+                    .with_static_gas(WITHDRAW_AURORA_CALL_GAS)
+                    .call(self.unlock_call_args(token_id, input))
+                    .into()
+            }
+            None => PromiseOrValue::Value(()),
+        }
+    }
+
+    /// Mint back tokens burnt by a withdraw whose unlock on Aurora failed.
+    fn revert_withdraw(&mut self, token_account_id: AccountId, sender_id: AccountId, amount: U128) {
+        self.record_withdraw_reverted(&token_account_id, amount.into());
+        self.record_failed_withdraw(&token_account_id);
+
+        self.emit_event(events::ConnectorEvent::WithdrawReverted {
+            token: self
+                .address_from_token_account_id(token_account_id.clone())
+                .to_string(),
+            receiver_id: sender_id.clone(),
+            amount,
+        });
+
+        ext::ext_near_token::ext(token_account_id)
+            .with_static_gas(self.gas_config.deposit_gas)
+            .deposit(sender_id, amount, Some("Withdraw reverted".to_string()));
+    }
+
+    /// Mint back tokens burnt by a withdraw that was rejected, e.g. because it is paused,
+    /// with `reason` as the memo. The withdraw was never recorded, so the accounting and
+    /// the failure counters are left untouched.
+    fn refund_rejected_withdraw(
+        &mut self,
+        token_account_id: AccountId,
+        sender_id: AccountId,
+        amount: U128,
+        reason: &str,
+    ) {
+        self.emit_event(events::ConnectorEvent::WithdrawReverted {
+            token: self
+                .address_from_token_account_id(token_account_id.clone())
+                .to_string(),
+            receiver_id: sender_id.clone(),
+            amount,
+        });
+
+        ext::ext_near_token::ext(token_account_id)
+            .with_static_gas(self.gas_config.deposit_gas)
+            .deposit(sender_id, amount, Some(reason.to_string()));
+    }
+
+    /// Gas for `on_withdraw_complete`, see `GasConfig::withdraw_callback_gas`.
+    fn withdraw_callback_gas(&self) -> Gas {
+        self.gas_config.withdraw_callback_gas()
+    }
+
+    fn deposit_plan(&self, token_account_id: &AccountId) -> DepositPlan {
+        if self.tokens.get(token_account_id).is_none() {
+            DepositPlan::DeployAndMint
+        } else {
+            DepositPlan::Mint
+        }
+    }
+
+    /// Emit `Paused` or `Unpaused` for `scope`.
+    pub(crate) fn emit_pause_changed(&self, scope: String, paused: bool) {
+        self.emit_event(if paused {
+            events::ConnectorEvent::Paused { scope }
+        } else {
+            events::ConnectorEvent::Unpaused { scope }
+        });
+    }
+
+    /// Emit `Paused` or `Unpaused` for each flow selected by `flags`.
+    fn emit_pause_flags(&self, flags: u8, paused: bool) {
+        for (flag, scope) in [
+            (PAUSE_DEPOSITS, "deposits"),
+            (PAUSE_WITHDRAWALS, "withdrawals"),
+            (PAUSE_CREATE_TOKEN, "create_token"),
+        ] {
+            if flags & flag != 0 {
+                self.emit_pause_changed(scope.to_string(), paused);
+            }
+        }
+    }
+
+    fn assert_not_paused(&self, flag: u8) {
+        require!(self.paused & flag == 0, ERR_PAUSED);
+    }
+
+    fn assert_deploy_not_paused(&self) {
+        require!(!self.deploy_paused, ERR_DEPLOY_PAUSED);
+    }
+
+    fn assert_owner(&self) {
+        require!(env::predecessor_account_id() == self.owner, ERR_ONLY_OWNER);
+    }
+
+    /// Replace the locker address and emit `LockerChanged`. The new locker must be
+    /// non-zero and produce a valid account id with the locker account format.
+    pub(crate) fn update_locker(&mut self, locker: aurora_sdk::Address) {
+        require!(!locker.is_zero(), ERR_ZERO_LOCKER);
+        require!(
+            format_locker_account_id(&self.locker_account_format, &locker, &self.aurora).is_some(),
+            ERR_INVALID_LOCKER_FORMAT
+        );
+
+        let old_locker = std::mem::replace(&mut self.locker, locker);
+        self.emit_event(events::ConnectorEvent::LockerChanged {
+            old_locker: old_locker.to_string(),
+            new_locker: self.locker.to_string(),
+        });
+    }
+
+    fn assert_locker(&self) {
+        let predecessor = env::predecessor_account_id();
+        require!(
+            self.is_locker(&predecessor) || self.silo_of_locker(&predecessor).is_some(),
+            ERR_ONLY_LOCKER
+        );
+    }
+
+    fn is_token_account_paused(&self, token_account_id: &AccountId) -> bool {
+        self.all_tokens_paused || self.paused_tokens.contains(token_account_id)
+    }
+}
+
+fn binary_hash(binary: &[u8]) -> CryptoHash {
+    env::sha256(binary).try_into().unwrap()
+}
+
+/// NEAR representative of the locker following `format`, if it is a valid account id.
+fn format_locker_account_id(
+    format: &str,
+    locker: &aurora_sdk::Address,
+    aurora: &AccountId,
+) -> Option<AccountId> {
+    format
+        .replace("{address}", &locker.encode())
+        .replace("{aurora}", aurora.as_str())
+        .parse()
+        .ok()
+}
+
+/// JSON arguments of the `new` call of a token. Tokens deployed without the metadata of
+/// their ERC-20 are named after their address, with a symbol made of the first 8 hex
+/// chars of the address and the 18 decimals most ERC-20 use. ETH always has the
+/// metadata of ETH.
+fn token_init_args(
+    token_address: &aurora_sdk::Address,
+    metadata: Option<aurora_sdk::TokenMetadata>,
+) -> String {
+    let metadata = match metadata {
+        _ if token_address == &eth::ETH_ADDRESS => eth::eth_metadata(),
+        Some(metadata) => metadata,
+        None => aurora_sdk::TokenMetadata {
+            name: token_address.to_string(),
+            symbol: token_address.encode()[..8].to_uppercase(),
+            decimals: 18,
+        },
+    };
+    near_sdk::serde_json::json!({ "metadata": metadata }).to_string()
+}
+
+/// Add a `storage_deposit` call for each account to `promise`, paid by the factory.
+/// JSON arguments of the `deposit` call of a token.
+fn deposit_args(receiver_id: &AccountId, amount: Balance) -> String {
+    near_sdk::serde_json::json!({
+        "receiver_id": receiver_id,
+        "amount": U128(amount),
+    })
+    .to_string()
+}
+
+/// JSON arguments of the `storage_deposit` call registering the receiver of a deposit.
+/// Accounts already registered get the attached deposit refunded.
+fn receiver_storage_deposit_args(receiver_id: &AccountId) -> String {
+    near_sdk::serde_json::json!({
+        "account_id": receiver_id,
+        "registration_only": true,
+    })
+    .to_string()
+}
+
+/// Register `accounts` on the token of `promise`, attaching `deposit` to each
+/// `storage_deposit` call.
+fn register_accounts(promise: Promise, accounts: Vec<AccountId>, deposit: Balance) -> Promise {
+    accounts.into_iter().fold(promise, |promise, account_id| {
+        promise.function_call(
+            "storage_deposit".to_string(),
+            near_sdk::serde_json::json!({ "account_id": account_id })
+                .to_string()
+                .into_bytes(),
+            deposit,
+            STORAGE_DEPOSIT_GAS,
+        )
+    })
+}
+
+/// Addresses that can't have a NEP-141 representative.
+fn is_reserved_token_address(address: &aurora_sdk::Address) -> bool {
+    address.is_zero()
+}
+
+/// Panic if the gas attached by `on_deposit` with `config` can exceed `MAX_DEPOSIT_GAS`.
+fn assert_deposit_gas_within_budget(config: &GasConfig) {
+    require!(
+        DepositPlan::DeployAndMint.required_gas(config) <= MAX_DEPOSIT_GAS
+            && DepositPlan::Mint.required_gas(config) <= MAX_DEPOSIT_GAS,
+        ERR_DEPOSIT_GAS_TOO_HIGH
+    );
+}
+
+/// Panic unless the withdraw callback fits in the gas the tokens reserve for it.
+fn assert_withdraw_gas_within_budget(config: &GasConfig) {
+    require!(
+        config.withdraw_callback_gas() <= TOKEN_WITHDRAW_CALLBACK_GAS,
+        ERR_WITHDRAW_GAS_TOO_HIGH
+    );
+}
+
+/// ABI encoded call of `withdraw(address,address,uint256)` on the locker. Each argument
+/// takes a word, see `abi::encode_call`.
+fn abi_encode_withdraw(
+    token_id: &aurora_sdk::Address,
+    receiver_id: &aurora_sdk::Address,
+    amount: u128,
+) -> Vec<u8> {
+    abi::encode_call(
+        WITHDRAW_SELECTOR,
+        &[
+            abi::AbiValue::Address(token_id.clone()),
+            abi::AbiValue::Address(receiver_id.clone()),
+            abi::AbiValue::Uint(amount.into()),
+        ],
+    )
+}
+
+/// Like `abi_encode_withdraw`, with a big-endian uint256 `amount`.
+fn abi_encode_withdraw_u256(
+    token_id: &aurora_sdk::Address,
+    receiver_id: &aurora_sdk::Address,
+    amount: aurora_sdk::RawU256,
+) -> Vec<u8> {
+    let input = abi::encode_call(
+        WITHDRAW_SELECTOR,
+        &[
+            abi::AbiValue::Address(token_id.clone()),
+            abi::AbiValue::Address(receiver_id.clone()),
+            abi::AbiValue::Uint256(amount),
+        ],
+    );
+    debug_assert_eq!(input.len(), 4 + 3 * abi::WORD_SIZE);
+    input
+}
+
+/// ABI encoded call of `withdrawAndCall(address,address,uint256,bytes)` on the locker,
+/// which unlocks the tokens to `receiver_id` and calls it with `data`.
+fn abi_encode_withdraw_and_call(
+    token_id: &aurora_sdk::Address,
+    receiver_id: &aurora_sdk::Address,
+    amount: aurora_sdk::RawU256,
+    data: &[u8],
+) -> Vec<u8> {
+    abi::encode_call(
+        WITHDRAW_AND_CALL_SELECTOR,
+        &[
+            abi::AbiValue::Address(token_id.clone()),
+            abi::AbiValue::Address(receiver_id.clone()),
+            abi::AbiValue::Uint256(amount),
+            abi::AbiValue::Bytes(data.to_vec()),
+        ],
+    )
+}
+
+/// ABI encoded call of `withdrawEth(address,uint256)` on the locker, which unlocks
+/// `amount` of ETH to `receiver_id`.
+fn abi_encode_withdraw_eth(
+    receiver_id: &aurora_sdk::Address,
+    amount: aurora_sdk::RawU256,
+) -> Vec<u8> {
+    abi::encode_call(
+        WITHDRAW_ETH_SELECTOR,
+        &[
+            abi::AbiValue::Address(receiver_id.clone()),
+            abi::AbiValue::Uint256(amount),
+        ],
+    )
+}
+
+/// Calldata of a withdraw `msg`: hex, with an optional `0x` prefix.
+fn parse_withdraw_msg(msg: &str) -> Option<Vec<u8>> {
+    hex::decode(msg.strip_prefix("0x").unwrap_or(msg)).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::abi::AbiValue;
+    use crate::aurora_sdk::{self, Address};
+    use crate::events::EventFormat;
+    use crate::test_utils;
+    use crate::upgrades::TOKEN_UPGRADED_CALLBACK_GAS;
+    use crate::{
+        abi_encode_withdraw, abi_encode_withdraw_and_call, abi_encode_withdraw_eth,
+        abi_encode_withdraw_u256, DEFAULT_CREATION_TRANSFER, DEFAULT_DEPLOY_BALANCE_MARGIN,
+        DEFAULT_MAX_DECIMALS, DEFAULT_MAX_DEPOSITS_PER_BATCH, DEFAULT_MAX_REGISTRATIONS_PER_CALL,
+        DEPOSIT_CALLBACK_GAS, DEPOSIT_COST, MAX_DEPOSIT_GAS, MAX_STORAGE_DEPOSIT, MAX_XCC_FEE,
+        MINT_CALLBACK_GAS, PAUSE_CREATE_TOKEN, PAUSE_DEPOSITS, PAUSE_WITHDRAWALS,
+        SET_CONTROLLER_GAS, STORAGE_DEPOSIT_COST, STORAGE_DEPOSIT_GAS,
+        TOKEN_ACCOUNT_STORAGE_OVERHEAD, TOKEN_DEPLOYED_CALLBACK_COST, TOKEN_DEPLOYMENT_COST,
+        UPDATE_METADATA_GAS, WITHDRAW_AND_CALL_AURORA_CALL_GAS, WITHDRAW_AND_CALL_SELECTOR,
+        WITHDRAW_AURORA_CALL_GAS, WITHDRAW_CALLBACK_BASE_COST, WITHDRAW_ETH_SELECTOR,
+        WITHDRAW_SELECTOR,
+    };
+    use crate::{
+        binary_hash, BatchLimits, BinaryInfo, CallbackGasConfig, ConnectorMetadata, DeployConfig,
+        DepositPlan, GasConfig, PendingDeployPolicy, TokenOrigin, UnlockExpectation,
+        WithdrawOutcome,
+    };
+    use crate::{fees, naming, rate_limits, Contract};
+    use near_sdk::borsh::{BorshDeserialize, BorshSerialize};
+    use near_sdk::json_types::{Base58CryptoHash, U128, U64};
+    use near_sdk::serde::{Deserialize, Serialize};
+    use near_sdk::{env, PromiseOrValue, PromiseResult};
+    use near_sdk::{AccountId, Balance, Gas};
+    use proptest::prelude::{any, prop_assert_eq, proptest};
+
+    #[test]
+    /// Check withdraw selector is properly computed. Function signature is:
+    /// "withdraw(address,address,uint256)"
+    fn test_withdraw_select() {
+        assert_eq!(
+            &ethabi::short_signature(
+                "withdraw",
+                &[
+                    ethabi::ParamType::Address,
+                    ethabi::ParamType::Address,
+                    ethabi::ParamType::Uint(256)
+                ],
+            ),
+            &WITHDRAW_SELECTOR
+        );
+    }
+
+    #[test]
+    fn test_abi_encode_withdraw() {
+        let token_id = [
+            12, 34, 56, 78, 90, 12, 34, 56, 78, 90, 12, 34, 56, 78, 90, 12, 34, 56, 78, 90,
+        ];
+        let receiver_id = [
+            12, 12, 12, 12, 34, 34, 34, 34, 56, 56, 56, 56, 78, 78, 78, 78, 90, 90, 90, 90,
+        ];
+        let amount = 0x998877665544332211u128;
+
+        assert_eq!(
+            &abi_encode_withdraw(&Address(token_id), &Address(receiver_id), amount,)[4..],
+            &ethabi::encode(&[
+                ethabi::Token::Address(ethabi::Address::try_from(&token_id).unwrap()),
+                ethabi::Token::Address(ethabi::Address::try_from(&receiver_id).unwrap()),
+                ethabi::Token::Uint(ethabi::Uint::from(amount)),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_abi_encode_withdraw_boundaries() {
+        let token_id = Address([0xff; 20]);
+        let receiver_id = Address([0; 20]);
+
+        for amount in [0, 1, u64::MAX as u128 + 1, u128::MAX] {
+            let encoded = abi_encode_withdraw(&token_id, &receiver_id, amount);
+            assert_eq!(encoded.len(), 4 + 3 * 32);
+            assert_eq!(&encoded[..4], &WITHDRAW_SELECTOR);
+
+            let decoded = ethabi::decode(
+                &[
+                    ethabi::ParamType::Address,
+                    ethabi::ParamType::Address,
+                    ethabi::ParamType::Uint(256),
+                ],
+                &encoded[4..],
+            )
+            .unwrap();
+            assert_eq!(
+                decoded,
+                [
+                    ethabi::Token::Address(ethabi::Address::from(token_id.0)),
+                    ethabi::Token::Address(ethabi::Address::from(receiver_id.0)),
+                    ethabi::Token::Uint(ethabi::Uint::from(amount)),
+                ]
+            );
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn prop_abi_encoders_match_ethabi(
+            token_id in any::<[u8; 20]>(),
+            receiver_id in any::<[u8; 20]>(),
+            amount in any::<u128>(),
+            data in proptest::collection::vec(any::<u8>(), 0..200),
+        ) {
+            let token = ethabi::Token::Address(ethabi::Address::from(token_id));
+            let receiver = ethabi::Token::Address(ethabi::Address::from(receiver_id));
+            let value = ethabi::Token::Uint(ethabi::Uint::from(amount));
+            let mut raw_amount = [0u8; 32];
+            ethabi::Uint::from(amount).to_big_endian(&mut raw_amount);
+            let (token_id, receiver_id) = (Address(token_id), Address(receiver_id));
+
+            let encoded = abi_encode_withdraw(&token_id, &receiver_id, amount);
+            prop_assert_eq!(&encoded[..4], &WITHDRAW_SELECTOR);
+            prop_assert_eq!(
+                &encoded[4..],
+                &ethabi::encode(&[token.clone(), receiver.clone(), value.clone()])
+            );
+
+            let encoded = abi_encode_withdraw_u256(&token_id, &receiver_id, raw_amount);
+            prop_assert_eq!(
+                &encoded[4..],
+                &ethabi::encode(&[token.clone(), receiver.clone(), value.clone()])
+            );
+
+            let encoded =
+                abi_encode_withdraw_and_call(&token_id, &receiver_id, raw_amount, &data);
+            prop_assert_eq!(&encoded[..4], &WITHDRAW_AND_CALL_SELECTOR);
+            prop_assert_eq!(
+                &encoded[4..],
+                &ethabi::encode(&[
+                    token,
+                    receiver.clone(),
+                    value.clone(),
+                    ethabi::Token::Bytes(data),
+                ])
+            );
+
+            let encoded = abi_encode_withdraw_eth(&receiver_id, raw_amount);
+            prop_assert_eq!(&encoded[..4], &WITHDRAW_ETH_SELECTOR);
+            prop_assert_eq!(&encoded[4..], &ethabi::encode(&[receiver, value]));
+        }
+    }
+
+    #[test]
+    fn test_encode_aurora_call() {
+        let contract = test_utils::new_contract();
+        let token = Address([1; 20]);
+        let receiver = Address([2; 20]);
+
+        let encoded = contract.encode_aurora_call(
+            token.clone(),
+            WITHDRAW_SELECTOR,
+            vec![
+                AbiValue::Address(token.clone()),
+                AbiValue::Address(receiver.clone()),
+                AbiValue::Uint(U128(1_000)),
+            ],
+        );
+
+        let expected = aurora_sdk::aurora::call_args(
+            token.clone(),
+            abi_encode_withdraw(&token, &receiver, 1_000),
+        );
+        assert_eq!(Vec::<u8>::from(encoded), expected.try_to_vec().unwrap());
+    }
+
+    #[test]
+    fn test_assert_withdraw_encoding() {
+        let contract = test_utils::new_contract();
+        let token_id = Address([1; 20]);
+        let receiver_id = Address([2; 20]);
+        let expected = [
+            WITHDRAW_SELECTOR.to_vec(),
+            ethabi::encode(&[
+                ethabi::Token::Address(ethabi::Address::from(token_id.0)),
+                ethabi::Token::Address(ethabi::Address::from(receiver_id.0)),
+                ethabi::Token::Uint(ethabi::Uint::from(1_000u128)),
+            ]),
+        ]
+        .concat();
+
+        assert!(contract.assert_withdraw_encoding(
+            token_id.clone(),
+            receiver_id.clone(),
+            U128(1_000),
+            expected.clone().into(),
+        ));
+        assert!(!contract.assert_withdraw_encoding(
+            token_id,
+            receiver_id,
+            U128(1_001),
+            expected.into(),
+        ));
+    }
+
+    #[test]
+    fn test_expected_unlock() {
+        let contract = test_utils::new_contract();
+        let token_id = Address([1; 20]);
+        let receiver = Address([2; 20]);
+
+        let expectation = contract.expected_unlock(token_id.clone(), receiver.clone(), U128(1_000));
+
+        let input = abi_encode_withdraw(&token_id, &receiver, 1_000);
+        let params = ethabi::decode(
+            &[
+                ethabi::ParamType::Address,
+                ethabi::ParamType::Address,
+                ethabi::ParamType::Uint(256),
+            ],
+            &input[4..],
+        )
+        .unwrap();
+        assert_eq!(
+            params,
+            [
+                ethabi::Token::Address(ethabi::Address::from(expectation.token.0)),
+                ethabi::Token::Address(ethabi::Address::from(expectation.receiver.0)),
+                ethabi::Token::Uint(ethabi::Uint::from(expectation.amount.0)),
+            ]
+        );
+        assert_eq!(
+            expectation,
+            UnlockExpectation {
+                token: token_id,
+                receiver,
+                amount: U128(1_000),
+            }
+        );
+    }
+
+    #[test]
+    fn test_validate_addresses() {
+        let contract = test_utils::new_contract();
+        let addresses = vec![Address([1; 20]), Address::ZERO, Address([2; 20])];
+
+        assert_eq!(
+            contract.validate_addresses(addresses.clone()),
+            [true, false, true]
+        );
+
+        // Derived account IDs get too long under a longer factory account ID.
+        near_sdk::testing_env!(near_sdk::test_utils::VMContextBuilder::new()
+            .current_account_id("a-much-longer-factory-name.near".parse().unwrap())
+            .build());
+        assert_eq!(
+            contract.validate_addresses(addresses),
+            [false, false, false]
+        );
+    }
+
+    #[test]
+    fn test_on_withdraw_complete_records_gas() {
+        let mut contract = test_utils::new_contract();
+        assert_eq!(contract.last_withdraw_gas(), None);
+
+        let result = aurora_sdk::SubmitResult {
+            version: 7,
+            status: aurora_sdk::TransactionStatus::Succeed(Vec::new()),
+            gas_used: 36_142,
+            logs: Vec::new(),
+        };
+        test_utils::set_callback_context(vec![PromiseResult::Successful(
+            result.try_to_vec().unwrap(),
+        )]);
+        let outcome = contract.on_withdraw_complete(
+            test_utils::token_account_id(&Address([1; 20])),
+            "alice.near".parse().unwrap(),
+            10.into(),
+            None,
+        );
+
+        assert_eq!(
+            outcome,
+            WithdrawOutcome {
+                success: true,
+                token: Address([1; 20]),
+                amount: 10.into(),
+            }
+        );
+        assert_eq!(contract.last_withdraw_gas(), Some(U64(36_142)));
+        assert!(near_sdk::test_utils::get_logs().is_empty());
+    }
+
+    #[test]
+    fn test_failed_unlock_reverts_withdraw() {
+        let mut contract = test_utils::new_contract();
+        let token_account_id = test_utils::token_account_id(&Address([1; 20]));
+        let sender_id: AccountId = "alice.near".parse().unwrap();
+
+        test_utils::set_callback_context(vec![PromiseResult::Failed]);
+        let outcome = contract.on_withdraw_complete(
+            token_account_id.clone(),
+            sender_id.clone(),
+            10.into(),
+            None,
+        );
+
+        assert!(!outcome.success);
+        assert_eq!(outcome.token, Address([1; 20]));
+
+        let logs = near_sdk::test_utils::get_logs();
+        assert_eq!(logs.len(), 1);
+        assert!(logs[0].contains(r#""event":"withdraw_reverted""#));
+        assert!(!logs[0].contains(r#""event":"deposit""#));
+
+        let calls = test_utils::function_calls(&token_account_id);
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].method, "deposit");
+        let args: near_sdk::serde_json::Value =
+            near_sdk::serde_json::from_slice(&calls[0].args).unwrap();
+        assert_eq!(args["receiver_id"], sender_id.as_str());
+        assert_eq!(args["amount"], "10");
+    }
+
+    #[test]
+    fn test_reverted_evm_transaction_reverts_withdraw() {
+        let mut contract = test_utils::new_contract();
+        let token_account_id = test_utils::token_account_id(&Address([1; 20]));
+        let result = aurora_sdk::SubmitResult {
+            version: 7,
+            status: aurora_sdk::TransactionStatus::Revert(Vec::new()),
+            gas_used: 21_000,
+            logs: Vec::new(),
+        };
+
+        test_utils::set_callback_context(vec![PromiseResult::Successful(
+            result.try_to_vec().unwrap(),
+        )]);
+        contract.on_withdraw_complete(
+            token_account_id.clone(),
+            "alice.near".parse().unwrap(),
+            10.into(),
+            None,
+        );
+
+        assert_eq!(contract.last_withdraw_gas(), Some(U64(21_000)));
+        assert_eq!(test_utils::function_calls(&token_account_id).len(), 1);
+    }
+
+    #[test]
+    fn test_withdraw_gas_budget() {
+        let mut contract = test_utils::new_contract();
+        let token_account_id = test_utils::token_account_id(&Address([1; 20]));
+
+        test_utils::set_context(token_account_id);
+        let _ = contract.on_withdraw(Address([3; 20]), 10.into(), None, None, None);
+
+        let calls = test_utils::function_calls(&test_utils::aurora());
+        assert_eq!(calls[0].method, "call");
+        assert!(calls[0].gas >= WITHDRAW_AURORA_CALL_GAS);
+        let callbacks = test_utils::function_calls(&test_utils::factory());
+        assert_eq!(callbacks[0].method, "on_withdraw_complete");
+        assert_eq!(
+            callbacks[0].gas,
+            Gas(WITHDRAW_CALLBACK_BASE_COST.0 + DEPOSIT_COST.0)
+        );
+    }
+
+    #[test]
+    fn test_withdraw_and_call() {
+        let mut contract = test_utils::new_contract();
+        let token = Address([1; 20]);
+        let token_account_id = test_utils::token_account_id(&token);
+
+        test_utils::set_context(token_account_id.clone());
+        let _ = contract.on_withdraw(
+            Address([3; 20]),
+            10.into(),
+            None,
+            None,
+            Some("0xdeadbeef".to_string()),
+        );
+
+        let calls = test_utils::function_calls(&test_utils::aurora());
+        assert!(calls[0].gas >= WITHDRAW_AND_CALL_AURORA_CALL_GAS);
+        let input = contract.encode_unlock_and_call(
+            &token_account_id,
+            &token,
+            &Address([3; 20]),
+            10,
+            &[0xde, 0xad, 0xbe, 0xef],
+        );
+        assert_eq!(&input[..4], &crate::WITHDRAW_AND_CALL_SELECTOR);
+        assert!(calls[0].args.ends_with(&input));
+        // A failed unlock with a call is not queued.
+        let callbacks = test_utils::function_calls(&test_utils::factory());
+        let args: near_sdk::serde_json::Value =
+            near_sdk::serde_json::from_slice(&callbacks[0].args).unwrap();
+        assert!(args["receiver_id"].is_null());
+    }
+
+    #[test]
+    fn test_withdraw_with_invalid_msg_is_refunded() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(
+            token.clone(),
+            "alice.near".parse().unwrap(),
+            10,
+            None,
+            None,
+            None,
+        );
+
+        let token_account_id = test_utils::token_account_id(&token);
+        test_utils::set_context(token_account_id.clone());
+        let outcome = contract.on_withdraw(
+            Address([3; 20]),
+            10.into(),
+            Some("alice.near".parse().unwrap()),
+            None,
+            Some("not hex".to_string()),
+        );
+
+        assert!(matches!(
+            outcome,
+            PromiseOrValue::Value(WithdrawOutcome { success: false, .. })
+        ));
+        assert_eq!(contract.mirrored_supply(token).0, 10);
+        assert!(test_utils::function_calls(&test_utils::aurora()).is_empty());
+        assert_eq!(
+            test_utils::function_calls(&token_account_id)[0].method,
+            "deposit"
+        );
+    }
+
+    #[test]
+    fn test_withdraw_without_enough_gas_is_reverted() {
+        let mut contract = test_utils::new_contract();
+        let token_account_id = test_utils::token_account_id(&Address([1; 20]));
+        let sender_id: AccountId = "alice.near".parse().unwrap();
+
+        near_sdk::testing_env!(test_utils::context(token_account_id.clone())
+            .prepaid_gas(Gas(WITHDRAW_AURORA_CALL_GAS.0
+                + WITHDRAW_CALLBACK_BASE_COST.0
+                + DEPOSIT_COST.0
+                - 1))
+            .build());
+        let result = contract.on_withdraw(
+            Address([3; 20]),
+            10.into(),
+            Some(sender_id.clone()),
+            None,
+            None,
+        );
+
+        assert!(matches!(result, PromiseOrValue::Value(outcome) if !outcome.success));
+        assert!(test_utils::function_calls(&test_utils::aurora()).is_empty());
+        let calls = test_utils::function_calls(&token_account_id);
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].method, "deposit");
+        let args: near_sdk::serde_json::Value =
+            near_sdk::serde_json::from_slice(&calls[0].args).unwrap();
+        assert_eq!(args["receiver_id"], sender_id.as_str());
+        assert_eq!(contract.gross_flows(Address([1; 20])).1, U128(0));
+    }
+
+    #[test]
+    fn test_withdraw_attaches_xcc_fee() {
+        let mut contract = test_utils::new_contract();
+        contract.set_xcc_fee(U128(1_000));
+        assert_eq!(contract.required_xcc_fee(), U128(1_000));
+        let token_account_id = test_utils::token_account_id(&Address([1; 20]));
+
+        test_utils::set_context(token_account_id.clone());
+        let _ = contract.on_withdraw(Address([3; 20]), 10.into(), None, None, None);
+        let calls = test_utils::function_calls(&test_utils::aurora());
+        assert_eq!(calls[0].deposit, 1_000);
+
+        // The fee attached to the withdraw replaces the default fee.
+        near_sdk::testing_env!(test_utils::context(token_account_id)
+            .attached_deposit(5_000)
+            .build());
+        let _ = contract.on_withdraw(Address([3; 20]), 10.into(), None, None, None);
+        let calls = test_utils::function_calls(&test_utils::aurora());
+        assert_eq!(calls[0].deposit, 5_000);
+    }
+
+    #[test]
+    fn test_rejected_withdraw_refunds_xcc_fee() {
+        let mut contract = test_utils::new_contract();
+        contract.pause(PAUSE_WITHDRAWALS);
+        let sender_id: AccountId = "alice.near".parse().unwrap();
+
+        near_sdk::testing_env!(test_utils::context(test_utils::token_account_id(&Address(
+            [1; 20]
+        )))
+        .attached_deposit(5_000)
+        .build());
+        let _ = contract.on_withdraw(
+            Address([3; 20]),
+            10.into(),
+            Some(sender_id.clone()),
+            None,
+            None,
+        );
+
+        assert!(test_utils::function_calls(&test_utils::aurora()).is_empty());
+        assert_eq!(test_utils::transfers(&sender_id), [5_000]);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_INVALID_XCC_FEE")]
+    fn test_xcc_fee_is_capped() {
+        let mut contract = test_utils::new_contract();
+        contract.set_xcc_fee(U128(MAX_XCC_FEE + 1));
+    }
+
+    #[test]
+    fn test_pause_all_tokens() {
+        let mut contract = test_utils::new_contract();
+        let first = Address([1; 20]);
+        let second = Address([2; 20]);
+
+        contract.pause_token(first.clone());
+        contract.pause_all_tokens();
+        assert!(contract.is_token_paused(first.clone()));
+        assert!(contract.is_token_paused(second.clone()));
+
+        // Individual pauses are preserved after unpausing all tokens.
+        contract.unpause_all_tokens();
+        assert!(contract.is_token_paused(first));
+        assert!(!contract.is_token_paused(second));
+    }
+
+    #[test]
+    fn test_paused_tokens() {
+        let mut contract = test_utils::new_contract();
+        let first = Address([1; 20]);
+        let second = Address([2; 20]);
+        contract.pause_token(first.clone());
+        contract.pause_token(second.clone());
+        contract.pause_all_tokens();
+
+        assert_eq!(contract.paused_tokens_count(), 2);
+        assert_eq!(
+            contract.paused_tokens(0, 10),
+            vec![first.clone(), second.clone()]
+        );
+        assert_eq!(contract.paused_tokens(1, 10), vec![second]);
+
+        contract.unpause_token(first);
+        assert_eq!(contract.paused_tokens_count(), 1);
+    }
+
+    #[test]
+    fn test_pause_events() {
+        let mut contract = test_utils::new_contract();
+        contract.pause_all_tokens();
+        contract.pause(PAUSE_WITHDRAWALS);
+
+        let events: Vec<near_sdk::serde_json::Value> = near_sdk::test_utils::get_logs()
+            .iter()
+            .map(|log| {
+                near_sdk::serde_json::from_str(log.strip_prefix("EVENT_JSON:").unwrap()).unwrap()
+            })
+            .collect();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0]["event"], "paused");
+        assert_eq!(events[0]["data"]["scope"], "all_tokens");
+        assert_eq!(events[1]["data"]["scope"], "withdrawals");
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_TOKEN_PAUSED")]
+    fn test_deposit_rejected_while_all_tokens_paused() {
+        let mut contract = test_utils::new_contract();
+        contract.pause_all_tokens();
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(
+            Address([1; 20]),
+            "alice.near".parse().unwrap(),
+            10,
+            None,
+            None,
+            None,
+        );
+    }
+
+    #[test]
+    fn test_withdraw_refunded_while_all_tokens_paused() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(
+            token.clone(),
+            "alice.near".parse().unwrap(),
+            10,
+            None,
+            None,
+            None,
+        );
+        test_utils::set_context(test_utils::factory());
+        contract.pause_all_tokens();
+
+        let token_account_id = test_utils::token_account_id(&token);
+        test_utils::set_context(token_account_id.clone());
+        let outcome = contract.on_withdraw(
+            Address([3; 20]),
+            10.into(),
+            Some("alice.near".parse().unwrap()),
+            None,
+            None,
+        );
+        assert!(matches!(
+            outcome,
+            PromiseOrValue::Value(WithdrawOutcome { success: false, .. })
+        ));
+        assert_eq!(contract.mirrored_supply(token).0, 10);
+        let calls = test_utils::function_calls(&token_account_id);
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].method, "deposit");
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_DEPLOY_PAUSED")]
+    fn test_deploy_paused_rejects_new_token() {
+        let mut contract = test_utils::new_contract_with_binary();
+        contract.pause_deploys();
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(
+            Address([1; 20]),
+            "alice.near".parse().unwrap(),
+            10,
+            None,
+            None,
+            None,
+        );
+    }
+
+    #[test]
+    fn test_deploy_paused_allows_mint_and_withdraw() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(
+            token.clone(),
+            "alice.near".parse().unwrap(),
+            10,
+            None,
+            None,
+            None,
+        );
+        test_utils::confirm_deploy(&mut contract, &token);
+
+        contract.pause_deploys();
+        assert!(contract.modes().deploy_paused);
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(
+            token.clone(),
+            "alice.near".parse().unwrap(),
+            10,
+            None,
+            None,
+            None,
+        );
+        test_utils::set_context(test_utils::token_account_id(&token));
+        let _ = contract.on_withdraw(Address([3; 20]), 5.into(), None, None, None);
+
+        assert_eq!(contract.mirrored_supply(token).0, 15);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_NOT_A_TOKEN")]
+    fn test_withdraw_rejects_short_caller() {
+        let mut contract = test_utils::new_contract();
+
+        test_utils::set_context("short.factory.near".parse().unwrap());
+        let _ = contract.on_withdraw(Address([3; 20]), 10.into(), None, None, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_NOT_A_TOKEN")]
+    fn test_withdraw_rejects_non_hex_caller() {
+        let mut contract = test_utils::new_contract();
+
+        let caller = format!("{}.factory.near", "z".repeat(40));
+        test_utils::set_context(caller.parse().unwrap());
+        let _ = contract.on_withdraw(Address([3; 20]), 10.into(), None, None, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_NOT_A_TOKEN")]
+    fn test_withdraw_rejects_short_hex_caller() {
+        let mut contract = test_utils::new_contract();
+
+        let caller = format!("{}.factory.near", &Address([1; 20]).encode()[2..]);
+        test_utils::set_context(caller.parse().unwrap());
+        let _ = contract.on_withdraw(Address([3; 20]), 10.into(), None, None, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_NOT_A_TOKEN")]
+    fn test_withdraw_rejects_foreign_suffix() {
+        let mut contract = test_utils::new_contract();
+
+        let caller = format!("{}.other.near", Address([1; 20]).encode());
+        test_utils::set_context(caller.parse().unwrap());
+        let _ = contract.on_withdraw(Address([3; 20]), 10.into(), None, None, None);
+    }
+
+    #[test]
+    fn test_default_locker_account_format() {
+        let contract = test_utils::new_contract();
+
+        assert_eq!(
+            contract.locker_account_id(),
+            test_utils::locker_account_id()
+        );
+    }
+
+    #[test]
+    fn test_alternate_locker_account_format() {
+        test_utils::set_context(test_utils::factory());
+        let mut contract = Contract::new_with_locker_format(
+            test_utils::aurora(),
+            test_utils::locker(),
+            naming::NamingScheme::Hex,
+            "0x{address}.{aurora}".to_string(),
+        );
+        contract.set_token_binary(vec![0, 97, 115, 109].into());
+        let locker_account_id: AccountId = format!("0x{}.aurora", test_utils::locker().encode())
+            .parse()
+            .unwrap();
+        assert_eq!(contract.locker_account_id(), locker_account_id);
+
+        test_utils::set_context(locker_account_id);
+        let _ = contract.create_token(Address([1; 20]), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_ZERO_LOCKER")]
+    fn test_new_rejects_zero_locker() {
+        test_utils::set_context(test_utils::factory());
+        Contract::new(test_utils::aurora(), Address([0; 20]), None);
+    }
+
+    #[test]
+    fn test_confirm_locker() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let old_locker = test_utils::locker();
+        let new_locker = Address([0x0b; 20]);
+        let new_locker_account_id: AccountId =
+            format!("{}.{}", new_locker.encode(), test_utils::aurora())
+                .parse()
+                .unwrap();
+
+        test_utils::set_locker(&mut contract, new_locker.clone());
+        assert_eq!(contract.locker_account_id(), new_locker_account_id);
+
+        let logs = near_sdk::test_utils::get_logs();
+        assert_eq!(logs.len(), 1);
+        let event: near_sdk::serde_json::Value =
+            near_sdk::serde_json::from_str(logs[0].strip_prefix("EVENT_JSON:").unwrap()).unwrap();
+        assert_eq!(event["event"], "locker_changed");
+        assert_eq!(event["data"]["old_locker"], old_locker.to_string());
+        assert_eq!(event["data"]["new_locker"], new_locker.to_string());
+
+        // The new locker passes `assert_locker`.
+        test_utils::set_context(new_locker_account_id);
+        let _ = contract.create_token(Address([1; 20]), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_ONLY_LOCKER")]
+    fn test_confirm_locker_revokes_old_locker() {
+        let mut contract = test_utils::new_contract_with_binary();
+        test_utils::set_locker(&mut contract, Address([0x0b; 20]));
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.create_token(Address([1; 20]), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_ZERO_LOCKER")]
+    fn test_confirm_locker_rejects_zero_address() {
+        let mut contract = test_utils::new_contract();
+        test_utils::set_locker(&mut contract, Address([0; 20]));
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_INVALID_LOCKER_FORMAT")]
+    fn test_locker_account_format_without_address() {
+        test_utils::set_context(test_utils::factory());
+        Contract::new_with_locker_format(
+            test_utils::aurora(),
+            test_utils::locker(),
+            naming::NamingScheme::Hex,
+            "locker.{aurora}".to_string(),
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_INVALID_LOCKER_FORMAT")]
+    fn test_locker_account_format_with_invalid_account_id() {
+        test_utils::set_context(test_utils::factory());
+        Contract::new_with_locker_format(
+            test_utils::aurora(),
+            test_utils::locker(),
+            naming::NamingScheme::Hex,
+            "{address}..{aurora}".to_string(),
+        );
+    }
+
+    /// Context where the locker calls the factory, which has `balance` and no storage
+    /// locked yet.
+    fn set_locker_context_with_balance(balance: Balance) {
+        near_sdk::testing_env!(test_utils::context(test_utils::locker_account_id())
+            .storage_usage(0)
+            .account_balance(balance)
+            .build());
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_INSUFFICIENT_ATTACHED")]
+    fn test_deploy_rejected_with_insufficient_attached_deposit() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let storage_deposit = contract.required_storage_deposit();
+        contract.set_min_deploy_attached_deposit(U128(storage_deposit));
+
+        near_sdk::testing_env!(test_utils::context(test_utils::locker_account_id())
+            .attached_deposit(storage_deposit - 1)
+            .build());
+        let _ = contract.on_deposit(
+            Address([1; 20]),
+            "alice.near".parse().unwrap(),
+            10,
+            None,
+            None,
+            None,
+        );
+    }
+
+    #[test]
+    fn test_deploy_refunds_excess_attached_deposit() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        let storage_deposit = contract.required_storage_deposit();
+        contract.set_min_deploy_attached_deposit(U128(storage_deposit));
+
+        near_sdk::testing_env!(test_utils::context(test_utils::locker_account_id())
+            .attached_deposit(storage_deposit + 5)
+            .build());
+        let _ = contract.on_deposit(
+            token.clone(),
+            "alice.near".parse().unwrap(),
+            10,
+            None,
+            None,
+            None,
+        );
+
+        assert_eq!(test_utils::transfers(&test_utils::locker_account_id()), [5]);
+        assert_eq!(contract.mirrored_supply(token.clone()).0, 10);
+
+        // The exact amount needs no refund.
+        near_sdk::testing_env!(test_utils::context(test_utils::locker_account_id())
+            .attached_deposit(storage_deposit)
+            .build());
+        let _ = contract.on_deposit(
+            Address([2; 20]),
+            "alice.near".parse().unwrap(),
+            10,
+            None,
+            None,
+            None,
+        );
+        assert!(test_utils::transfers(&test_utils::locker_account_id()).is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_INSUFFICIENT_FACTORY_BALANCE")]
+    fn test_deploy_rejected_below_balance_threshold() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let required = DEFAULT_CREATION_TRANSFER
+            + contract.required_storage_deposit()
+            + DEFAULT_DEPLOY_BALANCE_MARGIN;
+
+        set_locker_context_with_balance(required - 1);
+        let _ = contract.on_deposit(
+            Address([1; 20]),
+            "alice.near".parse().unwrap(),
+            10,
+            None,
+            None,
+            None,
+        );
+    }
+
+    #[test]
+    fn test_deploy_accepted_above_balance_threshold() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        let required = DEFAULT_CREATION_TRANSFER
+            + contract.required_storage_deposit()
+            + DEFAULT_DEPLOY_BALANCE_MARGIN;
+
+        // Leave room for the storage written by the deposit before the check.
+        set_locker_context_with_balance(required + required / 10);
+        let _ = contract.on_deposit(
+            token.clone(),
+            "alice.near".parse().unwrap(),
+            10,
+            None,
+            None,
+            None,
+        );
+        test_utils::confirm_deploy(&mut contract, &token);
+
+        // Mint-only deposits don't need balance.
+        set_locker_context_with_balance(0);
+        let _ = contract.on_deposit(
+            token.clone(),
+            "alice.near".parse().unwrap(),
+            10,
+            None,
+            None,
+            None,
+        );
+        assert_eq!(contract.mirrored_supply(token).0, 20);
+    }
+
+    #[test]
+    fn test_remaining_capacity() {
+        let mut contract = test_utils::new_contract_with_binary();
+        contract.set_creation_transfer(1, Some(U128(1_000)));
+        let cost = 1_000 + contract.required_storage_deposit();
+
+        near_sdk::testing_env!(test_utils::context(test_utils::factory())
+            .storage_usage(0)
+            .account_balance(DEFAULT_DEPLOY_BALANCE_MARGIN + 3 * cost + cost / 2)
+            .build());
+        assert_eq!(contract.remaining_capacity(), 3);
+
+        near_sdk::testing_env!(test_utils::context(test_utils::factory())
+            .storage_usage(0)
+            .account_balance(DEFAULT_DEPLOY_BALANCE_MARGIN - 1)
+            .build());
+        assert_eq!(contract.remaining_capacity(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_ZERO_TOKEN")]
+    fn test_deposit_rejects_zero_token() {
+        let mut contract = test_utils::new_contract_with_binary();
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(
+            Address::ZERO,
+            "alice.near".parse().unwrap(),
+            10,
+            None,
+            None,
+            None,
+        );
+    }
+
+    #[test]
+    fn test_deposit_accepts_non_zero_token() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(
+            token.clone(),
+            "alice.near".parse().unwrap(),
+            10,
+            None,
+            None,
+            None,
+        );
+        test_utils::confirm_deploy(&mut contract, &token);
+
+        assert!(contract
+            .tokens
+            .get(&test_utils::token_account_id(&token))
+            .is_some());
+    }
+
+    #[test]
+    fn test_deploy_forwards_token_metadata() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        let metadata = aurora_sdk::TokenMetadata {
+            name: "USD Coin".to_string(),
+            symbol: "USDC".to_string(),
+            decimals: 6,
+        };
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(
+            token.clone(),
+            "alice.near".parse().unwrap(),
+            10,
+            None,
+            Some(metadata),
+            None,
+        );
+
+        let calls = test_utils::function_calls(&test_utils::token_account_id(&token));
+        assert_eq!(calls[0].method, "new");
+        let args: near_sdk::serde_json::Value =
+            near_sdk::serde_json::from_slice(&calls[0].args).unwrap();
+        assert_eq!(args["metadata"]["name"], "USD Coin");
+        assert_eq!(args["metadata"]["symbol"], "USDC");
+        assert_eq!(args["metadata"]["decimals"], 6);
+    }
+
+    #[test]
+    fn test_deploy_accepts_decimals_up_to_max() {
+        let mut contract = test_utils::new_contract_with_binary();
+        assert_eq!(contract.max_decimals(), DEFAULT_MAX_DECIMALS);
+        let token = Address([1; 20]);
+        let metadata = aurora_sdk::TokenMetadata {
+            name: "Wrapped NEAR".to_string(),
+            symbol: "WNEAR".to_string(),
+            decimals: DEFAULT_MAX_DECIMALS,
+        };
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.create_token(token.clone(), Some(metadata));
+
+        let calls = test_utils::function_calls(&test_utils::token_account_id(&token));
+        let args: near_sdk::serde_json::Value =
+            near_sdk::serde_json::from_slice(&calls[0].args).unwrap();
+        assert_eq!(args["metadata"]["decimals"], 24);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_DECIMALS_OUT_OF_RANGE")]
+    fn test_deploy_rejects_decimals_above_max() {
+        let mut contract = test_utils::new_contract_with_binary();
+        contract.set_max_decimals(18);
+        let metadata = aurora_sdk::TokenMetadata {
+            name: "Odd Token".to_string(),
+            symbol: "ODD".to_string(),
+            decimals: 19,
+        };
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(
+            Address([1; 20]),
+            "alice.near".parse().unwrap(),
+            10,
+            None,
+            Some(metadata),
+            None,
+        );
+    }
+
+    #[test]
+    fn test_deploy_without_token_metadata() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([0xab; 20]);
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.create_token(token.clone(), None);
+
+        let calls = test_utils::function_calls(&test_utils::token_account_id(&token));
+        assert_eq!(calls[0].method, "new");
+        let args: near_sdk::serde_json::Value =
+            near_sdk::serde_json::from_slice(&calls[0].args).unwrap();
+        assert_eq!(args["metadata"]["name"], token.to_string());
+        assert_eq!(args["metadata"]["symbol"], "ABABABAB");
+        assert_eq!(args["metadata"]["decimals"], 18);
+    }
+
+    #[test]
+    fn test_deposit_event_includes_origin_tx_hash() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(
+            token.clone(),
+            "alice.near".parse().unwrap(),
+            10,
+            Some([0xab; 32]),
+            None,
+            None,
+        );
+
+        let logs = near_sdk::test_utils::get_logs();
+        let event: near_sdk::serde_json::Value =
+            near_sdk::serde_json::from_str(logs[0].strip_prefix("EVENT_JSON:").unwrap()).unwrap();
+        assert_eq!(event["event"], "deposit");
+        assert_eq!(
+            event["data"]["origin_tx_hash"],
+            format!("0x{}", "ab".repeat(32))
+        );
+        assert_eq!(event["data"]["token"], token.to_string());
+    }
+
+    #[test]
+    fn test_event_context_in_emitted_events() {
+        let mut contract = test_utils::new_contract_with_binary();
+        contract.set_event_context(Some("testnet-2".to_string()));
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(
+            Address([1; 20]),
+            "alice.near".parse().unwrap(),
+            10,
+            None,
+            None,
+            None,
+        );
+
+        let logs = near_sdk::test_utils::get_logs();
+        assert!(logs[0].contains(r#""context":"testnet-2""#));
+    }
+
+    #[test]
+    fn test_compact_event_format() {
+        let mut contract = test_utils::new_contract_with_binary();
+        contract.set_event_format(EventFormat::Compact);
+        assert_eq!(contract.event_format(), EventFormat::Compact);
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(
+            Address([1; 20]),
+            "alice.near".parse().unwrap(),
+            10,
+            None,
+            None,
+            None,
+        );
+
+        let logs = near_sdk::test_utils::get_logs();
+        assert!(logs[0].starts_with("EVENT:deposit amount=10 "));
+    }
+
+    #[test]
+    fn test_heartbeat() {
+        let contract = test_utils::new_contract_with_binary();
+        near_sdk::testing_env!(test_utils::context(test_utils::factory())
+            .block_index(77)
+            .build());
+
+        contract.heartbeat();
+
+        let logs = near_sdk::test_utils::get_logs();
+        assert_eq!(logs.len(), 1);
+        let event: near_sdk::serde_json::Value =
+            near_sdk::serde_json::from_str(logs[0].strip_prefix("EVENT_JSON:").unwrap()).unwrap();
+        assert_eq!(event["event"], "heartbeat");
+        assert_eq!(event["data"]["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(event["data"]["block_height"], "77");
+        assert_eq!(event["data"]["token_binary_version"], 1);
+    }
+
+    #[test]
+    fn test_modes() {
+        let mut contract = test_utils::new_contract();
+        assert!(!contract.modes().all_tokens_paused);
+
+        contract.pause_all_tokens();
+        assert!(contract.modes().all_tokens_paused);
+    }
+
+    #[test]
+    fn test_pause_deposits() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(
+            token.clone(),
+            "alice.near".parse().unwrap(),
+            10,
+            None,
+            None,
+            None,
+        );
+
+        test_utils::set_context(test_utils::factory());
+        contract.pause(PAUSE_DEPOSITS);
+        assert!(contract.modes().deposits_paused);
+        assert!(!contract.modes().withdrawals_paused);
+
+        // Withdrawals and views keep working.
+        test_utils::set_context(test_utils::token_account_id(&token));
+        let _ = contract.on_withdraw(Address([3; 20]), 5.into(), None, None, None);
+        assert_eq!(
+            contract.locker_account_id(),
+            test_utils::locker_account_id()
+        );
+
+        test_utils::set_context(test_utils::factory());
+        contract.unpause(PAUSE_DEPOSITS);
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(
+            token.clone(),
+            "alice.near".parse().unwrap(),
+            10,
+            None,
+            None,
+            None,
+        );
+        assert_eq!(contract.mirrored_supply(token).0, 15);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_PAUSED")]
+    fn test_pause_deposits_rejects_mint() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(
+            token.clone(),
+            "alice.near".parse().unwrap(),
+            10,
+            None,
+            None,
+            None,
+        );
+
+        // The locker keeps calling after the factory is paused.
+        test_utils::set_context(test_utils::factory());
+        contract.pause(PAUSE_DEPOSITS);
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(token, "alice.near".parse().unwrap(), 10, None, None, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_PAUSED")]
+    fn test_pause_create_token_rejects_create_token() {
+        let mut contract = test_utils::new_contract_with_binary();
+        contract.pause(PAUSE_CREATE_TOKEN);
+        assert!(contract.modes().create_token_paused);
+        assert!(!contract.modes().deposits_paused);
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.create_token(Address([1; 20]), None);
+    }
+
+    #[test]
+    fn test_pause_deposits_allows_create_token() {
+        let mut contract = test_utils::new_contract_with_binary();
+        contract.pause(PAUSE_DEPOSITS);
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.create_token(Address([1; 20]), None);
+        assert_eq!(
+            test_utils::function_calls(&test_utils::token_account_id(&Address([1; 20])))[0].method,
+            "new"
+        );
+    }
+
+    #[test]
+    fn test_pause_withdrawals_refunds_withdraw() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        contract.pause(PAUSE_WITHDRAWALS);
+        assert!(contract.modes().withdrawals_paused);
+
+        // Deposits keep working.
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(
+            token.clone(),
+            "alice.near".parse().unwrap(),
+            10,
+            None,
+            None,
+            None,
+        );
+
+        let token_account_id = test_utils::token_account_id(&token);
+        test_utils::set_context(token_account_id.clone());
+        let outcome = contract.on_withdraw(Address([3; 20]), 5.into(), None, None, None);
+        assert!(matches!(
+            outcome,
+            PromiseOrValue::Value(WithdrawOutcome { success: false, .. })
+        ));
+        // The burnt tokens are minted back, the locker on Aurora is not called.
+        assert_eq!(contract.mirrored_supply(token).0, 10);
+        let calls = test_utils::function_calls(&token_account_id);
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].method, "deposit");
+        assert!(test_utils::function_calls(&test_utils::aurora()).is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_INVALID_PAUSE_FLAGS")]
+    fn test_pause_rejects_unknown_flags() {
+        let mut contract = test_utils::new_contract();
+
+        contract.pause(1 << 3);
+    }
+
+    #[test]
+    fn test_create_token_with_registrations() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        let accounts: Vec<AccountId> =
+            vec!["alice.near".parse().unwrap(), "bob.near".parse().unwrap()];
+
+        let _ = contract.create_token_with_registrations(token.clone(), accounts.clone());
+
+        let calls = test_utils::function_calls(&test_utils::token_account_id(&token));
+        let methods: Vec<&str> = calls.iter().map(|call| call.method.as_str()).collect();
+        assert_eq!(methods, ["new", "storage_deposit", "storage_deposit"]);
+        for (call, account_id) in calls[1..].iter().zip(accounts) {
+            let args: near_sdk::serde_json::Value =
+                near_sdk::serde_json::from_slice(&call.args).unwrap();
+            assert_eq!(args["account_id"], account_id.as_str());
+        }
+        // The token is only registered once the deploy succeeded.
+        assert_eq!(
+            contract.get_token_version(test_utils::token_account_id(&token)),
+            None
+        );
+        test_utils::confirm_deploy(&mut contract, &token);
+        assert_eq!(
+            contract.get_token_version(test_utils::token_account_id(&token)),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn test_token_registered_at_deployed_version() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        let token_account_id = test_utils::token_account_id(&token);
+        let _ = contract.create_token_with_registrations(token.clone(), Vec::new());
+        let calls = test_utils::function_calls(&test_utils::factory());
+        let args: near_sdk::serde_json::Value =
+            near_sdk::serde_json::from_slice(&calls[0].args).unwrap();
+        assert_eq!(args["token_binary_version"], 1);
+
+        // The binary changes while the deploy is in flight.
+        test_utils::set_context(test_utils::factory());
+        test_utils::replace_token_binary(&mut contract, vec![0, 97, 115, 109, 2]);
+        test_utils::set_callback_context(vec![PromiseResult::Successful(Vec::new())]);
+        let _ = contract.on_token_deployed(token, token_account_id.clone(), Some(1), None);
+
+        assert_eq!(contract.get_token_version(token_account_id), Some(1));
+        assert_eq!(contract.version_distribution(), [(1, 1)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_TOKEN_DEPLOYMENT_FAILED")]
+    fn test_failed_create_token_with_registrations_is_not_registered() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        let _ = contract.create_token_with_registrations(token.clone(), Vec::new());
+
+        test_utils::set_callback_context(vec![PromiseResult::Failed]);
+        let _ = contract.on_token_deployed(
+            token.clone(),
+            test_utils::token_account_id(&token),
+            Some(1),
+            None,
+        );
+    }
+
+    #[test]
+    fn test_creation_transfer_by_binary_version() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        contract.set_creation_transfer(1, Some(U128(5)));
+        contract.set_creation_transfer(2, Some(U128(7)));
+
+        let _ = contract.create_token_with_registrations(token.clone(), Vec::new());
+
+        assert_eq!(
+            test_utils::transfers(&test_utils::token_account_id(&token)),
+            [5]
+        );
+        assert_eq!(
+            contract.creation_transfer(3),
+            U128(DEFAULT_CREATION_TRANSFER)
+        );
+    }
+
+    #[test]
+    fn test_token_spec_version() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let first = Address([1; 20]);
+        let second = Address([2; 20]);
+        contract.set_spec_version(2, Some(7));
+        assert_eq!(contract.spec_version(1), 1);
+        assert_eq!(contract.spec_version(2), 7);
+
+        test_utils::deploy_token(&mut contract, &first);
+        test_utils::replace_token_binary(&mut contract, vec![0, 97, 115, 109, 2]);
+        test_utils::deploy_token(&mut contract, &second);
+
+        assert_eq!(contract.token_spec_version(first), Some(1));
+        assert_eq!(contract.token_spec_version(second), Some(7));
+        assert_eq!(contract.token_spec_version(Address([3; 20])), None);
+    }
+
+    #[test]
+    fn test_effective_deploy_config() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let default_config = DeployConfig {
+            gas: TOKEN_DEPLOYMENT_COST,
+            storage_deposit: U128(contract.required_storage_deposit()),
+            creation_transfer: U128(DEFAULT_CREATION_TRANSFER),
+        };
+        assert_eq!(contract.effective_deploy_config(), default_config);
+
+        contract.set_creation_transfer(2, Some(U128(7)));
+        assert_eq!(contract.effective_deploy_config(), default_config);
+
+        test_utils::replace_token_binary(&mut contract, vec![0, 97, 115, 109, 2]);
+        assert_eq!(
+            contract.effective_deploy_config(),
+            DeployConfig {
+                storage_deposit: U128(contract.required_storage_deposit()),
+                creation_transfer: U128(7),
+                ..default_config
+            }
+        );
+    }
+
+    #[test]
+    fn test_storage_deposit_grows_with_binary() {
+        let mut contract = test_utils::new_contract();
+        assert_eq!(
+            contract.required_storage_deposit(),
+            Balance::from(TOKEN_ACCOUNT_STORAGE_OVERHEAD) * near_sdk::env::storage_byte_cost()
+        );
+
+        contract.set_token_binary(vec![0; 1_000].into());
+        let small = contract.required_storage_deposit();
+        test_utils::replace_token_binary(&mut contract, vec![0; 3_000]);
+        let large = contract.required_storage_deposit();
+
+        assert_eq!(large - small, 2_000 * near_sdk::env::storage_byte_cost());
+        assert_eq!(
+            contract.effective_deploy_config().storage_deposit,
+            U128(large)
+        );
+    }
+
+    #[test]
+    fn test_version_distribution() {
+        let mut contract = test_utils::new_contract_with_binary();
+        assert!(contract.version_distribution().is_empty());
+
+        test_utils::deploy_token(&mut contract, &Address([1; 20]));
+        test_utils::deploy_token(&mut contract, &Address([2; 20]));
+        test_utils::replace_token_binary(&mut contract, vec![0, 97, 115, 109, 2]);
+        test_utils::deploy_token(&mut contract, &Address([3; 20]));
+
+        assert_eq!(contract.version_distribution(), [(1, 2), (2, 1)]);
+    }
+
+    #[test]
+    fn test_token_origin() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        assert_eq!(contract.token_origin(token.clone()), None);
+
+        near_sdk::testing_env!(test_utils::context(test_utils::locker_account_id())
+            .block_index(10)
+            .build());
+        let _ = contract.create_token(token.clone(), None);
+        // A second deploy doesn't replace the origin.
+        near_sdk::testing_env!(test_utils::context(test_utils::factory())
+            .block_index(20)
+            .build());
+        let _ = contract.create_token_with_registrations(token.clone(), Vec::new());
+        test_utils::confirm_deploy(&mut contract, &token);
+
+        assert_eq!(
+            contract.token_origin(token),
+            Some(TokenOrigin {
+                deployer: test_utils::locker_account_id(),
+                deployed_at: U64(10),
+            })
+        );
+
+        let other = Address([2; 20]);
+        test_utils::deploy_token(&mut contract, &other);
+        assert_eq!(
+            contract.token_origin(other).unwrap().deployer,
+            test_utils::factory()
+        );
+    }
+
+    #[test]
+    fn test_tokens_created_between() {
+        let mut contract = test_utils::new_contract_with_binary();
+        for (block, token) in [
+            (10, Address([1; 20])),
+            (20, Address([2; 20])),
+            (30, Address([3; 20])),
+        ] {
+            near_sdk::testing_env!(test_utils::context(test_utils::factory())
+                .block_index(block)
+                .build());
+            test_utils::deploy_token(&mut contract, &token);
+        }
+
+        assert_eq!(contract.token_created_at(Address([2; 20])), Some(U64(20)));
+        assert_eq!(
+            contract.tokens_created_between(U64(15), U64(30), 0, 10),
+            [
+                test_utils::token_account_id(&Address([2; 20])),
+                test_utils::token_account_id(&Address([3; 20])),
+            ]
+        );
+        assert_eq!(
+            contract.tokens_created_between(U64(15), U64(30), 0, 2),
+            [test_utils::token_account_id(&Address([2; 20]))]
+        );
+        assert!(contract
+            .tokens_created_between(U64(21), U64(29), 0, 10)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_token_last_activity() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        assert_eq!(contract.token_last_activity(token.clone()), None);
+
+        near_sdk::testing_env!(test_utils::context(test_utils::locker_account_id())
+            .block_index(10)
+            .build());
+        let _ = contract.on_deposit(
+            token.clone(),
+            "alice.near".parse().unwrap(),
+            10,
+            None,
+            None,
+            None,
+        );
+        assert_eq!(contract.token_last_activity(token.clone()), Some(U64(10)));
+
+        near_sdk::testing_env!(test_utils::context(test_utils::token_account_id(&token))
+            .block_index(25)
+            .build());
+        let _ = contract.on_withdraw(Address([3; 20]), 5.into(), None, None, None);
+        assert_eq!(contract.token_last_activity(token.clone()), Some(U64(25)));
+
+        // Other tokens are not affected.
+        assert_eq!(contract.token_last_activity(Address([2; 20])), None);
+    }
+
+    #[test]
+    fn test_get_tokens() {
+        let mut contract = test_utils::new_contract_with_binary();
+        test_utils::deploy_token(&mut contract, &Address([1; 20]));
+        test_utils::replace_token_binary(&mut contract, vec![0, 97, 115, 109, 2]);
+        test_utils::deploy_token(&mut contract, &Address([2; 20]));
+        test_utils::deploy_token(&mut contract, &Address([3; 20]));
+        let all = [
+            (test_utils::token_account_id(&Address([1; 20])), 1),
+            (test_utils::token_account_id(&Address([2; 20])), 2),
+            (test_utils::token_account_id(&Address([3; 20])), 2),
+        ];
+
+        assert_eq!(contract.tokens_count(), 3);
+        assert_eq!(contract.get_tokens(0, 10), all);
+        assert_eq!(
+            [contract.get_tokens(0, 2), contract.get_tokens(2, 2)].concat(),
+            all
+        );
+        assert!(contract.get_tokens(0, 0).is_empty());
+        assert!(contract.get_tokens(3, 10).is_empty());
+    }
+
+    #[test]
+    fn test_registered_token_lookups() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        let token_account_id = test_utils::token_account_id(&token);
+        assert_eq!(contract.get_token_account_id(token.clone()), None);
+        assert_eq!(contract.get_erc20_address(token_account_id.clone()), None);
+        assert_eq!(contract.get_token_version(token_account_id.clone()), None);
+
+        test_utils::deploy_token(&mut contract, &token);
+        assert_eq!(
+            contract.get_token_account_id(token.clone()),
+            Some(token_account_id.clone())
+        );
+        assert_eq!(
+            contract.get_erc20_address(token_account_id.clone()),
+            Some(token)
+        );
+        assert_eq!(contract.get_token_version(token_account_id), Some(1));
+        assert_eq!(
+            contract.get_erc20_address("alice.near".parse().unwrap()),
+            None
+        );
+    }
+
+    #[test]
+    fn test_pruning_keeps_total_deploys() {
+        let mut contract = test_utils::new_contract_with_binary();
+        test_utils::deploy_token(&mut contract, &Address([1; 20]));
+        test_utils::deploy_token(&mut contract, &Address([2; 20]));
+
+        contract.prune_token(Address([1; 20]));
+
+        assert_eq!(contract.tokens_count(), 1);
+        assert_eq!(contract.total_deploys(), 2);
+        assert_eq!(contract.version_distribution(), [(1, 1)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_TOKEN_HAS_SUPPLY")]
+    fn test_prune_token_with_supply() {
+        let mut contract = test_utils::new_contract_with_binary();
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(
+            Address([1; 20]),
+            "alice.near".parse().unwrap(),
+            10,
+            None,
+            None,
+            None,
+        );
+        test_utils::confirm_deploy(&mut contract, &Address([1; 20]));
+
+        contract.prune_token(Address([1; 20]));
+    }
+
+    #[test]
+    fn test_deregister_token() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        let token_account_id = test_utils::token_account_id(&token);
+        test_utils::deploy_token(&mut contract, &token);
+
+        test_utils::set_context(test_utils::factory());
+        let _ = contract.deregister_token(token.clone());
+        let calls = test_utils::function_calls(&token_account_id);
+        assert_eq!(calls[0].method, "ft_total_supply");
+        assert_eq!(contract.tokens_count(), 1);
+
+        test_utils::set_callback_context(vec![PromiseResult::Successful(
+            near_sdk::serde_json::to_vec(&U128(0)).unwrap(),
+        )]);
+        contract.on_deregister_token(token.clone(), token_account_id);
+
+        assert_eq!(contract.tokens_count(), 0);
+        assert!(!contract.is_token_deployed(token));
+        let logs = near_sdk::test_utils::get_logs();
+        assert!(logs[0].contains(r#""event":"token_deregistered""#));
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_TOKEN_HAS_SUPPLY")]
+    fn test_deregister_token_with_supply() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        test_utils::deploy_token(&mut contract, &token);
+
+        test_utils::set_callback_context(vec![PromiseResult::Successful(
+            near_sdk::serde_json::to_vec(&U128(10)).unwrap(),
+        )]);
+        contract.on_deregister_token(token.clone(), test_utils::token_account_id(&token));
+    }
+
+    #[test]
+    fn test_set_token_transfers_enabled() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        test_utils::deploy_token(&mut contract, &token);
+
+        test_utils::set_context(test_utils::factory());
+        let _ = contract.set_token_transfers_enabled(token.clone(), false);
+
+        let calls = test_utils::function_calls(&test_utils::token_account_id(&token));
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].method, "set_transfers_enabled");
+        let args: near_sdk::serde_json::Value =
+            near_sdk::serde_json::from_slice(&calls[0].args).unwrap();
+        assert_eq!(args["enabled"], false);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_TOKEN_NOT_DEPLOYED")]
+    fn test_set_transfers_enabled_of_unknown_token() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let _ = contract.set_token_transfers_enabled(Address([1; 20]), false);
+    }
+
+    #[test]
+    fn test_transfer_token_control() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        test_utils::deploy_token(&mut contract, &token);
+
+        test_utils::set_context(test_utils::factory());
+        let _ = contract.transfer_token_control(token.clone(), "factory-v2.near".parse().unwrap());
+
+        let calls = test_utils::function_calls(&test_utils::token_account_id(&token));
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].method, "set_controller");
+        assert_eq!(calls[0].gas, SET_CONTROLLER_GAS);
+        let args: near_sdk::serde_json::Value =
+            near_sdk::serde_json::from_slice(&calls[0].args).unwrap();
+        assert_eq!(args["controller"], "factory-v2.near");
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_TOKEN_NOT_DEPLOYED")]
+    fn test_transfer_control_of_unknown_token() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let _ =
+            contract.transfer_token_control(Address([1; 20]), "factory-v2.near".parse().unwrap());
+    }
+
+    #[test]
+    fn test_set_token_metadata() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        test_utils::deploy_token(&mut contract, &token);
+
+        test_utils::set_context(test_utils::factory());
+        let _ = contract.set_token_metadata(
+            token.clone(),
+            aurora_sdk::TokenMetadata {
+                name: "Wrapped Ether".to_string(),
+                symbol: "WETH".to_string(),
+                decimals: 18,
+            },
+        );
+
+        let calls = test_utils::function_calls(&test_utils::token_account_id(&token));
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].method, "update_metadata");
+        assert_eq!(calls[0].gas, UPDATE_METADATA_GAS);
+        let args: near_sdk::serde_json::Value =
+            near_sdk::serde_json::from_slice(&calls[0].args).unwrap();
+        assert_eq!(args["metadata"]["symbol"], "WETH");
+        assert_eq!(args["metadata"]["decimals"], 18);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_TOKEN_NOT_DEPLOYED")]
+    fn test_set_metadata_of_unknown_token() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let _ = contract.set_token_metadata(
+            Address([1; 20]),
+            aurora_sdk::TokenMetadata {
+                name: "Wrapped Ether".to_string(),
+                symbol: "WETH".to_string(),
+                decimals: 18,
+            },
+        );
+    }
+
+    #[test]
+    fn test_register_receivers() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        test_utils::deploy_token(&mut contract, &token);
+        let accounts: Vec<AccountId> =
+            vec!["alice.near".parse().unwrap(), "bob.near".parse().unwrap()];
+
+        test_utils::set_context(test_utils::factory());
+        let _ = contract.register_receivers(token.clone(), accounts.clone());
+
+        let calls = test_utils::function_calls(&test_utils::token_account_id(&token));
+        assert_eq!(calls.len(), accounts.len());
+        for (call, account_id) in calls.iter().zip(accounts) {
+            assert_eq!(call.method, "storage_deposit");
+            assert_eq!(call.deposit, STORAGE_DEPOSIT_COST);
+            let args: near_sdk::serde_json::Value =
+                near_sdk::serde_json::from_slice(&call.args).unwrap();
+            assert_eq!(args["account_id"], account_id.as_str());
+        }
+    }
+
+    #[test]
+    fn test_register_receivers_uses_configured_storage_deposit() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        test_utils::deploy_token(&mut contract, &token);
+
+        test_utils::set_context(test_utils::factory());
+        contract.set_receiver_storage_deposit(U128(7));
+        let _ = contract.register_receivers(token.clone(), vec!["alice.near".parse().unwrap()]);
+
+        let calls = test_utils::function_calls(&test_utils::token_account_id(&token));
+        assert_eq!(calls[0].deposit, 7);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_INVALID_STORAGE_DEPOSIT")]
+    fn test_set_receiver_storage_deposit_above_max() {
+        let mut contract = test_utils::new_contract();
+
+        test_utils::set_context(test_utils::factory());
+        contract.set_receiver_storage_deposit(U128(MAX_STORAGE_DEPOSIT + 1));
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_TOKEN_NOT_DEPLOYED")]
+    fn test_register_receivers_on_unknown_token() {
+        let mut contract = test_utils::new_contract();
+
+        let _ = contract.register_receivers(Address([1; 20]), vec!["alice.near".parse().unwrap()]);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_TOO_MANY_ACCOUNTS")]
+    fn test_create_token_with_too_many_registrations() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let accounts = (0..=DEFAULT_MAX_REGISTRATIONS_PER_CALL)
+            .map(|i| format!("user{}.near", i).parse().unwrap())
+            .collect();
+
+        let _ = contract.create_token_with_registrations(Address([1; 20]), accounts);
+    }
+
+    #[test]
+    fn test_rescue_ft() {
+        let mut contract = test_utils::new_contract();
+        let token: AccountId = "usdc.near".parse().unwrap();
+
+        let _ = contract.rescue_ft(token.clone(), U128(10), "alice.near".parse().unwrap());
+
+        let calls = test_utils::function_calls(&token);
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].method, "ft_transfer");
+        assert_eq!(calls[0].deposit, 1);
+        let args: near_sdk::serde_json::Value =
+            near_sdk::serde_json::from_slice(&calls[0].args).unwrap();
+        assert_eq!(args["receiver_id"], "alice.near");
+        assert_eq!(args["amount"], "10");
+    }
+
+    #[test]
+    fn test_withdraw_near_keeps_reserve() {
+        let mut contract = test_utils::new_contract();
+        let to: AccountId = "alice.near".parse().unwrap();
+        contract.set_reserve_balance(U128(7));
+        let sweepable = contract.sweepable_balance().0;
+        assert_eq!(sweepable, contract.available_balance().0 - 7);
+
+        let _ = contract.withdraw_near(U128(sweepable), to.clone());
+
+        assert_eq!(test_utils::transfers(&to), [sweepable]);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_RESERVE_BREACHED")]
+    fn test_withdraw_near_rejects_breaching_reserve() {
+        let mut contract = test_utils::new_contract();
+        let sweepable = contract.sweepable_balance().0;
+
+        let _ = contract.withdraw_near(U128(sweepable + 1), "alice.near".parse().unwrap());
+    }
+
+    #[test]
+    fn test_creation_notifier_called_after_deploy() {
+        let mut contract = test_utils::new_contract();
+        let notifier: AccountId = "notifier.near".parse().unwrap();
+        let token = Address([1; 20]);
+        contract.set_creation_notifier(Some(notifier.clone()));
+
+        test_utils::set_callback_context(vec![PromiseResult::Successful(Vec::new())]);
+        let _ = contract.on_token_deployed(
+            token.clone(),
+            test_utils::token_account_id(&token),
+            None,
+            None,
+        );
+
+        let calls = test_utils::function_calls(&notifier);
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].method, "on_token_created");
+    }
+
+    #[test]
+    fn test_token_deployed_event() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.create_token(token.clone(), None);
+        test_utils::confirm_deploy(&mut contract, &token);
+
+        let logs = near_sdk::test_utils::get_logs();
+        assert_eq!(logs.len(), 1);
+        let event: near_sdk::serde_json::Value =
+            near_sdk::serde_json::from_str(logs[0].strip_prefix("EVENT_JSON:").unwrap()).unwrap();
+        assert_eq!(event["standard"], "nep141-connector");
+        assert_eq!(event["event"], "token_deployed");
+        assert_eq!(event["data"]["token"], token.to_string());
+        assert_eq!(
+            event["data"]["token_account_id"],
+            test_utils::token_account_id(&token).as_str()
+        );
+        assert_eq!(event["data"]["token_binary_version"], 1);
+    }
+
+    #[test]
+    fn test_withdraw_event() {
+        let mut contract = test_utils::new_contract();
+        let token = Address([1; 20]);
+
+        test_utils::set_context(test_utils::token_account_id(&token));
+        let _ = contract.on_withdraw(
+            Address([3; 20]),
+            U128(u128::MAX),
+            Some("alice.near".parse().unwrap()),
+            None,
+            None,
+        );
+
+        let logs = near_sdk::test_utils::get_logs();
+        assert_eq!(logs.len(), 1);
+        let event: near_sdk::serde_json::Value =
+            near_sdk::serde_json::from_str(logs[0].strip_prefix("EVENT_JSON:").unwrap()).unwrap();
+        assert_eq!(event["event"], "withdraw");
+        assert_eq!(event["data"]["token"], token.to_string());
+        assert_eq!(event["data"]["sender_id"], "alice.near");
+        assert_eq!(event["data"]["receiver_id"], Address([3; 20]).to_string());
+        // Amounts are strings, so they don't lose precision.
+        assert_eq!(event["data"]["amount"], u128::MAX.to_string());
+        assert!(event["data"].get("memo").is_none());
+    }
+
+    #[test]
+    fn test_withdraw_event_memo() {
+        let mut contract = test_utils::new_contract();
+
+        test_utils::set_context(test_utils::token_account_id(&Address([1; 20])));
+        let _ = contract.on_withdraw(
+            Address([3; 20]),
+            U128(10),
+            None,
+            Some("invoice 42".to_string()),
+            None,
         );
+
+        let logs = near_sdk::test_utils::get_logs();
+        assert_eq!(logs.len(), 1);
+        assert!(logs[0].contains(r#""memo":"invoice 42""#));
     }
-}
 
-/// Convert Aurora address of an ERC-20 to the NEAR account ID NEP-141 representative.
-fn account_id_from_token_address(address: aurora_sdk::Address) -> AccountId {
-    format!("{}.{}", address, env::current_account_id())
-        .parse()
-        .unwrap()
-}
+    #[test]
+    fn test_creation_notifier_skipped_when_unset() {
+        let mut contract = test_utils::new_contract();
+        let token = Address([1; 20]);
 
-/// Convert a NEAR account ID NEP-141 representative to the Aurora address of an ERC-20.
-fn address_from_token_account_id(account_id: AccountId) -> aurora_sdk::Address {
-    let mut buffer = [0u8; 20];
-    hex::decode_to_slice(&account_id.as_bytes()[0..40], &mut buffer).unwrap();
-    buffer.into()
-}
+        test_utils::set_callback_context(vec![PromiseResult::Successful(Vec::new())]);
+        let _ = contract.on_token_deployed(
+            token.clone(),
+            test_utils::token_account_id(&token),
+            None,
+            None,
+        );
 
-/// Manual implementation of abi encoding for efficiency.
-fn abi_encode_withdraw(
-    token_id: &aurora_sdk::Address,
-    receiver_id: &aurora_sdk::Address,
-    amount: u128,
-) -> Vec<u8> {
-    let mut buffer = [0u8; 4 + 32 + 32 + 32];
-    buffer[0..4].copy_from_slice(&WITHDRAW_SELECTOR);
-    buffer[16..36].copy_from_slice(&token_id.0);
-    buffer[48..68].copy_from_slice(&receiver_id.0);
-    buffer[84..100].copy_from_slice(&amount.to_be_bytes());
-    buffer.to_vec()
-}
+        assert!(near_sdk::test_utils::get_created_receipts().is_empty());
+    }
 
-#[cfg(test)]
-mod tests {
-    use crate::aurora_sdk::Address;
-    use crate::{abi_encode_withdraw, WITHDRAW_SELECTOR};
+    #[test]
+    #[should_panic(expected = "ERR_TOKEN_DEPLOYMENT_FAILED")]
+    fn test_failed_deploy_is_propagated() {
+        let mut contract = test_utils::new_contract();
+        contract.set_creation_notifier(Some("notifier.near".parse().unwrap()));
+        let token = Address([1; 20]);
+
+        test_utils::set_callback_context(vec![PromiseResult::Failed]);
+        let _ = contract.on_token_deployed(
+            token.clone(),
+            test_utils::token_account_id(&token),
+            None,
+            None,
+        );
+    }
 
     #[test]
-    /// Check withdraw selector is properly computed. Function signature is:
-    /// "withdraw(address,address,uint256)"
-    fn test_withdraw_select() {
+    fn test_failed_deploy_leaves_token_unregistered() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        let token_account_id = test_utils::token_account_id(&token);
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(
+            token.clone(),
+            "alice.near".parse().unwrap(),
+            10,
+            None,
+            None,
+            None,
+        );
+        assert!(contract.tokens.get(&token_account_id).is_none());
+
+        test_utils::set_callback_context(vec![PromiseResult::Failed]);
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            contract.on_token_deployed(token.clone(), token_account_id.clone(), None, None)
+        }));
+        assert!(result.is_err());
+        assert!(contract.tokens.get(&token_account_id).is_none());
+        assert_eq!(contract.tokens_count(), 0);
+
+        // The next deposit deploys the token again.
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(token, "alice.near".parse().unwrap(), 10, None, None, None);
         assert_eq!(
-            &ethabi::short_signature(
-                "withdraw",
-                &[
-                    ethabi::ParamType::Address,
-                    ethabi::ParamType::Address,
-                    ethabi::ParamType::Uint(256)
-                ],
+            test_utils::function_calls(&token_account_id)[0].method,
+            "new"
+        );
+    }
+
+    #[test]
+    fn test_failed_deploy_reverts_deposit() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        let token_account_id = test_utils::token_account_id(&token);
+        contract.set_fee_collector(Some("fees.near".parse().unwrap()));
+        test_utils::set_default_fees(
+            &mut contract,
+            fees::FeeConfig {
+                deposit_bps: 100,
+                ..Default::default()
+            },
+        );
+        contract.set_rate_limit(
+            token.clone(),
+            Some(rate_limits::RateLimit {
+                deposit: Some(U128(1_000)),
+                withdraw: None,
+            }),
+        );
+        contract.enable_holder_tracking(token.clone());
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(
+            token.clone(),
+            "alice.near".parse().unwrap(),
+            100,
+            None,
+            None,
+            Some(1),
+        );
+        assert_eq!(contract.mirrored_supply(token.clone()).0, 100);
+        assert_eq!(contract.token_holder_count(token.clone()), 2);
+        let calls = test_utils::function_calls(&test_utils::factory());
+        let args: near_sdk::serde_json::Value =
+            near_sdk::serde_json::from_slice(&calls[0].args).unwrap();
+        let deposit = near_sdk::serde_json::from_value(args["deposit"].clone()).unwrap();
+
+        test_utils::set_callback_context(vec![PromiseResult::Failed]);
+        assert!(matches!(
+            contract.on_token_deployed(
+                token.clone(),
+                token_account_id.clone(),
+                Some(1),
+                Some(deposit)
             ),
-            &WITHDRAW_SELECTOR
+            PromiseOrValue::Promise(_)
+        ));
+        let calls = test_utils::function_calls(&test_utils::factory());
+        assert_eq!(calls[0].method, "fail_token_deployment");
+
+        assert!(contract.tokens.get(&token_account_id).is_none());
+        assert_eq!(contract.mirrored_supply(token.clone()).0, 0);
+        assert_eq!(contract.gross_flows(token.clone()), (U128(0), U128(0)));
+        assert_eq!(contract.deposits_count, 0);
+        assert!(!contract.is_transfer_processed(U64(1), None));
+        assert_eq!(contract.rate_limit_usage(token.clone()).deposited, U128(0));
+        assert_eq!(contract.token_holder_count(token), 0);
+    }
+
+    #[test]
+    fn test_successful_deploy_registers_token() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(
+            token.clone(),
+            "alice.near".parse().unwrap(),
+            10,
+            None,
+            None,
+            None,
+        );
+        test_utils::confirm_deploy(&mut contract, &token);
+
+        assert_eq!(
+            contract.get_tokens(0, 10),
+            [(test_utils::token_account_id(&token), 1)]
         );
+        assert_eq!(contract.version_distribution(), [(1, 1)]);
     }
 
     #[test]
-    fn test_abi_encode_withdraw() {
-        let token_id = [
-            12, 34, 56, 78, 90, 12, 34, 56, 78, 90, 12, 34, 56, 78, 90, 12, 34, 56, 78, 90,
+    fn test_raw_token_entry() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        assert!(contract.raw_token_entry(token.clone()).is_none());
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(
+            token.clone(),
+            "alice.near".parse().unwrap(),
+            10,
+            None,
+            None,
+            None,
+        );
+        test_utils::confirm_deploy(&mut contract, &token);
+
+        let raw = contract.raw_token_entry(token).unwrap();
+        assert_eq!(u32::try_from_slice(&raw.0).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_deposit_callback_gas() {
+        let mut contract = test_utils::new_contract_with_binary();
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(
+            Address([1; 20]),
+            "alice.near".parse().unwrap(),
+            10,
+            None,
+            None,
+            None,
+        );
+
+        let calls = test_utils::function_calls(&test_utils::factory());
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].method, "on_token_deployed");
+        assert_eq!(calls[0].gas, DEPOSIT_CALLBACK_GAS);
+    }
+
+    /// Gas used by the execution of `on_deposit` itself, on top of the gas it attaches.
+    const DEPOSIT_EXECUTION_GAS_BUDGET: Gas = Gas(20_000_000_000_000);
+
+    #[test]
+    fn test_deposit_gas_profile() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        let token_account_id = test_utils::token_account_id(&token);
+
+        for plan in [DepositPlan::DeployAndMint, DepositPlan::Mint] {
+            let required_gas = contract.required_deposit_gas(token.clone());
+            assert_eq!(required_gas, plan.required_gas(&contract.gas_config));
+            assert!(required_gas <= MAX_DEPOSIT_GAS);
+
+            test_utils::set_context(test_utils::locker_account_id());
+            let _ = contract.on_deposit(
+                token.clone(),
+                "alice.near".parse().unwrap(),
+                10,
+                None,
+                None,
+                None,
+            );
+            let used_gas = env::used_gas();
+            let attached_gas: u64 = test_utils::function_calls(&token_account_id)
+                .iter()
+                .chain(test_utils::function_calls(&test_utils::factory()).iter())
+                .map(|call| call.gas.0)
+                .sum();
+            assert_eq!(Gas(attached_gas), required_gas, "{:?}", plan);
+            assert!(
+                used_gas <= DEPOSIT_EXECUTION_GAS_BUDGET,
+                "{:?} used {:?}",
+                plan,
+                used_gas
+            );
+
+            if plan == DepositPlan::DeployAndMint {
+                test_utils::confirm_deploy(&mut contract, &token);
+            }
+        }
+    }
+
+    #[test]
+    fn test_required_deposit_gas() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        assert_eq!(
+            contract.required_deposit_gas(token.clone()),
+            Gas(TOKEN_DEPLOYMENT_COST.0
+                + STORAGE_DEPOSIT_GAS.0
+                + DEPOSIT_COST.0
+                + DEPOSIT_CALLBACK_GAS.0)
+        );
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(
+            token.clone(),
+            "alice.near".parse().unwrap(),
+            10,
+            None,
+            None,
+            None,
+        );
+        test_utils::confirm_deploy(&mut contract, &token);
+
+        assert_eq!(
+            contract.required_deposit_gas(token),
+            Gas(STORAGE_DEPOSIT_GAS.0 + DEPOSIT_COST.0 + MINT_CALLBACK_GAS.0)
+        );
+    }
+
+    #[test]
+    fn test_failed_mint_is_refunded() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        let token_account_id = test_utils::token_account_id(&token);
+        let refund_address = Address([9; 20]);
+        contract.set_deposit_refund_address(Some(refund_address.clone()));
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(
+            token.clone(),
+            "alice.near".parse().unwrap(),
+            10,
+            None,
+            None,
+            None,
+        );
+        test_utils::confirm_deploy(&mut contract, &token);
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(
+            token.clone(),
+            "alice.near".parse().unwrap(),
+            5,
+            None,
+            None,
+            None,
+        );
+        let callbacks = test_utils::function_calls(&test_utils::factory());
+        assert_eq!(callbacks[0].method, "on_mint_complete");
+        assert_eq!(callbacks[0].gas, MINT_CALLBACK_GAS);
+        assert_eq!(contract.mirrored_supply(token.clone()).0, 15);
+
+        test_utils::set_callback_context(vec![PromiseResult::Failed]);
+        let _ = contract.on_mint_complete(
+            token_account_id,
+            "alice.near".parse().unwrap(),
+            5.into(),
+            None,
+        );
+        assert_eq!(contract.mirrored_supply(token.clone()).0, 10);
+
+        let logs = near_sdk::test_utils::get_logs();
+        let event: near_sdk::serde_json::Value = near_sdk::serde_json::from_str(
+            logs.last().unwrap().strip_prefix("EVENT_JSON:").unwrap(),
+        )
+        .unwrap();
+        assert_eq!(event["event"], "mint_failed");
+        assert_eq!(event["data"]["refund_address"], refund_address.to_string());
+
+        let calls = test_utils::function_calls(&test_utils::aurora());
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].method, "call");
+    }
+
+    #[test]
+    fn test_failed_mint_without_refund_address() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(
+            token.clone(),
+            "alice.near".parse().unwrap(),
+            10,
+            None,
+            None,
+            None,
+        );
+        test_utils::confirm_deploy(&mut contract, &token);
+
+        test_utils::set_callback_context(vec![PromiseResult::Failed]);
+        let _ = contract.on_mint_complete(
+            test_utils::token_account_id(&token),
+            "alice.near".parse().unwrap(),
+            10.into(),
+            Some(1),
+        );
+        assert_eq!(contract.mirrored_supply(token).0, 0);
+        assert!(test_utils::function_calls(&test_utils::aurora()).is_empty());
+    }
+
+    #[test]
+    fn test_deposit_batch_deploys_once() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        let token_account_id = test_utils::token_account_id(&token);
+        let deposits: Vec<(AccountId, u128)> = vec![
+            ("alice.near".parse().unwrap(), 10),
+            ("bob.near".parse().unwrap(), 20),
+            ("carol.near".parse().unwrap(), 30),
         ];
-        let receiver_id = [
-            12, 12, 12, 12, 34, 34, 34, 34, 56, 56, 56, 56, 78, 78, 78, 78, 90, 90, 90, 90,
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit_batch(token.clone(), deposits, None);
+
+        let methods: Vec<String> = test_utils::function_calls(&token_account_id)
+            .into_iter()
+            .map(|call| call.method)
+            .collect();
+        assert_eq!(
+            methods,
+            [
+                "new",
+                "storage_deposit",
+                "deposit",
+                "storage_deposit",
+                "deposit",
+                "storage_deposit",
+                "deposit"
+            ]
+        );
+        let callbacks = test_utils::function_calls(&test_utils::factory());
+        assert_eq!(callbacks.len(), 1);
+        assert_eq!(callbacks[0].method, "on_token_deployed");
+        assert_eq!(contract.mirrored_supply(token).0, 60);
+    }
+
+    #[test]
+    fn test_deposit_batch_of_deployed_token() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        let token_account_id = test_utils::token_account_id(&token);
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(
+            token.clone(),
+            "alice.near".parse().unwrap(),
+            10,
+            None,
+            None,
+            None,
+        );
+        test_utils::confirm_deploy(&mut contract, &token);
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit_batch(
+            token.clone(),
+            vec![
+                ("alice.near".parse().unwrap(), 10),
+                ("bob.near".parse().unwrap(), 20),
+            ],
+            None,
+        );
+
+        let receipts: Vec<_> = near_sdk::test_utils::get_created_receipts()
+            .into_iter()
+            .filter(|receipt| receipt.receiver_id == token_account_id)
+            .collect();
+        assert_eq!(receipts.len(), 1);
+        assert_eq!(receipts[0].actions.len(), 4);
+        let calls = test_utils::function_calls(&token_account_id);
+        let args: near_sdk::serde_json::Value =
+            near_sdk::serde_json::from_slice(&calls[3].args).unwrap();
+        assert_eq!(args["receiver_id"], "bob.near");
+        assert_eq!(args["amount"], "20");
+        assert_eq!(contract.mirrored_supply(token).0, 40);
+    }
+
+    #[test]
+    fn test_failed_deposit_batch_mint_is_refunded() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        let token_account_id = test_utils::token_account_id(&token);
+        let refund_address = Address([9; 20]);
+        test_utils::deploy_token(&mut contract, &token);
+        contract.set_deposit_refund_address(Some(refund_address));
+        contract.set_fee_collector(Some("fees.near".parse().unwrap()));
+        test_utils::set_default_fees(
+            &mut contract,
+            fees::FeeConfig {
+                deposit_bps: 1_000,
+                ..Default::default()
+            },
+        );
+        contract.set_rate_limit(
+            token.clone(),
+            Some(rate_limits::RateLimit {
+                deposit: Some(U128(1_000)),
+                withdraw: None,
+            }),
+        );
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit_batch(
+            token.clone(),
+            vec![
+                ("alice.near".parse().unwrap(), 10),
+                ("bob.near".parse().unwrap(), 20),
+            ],
+            None,
+        );
+        assert_eq!(contract.mirrored_supply(token.clone()).0, 30);
+        assert_eq!(contract.deposits_count, 4);
+        let callbacks = test_utils::function_calls(&test_utils::factory());
+        assert_eq!(callbacks[0].method, "on_batch_mint_complete");
+        assert_eq!(callbacks[0].gas, MINT_CALLBACK_GAS);
+        let args: near_sdk::serde_json::Value =
+            near_sdk::serde_json::from_slice(&callbacks[0].args).unwrap();
+        assert_eq!(args["mints"], 4);
+
+        test_utils::set_callback_context(vec![PromiseResult::Failed]);
+        let _ = contract.on_batch_mint_complete(
+            token_account_id,
+            vec![
+                ("alice.near".parse().unwrap(), U128(10)),
+                ("bob.near".parse().unwrap(), U128(20)),
+            ],
+            4,
+        );
+        assert_eq!(contract.mirrored_supply(token.clone()).0, 0);
+        assert_eq!(contract.deposits_count, 0);
+        assert_eq!(contract.rate_limit_usage(token).deposited, U128(0));
+        let failed_mints = near_sdk::test_utils::get_logs()
+            .iter()
+            .filter(|log| log.contains("mint_failed"))
+            .count();
+        assert_eq!(failed_mints, 2);
+        let calls = test_utils::function_calls(&test_utils::aurora());
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].method, "call");
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_INVALID_BATCH_SIZE")]
+    fn test_deposit_batch_rejects_oversized_batch() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let deposits = (0..=DEFAULT_MAX_DEPOSITS_PER_BATCH)
+            .map(|i| (format!("user{}.near", i).parse().unwrap(), 1))
+            .collect();
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit_batch(Address([1; 20]), deposits, None);
+    }
+
+    #[test]
+    fn test_create_token_pending_deploy_redeploys_by_default() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        assert_eq!(
+            contract.pending_deploy_policy(),
+            PendingDeployPolicy::Redeploy
+        );
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.create_token(token.clone(), None);
+        let result = contract.create_token(token.clone(), None);
+
+        assert!(matches!(result, PromiseOrValue::Promise(_)));
+        assert_eq!(
+            test_utils::function_calls(&test_utils::token_account_id(&token)).len(),
+            2
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_DEPLOY_IN_PROGRESS")]
+    fn test_create_token_pending_deploy_reject() {
+        let mut contract = test_utils::new_contract_with_binary();
+        contract.set_pending_deploy_policy(PendingDeployPolicy::Reject);
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.create_token(Address([1; 20]), None);
+        let _ = contract.create_token(Address([1; 20]), None);
+    }
+
+    #[test]
+    fn test_create_token_pending_deploy_ignore() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        contract.set_pending_deploy_policy(PendingDeployPolicy::Ignore);
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.create_token(token.clone(), None);
+        let result = contract.create_token(token.clone(), None);
+
+        assert!(matches!(result, PromiseOrValue::Value(())));
+        assert_eq!(
+            test_utils::function_calls(&test_utils::token_account_id(&token)).len(),
+            1
+        );
+
+        // Once the deploy is confirmed, the token is no longer pending.
+        test_utils::confirm_deploy(&mut contract, &token);
+        test_utils::set_context(test_utils::locker_account_id());
+        let result = contract.create_token(token, None);
+        assert!(matches!(result, PromiseOrValue::Promise(_)));
+    }
+
+    #[test]
+    fn test_state_digest() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let digest = contract.state_digest();
+        assert_eq!(contract.state_digest(), digest);
+
+        test_utils::replace_token_binary(&mut contract, vec![0, 97, 115, 109, 1]);
+        let after_upgrade = contract.state_digest();
+        assert_ne!(after_upgrade, digest);
+
+        contract.set_owner("new-owner.near".parse().unwrap());
+        let after_owner_change = contract.state_digest();
+        assert_ne!(after_owner_change, after_upgrade);
+
+        let token = Address([1; 20]);
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(
+            token.clone(),
+            "alice.near".parse().unwrap(),
+            10,
+            None,
+            None,
+            None,
+        );
+        test_utils::confirm_deploy(&mut contract, &token);
+        assert_ne!(contract.state_digest(), after_owner_change);
+    }
+
+    #[test]
+    fn test_callback_gas_config() {
+        let mut contract = test_utils::new_contract();
+        assert_eq!(
+            contract.callback_gas_config(),
+            CallbackGasConfig {
+                deposit: DEPOSIT_CALLBACK_GAS,
+                withdraw: Gas(WITHDRAW_CALLBACK_BASE_COST.0 + DEPOSIT_COST.0),
+                deploy: TOKEN_DEPLOYED_CALLBACK_COST,
+                upgrade: TOKEN_UPGRADED_CALLBACK_GAS,
+                mint: MINT_CALLBACK_GAS,
+            }
+        );
+
+        // The withdraw callback follows the configured deposit gas.
+        contract.set_gas_config(TOKEN_DEPLOYMENT_COST, Gas(1_000_000_000_000));
+        assert_eq!(
+            contract.callback_gas_config().withdraw,
+            Gas(WITHDRAW_CALLBACK_BASE_COST.0 + 1_000_000_000_000)
+        );
+    }
+
+    #[test]
+    fn test_set_batch_limits() {
+        let mut contract = test_utils::new_contract_with_binary();
+        assert_eq!(contract.batch_limits(), BatchLimits::default());
+
+        let limits = BatchLimits {
+            max_registrations: 5,
+            max_deposits: 2,
+        };
+        contract.set_batch_limits(limits);
+        assert_eq!(contract.batch_limits(), limits);
+
+        // Batches within the limits are accepted.
+        test_utils::set_context(test_utils::locker_account_id());
+        let deposits = vec![
+            ("alice.near".parse().unwrap(), 1),
+            ("bob.near".parse().unwrap(), 2),
         ];
-        let amount = 0x998877665544332211u128;
+        let _ = contract.on_deposit_batch(Address([1; 20]), deposits, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_INVALID_BATCH_SIZE")]
+    fn test_deposit_batch_rejects_batch_above_configured_limit() {
+        let mut contract = test_utils::new_contract_with_binary();
+        contract.set_batch_limits(BatchLimits {
+            max_registrations: 5,
+            max_deposits: 2,
+        });
+        let deposits = (0..3)
+            .map(|i| (format!("user{}.near", i).parse().unwrap(), 1))
+            .collect();
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit_batch(Address([1; 20]), deposits, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_INVALID_BATCH_LIMITS")]
+    fn test_set_batch_limits_rejects_zero() {
+        let mut contract = test_utils::new_contract();
+        contract.set_batch_limits(BatchLimits {
+            max_registrations: 0,
+            max_deposits: 2,
+        });
+    }
+
+    #[test]
+    fn test_set_gas_config() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        assert_eq!(contract.get_gas_config(), GasConfig::default());
 
+        contract.set_gas_config(Gas(7_000_000_000_000), Gas(1_000_000_000_000));
         assert_eq!(
-            &abi_encode_withdraw(&Address(token_id), &Address(receiver_id), amount,)[4..],
-            &ethabi::encode(&[
-                ethabi::Token::Address(ethabi::Address::try_from(&token_id).unwrap()),
-                ethabi::Token::Address(ethabi::Address::try_from(&receiver_id).unwrap()),
-                ethabi::Token::Uint(ethabi::Uint::from(amount)),
-            ])
+            contract.effective_deploy_config().gas,
+            Gas(7_000_000_000_000)
+        );
+        assert_eq!(
+            contract.required_deposit_gas(token.clone()),
+            Gas(8_000_000_000_000 + STORAGE_DEPOSIT_GAS.0 + DEPOSIT_CALLBACK_GAS.0)
         );
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(
+            token.clone(),
+            "alice.near".parse().unwrap(),
+            10,
+            None,
+            None,
+            None,
+        );
+        test_utils::confirm_deploy(&mut contract, &token);
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(
+            token.clone(),
+            "alice.near".parse().unwrap(),
+            10,
+            None,
+            None,
+            None,
+        );
+
+        let calls = test_utils::function_calls(&test_utils::token_account_id(&token));
+        assert_eq!(calls[1].method, "deposit");
+        assert_eq!(calls[1].gas, Gas(1_000_000_000_000));
+    }
+
+    #[test]
+    fn test_deposit_registers_receiver_first() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        let token_account_id = test_utils::token_account_id(&token);
+        contract.set_receiver_storage_deposit(U128(7));
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(
+            token.clone(),
+            "alice.near".parse().unwrap(),
+            10,
+            None,
+            None,
+            None,
+        );
+        let methods: Vec<String> = test_utils::function_calls(&token_account_id)
+            .into_iter()
+            .map(|call| call.method)
+            .collect();
+        assert_eq!(methods, ["new", "storage_deposit", "deposit"]);
+
+        test_utils::confirm_deploy(&mut contract, &token);
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(token, "bob.near".parse().unwrap(), 10, None, None, None);
+
+        let calls = test_utils::function_calls(&token_account_id);
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[0].method, "storage_deposit");
+        assert_eq!(calls[0].deposit, 7);
+        let args: near_sdk::serde_json::Value =
+            near_sdk::serde_json::from_slice(&calls[0].args).unwrap();
+        assert_eq!(args["account_id"], "bob.near");
+        assert_eq!(args["registration_only"], true);
+        assert_eq!(calls[1].method, "deposit");
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_ZERO_GAS")]
+    fn test_set_gas_config_rejects_zero_gas() {
+        let mut contract = test_utils::new_contract();
+        contract.set_gas_config(TOKEN_DEPLOYMENT_COST, Gas(0));
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_DEPOSIT_GAS_TOO_HIGH")]
+    fn test_set_gas_config_rejects_gas_above_budget() {
+        let mut contract = test_utils::new_contract();
+        contract.set_gas_config(Gas(200_000_000_000_000), DEPOSIT_COST);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_WITHDRAW_GAS_TOO_HIGH")]
+    fn test_set_gas_config_rejects_withdraw_gas_above_token_budget() {
+        let mut contract = test_utils::new_contract();
+        contract.set_gas_config(
+            TOKEN_DEPLOYMENT_COST,
+            Gas(TOKEN_WITHDRAW_CALLBACK_GAS.0 - WITHDRAW_CALLBACK_BASE_COST.0 + 1),
+        );
+    }
+
+    #[test]
+    fn test_connector_metadata() {
+        let mut contract = test_utils::new_contract();
+        assert_eq!(contract.connector_metadata(), ConnectorMetadata::default());
+
+        let metadata = ConnectorMetadata {
+            name: "Silo connector".to_string(),
+            description: "ERC-20 connector of the silo".to_string(),
+        };
+        contract.set_connector_metadata(metadata.clone());
+
+        assert_eq!(contract.connector_metadata(), metadata);
+    }
+
+    #[test]
+    fn test_is_active_binary() {
+        let mut contract = test_utils::new_contract();
+        let binary = vec![0, 97, 115, 109, 1];
+        let expected_hash = binary_hash(&binary).into();
+        assert!(!contract.is_active_binary(expected_hash));
+
+        contract.set_token_binary(binary.into());
+
+        assert_eq!(contract.token_binary_hash(), Some(expected_hash));
+        assert!(contract.is_active_binary(expected_hash));
+        assert!(!contract.is_active_binary(binary_hash(&[1, 2, 3]).into()));
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_ONLY_OWNER")]
+    fn test_set_token_binary_by_non_owner() {
+        let mut contract = test_utils::new_contract();
+
+        test_utils::set_context("alice.near".parse().unwrap());
+        contract.set_token_binary(vec![0, 97, 115, 109].into());
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_TOKEN_BINARY_ALREADY_SET")]
+    fn test_set_token_binary_rejects_replacement() {
+        let mut contract = test_utils::new_contract_with_binary();
+        contract.set_token_binary(vec![0, 97, 115, 109, 2].into());
+    }
+
+    #[test]
+    fn test_owner_defaults_to_deployer() {
+        let deployer: AccountId = "deployer.near".parse().unwrap();
+        test_utils::set_context(deployer.clone());
+        let contract = Contract::new(test_utils::aurora(), test_utils::locker(), None);
+        assert_eq!(contract.get_owner(), deployer);
+
+        let owner: AccountId = "owner.near".parse().unwrap();
+        let contract = Contract::new(
+            test_utils::aurora(),
+            test_utils::locker(),
+            Some(owner.clone()),
+        );
+        assert_eq!(contract.get_owner(), owner);
+    }
+
+    #[test]
+    fn test_set_owner() {
+        let mut contract = test_utils::new_contract();
+        let owner: AccountId = "owner.near".parse().unwrap();
+
+        contract.set_owner(owner.clone());
+        assert_eq!(contract.get_owner(), owner);
+
+        test_utils::set_context(owner);
+        contract.set_token_binary(vec![0, 97, 115, 109].into());
+        assert_eq!(contract.token_binary_version, 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_ONLY_OWNER")]
+    fn test_previous_owner_loses_role() {
+        let mut contract = test_utils::new_contract();
+        contract.set_owner("owner.near".parse().unwrap());
+
+        contract.set_owner(test_utils::factory());
+    }
+
+    #[test]
+    fn test_binary_version_info() {
+        let mut contract = test_utils::new_contract();
+        let binary = vec![0, 97, 115, 109, 1];
+        near_sdk::testing_env!(test_utils::context(test_utils::factory())
+            .block_index(42)
+            .build());
+
+        contract.set_token_binary(binary.clone().into());
+
+        assert_eq!(
+            contract.binary_version_info(1),
+            Some(BinaryInfo {
+                uploaded_at: U64(42),
+                uploader: test_utils::factory(),
+                hash: binary_hash(&binary).into(),
+            })
+        );
+        assert_eq!(contract.binary_version_info(2), None);
+    }
+
+    #[test]
+    fn test_supports_version() {
+        let mut contract = test_utils::new_contract_with_binary();
+
+        assert!(contract.supports_version(1));
+        assert!(!contract.supports_version(0));
+        assert!(!contract.supports_version(2));
+
+        test_utils::replace_token_binary(&mut contract, vec![0, 97, 115, 109, 2]);
+        assert!(contract.supports_version(2));
     }
 }
@@ -0,0 +1,137 @@
+//! Plain text logs of the bridging methods, for debugging. Unlike events, they are
+//! only emitted up to the configured verbosity.
+use crate::{Contract, ContractExt};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{env, near_bindgen};
+
+/// Verbosity of the logs. Each level includes the logs of the previous ones.
+#[derive(
+    BorshSerialize,
+    BorshDeserialize,
+    Serialize,
+    Deserialize,
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+)]
+#[serde(crate = "near_sdk::serde")]
+pub enum LogLevel {
+    /// Nothing is logged.
+    Off,
+    /// Failed deploys and unlocks.
+    Errors,
+    /// Token deployments.
+    Info,
+    /// Every deposit and withdraw.
+    Debug,
+}
+
+impl Default for LogLevel {
+    fn default() -> Self {
+        Self::Off
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Set the verbosity of the logs. Logs cost gas, so it SHOULD only be raised
+    /// temporarily to investigate incidents. ONLY the `Owner` role can call this
+    /// method.
+    pub fn set_log_level(&mut self, level: LogLevel) {
+        self.assert_owner();
+        self.record_admin_action("set_log_level");
+
+        self.log_level = level;
+    }
+
+    /// Verbosity of the logs.
+    pub fn log_level(&self) -> LogLevel {
+        self.log_level
+    }
+}
+
+impl Contract {
+    /// Log `msg` if the verbosity is at least `level`.
+    pub(crate) fn log_at(&self, level: LogLevel, msg: &str) {
+        if level != LogLevel::Off && level <= self.log_level {
+            env::log_str(msg);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LogLevel;
+    use crate::aurora_sdk::Address;
+    use crate::test_utils;
+
+    /// Logs that are not events.
+    fn text_logs() -> Vec<String> {
+        near_sdk::test_utils::get_logs()
+            .into_iter()
+            .filter(|log| !log.starts_with("EVENT_JSON:"))
+            .collect()
+    }
+
+    #[test]
+    fn test_off_suppresses_logs() {
+        let mut contract = test_utils::new_contract_with_binary();
+        assert_eq!(contract.log_level(), LogLevel::Off);
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(
+            Address([1; 20]),
+            "alice.near".parse().unwrap(),
+            10,
+            None,
+            None,
+            None,
+        );
+
+        assert!(text_logs().is_empty());
+    }
+
+    #[test]
+    fn test_debug_emits_logs() {
+        let mut contract = test_utils::new_contract_with_binary();
+        contract.set_log_level(LogLevel::Debug);
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(
+            Address([1; 20]),
+            "alice.near".parse().unwrap(),
+            10,
+            None,
+            None,
+            None,
+        );
+
+        let logs = text_logs();
+        assert_eq!(logs.len(), 2);
+        assert!(logs[0].starts_with("Deposit of 10 "));
+        assert!(logs[1].starts_with("Deploying "));
+    }
+
+    #[test]
+    fn test_info_skips_debug_logs() {
+        let mut contract = test_utils::new_contract_with_binary();
+        contract.set_log_level(LogLevel::Info);
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(
+            Address([1; 20]),
+            "alice.near".parse().unwrap(),
+            10,
+            None,
+            None,
+            None,
+        );
+
+        assert_eq!(text_logs().len(), 1);
+    }
+}
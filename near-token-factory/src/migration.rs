@@ -0,0 +1,396 @@
+//! Upgrade of the factory code and migration of its state.
+//!
+//! Every layout of the state is a variant of `VersionedContract`, from the first release,
+//! `ContractV1`, to the current one, `Contract`. The stored state is read as the
+//! variant whose layout decodes it exactly, see `VersionedContract::read`, and
+//! `migrate` converts it to the current layout. Releases that change the layout MUST
+//! keep the layout of the previous release as `ContractV{n}`, add the current one as a
+//! new variant, and migrate the previous variant in `VersionedContract::migrate`.
+use crate::{
+    aurora_sdk, binary_hash, naming, timelock, ConnectorMetadata, Contract, ContractExt,
+    StorageKey, DEFAULT_DEPLOY_BALANCE_MARGIN, DEFAULT_LOCKER_ACCOUNT_FORMAT, DEFAULT_MAX_DECIMALS,
+    DEFAULT_RESERVE_BALANCE,
+};
+use near_sdk::borsh::{self, BorshDeserialize};
+use near_sdk::collections::{LazyOption, LookupMap, UnorderedMap, UnorderedSet, Vector};
+use near_sdk::json_types::Base64VecU8;
+use near_sdk::{env, near_bindgen, require, AccountId, Gas, Promise};
+
+/// Storage key of the contract state, see `near_sdk::env::state_read`.
+const STATE_KEY: &[u8] = b"STATE";
+/// Gas attached to `migrate` when the factory code is upgraded.
+const MIGRATE_GAS: Gas = Gas(100_000_000_000_000);
+
+const ERR_NO_STATE: &str = "ERR_NO_STATE: Contract state is not initialized.";
+const ERR_MIGRATION_TOKENS: &str =
+    "ERR_MIGRATION_TOKENS: Number of deployed tokens changed during migration.";
+const ERR_MIGRATION_VERSION: &str =
+    "ERR_MIGRATION_VERSION: Token binary version changed during migration.";
+const ERR_MIGRATION_OWNER: &str =
+    "ERR_MIGRATION_OWNER: Privileged methods changed owner during migration.";
+const ERR_UNKNOWN_STATE_LAYOUT: &str =
+    "ERR_UNKNOWN_STATE_LAYOUT: State doesn't match any known layout of the contract.";
+
+/// Layout of the contract state of the first release of the factory. The field order
+/// MUST NOT change, as it is the order of the borsh encoded state.
+#[derive(BorshDeserialize)]
+pub(crate) struct ContractV1 {
+    /// Kept as `Contract::aurora`.
+    pub aurora: AccountId,
+    /// Kept as `Contract::token_binary`, and hashed into `Contract::token_binary_hash`.
+    pub token_binary: LazyOption<Vec<u8>>,
+    /// Kept as `Contract::token_binary_version`.
+    pub token_binary_version: u32,
+    /// Kept as `Contract::tokens`. Its length becomes `Contract::total_deploys`, and
+    /// its values are counted into `Contract::version_counts`.
+    pub tokens: UnorderedMap<AccountId, u32>,
+    /// Kept as `Contract::locker`, with the default locker account format.
+    pub locker: aurora_sdk::Address,
+}
+
+/// Layouts of the contract state, from the oldest to the current one.
+pub(crate) enum VersionedContract {
+    /// Layout of the first release.
+    V1(ContractV1),
+    /// Current layout.
+    V2(Box<Contract>),
+}
+
+impl VersionedContract {
+    /// Read the stored state as the variant of its layout. Every layout adds fields to
+    /// the previous one, so a state only decodes exactly with the layout it was written
+    /// with. Layouts are tried from the current one to the oldest.
+    pub(crate) fn read() -> Self {
+        let state = env::storage_read(STATE_KEY).unwrap_or_else(|| env::panic_str(ERR_NO_STATE));
+        if let Ok(contract) = Contract::try_from_slice(&state) {
+            return Self::V2(Box::new(contract));
+        }
+        if let Ok(old) = ContractV1::try_from_slice(&state) {
+            return Self::V1(old);
+        }
+        env::panic_str(ERR_UNKNOWN_STATE_LAYOUT)
+    }
+
+    /// Convert the state to the current layout.
+    pub(crate) fn migrate(self) -> Contract {
+        match self {
+            Self::V1(old) => Contract::migrate_from_v1(old),
+            Self::V2(contract) => *contract,
+        }
+    }
+}
+
+/// Values that MUST be preserved by a migration.
+pub(crate) struct MigrationInvariants {
+    pub tokens_count: u64,
+    pub token_binary_version: u32,
+    /// Account allowed to call the privileged methods.
+    pub owner: AccountId,
+}
+
+impl From<&ContractV1> for MigrationInvariants {
+    fn from(old: &ContractV1) -> Self {
+        Self {
+            tokens_count: old.tokens.len(),
+            token_binary_version: old.token_binary_version,
+            // Only the factory itself could call the privileged methods of the old state.
+            owner: env::current_account_id(),
+        }
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Deploy `code` as the new code of the factory and migrate the state with
+    /// `migrate` in the same batch, so the new code is reverted if the migration fails.
+    /// ONLY the `Owner` role can call this method.
+    pub fn upgrade(&mut self, code: Base64VecU8) -> Promise {
+        self.assert_owner();
+        self.record_admin_action("upgrade");
+
+        Promise::new(env::current_account_id())
+            .deploy_contract(code.into())
+            .function_call("migrate".to_string(), Vec::new(), 0, MIGRATE_GAS)
+    }
+
+    /// Migrate the state of the factory after the contract code is upgraded, from the
+    /// layout it was written with to the current one, see `VersionedContract`. The
+    /// state is kept as it is if it already has the current layout. The resulting state
+    /// is verified, and the whole upgrade is reverted if it is not consistent with the
+    /// old state. This method can only be called by the contract itself.
+    #[private]
+    #[init(ignore_state)]
+    pub fn migrate() -> Self {
+        VersionedContract::read().migrate()
+    }
+}
+
+impl Contract {
+    fn migrate_from_v1(old: ContractV1) -> Self {
+        let invariants = MigrationInvariants::from(&old);
+        let old_tokens_count = old.tokens.len();
+
+        // Fields of the old state are kept as they are, and the hash of the binary is
+        // computed from the stored binary. Other fields added after the first release
+        // start empty: the mirrored supply of previously deployed tokens is only
+        // tracked after the migration. Every token of the old state counts as deployed.
+        let mut contract = Self {
+            aurora: old.aurora,
+            token_binary_hash: old.token_binary.get().as_deref().map(binary_hash),
+            token_binary: old.token_binary,
+            token_binary_version: old.token_binary_version,
+            binary_upload_chunks: Vector::new(StorageKey::BinaryUploadChunks),
+            binary_upload_size: None,
+            tokens: old.tokens,
+            locker: old.locker,
+            owner: invariants.owner.clone(),
+            locker_account_format: DEFAULT_LOCKER_ACCOUNT_FORMAT.to_string(),
+            last_withdraw_gas: None,
+            paused_tokens: UnorderedSet::new(StorageKey::PausedTokens),
+            all_tokens_paused: false,
+            deploy_paused: false,
+            upgrades_frozen: false,
+            paused: 0,
+            dry_run: false,
+            log_level: Default::default(),
+            token_supply: LookupMap::new(StorageKey::TokenSupply),
+            top_tokens: Vec::new(),
+            creation_notifier: None,
+            deposit_refund_address: None,
+            default_fees: Default::default(),
+            token_fees: LookupMap::new(StorageKey::TokenFees),
+            fee_collector: None,
+            storage_payer: Default::default(),
+            storage_pool: 0,
+            token_allowlist_enabled: false,
+            allowed_tokens: UnorderedSet::new(StorageKey::AllowedTokens),
+            rate_limits: LookupMap::new(StorageKey::RateLimits),
+            token_volumes: LookupMap::new(StorageKey::TokenVolumes),
+            silos: UnorderedMap::new(StorageKey::Silos),
+            token_silos: LookupMap::new(StorageKey::TokenSilos),
+            locked_nep141: LookupMap::new(StorageKey::LockedNep141),
+            processed_transfers: LookupMap::new(StorageKey::ProcessedTransfers),
+            transfer_log: LookupMap::new(StorageKey::TransferLog),
+            transfer_log_head: 0,
+            transfer_log_tail: 0,
+            transfer_id_floors: LookupMap::new(StorageKey::TransferIdFloors),
+            token_detaches: LookupMap::new(StorageKey::TokenDetaches),
+            detached_tokens: UnorderedSet::new(StorageKey::DetachedTokens),
+            xcc_fee: 0,
+            lazy_upgrades: false,
+            lazy_upgrade_opt_outs: UnorderedSet::new(StorageKey::LazyUpgradeOptOuts),
+            treasury: None,
+            deposits_count: 0,
+            withdrawals_count: 0,
+            receiver_allowlists: LookupMap::new(StorageKey::ReceiverAllowlists),
+            metadata: ConnectorMetadata::default(),
+            supply_caps: LookupMap::new(StorageKey::SupplyCaps),
+            remainder_policy: Default::default(),
+            paused_deposit_policy: Default::default(),
+            refund_recipient: None,
+            deferred_deposits: UnorderedMap::new(StorageKey::DeferredDeposits),
+            next_deferred_id: 0,
+            failed_unlock_policy: Default::default(),
+            queued_unlocks: UnorderedMap::new(StorageKey::QueuedUnlocks),
+            next_unlock_id: 0,
+            gross_flows: LookupMap::new(StorageKey::GrossFlows),
+            creation_transfers: LookupMap::new(StorageKey::CreationTransfers),
+            spec_versions: LookupMap::new(StorageKey::SpecVersions),
+            token_spec_versions: LookupMap::new(StorageKey::TokenSpecVersions),
+            minters: UnorderedSet::new(StorageKey::Minters),
+            token_upgraders: UnorderedSet::new(StorageKey::TokenUpgraders),
+            pausers: UnorderedSet::new(StorageKey::Pausers),
+            token_holders: LookupMap::new(StorageKey::TokenHoldersMap),
+            version_counts: LookupMap::new(StorageKey::VersionCounts),
+            token_created_at: LookupMap::new(StorageKey::TokenCreatedAt),
+            token_origins: LookupMap::new(StorageKey::TokenOrigins),
+            token_scaling: LookupMap::new(StorageKey::TokenScaling),
+            token_dust: LookupMap::new(StorageKey::TokenDust),
+            token_last_activity: LookupMap::new(StorageKey::TokenLastActivity),
+            total_deploys: old_tokens_count,
+            binary_versions: LookupMap::new(StorageKey::BinaryVersions),
+            event_context: None,
+            event_format: Default::default(),
+            pending_deploys: UnorderedSet::new(StorageKey::PendingDeploys),
+            failed_withdraws: LookupMap::new(StorageKey::FailedWithdraws),
+            deploy_balance_margin: DEFAULT_DEPLOY_BALANCE_MARGIN,
+            max_decimals: DEFAULT_MAX_DECIMALS,
+            gas_config: Default::default(),
+            batch_limits: Default::default(),
+            reserve_balance: DEFAULT_RESERVE_BALANCE,
+            min_deploy_attached_deposit: 0,
+            naming_scheme: naming::NamingScheme::Hex,
+            token_account_ids: LookupMap::new(StorageKey::TokenAccountIds),
+            token_addresses: LookupMap::new(StorageKey::TokenAddresses),
+            auto_pause_threshold: None,
+            failure_count: 0,
+            incident_pending: false,
+            timelock_delay: timelock::DEFAULT_TIMELOCK_DELAY,
+            pending_changes: Vec::new(),
+            locker_grace_period: 0,
+            retired_locker: None,
+            unknown_token_policy: Default::default(),
+            excess_withdraw_policy: Default::default(),
+            pending_deploy_policy: Default::default(),
+            admin_log: LookupMap::new(StorageKey::AdminLog),
+            admin_log_len: 0,
+            deployer: None,
+            deployer_enabled: false,
+        };
+
+        let versions: Vec<u32> = contract.tokens.values().collect();
+        for version in versions {
+            contract.add_to_version_count(version, 1);
+        }
+
+        contract.verify_post_migration(&invariants);
+        contract
+    }
+
+    /// Panic if the migrated state doesn't preserve the invariants of the old state.
+    pub(crate) fn verify_post_migration(&self, invariants: &MigrationInvariants) {
+        require!(
+            self.tokens.len() == invariants.tokens_count,
+            ERR_MIGRATION_TOKENS
+        );
+        require!(
+            self.token_binary_version == invariants.token_binary_version,
+            ERR_MIGRATION_VERSION
+        );
+        require!(self.owner == invariants.owner, ERR_MIGRATION_OWNER);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MigrationInvariants, VersionedContract, STATE_KEY};
+    use crate::{binary_hash, test_utils, Contract, GasConfig, StorageKey};
+    use near_sdk::borsh::BorshSerialize;
+    use near_sdk::collections::{LazyOption, UnorderedMap};
+    use near_sdk::env;
+
+    /// Write a state with the layout of the first release, i.e. `aurora`,
+    /// `token_binary`, `token_binary_version`, `tokens` and `locker` in this order, with
+    /// the storage keys it used.
+    fn write_baseline_state() {
+        let mut tokens = UnorderedMap::new(StorageKey::TokenMap);
+        tokens.insert(
+            &test_utils::token_account_id(&crate::aurora_sdk::Address([1; 20])),
+            &2,
+        );
+        env::state_write(&(
+            test_utils::aurora(),
+            LazyOption::new(StorageKey::TokenBinary, Some(&vec![1, 2, 3])),
+            2u32,
+            tokens,
+            test_utils::locker(),
+        ));
+    }
+
+    #[test]
+    fn test_migrate() {
+        test_utils::set_context(test_utils::factory());
+        write_baseline_state();
+        assert!(matches!(
+            VersionedContract::read(),
+            VersionedContract::V1(_)
+        ));
+
+        let contract = Contract::migrate();
+
+        assert_eq!(contract.token_binary_version, 2);
+        assert_eq!(contract.tokens.len(), 1);
+        assert_eq!(contract.get_owner(), test_utils::factory());
+        assert_eq!(
+            contract.locker_account_id(),
+            test_utils::locker_account_id()
+        );
+        // Derived fields.
+        assert_eq!(contract.token_binary_hash, Some(binary_hash(&[1, 2, 3])));
+        assert_eq!(contract.total_deploys, 1);
+        assert_eq!(contract.version_distribution(), vec![(2, 1)]);
+        // New fields start with their defaults.
+        assert_eq!(contract.paused, 0);
+        assert_eq!(contract.get_gas_config(), GasConfig::default());
+        assert_eq!(contract.deferred_deposits(0, 10), Vec::new());
+    }
+
+    #[test]
+    fn test_migrated_state_round_trip() {
+        test_utils::set_context(test_utils::factory());
+        write_baseline_state();
+        let contract = Contract::migrate();
+        env::state_write(&contract);
+        let state = env::storage_read(STATE_KEY).unwrap();
+
+        let contract = match VersionedContract::read() {
+            VersionedContract::V2(contract) => *contract,
+            VersionedContract::V1(_) => panic!("Migrated state read as the first layout"),
+        };
+        assert_eq!(contract.try_to_vec().unwrap(), state);
+        let contract = Contract::migrate();
+        assert_eq!(contract.try_to_vec().unwrap(), state);
+        assert_eq!(contract.token_binary_version, 2);
+        assert_eq!(contract.total_deploys, 1);
+    }
+
+    #[test]
+    fn test_migrate_current_state() {
+        let mut contract = test_utils::new_contract_with_binary();
+        contract.set_max_decimals(18);
+        env::state_write(&contract);
+
+        let contract = Contract::migrate();
+        assert_eq!(contract.max_decimals(), 18);
+        assert_eq!(contract.token_binary_version, 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_UNKNOWN_STATE_LAYOUT")]
+    fn test_migrate_unknown_state() {
+        let contract = test_utils::new_contract();
+        let mut state = contract.try_to_vec().unwrap();
+        state.push(0);
+        env::storage_write(STATE_KEY, &state);
+
+        let _ = Contract::migrate();
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_NO_STATE")]
+    fn test_migrate_without_state() {
+        test_utils::set_context(test_utils::factory());
+        let _ = Contract::migrate();
+    }
+
+    #[test]
+    fn test_upgrade() {
+        let mut contract = test_utils::new_contract();
+        let _ = contract.upgrade(vec![0, 97, 115, 109].into());
+
+        let calls = test_utils::function_calls(&test_utils::factory());
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].method, "migrate");
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_ONLY_OWNER")]
+    fn test_upgrade_by_non_owner() {
+        let mut contract = test_utils::new_contract();
+
+        test_utils::set_context("mallory.near".parse().unwrap());
+        let _ = contract.upgrade(vec![0, 97, 115, 109].into());
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_MIGRATION_TOKENS")]
+    fn test_inconsistent_migration_aborts() {
+        let contract = test_utils::new_contract();
+
+        contract.verify_post_migration(&MigrationInvariants {
+            tokens_count: 1,
+            token_binary_version: 0,
+            owner: test_utils::factory(),
+        });
+    }
+}
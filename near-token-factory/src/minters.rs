@@ -0,0 +1,125 @@
+//! Secondary minters: accounts other than the locker that are allowed to deposit.
+use crate::{events, Contract, ContractExt};
+use near_sdk::{env, near_bindgen, require, AccountId};
+
+const ERR_NOT_MINTER: &str = "ERR_NOT_MINTER: Only the locker or a minter can deposit.";
+
+#[near_bindgen]
+impl Contract {
+    /// Allow `account_id` to call `on_deposit`. ONLY the `Owner` role can call this
+    /// method.
+    pub fn add_minter(&mut self, account_id: AccountId) {
+        self.assert_owner();
+        self.record_admin_action("add_minter");
+
+        if self.minters.insert(&account_id) {
+            self.emit_event(events::ConnectorEvent::RoleGranted {
+                role: "minter".to_string(),
+                account_id,
+            });
+        }
+    }
+
+    /// Stop allowing `account_id` to call `on_deposit`. The locker can't be removed.
+    /// ONLY the `Owner` role can call this method.
+    pub fn remove_minter(&mut self, account_id: AccountId) {
+        self.assert_owner();
+        self.record_admin_action("remove_minter");
+
+        if self.minters.remove(&account_id) {
+            self.emit_event(events::ConnectorEvent::RoleRevoked {
+                role: "minter".to_string(),
+                account_id,
+            });
+        }
+    }
+
+    /// Accounts allowed to call `on_deposit` besides the locker.
+    pub fn minters(&self) -> Vec<AccountId> {
+        self.minters.to_vec()
+    }
+}
+
+impl Contract {
+    pub(crate) fn assert_can_deposit(&self) {
+        require!(
+            self.is_depositor(&env::predecessor_account_id()),
+            ERR_NOT_MINTER
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::aurora_sdk::Address;
+    use crate::test_utils;
+    use near_sdk::AccountId;
+
+    fn minter() -> AccountId {
+        "minter.near".parse().unwrap()
+    }
+
+    #[test]
+    fn test_locker_can_always_deposit() {
+        let mut contract = test_utils::new_contract_with_binary();
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(
+            Address([1; 20]),
+            "alice.near".parse().unwrap(),
+            10,
+            None,
+            None,
+            None,
+        );
+
+        test_utils::set_context(test_utils::factory());
+        contract.add_minter(minter());
+        contract.remove_minter(test_utils::locker_account_id());
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(
+            Address([1; 20]),
+            "alice.near".parse().unwrap(),
+            10,
+            None,
+            None,
+            None,
+        );
+    }
+
+    #[test]
+    fn test_minter_can_deposit() {
+        let mut contract = test_utils::new_contract_with_binary();
+        contract.add_minter(minter());
+
+        test_utils::set_context(minter());
+        let _ = contract.on_deposit(
+            Address([1; 20]),
+            "alice.near".parse().unwrap(),
+            10,
+            None,
+            None,
+            None,
+        );
+
+        assert_eq!(contract.minters(), [minter()]);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_NOT_MINTER")]
+    fn test_unknown_account_cannot_deposit() {
+        let mut contract = test_utils::new_contract_with_binary();
+        contract.add_minter(minter());
+
+        test_utils::set_context("mallory.near".parse().unwrap());
+        let _ = contract.on_deposit(
+            Address([1; 20]),
+            "alice.near".parse().unwrap(),
+            10,
+            None,
+            None,
+            None,
+        );
+    }
+}
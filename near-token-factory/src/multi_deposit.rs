@@ -0,0 +1,260 @@
+//! Deposits of several tokens relayed in a single call, e.g. by the locker for
+//! high-throughput tokens. Unlike `on_deposit_batch`, each entry is checked on its own,
+//! and an entry that can't be minted is reported in the returned statuses instead of
+//! failing the whole call. Entries are only minted on deployed tokens. Entries of a
+//! token that is not deployed yet come back as `NotDeployed` without being recorded.
+//! The caller relays the first of them with `on_deposit`, which deploys the token, and
+//! the others with a later batch once the deploy succeeded.
+use crate::deferred::PausedDepositPolicy;
+use crate::detach::ERR_TOKEN_DETACHED;
+use crate::rate_limits::ERR_RATE_LIMIT_EXCEEDED;
+use crate::receiver_allowlist::ERR_RECEIVER_NOT_ALLOWED;
+use crate::token_allowlist::ERR_TOKEN_NOT_ALLOWED;
+use crate::{
+    aurora_sdk, events, is_reserved_token_address, Contract, ContractExt, DepositPlan,
+    ERR_DEPOSIT_GAS_TOO_HIGH, ERR_INVALID_BATCH_SIZE, ERR_TOKEN_PAUSED, ERR_ZERO_TOKEN,
+    MINT_CALLBACK_GAS, PAUSE_DEPOSITS,
+};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::serde::Serialize;
+use near_sdk::{env, near_bindgen, require, AccountId, Gas, Promise};
+
+/// Gas of the processing of an entry by `on_deposit_entries`, on top of the gas
+/// attached to its mint.
+const DEPOSIT_ENTRY_GAS: Gas = Gas(5_000_000_000_000);
+
+/// Deposit relayed by `on_deposit_entries`.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct DepositEntry {
+    pub token: aurora_sdk::Address,
+    pub receiver_id: AccountId,
+    pub amount: u128,
+    /// Transfer id of the deposit, see `is_transfer_processed`.
+    pub transfer_id: Option<u64>,
+}
+
+/// Outcome of an entry of `on_deposit_entries`.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(crate = "near_sdk::serde")]
+pub enum DepositEntryStatus {
+    /// The mint was scheduled. A failed mint is handled by `on_mint_complete`.
+    Minted,
+    /// The token is paused, so the deposit was queued, see `PausedDepositPolicy`.
+    Queued,
+    /// The transfer id was already processed, so the entry was ignored.
+    Duplicate,
+    /// The token is not deployed yet, so the entry was not processed. It must be
+    /// relayed again with `on_deposit`, which deploys the token.
+    NotDeployed,
+    /// The entry was not processed, for the given error.
+    Rejected(String),
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Mint the deposits of `entries`, of any deployed tokens, and return the outcome
+    /// of each entry in the same order. Rejected and `NotDeployed` entries are not
+    /// recorded, so they can be relayed again. Entries that don't fit in the remaining
+    /// gas are rejected. At most `max_deposits` of the batch limits can be relayed.
+    /// This method can only be called by the locker and the minters.
+    pub fn on_deposit_entries(
+        &mut self,
+        #[serializer(borsh)] entries: Vec<DepositEntry>,
+    ) -> Vec<DepositEntryStatus> {
+        self.assert_not_paused(PAUSE_DEPOSITS);
+        self.assert_can_deposit();
+        require!(
+            !entries.is_empty() && entries.len() <= self.batch_limits.max_deposits as usize,
+            ERR_INVALID_BATCH_SIZE
+        );
+
+        entries
+            .into_iter()
+            .map(|entry| match self.process_deposit_entry(entry) {
+                Ok(status) => status,
+                Err(err) => DepositEntryStatus::Rejected(err.to_string()),
+            })
+            .collect()
+    }
+}
+
+impl Contract {
+    fn process_deposit_entry(
+        &mut self,
+        entry: DepositEntry,
+    ) -> Result<DepositEntryStatus, &'static str> {
+        let DepositEntry {
+            token,
+            receiver_id,
+            amount,
+            transfer_id,
+        } = entry;
+        // Replays are reported as such, whatever changed since the first relay.
+        if let Some(transfer_id) = transfer_id {
+            if self.transfer_processed(&env::predecessor_account_id(), transfer_id) {
+                return Ok(DepositEntryStatus::Duplicate);
+            }
+        }
+        if is_reserved_token_address(&token) {
+            return Err(ERR_ZERO_TOKEN);
+        }
+        if !self.is_token_allowed(token.clone()) {
+            return Err(ERR_TOKEN_NOT_ALLOWED);
+        }
+        let token_account_id = self.route_deposit(&token);
+        if self.deposit_plan(&token_account_id) != DepositPlan::Mint {
+            return Ok(DepositEntryStatus::NotDeployed);
+        }
+        if self.detached_tokens.contains(&token_account_id) {
+            return Err(ERR_TOKEN_DETACHED);
+        }
+        if !self.is_receiver_allowed_internal(&token_account_id, &receiver_id) {
+            return Err(ERR_RECEIVER_NOT_ALLOWED);
+        }
+        if !self.is_deposit_within_limit(&token_account_id, amount) {
+            return Err(ERR_RATE_LIMIT_EXCEEDED);
+        }
+        let mints = 1 + u64::from(self.fee_collector.is_some());
+        let required_gas =
+            self.gas_config.mint_gas().0 * mints + MINT_CALLBACK_GAS.0 + DEPOSIT_ENTRY_GAS.0;
+        if env::prepaid_gas().0.saturating_sub(env::used_gas().0) < required_gas {
+            return Err(ERR_DEPOSIT_GAS_TOO_HIGH);
+        }
+        let paused = self.is_token_account_paused(&token_account_id);
+        if paused && self.paused_deposit_policy != PausedDepositPolicy::Queue {
+            return Err(ERR_TOKEN_PAUSED);
+        }
+
+        // The transfer id is not marked in the dry-run mode, see `on_deposit`.
+        if self.dry_run {
+            if paused {
+                return Ok(DepositEntryStatus::Queued);
+            }
+            self.emit_planned_mint(&token_account_id, &receiver_id, amount);
+            return Ok(DepositEntryStatus::Minted);
+        }
+        if let Some(transfer_id) = transfer_id {
+            if !self.mark_transfer_processed(transfer_id) {
+                return Ok(DepositEntryStatus::Duplicate);
+            }
+        }
+        if paused {
+            self.queue_paused_deposit(&token_account_id, &receiver_id, amount, None);
+            return Ok(DepositEntryStatus::Queued);
+        }
+
+        self.consume_deposit_limit(&token_account_id, amount);
+        let (amount, _) = self.clamp_deposit(&token_account_id, &receiver_id, amount, None);
+        let fee = self.deposit_fee(&token_account_id, amount);
+        let minted = amount - fee;
+        self.record_deposit(&token_account_id, &receiver_id, minted);
+        let fee_collector = self.charge_fee(&token_account_id, &receiver_id, fee);
+        let mints = 1 + u64::from(fee_collector.is_some());
+        self.emit_event(events::ConnectorEvent::Deposit {
+            token: token.to_string(),
+            receiver_id: receiver_id.clone(),
+            amount: minted.into(),
+            origin_tx_hash: None,
+        });
+
+        let promise = Promise::new(token_account_id.clone());
+        self.append_deposit_mints(promise, &receiver_id, minted, fee_collector, fee)
+            .then(
+                Contract::ext(env::current_account_id())
+                    .with_static_gas(MINT_CALLBACK_GAS)
+                    .with_unused_gas_weight(0)
+                    .on_mint_complete(token_account_id, receiver_id, amount.into(), Some(mints)),
+            );
+        Ok(DepositEntryStatus::Minted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DepositEntry, DepositEntryStatus};
+    use crate::aurora_sdk::Address;
+    use crate::deferred::PausedDepositPolicy;
+    use crate::test_utils;
+    use near_sdk::json_types::U64;
+
+    fn entry(token: u8, amount: u128, transfer_id: Option<u64>) -> DepositEntry {
+        DepositEntry {
+            token: Address([token; 20]),
+            receiver_id: "alice.near".parse().unwrap(),
+            amount,
+            transfer_id,
+        }
+    }
+
+    #[test]
+    fn test_deposit_entries() {
+        let mut contract = test_utils::new_contract_with_binary();
+        test_utils::deploy_token(&mut contract, &Address([1; 20]));
+        test_utils::deploy_token(&mut contract, &Address([2; 20]));
+        contract.pause_token(Address([2; 20]));
+        contract.set_paused_deposit_policy(PausedDepositPolicy::Queue);
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let statuses = contract.on_deposit_entries(vec![
+            entry(1, 10, Some(1)),
+            entry(3, 20, None),
+            entry(1, 30, Some(1)),
+            entry(2, 40, None),
+        ]);
+
+        assert_eq!(
+            statuses,
+            [
+                DepositEntryStatus::Minted,
+                DepositEntryStatus::NotDeployed,
+                DepositEntryStatus::Duplicate,
+                DepositEntryStatus::Queued,
+            ]
+        );
+        let calls = test_utils::function_calls(&test_utils::token_account_id(&Address([1; 20])));
+        let methods: Vec<&str> = calls.iter().map(|call| call.method.as_str()).collect();
+        assert_eq!(methods, ["storage_deposit", "deposit"]);
+        assert_eq!(contract.mirrored_supply(Address([1; 20])).0, 10);
+        // The entry of the token that is not deployed is not recorded, so it can be
+        // relayed again.
+        assert!(
+            test_utils::function_calls(&test_utils::token_account_id(&Address([3; 20]))).is_empty()
+        );
+    }
+
+    #[test]
+    fn test_replayed_entry_of_paused_token_is_duplicate() {
+        let mut contract = test_utils::new_contract_with_binary();
+        test_utils::deploy_token(&mut contract, &Address([1; 20]));
+        test_utils::set_context(test_utils::locker_account_id());
+        let statuses = contract.on_deposit_entries(vec![entry(1, 10, Some(1))]);
+        assert_eq!(statuses, [DepositEntryStatus::Minted]);
+
+        test_utils::set_context(test_utils::factory());
+        contract.pause_token(Address([1; 20]));
+        test_utils::set_context(test_utils::locker_account_id());
+        let statuses = contract.on_deposit_entries(vec![entry(1, 10, Some(1))]);
+        assert_eq!(statuses, [DepositEntryStatus::Duplicate]);
+    }
+
+    #[test]
+    fn test_dry_run_deposit_entries_keep_transfer_ids() {
+        let mut contract = test_utils::new_contract_with_binary();
+        test_utils::deploy_token(&mut contract, &Address([1; 20]));
+        contract.set_dry_run(true);
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let statuses = contract.on_deposit_entries(vec![entry(1, 10, Some(1))]);
+        assert_eq!(statuses, [DepositEntryStatus::Minted]);
+        assert!(!contract.is_transfer_processed(U64(1), None));
+        assert_eq!(contract.mirrored_supply(Address([1; 20])).0, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_INVALID_BATCH_SIZE")]
+    fn test_empty_deposit_entries() {
+        let mut contract = test_utils::new_contract_with_binary();
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit_entries(Vec::new());
+    }
+}
@@ -0,0 +1,598 @@
+//! Derivation of the NEP-141 representative account ids from ERC-20 addresses.
+use crate::{aurora_sdk, Contract, ContractExt, ERR_INVALID_ACCOUNT, ERR_INVALID_TOKEN_ACCOUNT};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{env, near_bindgen, require, AccountId};
+
+/// Maximum length of a token account id.
+pub const MAX_ACCOUNT_ID_LEN: usize = 63;
+/// Maximum number of registered tokens checked by a call to `index_consistent`.
+const MAX_INDEX_CHECKS: u64 = 50;
+
+const ERR_TOKEN_ACCOUNT_COLLISION: &str =
+    "ERR_TOKEN_ACCOUNT_COLLISION: Token account id is already used by another address.";
+
+/// Label of the token account ids, i.e. the part before `.{factory}`.
+#[derive(
+    BorshSerialize, BorshDeserialize, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq,
+)]
+#[serde(crate = "near_sdk::serde")]
+pub enum NamingScheme {
+    /// The 40 hex chars of the address. The address can be decoded from the account id.
+    Hex,
+    /// The first 32 hex chars of the sha256 hash of the address, for factories whose
+    /// account id is too long for `Hex`. The address can't be decoded from the
+    /// account id, so it is looked up in the reverse index.
+    HashedLabel,
+}
+
+impl Default for NamingScheme {
+    fn default() -> Self {
+        NamingScheme::Hex
+    }
+}
+
+impl NamingScheme {
+    pub fn label_len(self) -> usize {
+        match self {
+            NamingScheme::Hex => 40,
+            NamingScheme::HashedLabel => 32,
+        }
+    }
+
+    pub(crate) fn label(self, address: &aurora_sdk::Address) -> String {
+        match self {
+            NamingScheme::Hex => address.encode(),
+            NamingScheme::HashedLabel => {
+                let mut label = hex::encode(env::sha256(&address.0));
+                label.truncate(self.label_len());
+                label
+            }
+        }
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// NEAR account ID of the NEP-141 representative of the ERC-20 at `token_address`,
+    /// whether it is deployed or not.
+    pub fn token_account_id(&self, token_address: aurora_sdk::Address) -> AccountId {
+        self.account_id_from_token_address(token_address)
+    }
+
+    /// Aurora address of the ERC-20 mirrored by `token_account_id`. It fails with
+    /// `ERR_INVALID_TOKEN_ACCOUNT` if the account is not a token account of the factory.
+    pub fn token_address(&self, token_account_id: AccountId) -> aurora_sdk::Address {
+        self.address_from_token_account_id(token_account_id)
+    }
+
+    /// Scheme used to derive the account ids of the tokens.
+    pub fn naming_scheme(&self) -> NamingScheme {
+        self.naming_scheme
+    }
+
+    /// Length of the longest token account id under the current naming scheme and
+    /// parent account of new tokens, i.e. the one of the all-`f` address. Deployers can
+    /// check it is at most `MAX_ACCOUNT_ID_LEN`, so every address can have a
+    /// representative.
+    pub fn worst_case_account_id_len(&self) -> usize {
+        let label = self.naming_scheme.label(&aurora_sdk::Address([0xff; 20]));
+        label.len() + 1 + self.token_parent_account_id().as_str().len()
+    }
+
+    /// Whether the NEP-141 representative of the ERC-20 at `token_address` is deployed
+    /// and registered.
+    pub fn is_token_deployed(&self, token_address: aurora_sdk::Address) -> bool {
+        self.try_account_id_from_token_address(token_address)
+            .map_or(false, |account_id| self.tokens.get(&account_id).is_some())
+    }
+
+    /// Re-derive the forward and reverse index entries of the registered tokens in
+    /// `[from, from + limit)`, e.g. if they became inconsistent after a migration.
+    /// Under `NamingScheme::HashedLabel`, and for tokens created by the deployer, only
+    /// tokens still in the reverse index can be repaired. Returns the number of tokens
+    /// indexed. ONLY the `Owner` role can call this method.
+    pub fn rebuild_reverse_index(&mut self, from: u64, limit: u64) -> u64 {
+        self.assert_owner();
+        self.record_admin_action("rebuild_reverse_index");
+
+        let entries: Vec<(aurora_sdk::Address, AccountId)> = self
+            .tokens
+            .keys()
+            .skip(from as usize)
+            .take(limit as usize)
+            .filter_map(|account_id| {
+                let address = match self.naming_scheme {
+                    NamingScheme::Hex => decode_hex_label(&account_id)
+                        .or_else(|| self.token_addresses.get(&account_id)),
+                    NamingScheme::HashedLabel => self.token_addresses.get(&account_id),
+                }?;
+                Some((address, account_id))
+            })
+            .collect();
+
+        for (address, account_id) in entries.iter() {
+            self.index_token_account(address, account_id);
+        }
+        entries.len() as u64
+    }
+
+    /// Whether the indexes match the registered tokens, i.e. each checked token has a
+    /// reverse index entry whose forward index entry points back to the token. At most
+    /// `MAX_INDEX_CHECKS` tokens are checked, starting at an offset that changes with
+    /// the block height, so repeated calls eventually cover every token. Monitoring
+    /// SHOULD call it after migrations, see `rebuild_reverse_index` to repair the
+    /// indexes.
+    pub fn index_consistent(&self) -> bool {
+        let len = self.tokens.len();
+        if len == 0 {
+            return true;
+        }
+        let start = env::block_height().wrapping_mul(MAX_INDEX_CHECKS) % len;
+
+        self.tokens
+            .keys()
+            .skip(start as usize)
+            .chain(self.tokens.keys())
+            .take(len.min(MAX_INDEX_CHECKS) as usize)
+            .all(|account_id| {
+                if self.token_silos.get(&account_id).is_some() {
+                    return self.token_addresses.get(&account_id).is_some();
+                }
+                self.token_addresses
+                    .get(&account_id)
+                    .map_or(false, |address| {
+                        self.token_account_ids.get(&address).as_ref() == Some(&account_id)
+                    })
+            })
+    }
+}
+
+impl Contract {
+    /// Convert Aurora address of an ERC-20 to the NEAR account ID NEP-141 representative.
+    pub(crate) fn account_id_from_token_address(&self, address: aurora_sdk::Address) -> AccountId {
+        self.try_account_id_from_token_address(address)
+            .unwrap_or_else(|| env::panic_str(ERR_INVALID_ACCOUNT))
+    }
+
+    /// NEAR account ID NEP-141 representative of an ERC-20, or `None` if the derived
+    /// account ID is not valid. Tokens that are not indexed yet are derived under the
+    /// parent account of new tokens, see `token_parent_account_id`.
+    pub(crate) fn try_account_id_from_token_address(
+        &self,
+        address: aurora_sdk::Address,
+    ) -> Option<AccountId> {
+        if let Some(account_id) = self.token_account_ids.get(&address) {
+            return Some(account_id);
+        }
+        let account_id = format!(
+            "{}.{}",
+            self.naming_scheme.label(&address),
+            self.token_parent_account_id()
+        );
+        if account_id.len() > MAX_ACCOUNT_ID_LEN {
+            return None;
+        }
+        account_id.parse().ok()
+    }
+
+    /// Convert a NEAR account ID NEP-141 representative to the Aurora address of an
+    /// ERC-20.
+    pub(crate) fn address_from_token_account_id(
+        &self,
+        account_id: AccountId,
+    ) -> aurora_sdk::Address {
+        self.try_address_from_token_account_id(&account_id)
+            .unwrap_or_else(|| env::panic_str(ERR_INVALID_TOKEN_ACCOUNT))
+    }
+
+    /// Aurora address of an ERC-20, or `None` if `account_id` is not a token account
+    /// id of the factory. Under `NamingScheme::Hex` it must be of the form
+    /// `{40 hex chars}.{factory}`, under `NamingScheme::HashedLabel` it must be in the
+    /// reverse index.
+    pub(crate) fn try_address_from_token_account_id(
+        &self,
+        account_id: &AccountId,
+    ) -> Option<aurora_sdk::Address> {
+        if let Some(address) = self.token_addresses.get(account_id) {
+            return Some(address);
+        }
+        match self.naming_scheme {
+            NamingScheme::Hex => decode_hex_label(account_id),
+            NamingScheme::HashedLabel => None,
+        }
+    }
+
+    /// Record the account id of a token in the forward and reverse indexes. It panics if
+    /// the account id is already indexed for another address, e.g. if two addresses have
+    /// the same label under `NamingScheme::HashedLabel`.
+    pub(crate) fn index_token_account(
+        &mut self,
+        address: &aurora_sdk::Address,
+        account_id: &AccountId,
+    ) {
+        require!(
+            self.token_addresses
+                .get(account_id)
+                .map_or(true, |indexed| &indexed == address),
+            ERR_TOKEN_ACCOUNT_COLLISION
+        );
+        // The address of a silo token also has a representative on the default engine,
+        // which keeps the forward index entry.
+        if self.token_silos.get(account_id).is_none() {
+            self.token_account_ids.insert(address, account_id);
+        }
+        self.token_addresses.insert(account_id, address);
+    }
+}
+
+/// Address encoded in an account id of the form `{40 hex chars}.{factory}`. Labels are
+/// encoded in lowercase, and the label is normalized to lowercase before decoding, so
+/// the decoded address never depends on the case of the hex chars.
+pub(crate) fn decode_hex_label(account_id: &AccountId) -> Option<aurora_sdk::Address> {
+    let label = account_id
+        .as_str()
+        .strip_suffix(env::current_account_id().as_str())?
+        .strip_suffix('.')?;
+    if label.len() != NamingScheme::Hex.label_len() {
+        return None;
+    }
+    let mut buffer = [0u8; 20];
+    hex::decode_to_slice(label.to_ascii_lowercase(), &mut buffer).ok()?;
+    Some(buffer.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{NamingScheme, MAX_ACCOUNT_ID_LEN};
+    use crate::aurora_sdk::Address;
+    use crate::test_utils;
+    use crate::Contract;
+    use near_sdk::testing_env;
+    use proptest::prelude::{any, prop_assert, prop_assert_eq, proptest};
+
+    const LONG_FACTORY: &str = "long-token-factory-name.near";
+
+    fn new_hashed_contract() -> Contract {
+        testing_env!(test_utils::context(LONG_FACTORY.parse().unwrap())
+            .current_account_id(LONG_FACTORY.parse().unwrap())
+            .build());
+        let mut contract = Contract::new_with_naming_scheme(
+            test_utils::aurora(),
+            test_utils::locker(),
+            NamingScheme::HashedLabel,
+        );
+        contract.set_token_binary(vec![0, 97, 115, 109].into());
+        contract
+    }
+
+    #[test]
+    fn test_hashed_label_round_trip() {
+        let mut contract = new_hashed_contract();
+        let token = Address([1; 20]);
+        let token_account_id = contract.account_id_from_token_address(token.clone());
+        assert_eq!(token_account_id.as_str().len(), 32 + 1 + LONG_FACTORY.len());
+        // Not deployed yet, so it can't be resolved back.
+        assert_eq!(
+            contract.try_address_from_token_account_id(&token_account_id),
+            None
+        );
+
+        testing_env!(test_utils::context(test_utils::locker_account_id())
+            .current_account_id(LONG_FACTORY.parse().unwrap())
+            .build());
+        let _ = contract.on_deposit(
+            token.clone(),
+            "alice.near".parse().unwrap(),
+            10,
+            None,
+            None,
+            None,
+        );
+
+        assert_eq!(
+            contract.account_id_from_token_address(token.clone()),
+            token_account_id
+        );
+        assert_eq!(
+            contract.address_from_token_account_id(token_account_id.clone()),
+            token
+        );
+
+        // Withdrawals of the token resolve its address through the reverse index.
+        testing_env!(test_utils::context(token_account_id)
+            .current_account_id(LONG_FACTORY.parse().unwrap())
+            .build());
+        let _ = contract.on_withdraw(Address([3; 20]), 5.into(), None, None, None);
+        assert_eq!(contract.mirrored_supply(token).0, 5);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_TOKEN_ACCOUNT_COLLISION")]
+    fn test_hashed_label_collision() {
+        let mut contract = new_hashed_contract();
+        let token = Address([1; 20]);
+        // Pretend another address has the same label.
+        let token_account_id = contract.account_id_from_token_address(token.clone());
+        contract
+            .token_addresses
+            .insert(&token_account_id, &Address([2; 20]));
+
+        testing_env!(test_utils::context(test_utils::locker_account_id())
+            .current_account_id(LONG_FACTORY.parse().unwrap())
+            .build());
+        let _ = contract.on_deposit(token, "alice.near".parse().unwrap(), 10, None, None, None);
+    }
+
+    /// Derive the id of a token deployed by a factory under `parent` and reverse it,
+    /// before and after the token is deployed.
+    fn assert_hex_round_trip(parent: &str) {
+        let factory: near_sdk::AccountId = format!("factory.{}", parent).parse().unwrap();
+        testing_env!(test_utils::context(factory.clone())
+            .current_account_id(factory.clone())
+            .build());
+        let mut contract = Contract::new(test_utils::aurora(), test_utils::locker(), None);
+        contract.set_token_binary(vec![0, 97, 115, 109].into());
+        let token = Address([0xab; 20]);
+
+        let token_account_id = contract.account_id_from_token_address(token.clone());
+        assert_eq!(
+            token_account_id.as_str(),
+            format!("{}.{}", token.encode(), factory)
+        );
+        assert_eq!(
+            contract.address_from_token_account_id(token_account_id.clone()),
+            token
+        );
+
+        testing_env!(test_utils::context(test_utils::locker_account_id())
+            .current_account_id(factory)
+            .build());
+        let _ = contract.on_deposit(
+            token.clone(),
+            "alice.near".parse().unwrap(),
+            10,
+            None,
+            None,
+            None,
+        );
+        assert_eq!(
+            test_utils::function_calls(&token_account_id)[0].method,
+            "new"
+        );
+        assert_eq!(
+            contract.address_from_token_account_id(token_account_id),
+            token
+        );
+    }
+
+    #[test]
+    fn test_token_conversion_views() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        let token_account_id = test_utils::token_account_id(&token);
+        assert_eq!(contract.token_account_id(token.clone()), token_account_id);
+        assert_eq!(contract.token_address(token_account_id), token);
+        assert!(!contract.is_token_deployed(token.clone()));
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(
+            token.clone(),
+            "alice.near".parse().unwrap(),
+            10,
+            None,
+            None,
+            None,
+        );
+        test_utils::confirm_deploy(&mut contract, &token);
+
+        assert!(contract.is_token_deployed(token));
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_INVALID_TOKEN_ACCOUNT")]
+    fn test_token_address_rejects_non_token_account() {
+        let contract = test_utils::new_contract();
+        contract.token_address("alice.near".parse().unwrap());
+    }
+
+    #[test]
+    fn test_worst_case_account_id_len() {
+        // 22 chars, the longest factory account id for hex labels.
+        let factory: near_sdk::AccountId = "factory-at-limit1.near".parse().unwrap();
+        testing_env!(test_utils::context(factory.clone())
+            .current_account_id(factory)
+            .build());
+        let contract = Contract::new(test_utils::aurora(), test_utils::locker(), None);
+
+        assert_eq!(contract.worst_case_account_id_len(), MAX_ACCOUNT_ID_LEN);
+        assert!(contract
+            .try_account_id_from_token_address(Address([0xff; 20]))
+            .is_some());
+
+        let contract = new_hashed_contract();
+        assert_eq!(
+            contract.worst_case_account_id_len(),
+            32 + 1 + LONG_FACTORY.len()
+        );
+    }
+
+    #[test]
+    fn test_hex_round_trip_under_near() {
+        assert_hex_round_trip("near");
+    }
+
+    #[test]
+    fn test_hex_round_trip_under_testnet() {
+        assert_hex_round_trip("testnet");
+    }
+
+    #[test]
+    fn test_hex_labels_are_lowercase() {
+        let contract = test_utils::new_contract();
+        for address in [
+            Address([0x00; 20]),
+            Address([0xab; 20]),
+            Address([0xff; 20]),
+            Address([
+                0xde, 0xad, 0xbe, 0xef, 0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef, 0xa0, 0xb1,
+                0xc2, 0xd3, 0xe4, 0xf5, 0x0a, 0xfc,
+            ]),
+        ] {
+            let display = address.to_string();
+            assert_eq!(display, display.to_ascii_lowercase());
+
+            let token_account_id = contract.account_id_from_token_address(address.clone());
+            let label = token_account_id.as_str().split('.').next().unwrap();
+            assert_eq!(label, address.encode());
+            assert_eq!(label, label.to_ascii_lowercase());
+            assert_eq!(
+                contract.address_from_token_account_id(token_account_id),
+                address
+            );
+        }
+    }
+
+    #[test]
+    fn test_rebuild_reverse_index() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let tokens = [Address([1; 20]), Address([2; 20])];
+        test_utils::set_context(test_utils::locker_account_id());
+        for token in tokens.iter() {
+            let _ = contract.on_deposit(
+                token.clone(),
+                "alice.near".parse().unwrap(),
+                10,
+                None,
+                None,
+                None,
+            );
+        }
+        for token in tokens.iter() {
+            test_utils::confirm_deploy(&mut contract, token);
+        }
+
+        for token in tokens.iter() {
+            contract.token_account_ids.remove(token);
+            contract
+                .token_addresses
+                .remove(&test_utils::token_account_id(token));
+        }
+
+        test_utils::set_context(test_utils::factory());
+        assert_eq!(contract.rebuild_reverse_index(0, 1), 1);
+        assert_eq!(contract.rebuild_reverse_index(1, 10), 1);
+
+        for token in tokens.iter() {
+            let token_account_id = test_utils::token_account_id(token);
+            assert_eq!(
+                contract.token_account_ids.get(token),
+                Some(token_account_id.clone())
+            );
+            assert_eq!(
+                contract.token_addresses.get(&token_account_id),
+                Some(token.clone())
+            );
+        }
+    }
+
+    #[test]
+    fn test_index_consistent() {
+        let mut contract = test_utils::new_contract_with_binary();
+        assert!(contract.index_consistent());
+        let tokens = [Address([1; 20]), Address([2; 20])];
+        for token in tokens.iter() {
+            test_utils::deploy_token(&mut contract, token);
+        }
+        assert!(contract.index_consistent());
+
+        // The forward index points to the wrong token.
+        contract
+            .token_account_ids
+            .insert(&tokens[0], &test_utils::token_account_id(&tokens[1]));
+        assert!(!contract.index_consistent());
+
+        contract.rebuild_reverse_index(0, 10);
+        assert!(contract.index_consistent());
+
+        // The reverse index entry is missing.
+        contract
+            .token_addresses
+            .remove(&test_utils::token_account_id(&tokens[1]));
+        assert!(!contract.index_consistent());
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_INVALID_ACCOUNT")]
+    fn test_hex_labels_reject_long_factory() {
+        testing_env!(test_utils::context(LONG_FACTORY.parse().unwrap())
+            .current_account_id(LONG_FACTORY.parse().unwrap())
+            .build());
+        let _ = Contract::new(test_utils::aurora(), test_utils::locker(), None);
+    }
+
+    /// Make `factory` the current account, which can be longer than the factories
+    /// `Contract::new` accepts.
+    fn set_factory(factory: &str) {
+        testing_env!(test_utils::context(test_utils::factory())
+            .current_account_id(factory.parse().unwrap())
+            .build());
+    }
+
+    proptest! {
+        #[test]
+        fn prop_hex_round_trip(
+            address in any::<[u8; 20]>(),
+            factory in "[a-z0-9]{1,25}\\.near",
+        ) {
+            let mut contract = test_utils::new_contract();
+            set_factory(&factory);
+            let address = Address(address);
+
+            let account_id = contract.try_account_id_from_token_address(address.clone());
+            if NamingScheme::Hex.label_len() + 1 + factory.len() > MAX_ACCOUNT_ID_LEN {
+                prop_assert_eq!(account_id, None);
+                return Ok(());
+            }
+            let account_id = account_id.unwrap();
+            prop_assert!(account_id.as_str().len() <= MAX_ACCOUNT_ID_LEN);
+            prop_assert_eq!(
+                contract.try_address_from_token_account_id(&account_id),
+                Some(address.clone())
+            );
+            // Indexing the account id doesn't change the round trip.
+            contract.index_token_account(&address, &account_id);
+            prop_assert_eq!(
+                contract.try_account_id_from_token_address(address.clone()),
+                Some(account_id.clone())
+            );
+            prop_assert_eq!(
+                contract.try_address_from_token_account_id(&account_id),
+                Some(address)
+            );
+        }
+
+        #[test]
+        fn prop_hashed_label_round_trip(
+            address in any::<[u8; 20]>(),
+            factory in "[a-z0-9]{1,30}\\.near",
+        ) {
+            let mut contract = new_hashed_contract();
+            set_factory(&factory);
+            let address = Address(address);
+
+            let account_id = contract.try_account_id_from_token_address(address.clone());
+            if NamingScheme::HashedLabel.label_len() + 1 + factory.len() > MAX_ACCOUNT_ID_LEN {
+                prop_assert_eq!(account_id, None);
+                return Ok(());
+            }
+            let account_id = account_id.unwrap();
+            prop_assert_eq!(contract.try_address_from_token_account_id(&account_id), None);
+            contract.index_token_account(&address, &account_id);
+            prop_assert_eq!(
+                contract.try_address_from_token_account_id(&account_id),
+                Some(address)
+            );
+        }
+    }
+}
@@ -0,0 +1,299 @@
+//! Bridging of NEP-141 tokens into Aurora, the reverse of the ERC-20 bridging. A NEP-141
+//! transferred to the factory with `ft_transfer_call` is locked by the factory, and the
+//! locker mints the same amount of its wrapped ERC-20 to the Aurora address in `msg`,
+//! deploying the wrapped ERC-20 on first use. Burning the wrapped ERC-20 with the
+//! locker `release` calls `on_release`, which transfers the locked tokens back.
+//!
+//! The NEP-141 representatives deployed by the factory can't be bridged this way, they
+//! are withdrawn instead.
+use crate::{
+    abi, aurora_sdk, events, ext, logging, Contract, ContractExt, ERR_ONLY_LOCKER, FT_TRANSFER_GAS,
+    PAUSE_DEPOSITS, PAUSE_WITHDRAWALS,
+};
+use near_sdk::borsh::BorshDeserialize;
+use near_sdk::json_types::U128;
+use near_sdk::{
+    env, near_bindgen, require, AccountId, Balance, Gas, PromiseOrValue, PromiseResult,
+};
+
+/// Selector of the locker `mintWrapped`, which mints the wrapped ERC-20 of a NEP-141.
+pub const MINT_WRAPPED_SELECTOR: [u8; 4] = abi::selector("mintWrapped(bytes,address,uint256)");
+/// Gas attached to the Aurora call minting the wrapped ERC-20, which may deploy it.
+const MINT_WRAPPED_AURORA_CALL_GAS: Gas = Gas(100_000_000_000_000);
+/// Gas attached to `on_wrapped_mint_complete`.
+const WRAPPED_MINT_CALLBACK_GAS: Gas = Gas(5_000_000_000_000);
+/// Gas attached to `on_release_complete`.
+const RELEASE_CALLBACK_GAS: Gas = Gas(5_000_000_000_000);
+
+const ERR_RELEASE_EXCEEDS_LOCKED: &str =
+    "ERR_RELEASE_EXCEEDS_LOCKED: Release exceeds the amount of the NEP-141 locked.";
+
+#[near_bindgen]
+impl Contract {
+    /// Receiver of the `ft_transfer_call` bridging a NEP-141 into Aurora. `msg` is the
+    /// Aurora address receiving the wrapped ERC-20, see `Address::decode`.
+    /// The whole amount is refunded if `msg` is not an address, if withdrawals are
+    /// paused, if the token is a representative deployed by the factory, or if the mint
+    /// on Aurora fails.
+    pub fn ft_on_transfer(
+        &mut self,
+        sender_id: AccountId,
+        amount: U128,
+        msg: String,
+    ) -> PromiseOrValue<U128> {
+        let token_account_id = env::predecessor_account_id();
+        let receiver = match msg.parse::<aurora_sdk::Address>().ok() {
+            Some(receiver) => receiver,
+            None => return self.reject_nep141(&token_account_id, amount, "msg is not an address"),
+        };
+        if self.paused & PAUSE_WITHDRAWALS != 0 {
+            return self.reject_nep141(&token_account_id, amount, "bridging is paused");
+        }
+        if self.tokens.get(&token_account_id).is_some()
+            || self.pending_deploys.contains(&token_account_id)
+        {
+            return self.reject_nep141(&token_account_id, amount, "token must be withdrawn");
+        }
+
+        let input = abi::encode_call(
+            MINT_WRAPPED_SELECTOR,
+            &[
+                abi::AbiValue::Bytes(token_account_id.as_bytes().to_vec()),
+                abi::AbiValue::Address(receiver.clone()),
+                abi::AbiValue::Uint(amount),
+            ],
+        );
+        if self.dry_run {
+            self.emit_planned_call(
+                &self.aurora,
+                "call",
+                format!("0x{}", hex::encode(&input)),
+                MINT_WRAPPED_AURORA_CALL_GAS,
+            );
+            return PromiseOrValue::Value(amount);
+        }
+
+        let locked = self.locked_nep141(token_account_id.clone()).0 + amount.0;
+        self.locked_nep141.insert(&token_account_id, &locked);
+        self.emit_event(events::ConnectorEvent::Nep141Locked {
+            token_account_id: token_account_id.clone(),
+            sender_id,
+            receiver: receiver.to_string(),
+            amount,
+        });
+
+        aurora_sdk::aurora::ext_aurora::ext(self.aurora.clone())
+            .with_static_gas(MINT_WRAPPED_AURORA_CALL_GAS)
+            .call(aurora_sdk::aurora::call_args(self.locker.clone(), input))
+            .then(
+                Contract::ext(env::current_account_id())
+                    .with_static_gas(WRAPPED_MINT_CALLBACK_GAS)
+                    .with_unused_gas_weight(0)
+                    .on_wrapped_mint_complete(token_account_id, amount),
+            )
+            .into()
+    }
+
+    /// Callback invoked after the Aurora call issued by `ft_on_transfer`. If the mint
+    /// failed, the tokens are unlocked, and the whole amount is returned as unused, so
+    /// the NEP-141 refunds the sender.
+    /// This is a callback function that can be only executed from the contract itself.
+    #[private]
+    pub fn on_wrapped_mint_complete(&mut self, token_account_id: AccountId, amount: U128) -> U128 {
+        let minted = match env::promise_result(0) {
+            PromiseResult::Successful(output) => {
+                match aurora_sdk::SubmitResult::try_from_slice(&output) {
+                    Ok(result) => result.status.is_ok(),
+                    Err(_) => false,
+                }
+            }
+            _ => false,
+        };
+        if minted {
+            return U128(0);
+        }
+
+        self.log_at(
+            logging::LogLevel::Errors,
+            &format!("Mint of {} wrapped {} failed", amount.0, token_account_id),
+        );
+        self.unlock_nep141(&token_account_id, amount.0);
+        amount
+    }
+
+    /// Method called by the locker when `amount` of the wrapped ERC-20 of the NEP-141 at
+    /// `token_account_id` was burnt on Aurora. The locked tokens are transferred to
+    /// `receiver_id`, which MUST be registered on the NEP-141.
+    pub fn on_release(
+        &mut self,
+        #[serializer(borsh)] token_account_id: AccountId,
+        #[serializer(borsh)] receiver_id: AccountId,
+        #[serializer(borsh)] amount: u128,
+    ) -> PromiseOrValue<()> {
+        self.assert_not_paused(PAUSE_DEPOSITS);
+        require!(
+            self.is_locker(&env::predecessor_account_id()),
+            ERR_ONLY_LOCKER
+        );
+        require!(
+            amount <= self.locked_nep141(token_account_id.clone()).0,
+            ERR_RELEASE_EXCEEDS_LOCKED
+        );
+        if self.dry_run {
+            self.emit_planned_call(
+                &token_account_id,
+                "ft_transfer",
+                format!("{} {}", receiver_id, amount),
+                FT_TRANSFER_GAS,
+            );
+            return PromiseOrValue::Value(());
+        }
+
+        self.unlock_nep141(&token_account_id, amount);
+        self.emit_event(events::ConnectorEvent::Nep141Released {
+            token_account_id: token_account_id.clone(),
+            receiver_id: receiver_id.clone(),
+            amount: amount.into(),
+        });
+
+        ext::ext_fungible_token::ext(token_account_id.clone())
+            .with_static_gas(FT_TRANSFER_GAS)
+            .with_attached_deposit(1)
+            .ft_transfer(receiver_id, amount.into(), None)
+            .then(
+                Contract::ext(env::current_account_id())
+                    .with_static_gas(RELEASE_CALLBACK_GAS)
+                    .with_unused_gas_weight(0)
+                    .on_release_complete(token_account_id, amount.into()),
+            )
+            .into()
+    }
+
+    /// Callback invoked after the transfer issued by `on_release`. If the transfer
+    /// failed, e.g. because the receiver is not registered, the tokens stay with the
+    /// factory, since their wrapped ERC-20 is already burnt, and the owner can transfer
+    /// them to the receiver with `rescue_ft`.
+    /// This is a callback function that can be only executed from the contract itself.
+    #[private]
+    pub fn on_release_complete(&mut self, token_account_id: AccountId, amount: U128) {
+        if !matches!(env::promise_result(0), PromiseResult::Successful(_)) {
+            self.log_at(
+                logging::LogLevel::Errors,
+                &format!("Release of {} {} failed", amount.0, token_account_id),
+            );
+        }
+    }
+
+    /// Amount of the NEP-141 at `token_account_id` locked by the factory, i.e. the
+    /// supply of its wrapped ERC-20.
+    pub fn locked_nep141(&self, token_account_id: AccountId) -> U128 {
+        self.locked_nep141
+            .get(&token_account_id)
+            .unwrap_or(0)
+            .into()
+    }
+}
+
+impl Contract {
+    fn reject_nep141(
+        &self,
+        token_account_id: &AccountId,
+        amount: U128,
+        reason: &str,
+    ) -> PromiseOrValue<U128> {
+        self.log_at(
+            logging::LogLevel::Errors,
+            &format!(
+                "Bridging of {} {} rejected: {}",
+                amount.0, token_account_id, reason
+            ),
+        );
+        PromiseOrValue::Value(amount)
+    }
+
+    fn unlock_nep141(&mut self, token_account_id: &AccountId, amount: Balance) {
+        let locked = self.locked_nep141(token_account_id.clone()).0 - amount;
+        if locked == 0 {
+            self.locked_nep141.remove(token_account_id);
+        } else {
+            self.locked_nep141.insert(token_account_id, &locked);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MINT_WRAPPED_SELECTOR;
+    use crate::aurora_sdk::Address;
+    use crate::test_utils;
+    use near_sdk::json_types::U128;
+    use near_sdk::{AccountId, PromiseOrValue, PromiseResult};
+
+    fn wnear() -> AccountId {
+        "wrap.near".parse().unwrap()
+    }
+
+    fn lock(contract: &mut crate::Contract, amount: u128) -> PromiseOrValue<U128> {
+        test_utils::set_context(wnear());
+        contract.ft_on_transfer(
+            "alice.near".parse().unwrap(),
+            U128(amount),
+            format!("0x{}", Address([3; 20]).encode()),
+        )
+    }
+
+    #[test]
+    fn test_lock_and_release() {
+        let mut contract = test_utils::new_contract();
+        let _ = lock(&mut contract, 10);
+        assert_eq!(contract.locked_nep141(wnear()), U128(10));
+        let calls = test_utils::function_calls(&test_utils::aurora());
+        assert_eq!(calls[0].method, "call");
+        assert!(calls[0]
+            .args
+            .windows(4)
+            .any(|window| window == MINT_WRAPPED_SELECTOR));
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_release(wnear(), "bob.near".parse().unwrap(), 4);
+        assert_eq!(contract.locked_nep141(wnear()), U128(6));
+        assert_eq!(
+            test_utils::function_calls(&wnear())[0].method,
+            "ft_transfer"
+        );
+    }
+
+    #[test]
+    fn test_failed_mint_is_refunded() {
+        let mut contract = test_utils::new_contract();
+        let _ = lock(&mut contract, 10);
+
+        test_utils::set_callback_context(vec![PromiseResult::Failed]);
+        assert_eq!(
+            contract.on_wrapped_mint_complete(wnear(), U128(10)),
+            U128(10)
+        );
+        assert_eq!(contract.locked_nep141(wnear()), U128(0));
+    }
+
+    #[test]
+    fn test_invalid_msg_is_refunded() {
+        let mut contract = test_utils::new_contract();
+
+        test_utils::set_context(wnear());
+        let outcome =
+            contract.ft_on_transfer("alice.near".parse().unwrap(), U128(10), "bob".to_string());
+        assert!(matches!(outcome, PromiseOrValue::Value(U128(10))));
+        assert_eq!(contract.locked_nep141(wnear()), U128(0));
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_RELEASE_EXCEEDS_LOCKED")]
+    fn test_release_above_locked() {
+        let mut contract = test_utils::new_contract();
+        let _ = lock(&mut contract, 10);
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_release(wnear(), "bob.near".parse().unwrap(), 11);
+    }
+}
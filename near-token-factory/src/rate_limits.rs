@@ -0,0 +1,312 @@
+//! Rolling 24 hours limits of the volume bridged per token, as a defense in depth
+//! against an exploit of the locker. Volumes are tracked in hourly buckets, so the
+//! window moves forward one hour at a time. Deposits above the limit fail, and
+//! withdrawals above the limit are minted back to the sender.
+use crate::{aurora_sdk, Contract, ContractExt};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::U128;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{env, near_bindgen, require, AccountId, Balance};
+
+/// Length of a bucket in nanoseconds.
+const BUCKET_DURATION: u64 = 3_600_000_000_000;
+/// Number of buckets in the window.
+const WINDOW_BUCKETS: u64 = 24;
+
+pub(crate) const ERR_RATE_LIMIT_EXCEEDED: &str =
+    "ERR_RATE_LIMIT_EXCEEDED: Deposit exceeds the daily limit of the token.";
+
+/// Maximum volume of a token bridged in each direction over the last 24 hours. `None`
+/// means unlimited.
+#[derive(
+    BorshSerialize, BorshDeserialize, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq,
+)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RateLimit {
+    pub deposit: Option<U128>,
+    pub withdraw: Option<U128>,
+}
+
+/// Volume of a token bridged over the last 24 hours, and what is left under its limit.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RateLimitUsage {
+    pub deposited: U128,
+    pub withdrawn: U128,
+    /// Volume that can still be deposited, `None` if deposits are unlimited.
+    pub deposit_remaining: Option<U128>,
+    /// Volume that can still be withdrawn, `None` if withdrawals are unlimited.
+    pub withdraw_remaining: Option<U128>,
+}
+
+/// Volumes of the last 24 hours, by hour.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct VolumeWindow {
+    buckets: Vec<(u64, Balance)>,
+}
+
+impl VolumeWindow {
+    fn volume(&self, hour: u64) -> Balance {
+        self.buckets
+            .iter()
+            .filter(|(bucket, _)| bucket + WINDOW_BUCKETS > hour)
+            .map(|(_, volume)| volume)
+            .fold(0, |total: Balance, volume| total.saturating_add(*volume))
+    }
+
+    /// Remove `amount` from the volume, newest buckets first.
+    fn release(&mut self, amount: Balance) {
+        let mut left = amount;
+        for (_, volume) in self.buckets.iter_mut().rev() {
+            let released = left.min(*volume);
+            *volume -= released;
+            left -= released;
+        }
+    }
+
+    fn record(&mut self, hour: u64, amount: Balance) {
+        self.buckets
+            .retain(|(bucket, _)| bucket + WINDOW_BUCKETS > hour);
+        match self.buckets.last_mut() {
+            Some((bucket, volume)) if *bucket == hour => *volume = volume.saturating_add(amount),
+            _ => self.buckets.push((hour, amount)),
+        }
+    }
+}
+
+/// Volumes of a token in both directions.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct TokenVolumes {
+    deposits: VolumeWindow,
+    withdrawals: VolumeWindow,
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Set the daily limits of the token, or remove them if `limit` is `None`. ONLY
+    /// the `Owner` role can call this method.
+    pub fn set_rate_limit(&mut self, token_address: aurora_sdk::Address, limit: Option<RateLimit>) {
+        self.assert_owner();
+        self.record_admin_action("set_rate_limit");
+
+        let token_account_id = self.account_id_from_token_address(token_address);
+        match limit {
+            Some(limit) => {
+                self.rate_limits.insert(&token_account_id, &limit);
+            }
+            None => {
+                self.rate_limits.remove(&token_account_id);
+            }
+        }
+    }
+
+    /// Daily limits of the token, if any.
+    pub fn rate_limit(&self, token_address: aurora_sdk::Address) -> Option<RateLimit> {
+        self.rate_limits
+            .get(&self.account_id_from_token_address(token_address))
+    }
+
+    /// Volume of the token bridged over the last 24 hours, and the remaining capacity.
+    pub fn rate_limit_usage(&self, token_address: aurora_sdk::Address) -> RateLimitUsage {
+        let token_account_id = self.account_id_from_token_address(token_address);
+        let hour = current_hour();
+        let volumes = self
+            .token_volumes
+            .get(&token_account_id)
+            .unwrap_or_default();
+        let limit = self.rate_limits.get(&token_account_id);
+        let deposited = volumes.deposits.volume(hour);
+        let withdrawn = volumes.withdrawals.volume(hour);
+
+        RateLimitUsage {
+            deposited: deposited.into(),
+            withdrawn: withdrawn.into(),
+            deposit_remaining: limit
+                .and_then(|limit| limit.deposit)
+                .map(|cap| cap.0.saturating_sub(deposited).into()),
+            withdraw_remaining: limit
+                .and_then(|limit| limit.withdraw)
+                .map(|cap| cap.0.saturating_sub(withdrawn).into()),
+        }
+    }
+}
+
+impl Contract {
+    /// Record a deposit of `amount` of the token in its daily volume. It panics if the
+    /// deposit exceeds the daily limit.
+    pub(crate) fn consume_deposit_limit(&mut self, token_account_id: &AccountId, amount: Balance) {
+        let hour = current_hour();
+        let mut volumes = self.token_volumes.get(token_account_id).unwrap_or_default();
+        if let Some(cap) = self
+            .rate_limits
+            .get(token_account_id)
+            .and_then(|limit| limit.deposit)
+        {
+            require!(
+                volumes.deposits.volume(hour).saturating_add(amount) <= cap.0,
+                ERR_RATE_LIMIT_EXCEEDED
+            );
+        }
+        volumes.deposits.record(hour, amount);
+        self.token_volumes.insert(token_account_id, &volumes);
+    }
+
+    /// Remove from the daily volume of the token a deposit of `amount` that was undone.
+    pub(crate) fn release_deposit_limit(&mut self, token_account_id: &AccountId, amount: Balance) {
+        if let Some(mut volumes) = self.token_volumes.get(token_account_id) {
+            volumes.deposits.release(amount);
+            self.token_volumes.insert(token_account_id, &volumes);
+        }
+    }
+
+    /// Whether a deposit of `amount` of the token fits in its daily limit.
+    pub(crate) fn is_deposit_within_limit(
+        &self,
+        token_account_id: &AccountId,
+        amount: Balance,
+    ) -> bool {
+        let cap = match self
+            .rate_limits
+            .get(token_account_id)
+            .and_then(|limit| limit.deposit)
+        {
+            Some(cap) => cap.0,
+            None => return true,
+        };
+        let deposited = self
+            .token_volumes
+            .get(token_account_id)
+            .map_or(0, |volumes| volumes.deposits.volume(current_hour()));
+        deposited.saturating_add(amount) <= cap
+    }
+
+    /// Whether a withdraw of `amount` of the token fits in its daily limit.
+    pub(crate) fn is_withdraw_within_limit(
+        &self,
+        token_account_id: &AccountId,
+        amount: Balance,
+    ) -> bool {
+        let cap = match self
+            .rate_limits
+            .get(token_account_id)
+            .and_then(|limit| limit.withdraw)
+        {
+            Some(cap) => cap.0,
+            None => return true,
+        };
+        let withdrawn = self
+            .token_volumes
+            .get(token_account_id)
+            .map_or(0, |volumes| volumes.withdrawals.volume(current_hour()));
+        withdrawn.saturating_add(amount) <= cap
+    }
+
+    /// Record a withdraw of `amount` of the token in its daily volume.
+    pub(crate) fn record_withdraw_volume(&mut self, token_account_id: &AccountId, amount: Balance) {
+        let mut volumes = self.token_volumes.get(token_account_id).unwrap_or_default();
+        volumes.withdrawals.record(current_hour(), amount);
+        self.token_volumes.insert(token_account_id, &volumes);
+    }
+}
+
+fn current_hour() -> u64 {
+    env::block_timestamp() / BUCKET_DURATION
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RateLimit, VolumeWindow, BUCKET_DURATION};
+    use crate::aurora_sdk::Address;
+    use crate::test_utils;
+    use near_sdk::json_types::U128;
+    use near_sdk::{testing_env, PromiseOrValue};
+
+    fn limit(deposit: u128, withdraw: u128) -> Option<RateLimit> {
+        Some(RateLimit {
+            deposit: Some(U128(deposit)),
+            withdraw: Some(U128(withdraw)),
+        })
+    }
+
+    fn deposit(contract: &mut crate::Contract, token: &Address, amount: u128, hour: u64) {
+        testing_env!(test_utils::context(test_utils::locker_account_id())
+            .block_timestamp(hour * BUCKET_DURATION)
+            .build());
+        let _ = contract.on_deposit(
+            token.clone(),
+            "alice.near".parse().unwrap(),
+            amount,
+            None,
+            None,
+            None,
+        );
+    }
+
+    #[test]
+    fn test_volume_window() {
+        let mut window = VolumeWindow::default();
+        window.record(10, 5);
+        window.record(10, 5);
+        window.record(20, 1);
+        assert_eq!(window.volume(20), 11);
+        // The volume of hour 10 leaves the window 24 hours later.
+        assert_eq!(window.volume(34), 1);
+        window.record(34, 2);
+        assert_eq!(window.buckets, vec![(20, 1), (34, 2)]);
+    }
+
+    #[test]
+    fn test_deposits_within_limit() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        contract.set_rate_limit(token.clone(), limit(100, 0));
+
+        deposit(&mut contract, &token, 60, 0);
+        deposit(&mut contract, &token, 40, 23);
+        let usage = contract.rate_limit_usage(token.clone());
+        assert_eq!(usage.deposited, U128(100));
+        assert_eq!(usage.deposit_remaining, Some(U128(0)));
+        // The first deposit left the window.
+        deposit(&mut contract, &token, 60, 24);
+        assert_eq!(contract.rate_limit_usage(token).deposited, U128(100));
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_RATE_LIMIT_EXCEEDED")]
+    fn test_deposit_above_limit() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        contract.set_rate_limit(token.clone(), limit(100, 0));
+
+        deposit(&mut contract, &token, 60, 0);
+        deposit(&mut contract, &token, 41, 1);
+    }
+
+    #[test]
+    fn test_withdraw_above_limit_is_refunded() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        test_utils::deploy_token(&mut contract, &token);
+        deposit(&mut contract, &token, 100, 0);
+        test_utils::set_context(test_utils::factory());
+        contract.set_rate_limit(token.clone(), limit(1_000, 50));
+
+        let token_account_id = test_utils::token_account_id(&token);
+        test_utils::set_context(token_account_id.clone());
+        let outcome = contract.on_withdraw(
+            Address([3; 20]),
+            60.into(),
+            Some("alice.near".parse().unwrap()),
+            None,
+            None,
+        );
+
+        assert!(matches!(outcome, PromiseOrValue::Value(outcome) if !outcome.success));
+        assert!(test_utils::function_calls(&test_utils::aurora()).is_empty());
+        assert_eq!(
+            test_utils::function_calls(&token_account_id)[0].method,
+            "deposit"
+        );
+        assert_eq!(contract.rate_limit_usage(token).withdrawn, U128(0));
+    }
+}
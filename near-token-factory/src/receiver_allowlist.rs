@@ -0,0 +1,154 @@
+//! Optional per-token allowlist of accounts that can receive deposits.
+use crate::{aurora_sdk, Contract, ContractExt, StorageKey};
+use near_sdk::collections::UnorderedSet;
+use near_sdk::{near_bindgen, require, AccountId};
+
+pub(crate) const ERR_RECEIVER_NOT_ALLOWED: &str =
+    "ERR_RECEIVER_NOT_ALLOWED: Receiver is not allowed to receive this token.";
+
+#[near_bindgen]
+impl Contract {
+    /// Allow `account_id` to receive deposits of the token. Once the first receiver is
+    /// added, only receivers in the allowlist can receive deposits of the token.
+    /// ONLY the `Owner` role can call this method.
+    pub fn add_allowed_receiver(
+        &mut self,
+        token_address: aurora_sdk::Address,
+        account_id: AccountId,
+    ) {
+        self.assert_owner();
+        self.record_admin_action("add_allowed_receiver");
+
+        let token_account_id = self.account_id_from_token_address(token_address);
+        let mut allowlist = self
+            .receiver_allowlists
+            .get(&token_account_id)
+            .unwrap_or_else(|| {
+                UnorderedSet::new(StorageKey::ReceiverAllowlist {
+                    token_account_id: token_account_id.clone(),
+                })
+            });
+        allowlist.insert(&account_id);
+        self.receiver_allowlists
+            .insert(&token_account_id, &allowlist);
+    }
+
+    /// Remove `account_id` from the allowlist of the token. The allowlist remains
+    /// enforced even if it becomes empty. ONLY the `Owner` role can call this method.
+    pub fn remove_allowed_receiver(
+        &mut self,
+        token_address: aurora_sdk::Address,
+        account_id: AccountId,
+    ) {
+        self.assert_owner();
+        self.record_admin_action("remove_allowed_receiver");
+
+        let token_account_id = self.account_id_from_token_address(token_address);
+        if let Some(mut allowlist) = self.receiver_allowlists.get(&token_account_id) {
+            allowlist.remove(&account_id);
+            self.receiver_allowlists
+                .insert(&token_account_id, &allowlist);
+        }
+    }
+
+    /// Remove the allowlist of the token, so any account can receive deposits again.
+    /// ONLY the `Owner` role can call this method.
+    pub fn clear_allowed_receivers(&mut self, token_address: aurora_sdk::Address) {
+        self.assert_owner();
+        self.record_admin_action("clear_allowed_receivers");
+
+        let token_account_id = self.account_id_from_token_address(token_address);
+        if let Some(mut allowlist) = self.receiver_allowlists.remove(&token_account_id) {
+            allowlist.clear();
+        }
+    }
+
+    /// Whether `account_id` can receive deposits of the token.
+    pub fn is_receiver_allowed(
+        &self,
+        token_address: aurora_sdk::Address,
+        account_id: AccountId,
+    ) -> bool {
+        self.is_receiver_allowed_internal(
+            &self.account_id_from_token_address(token_address),
+            &account_id,
+        )
+    }
+}
+
+impl Contract {
+    pub(crate) fn is_receiver_allowed_internal(
+        &self,
+        token_account_id: &AccountId,
+        receiver_id: &AccountId,
+    ) -> bool {
+        self.receiver_allowlists
+            .get(token_account_id)
+            .map_or(true, |allowlist| allowlist.contains(receiver_id))
+    }
+
+    pub(crate) fn assert_receiver_allowed(
+        &self,
+        token_account_id: &AccountId,
+        receiver_id: &AccountId,
+    ) {
+        require!(
+            self.is_receiver_allowed_internal(token_account_id, receiver_id),
+            ERR_RECEIVER_NOT_ALLOWED
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::aurora_sdk::Address;
+    use crate::test_utils;
+    use near_sdk::AccountId;
+
+    fn alice() -> AccountId {
+        "alice.near".parse().unwrap()
+    }
+
+    fn bob() -> AccountId {
+        "bob.near".parse().unwrap()
+    }
+
+    #[test]
+    fn test_allowed_receiver_can_receive_deposit() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        assert!(contract.is_receiver_allowed(token.clone(), bob()));
+
+        contract.add_allowed_receiver(token.clone(), alice());
+        assert!(contract.is_receiver_allowed(token.clone(), alice()));
+        assert!(!contract.is_receiver_allowed(token.clone(), bob()));
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(token.clone(), alice(), 10, None, None, None);
+        assert_eq!(contract.mirrored_supply(token).0, 10);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_RECEIVER_NOT_ALLOWED")]
+    fn test_disallowed_receiver_is_rejected() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        contract.add_allowed_receiver(token.clone(), alice());
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(token, bob(), 10, None, None, None);
+    }
+
+    #[test]
+    fn test_clear_allowed_receivers() {
+        let mut contract = test_utils::new_contract();
+        let token = Address([1; 20]);
+        contract.add_allowed_receiver(token.clone(), alice());
+        contract.remove_allowed_receiver(token.clone(), alice());
+        assert!(!contract.is_receiver_allowed(token.clone(), alice()));
+
+        contract.clear_allowed_receivers(token.clone());
+        assert!(contract.is_receiver_allowed(token.clone(), alice()));
+        assert!(contract.is_receiver_allowed(token, bob()));
+    }
+}
@@ -0,0 +1,234 @@
+//! Recovery of tokens whose deployment didn't complete and of NEAR left over in the
+//! factory and the tokens. The deploy batch is atomic, so a failed deploy normally
+//! leaves no token account and its balance is refunded to the factory, but the token
+//! stays pending. `clear_pending_deploy` forgets it, so it can be deployed again
+//! whatever the `PendingDeployPolicy`. A token account created without running `new`,
+//! e.g. by an earlier factory, is initialized by `reinitialize_token`. The factory
+//! holds no key of the token accounts, so it can't delete them.
+use crate::{
+    aurora_sdk, ext, logging, token_init_args, Contract, ContractExt,
+    ERR_INSUFFICIENT_FACTORY_BALANCE, ERR_TOKEN_ALREADY_DEPLOYED, ERR_TOKEN_NOT_DEPLOYED,
+    TOKEN_DEPLOYED_CALLBACK_COST,
+};
+use near_sdk::{env, near_bindgen, require, AccountId, Gas, Promise};
+
+const ERR_DEPLOY_NOT_PENDING: &str =
+    "ERR_DEPLOY_NOT_PENDING: Deployment of this token is not pending.";
+const ERR_TREASURY_NOT_SET: &str = "ERR_TREASURY_NOT_SET: Treasury account is not set.";
+
+/// Gas for `sweep_near` of the token.
+const SWEEP_NEAR_GAS: Gas = Gas(5_000_000_000_000);
+
+#[near_bindgen]
+impl Contract {
+    /// Set the account receiving the NEAR swept by `sweep_to_treasury` and
+    /// `sweep_token`. ONLY the `Owner` role can call this method.
+    pub fn set_treasury(&mut self, treasury: Option<AccountId>) {
+        self.assert_owner();
+        self.record_admin_action("set_treasury");
+
+        self.treasury = treasury;
+    }
+
+    /// Account receiving the swept NEAR, if any.
+    pub fn treasury(&self) -> Option<AccountId> {
+        self.treasury.clone()
+    }
+
+    /// Transfer the sweepable balance of the factory to the treasury, see
+    /// `sweepable_balance`. ONLY the `Owner` role can call this method.
+    pub fn sweep_to_treasury(&mut self) -> Promise {
+        self.assert_owner();
+        self.record_admin_action("sweep_to_treasury");
+        let treasury = self.treasury_account();
+
+        Promise::new(treasury).transfer(self.sweepable_balance().0)
+    }
+
+    /// Make the token transfer its balance above its storage requirement to the
+    /// treasury, e.g. storage deposits it refunded. Tokens running a binary without
+    /// `sweep_near` fail. ONLY the `Owner` role can call this method.
+    pub fn sweep_token(&mut self, token_address: aurora_sdk::Address) -> Promise {
+        self.assert_owner();
+        self.record_admin_action("sweep_token");
+        let treasury = self.treasury_account();
+
+        let token_account_id = self.account_id_from_token_address(token_address);
+        require!(
+            self.tokens.get(&token_account_id).is_some(),
+            ERR_TOKEN_NOT_DEPLOYED
+        );
+        ext::ext_near_token::ext(token_account_id)
+            .with_static_gas(SWEEP_NEAR_GAS)
+            .sweep_near(treasury)
+    }
+
+    /// Forget the pending deploy of a token that is not registered, so `create_token`
+    /// and deposits deploy it again. ONLY the `Owner` role can call this method.
+    pub fn clear_pending_deploy(&mut self, token_address: aurora_sdk::Address) {
+        self.assert_owner();
+        self.record_admin_action("clear_pending_deploy");
+
+        let token_account_id = self.assert_deploy_pending(token_address);
+        self.pending_deploys.remove(&token_account_id);
+        self.log_at(
+            logging::LogLevel::Info,
+            &format!("Pending deploy of {} cleared", token_account_id),
+        );
+    }
+
+    /// Call `new` on the account of a pending token, with the default metadata, for an
+    /// account that exists with the token binary but was never initialized. The token
+    /// is registered by `on_token_deployed` if the call succeeds. ONLY the `Owner` role
+    /// can call this method.
+    pub fn reinitialize_token(&mut self, token_address: aurora_sdk::Address) -> Promise {
+        self.assert_owner();
+        self.record_admin_action("reinitialize_token");
+
+        let token_account_id = self.assert_deploy_pending(token_address.clone());
+        let config = self.effective_deploy_config();
+        require!(
+            self.available_balance().0 >= config.storage_deposit.0 + self.deploy_balance_margin,
+            ERR_INSUFFICIENT_FACTORY_BALANCE
+        );
+        self.log_at(
+            logging::LogLevel::Info,
+            &format!("Initializing {}", token_account_id),
+        );
+        Promise::new(token_account_id.clone())
+            .function_call(
+                "new".to_string(),
+                token_init_args(&token_address, None).into_bytes(),
+                config.storage_deposit.0,
+                config.gas,
+            )
+            .then(self.token_deployed_callback(
+                token_address,
+                token_account_id,
+                TOKEN_DEPLOYED_CALLBACK_COST,
+                None,
+            ))
+    }
+}
+
+impl Contract {
+    fn treasury_account(&self) -> AccountId {
+        self.treasury
+            .clone()
+            .unwrap_or_else(|| env::panic_str(ERR_TREASURY_NOT_SET))
+    }
+
+    /// Account id of the token, panicking unless its deploy is pending and it is not
+    /// registered.
+    fn assert_deploy_pending(&self, token_address: aurora_sdk::Address) -> AccountId {
+        let token_account_id = self.account_id_from_token_address(token_address);
+        require!(
+            self.tokens.get(&token_account_id).is_none(),
+            ERR_TOKEN_ALREADY_DEPLOYED
+        );
+        require!(
+            self.pending_deploys.contains(&token_account_id),
+            ERR_DEPLOY_NOT_PENDING
+        );
+        token_account_id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::aurora_sdk::Address;
+    use crate::test_utils;
+    use crate::PendingDeployPolicy;
+    use near_sdk::AccountId;
+
+    fn treasury() -> AccountId {
+        "treasury.near".parse().unwrap()
+    }
+
+    /// Contract with a pending deploy of `Address([1; 20])`.
+    fn contract_with_pending_deploy() -> crate::Contract {
+        let mut contract = test_utils::new_contract_with_binary();
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.create_token(Address([1; 20]), None);
+        test_utils::set_context(test_utils::factory());
+        contract
+    }
+
+    #[test]
+    fn test_clear_pending_deploy() {
+        let mut contract = contract_with_pending_deploy();
+        contract.set_pending_deploy_policy(PendingDeployPolicy::Reject);
+        contract.clear_pending_deploy(Address([1; 20]));
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.create_token(Address([1; 20]), None);
+        let calls = test_utils::function_calls(&test_utils::token_account_id(&Address([1; 20])));
+        assert_eq!(calls[0].method, "new");
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_DEPLOY_NOT_PENDING")]
+    fn test_clear_pending_deploy_not_pending() {
+        let mut contract = contract_with_pending_deploy();
+        contract.clear_pending_deploy(Address([2; 20]));
+    }
+
+    #[test]
+    fn test_reinitialize_token() {
+        let mut contract = contract_with_pending_deploy();
+        let token = Address([1; 20]);
+        let _ = contract.reinitialize_token(token.clone());
+
+        let calls = test_utils::function_calls(&test_utils::token_account_id(&token));
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].method, "new");
+        let callbacks = test_utils::function_calls(&test_utils::factory());
+        assert_eq!(callbacks[0].method, "on_token_deployed");
+
+        test_utils::confirm_deploy(&mut contract, &token);
+        assert_eq!(
+            contract.get_token_version(test_utils::token_account_id(&token)),
+            Some(1)
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_TOKEN_ALREADY_DEPLOYED")]
+    fn test_reinitialize_registered_token() {
+        let mut contract = test_utils::new_contract_with_binary();
+        test_utils::deploy_token(&mut contract, &Address([1; 20]));
+        let _ = contract.reinitialize_token(Address([1; 20]));
+    }
+
+    #[test]
+    fn test_sweep_to_treasury() {
+        let mut contract = test_utils::new_contract_with_binary();
+        contract.set_treasury(Some(treasury()));
+        let sweepable = contract.sweepable_balance().0;
+        let _ = contract.sweep_to_treasury();
+
+        assert_eq!(test_utils::transfers(&treasury()), [sweepable]);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_TREASURY_NOT_SET")]
+    fn test_sweep_without_treasury() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let _ = contract.sweep_to_treasury();
+    }
+
+    #[test]
+    fn test_sweep_token() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        test_utils::deploy_token(&mut contract, &token);
+        contract.set_treasury(Some(treasury()));
+        let _ = contract.sweep_token(token.clone());
+
+        let calls = test_utils::function_calls(&test_utils::token_account_id(&token));
+        assert_eq!(calls[0].method, "sweep_near");
+        let args: near_sdk::serde_json::Value =
+            near_sdk::serde_json::from_slice(&calls[0].args).unwrap();
+        assert_eq!(args["receiver_id"], "treasury.near");
+    }
+}
@@ -0,0 +1,207 @@
+//! Delegated roles: accounts other than the owner that are allowed to call a subset of
+//! the privileged methods, e.g. a DAO that upgrades tokens without owning the factory.
+use crate::{events, Contract, ContractExt};
+use near_sdk::collections::UnorderedSet;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{env, near_bindgen, require, AccountId};
+
+const ERR_MISSING_ROLE: &str = "ERR_MISSING_ROLE: Caller doesn't have the role of this method.";
+
+/// Role that can be granted to accounts besides the owner. The owner implicitly has
+/// every role.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(crate = "near_sdk::serde")]
+pub enum Role {
+    /// Can set the token binary and upgrade the tokens.
+    TokenUpgrader,
+    /// Can pause deposits, withdrawals and deployments. Only the owner can unpause.
+    Pauser,
+}
+
+impl Role {
+    /// Name of the role in the events, in snake case.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Role::TokenUpgrader => "token_upgrader",
+            Role::Pauser => "pauser",
+        }
+    }
+}
+
+/// Methods that the `TokenUpgrader` role can call besides the owner.
+pub(crate) const TOKEN_UPGRADER_ACTIONS: &[&str] = &[
+    "append_binary_chunk",
+    "confirm_token_binary",
+    "finalize_binary_upload",
+    "propose_token_binary",
+    "set_token_binary",
+    "start_binary_upload",
+    "upgrade_token",
+    "upgrade_tokens",
+];
+
+/// Methods that the `Pauser` role can call besides the owner.
+pub(crate) const PAUSER_ACTIONS: &[&str] =
+    &["pause", "pause_all_tokens", "pause_deploys", "pause_token"];
+
+#[near_bindgen]
+impl Contract {
+    /// Grant `role` to `account_id`. ONLY the `Owner` role can call this method.
+    pub fn grant_role(&mut self, role: Role, account_id: AccountId) {
+        self.assert_owner();
+        self.record_admin_action("grant_role");
+
+        if self.role_members_mut(role).insert(&account_id) {
+            self.emit_event(events::ConnectorEvent::RoleGranted {
+                role: role.name().to_string(),
+                account_id,
+            });
+        }
+    }
+
+    /// Revoke `role` from `account_id`. It doesn't affect the owner. ONLY the `Owner`
+    /// role can call this method.
+    pub fn revoke_role(&mut self, role: Role, account_id: AccountId) {
+        self.assert_owner();
+        self.record_admin_action("revoke_role");
+
+        if self.role_members_mut(role).remove(&account_id) {
+            self.emit_event(events::ConnectorEvent::RoleRevoked {
+                role: role.name().to_string(),
+                account_id,
+            });
+        }
+    }
+
+    /// Whether `account_id` has `role`, either granted or as the owner.
+    pub fn has_role(&self, role: Role, account_id: AccountId) -> bool {
+        self.is_owner(&account_id) || self.role_members_set(role).contains(&account_id)
+    }
+
+    /// Accounts `role` was granted to. The owner is not included.
+    pub fn role_members(&self, role: Role) -> Vec<AccountId> {
+        self.role_members_set(role).to_vec()
+    }
+}
+
+impl Contract {
+    /// Panic unless the predecessor is the owner or has `role`.
+    pub(crate) fn assert_role(&self, role: Role) {
+        require!(
+            self.has_role(role, env::predecessor_account_id()),
+            ERR_MISSING_ROLE
+        );
+    }
+
+    fn role_members_set(&self, role: Role) -> &UnorderedSet<AccountId> {
+        match role {
+            Role::TokenUpgrader => &self.token_upgraders,
+            Role::Pauser => &self.pausers,
+        }
+    }
+
+    fn role_members_mut(&mut self, role: Role) -> &mut UnorderedSet<AccountId> {
+        match role {
+            Role::TokenUpgrader => &mut self.token_upgraders,
+            Role::Pauser => &mut self.pausers,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Role;
+    use crate::aurora_sdk::Address;
+    use crate::test_utils;
+    use near_sdk::AccountId;
+
+    fn dao() -> AccountId {
+        "dao.near".parse().unwrap()
+    }
+
+    #[test]
+    fn test_grant_and_revoke_role() {
+        let mut contract = test_utils::new_contract();
+        assert!(!contract.has_role(Role::Pauser, dao()));
+        assert!(contract.has_role(Role::Pauser, test_utils::factory()));
+
+        contract.grant_role(Role::Pauser, dao());
+        assert!(contract.has_role(Role::Pauser, dao()));
+        assert!(!contract.has_role(Role::TokenUpgrader, dao()));
+        assert_eq!(contract.role_members(Role::Pauser), vec![dao()]);
+
+        contract.revoke_role(Role::Pauser, dao());
+        assert!(!contract.has_role(Role::Pauser, dao()));
+        assert!(contract.role_members(Role::Pauser).is_empty());
+    }
+
+    #[test]
+    fn test_role_granted_event() {
+        let mut contract = test_utils::new_contract();
+        contract.grant_role(Role::Pauser, dao());
+        // Granting the role again is a no-op.
+        contract.grant_role(Role::Pauser, dao());
+
+        let logs = near_sdk::test_utils::get_logs();
+        assert_eq!(logs.len(), 1);
+        let event: near_sdk::serde_json::Value =
+            near_sdk::serde_json::from_str(logs[0].strip_prefix("EVENT_JSON:").unwrap()).unwrap();
+        assert_eq!(event["event"], "role_granted");
+        assert_eq!(event["data"]["role"], "pauser");
+        assert_eq!(event["data"]["account_id"], "dao.near");
+    }
+
+    #[test]
+    fn test_token_upgrader_can_upgrade() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        test_utils::deploy_token(&mut contract, &token);
+        contract.grant_role(Role::TokenUpgrader, dao());
+
+        test_utils::set_context(dao());
+        test_utils::replace_token_binary(&mut contract, vec![0, 97, 115, 109, 1]);
+        let _ = contract.upgrade_token(token);
+    }
+
+    #[test]
+    fn test_pauser_can_pause() {
+        let mut contract = test_utils::new_contract();
+        contract.grant_role(Role::Pauser, dao());
+
+        test_utils::set_context(dao());
+        contract.pause_all_tokens();
+        contract.pause_deploys();
+        assert!(contract.modes().all_tokens_paused);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_ONLY_OWNER")]
+    fn test_pauser_cannot_unpause() {
+        let mut contract = test_utils::new_contract();
+        contract.grant_role(Role::Pauser, dao());
+
+        test_utils::set_context(dao());
+        contract.pause_all_tokens();
+        contract.unpause_all_tokens();
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_MISSING_ROLE")]
+    fn test_pauser_cannot_upgrade() {
+        let mut contract = test_utils::new_contract();
+        contract.grant_role(Role::Pauser, dao());
+
+        test_utils::set_context(dao());
+        contract.set_token_binary(vec![0, 97, 115, 109].into());
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_ONLY_OWNER")]
+    fn test_only_owner_can_grant_roles() {
+        let mut contract = test_utils::new_contract();
+        contract.grant_role(Role::Pauser, dao());
+
+        test_utils::set_context(dao());
+        contract.grant_role(Role::TokenUpgrader, dao());
+    }
+}
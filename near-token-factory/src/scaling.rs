@@ -0,0 +1,395 @@
+//! Decimal scaling of ERC-20 tokens with more decimals than their NEP-141
+//! representative, e.g. tokens whose amounts don't fit in 128 bits.
+//!
+//! Deposits of a scaled token are divided by `10^(original_decimals - decimals)`,
+//! rounding down. The remainder stays locked on Aurora and is accounted as the dust of
+//! the token once the deposit is accepted. Withdrawals are multiplied by the same
+//! factor, which is exact.
+use crate::{
+    abi_encode_withdraw_and_call, abi_encode_withdraw_eth, abi_encode_withdraw_u256, aurora_sdk,
+    eth, Contract, ContractExt,
+};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{near_bindgen, require, AccountId, PromiseOrValue};
+
+uint::construct_uint! {
+    /// Unsigned 256-bit integer, the type of ERC-20 amounts.
+    pub struct U256(4);
+}
+
+/// Largest difference between the decimals of an ERC-20 and its NEP-141
+/// representative. Scaled withdrawals of any `u128` amount fit in 256 bits.
+const MAX_SCALING_EXPONENT: u8 = 38;
+
+const ERR_INVALID_SCALING: &str =
+    "ERR_INVALID_SCALING: Decimals must be at most the original decimals, and at most 38 less.";
+const ERR_SCALING_WITH_SUPPLY: &str =
+    "ERR_SCALING_WITH_SUPPLY: Scaling of a token can't change while it has a mirrored supply.";
+const ERR_AMOUNT_OVERFLOW: &str = "ERR_AMOUNT_OVERFLOW: Scaled amount doesn't fit in 128 bits.";
+
+/// Decimals of an ERC-20 and of its NEP-141 representative.
+#[derive(
+    BorshSerialize, BorshDeserialize, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq,
+)]
+#[serde(crate = "near_sdk::serde")]
+pub struct DecimalScaling {
+    /// Decimals of the ERC-20 on Aurora.
+    pub original_decimals: u8,
+    /// Decimals of the NEP-141 representative.
+    pub decimals: u8,
+}
+
+impl DecimalScaling {
+    /// ERC-20 units per NEP-141 unit.
+    fn factor(&self) -> U256 {
+        U256::exp10((self.original_decimals - self.decimals) as usize)
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Scale the amounts of a token from `original_decimals`, the decimals of the
+    /// ERC-20, down to `decimals`, the decimals of its NEP-141 representative. Equal
+    /// decimals remove the scaling. The scaling can't change while the token has a
+    /// mirrored supply. ONLY the `Owner` role can call this method.
+    pub fn set_token_scaling(
+        &mut self,
+        token_address: aurora_sdk::Address,
+        original_decimals: u8,
+        decimals: u8,
+    ) {
+        self.assert_owner();
+        self.record_admin_action("set_token_scaling");
+        require!(
+            decimals <= original_decimals && original_decimals - decimals <= MAX_SCALING_EXPONENT,
+            ERR_INVALID_SCALING
+        );
+
+        let token_account_id = self.account_id_from_token_address(token_address);
+        require!(
+            self.token_supply.get(&token_account_id).unwrap_or(0) == 0,
+            ERR_SCALING_WITH_SUPPLY
+        );
+        if decimals == original_decimals {
+            self.token_scaling.remove(&token_account_id);
+        } else {
+            self.token_scaling.insert(
+                &token_account_id,
+                &DecimalScaling {
+                    original_decimals,
+                    decimals,
+                },
+            );
+        }
+    }
+
+    /// Decimal scaling of the token, if any.
+    pub fn token_scaling(&self, token_address: aurora_sdk::Address) -> Option<DecimalScaling> {
+        self.token_scaling
+            .get(&self.account_id_from_token_address(token_address))
+    }
+
+    /// Amount of the ERC-20 left locked on Aurora by the rounding of scaled deposits, in
+    /// the decimals of the ERC-20. It is a decimal string since it may not fit in 128
+    /// bits.
+    pub fn token_dust(&self, token_address: aurora_sdk::Address) -> String {
+        self.dust_of(&self.account_id_from_token_address(token_address))
+            .to_string()
+    }
+
+    /// Like `on_deposit`, with `amount` given as a big-endian uint256 in the decimals of
+    /// the ERC-20. For scaled tokens the amount is scaled down, rounding down, and the
+    /// remainder is added to the dust of the token once the deposit is accepted. The
+    /// decimals of `metadata` are replaced with the decimals of the NEP-141
+    /// representative. The scaled amount must fit in 128 bits. `transfer_id` protects
+    /// against replays like for `on_deposit`. This method can only be called by the
+    /// locker and the minters.
+    #[payable]
+    pub fn on_deposit_u256(
+        &mut self,
+        #[serializer(borsh)] token: aurora_sdk::Address,
+        #[serializer(borsh)] receiver_id: AccountId,
+        #[serializer(borsh)] amount: aurora_sdk::RawU256,
+        #[serializer(borsh)] origin_tx_hash: Option<aurora_sdk::RawU256>,
+        #[serializer(borsh)] metadata: Option<aurora_sdk::TokenMetadata>,
+        #[serializer(borsh)] transfer_id: Option<u64>,
+    ) -> PromiseOrValue<()> {
+        let token_account_id = self.route_deposit(&token);
+        let amount = U256::from_big_endian(&amount);
+        let (amount, dust, metadata) = match self.token_scaling.get(&token_account_id) {
+            Some(scaling) => {
+                let (scaled, dust) = amount.div_mod(scaling.factor());
+                let metadata = metadata.map(|metadata| aurora_sdk::TokenMetadata {
+                    decimals: scaling.decimals,
+                    ..metadata
+                });
+                // The dust is below the factor, so it fits in 128 bits.
+                (scaled, dust.as_u128(), metadata)
+            }
+            None => (amount, 0, metadata),
+        };
+        require!(amount <= U256::from(u128::MAX), ERR_AMOUNT_OVERFLOW);
+
+        self.process_deposit(
+            token,
+            receiver_id,
+            amount.as_u128(),
+            origin_tx_hash,
+            metadata,
+            transfer_id,
+            dust,
+        )
+    }
+}
+
+impl Contract {
+    /// ABI encoded call of the locker `withdraw` unlocking the ERC-20 equivalent of
+    /// `amount` of the NEP-141 representative, or of `withdrawEth` for ETH.
+    pub(crate) fn encode_unlock(
+        &self,
+        token_account_id: &AccountId,
+        token_id: &aurora_sdk::Address,
+        receiver_id: &aurora_sdk::Address,
+        amount: u128,
+    ) -> Vec<u8> {
+        if token_id == &eth::ETH_ADDRESS {
+            return abi_encode_withdraw_eth(
+                receiver_id,
+                self.unlocked_amount(token_account_id, amount),
+            );
+        }
+        abi_encode_withdraw_u256(
+            token_id,
+            receiver_id,
+            self.unlocked_amount(token_account_id, amount),
+        )
+    }
+
+    /// Like `encode_unlock`, but the call of the locker `withdrawAndCall` also calls
+    /// `receiver_id` with `data` after the tokens are unlocked.
+    pub(crate) fn encode_unlock_and_call(
+        &self,
+        token_account_id: &AccountId,
+        token_id: &aurora_sdk::Address,
+        receiver_id: &aurora_sdk::Address,
+        amount: u128,
+        data: &[u8],
+    ) -> Vec<u8> {
+        abi_encode_withdraw_and_call(
+            token_id,
+            receiver_id,
+            self.unlocked_amount(token_account_id, amount),
+            data,
+        )
+    }
+
+    /// ERC-20 equivalent of `amount` of the NEP-141 representative.
+    fn unlocked_amount(&self, token_account_id: &AccountId, amount: u128) -> aurora_sdk::RawU256 {
+        let amount = match self.token_scaling.get(token_account_id) {
+            Some(scaling) => U256::from(amount) * scaling.factor(),
+            None => U256::from(amount),
+        };
+        to_raw(amount)
+    }
+
+    /// Add `dust` to the dust of the token.
+    pub(crate) fn record_dust(&mut self, token_account_id: &AccountId, dust: u128) {
+        if dust > 0 {
+            let total_dust = self.dust_of(token_account_id).saturating_add(dust.into());
+            self.token_dust
+                .insert(token_account_id, &to_raw(total_dust));
+        }
+    }
+
+    /// Remove `dust` of a deposit that was refunded on Aurora from the dust of the token.
+    pub(crate) fn release_dust(&mut self, token_account_id: &AccountId, dust: u128) {
+        if dust > 0 {
+            let total_dust = self.dust_of(token_account_id).saturating_sub(dust.into());
+            self.token_dust
+                .insert(token_account_id, &to_raw(total_dust));
+        }
+    }
+
+    fn dust_of(&self, token_account_id: &AccountId) -> U256 {
+        self.token_dust
+            .get(token_account_id)
+            .map_or_else(U256::zero, |dust| U256::from_big_endian(&dust))
+    }
+}
+
+/// Big-endian encoding of `value`.
+fn to_raw(value: U256) -> aurora_sdk::RawU256 {
+    let mut bytes = [0u8; 32];
+    value.to_big_endian(&mut bytes);
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{to_raw, DecimalScaling, U256};
+    use crate::aurora_sdk::Address;
+    use crate::test_utils;
+    use near_sdk::{AccountId, PromiseResult};
+
+    /// Deposit 5 tokens and 7 units of dust of `token`, scaled from 30 to 18 decimals.
+    fn scaled_deposit(contract: &mut crate::Contract, token: &Address, transfer_id: Option<u64>) {
+        test_utils::set_context(test_utils::locker_account_id());
+        let amount = U256::exp10(30) * U256::from(5) + U256::from(7);
+        let _ = contract.on_deposit_u256(
+            token.clone(),
+            "alice.near".parse().unwrap(),
+            to_raw(amount),
+            None,
+            None,
+            transfer_id,
+        );
+    }
+
+    #[test]
+    fn test_scaled_deposit_and_withdraw() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        let alice: AccountId = "alice.near".parse().unwrap();
+        contract.set_token_scaling(token.clone(), 30, 18);
+        assert_eq!(
+            contract.token_scaling(token.clone()),
+            Some(DecimalScaling {
+                original_decimals: 30,
+                decimals: 18,
+            })
+        );
+
+        // 5 tokens and 7 units of dust.
+        let amount = U256::exp10(30) * U256::from(5) + U256::from(7);
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit_u256(token.clone(), alice, to_raw(amount), None, None, None);
+        assert_eq!(
+            contract.mirrored_supply(token.clone()).0,
+            5 * 10u128.pow(18)
+        );
+        assert_eq!(contract.token_dust(token.clone()), "7");
+
+        let token_account_id = test_utils::token_account_id(&token);
+        let input = contract.encode_unlock(
+            &token_account_id,
+            &token,
+            &Address([3; 20]),
+            2 * 10u128.pow(18),
+        );
+        assert_eq!(
+            U256::from_big_endian(&input[68..100]),
+            U256::exp10(30) * U256::from(2)
+        );
+    }
+
+    #[test]
+    fn test_unscaled_deposit() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit_u256(
+            token.clone(),
+            "alice.near".parse().unwrap(),
+            to_raw(U256::from(10)),
+            None,
+            None,
+            None,
+        );
+        assert_eq!(contract.mirrored_supply(token.clone()).0, 10);
+        assert_eq!(contract.token_dust(token), "0");
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_AMOUNT_OVERFLOW")]
+    fn test_deposit_overflowing_u128() {
+        let mut contract = test_utils::new_contract_with_binary();
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit_u256(
+            Address([1; 20]),
+            "alice.near".parse().unwrap(),
+            to_raw(U256::from(u128::MAX) + U256::one()),
+            None,
+            None,
+            None,
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_SCALING_WITH_SUPPLY")]
+    fn test_scaling_of_token_with_supply() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(
+            token.clone(),
+            "alice.near".parse().unwrap(),
+            10,
+            None,
+            None,
+            None,
+        );
+
+        test_utils::set_context(test_utils::factory());
+        contract.set_token_scaling(token, 30, 18);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_INVALID_SCALING")]
+    fn test_invalid_scaling() {
+        let mut contract = test_utils::new_contract();
+        contract.set_token_scaling(Address([1; 20]), 18, 24);
+    }
+
+    #[test]
+    fn test_dry_run_scaled_deposit_keeps_no_dust() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        contract.set_token_scaling(token.clone(), 30, 18);
+        contract.set_dry_run(true);
+
+        scaled_deposit(&mut contract, &token, None);
+        assert_eq!(contract.token_dust(token), "0");
+    }
+
+    #[test]
+    fn test_replayed_scaled_deposit_is_ignored() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        contract.set_token_scaling(token.clone(), 30, 18);
+
+        scaled_deposit(&mut contract, &token, Some(1));
+        scaled_deposit(&mut contract, &token, Some(1));
+        assert_eq!(
+            contract.mirrored_supply(token.clone()).0,
+            5 * 10u128.pow(18)
+        );
+        assert_eq!(contract.token_dust(token), "7");
+    }
+
+    #[test]
+    fn test_failed_deploy_releases_dust() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        contract.set_token_scaling(token.clone(), 30, 18);
+
+        scaled_deposit(&mut contract, &token, None);
+        assert_eq!(contract.token_dust(token.clone()), "7");
+        let calls = test_utils::function_calls(&test_utils::factory());
+        let args: near_sdk::serde_json::Value =
+            near_sdk::serde_json::from_slice(&calls[0].args).unwrap();
+        let deposit = near_sdk::serde_json::from_value(args["deposit"].clone()).unwrap();
+
+        test_utils::set_callback_context(vec![PromiseResult::Failed]);
+        let _ = contract.on_token_deployed(
+            token.clone(),
+            test_utils::token_account_id(&token),
+            Some(1),
+            Some(deposit),
+        );
+        assert_eq!(contract.mirrored_supply(token.clone()).0, 0);
+        assert_eq!(contract.token_dust(token), "0");
+    }
+}
@@ -0,0 +1,266 @@
+//! Aurora silos served by the factory besides its default engine. Each silo runs its own
+//! engine with its own locker. The tokens of a silo are deployed at
+//! `{silo_id}-{label}.{factory}`, so the same ERC-20 address has a distinct
+//! representative on each silo. Deposits are routed to the silo of the calling locker,
+//! and withdraws are unlocked on the engine of the silo of the token.
+//!
+//! Per-token settings addressed by ERC-20 address, e.g. fees or rate limits, apply to
+//! the tokens of the default engine.
+use crate::naming::MAX_ACCOUNT_ID_LEN;
+use crate::{aurora_sdk, format_locker_account_id, Contract, ContractExt, ERR_INVALID_ACCOUNT};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{env, near_bindgen, require, AccountId};
+
+/// Maximum length of a silo id.
+const MAX_SILO_ID_LEN: usize = 8;
+/// Maximum number of silos, since deposits look up the silo of their caller.
+const MAX_SILOS: u64 = 16;
+
+const ERR_INVALID_SILO: &str =
+    "ERR_INVALID_SILO: Silo id must be 1 to 8 lowercase alphanumeric chars, with a non-zero locker and token account ids that fit.";
+const ERR_SILO_EXISTS: &str = "ERR_SILO_EXISTS: Silo is already registered.";
+const ERR_TOO_MANY_SILOS: &str = "ERR_TOO_MANY_SILOS: Maximum number of silos reached.";
+const ERR_UNKNOWN_SILO: &str = "ERR_UNKNOWN_SILO: Silo is not registered.";
+const ERR_SILO_HAS_TOKENS: &str =
+    "ERR_SILO_HAS_TOKENS: Silo can't be removed while it has registered tokens.";
+
+/// Engine and locker of a silo.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Silo {
+    /// Account id of the engine of the silo.
+    pub engine: AccountId,
+    /// Address of the locker in the silo.
+    pub locker: aurora_sdk::Address,
+    /// Number of tokens routed to the silo.
+    pub token_count: u64,
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Register the silo `silo_id` with its `engine` and `locker`. The locker account
+    /// follows the locker account format of the factory. ONLY the `Owner` role can call
+    /// this method.
+    pub fn add_silo(&mut self, silo_id: String, engine: AccountId, locker: aurora_sdk::Address) {
+        self.assert_owner();
+        self.record_admin_action("add_silo");
+        require!(
+            !silo_id.is_empty()
+                && silo_id.len() <= MAX_SILO_ID_LEN
+                && silo_id
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit())
+                && !locker.is_zero()
+                && format_locker_account_id(&self.locker_account_format, &locker, &engine)
+                    .is_some()
+                && silo_id.len() + 1 + self.worst_case_account_id_len() <= MAX_ACCOUNT_ID_LEN,
+            ERR_INVALID_SILO
+        );
+        require!(self.silos.get(&silo_id).is_none(), ERR_SILO_EXISTS);
+        require!(self.silos.len() < MAX_SILOS, ERR_TOO_MANY_SILOS);
+
+        self.silos.insert(
+            &silo_id,
+            &Silo {
+                engine,
+                locker,
+                token_count: 0,
+            },
+        );
+    }
+
+    /// Unregister the silo `silo_id`, so its locker can no longer deposit. It fails while
+    /// tokens are routed to the silo. ONLY the `Owner` role can call this method.
+    pub fn remove_silo(&mut self, silo_id: String) {
+        self.assert_owner();
+        self.record_admin_action("remove_silo");
+
+        let silo = self
+            .silos
+            .get(&silo_id)
+            .unwrap_or_else(|| env::panic_str(ERR_UNKNOWN_SILO));
+        require!(silo.token_count == 0, ERR_SILO_HAS_TOKENS);
+        self.silos.remove(&silo_id);
+    }
+
+    /// Registered silos by id.
+    pub fn silos(&self) -> Vec<(String, Silo)> {
+        self.silos.to_vec()
+    }
+
+    /// NEAR account ID of the NEP-141 representative of the ERC-20 at `token_address` on
+    /// the silo `silo_id`, whether it is deployed or not.
+    pub fn silo_token_account_id(
+        &self,
+        silo_id: String,
+        token_address: aurora_sdk::Address,
+    ) -> AccountId {
+        require!(self.silos.get(&silo_id).is_some(), ERR_UNKNOWN_SILO);
+        self.silo_account_id(&silo_id, &token_address)
+    }
+
+    /// Silo of the token, or `None` if it belongs to the default engine.
+    pub fn token_silo(&self, token_account_id: AccountId) -> Option<String> {
+        self.token_silos.get(&token_account_id)
+    }
+}
+
+impl Contract {
+    /// Silo whose locker is `account_id`, if any.
+    pub(crate) fn silo_of_locker(&self, account_id: &AccountId) -> Option<String> {
+        self.silos.iter().find_map(|(silo_id, silo)| {
+            (format_locker_account_id(&self.locker_account_format, &silo.locker, &silo.engine)
+                .as_ref()
+                == Some(account_id))
+            .then_some(silo_id)
+        })
+    }
+
+    /// Account id of the token deposited by the predecessor. Deposits of a silo locker
+    /// are routed to the token of the silo, which is recorded on first use.
+    pub(crate) fn route_deposit(&mut self, token: &aurora_sdk::Address) -> AccountId {
+        let silo_id = match self.silo_of_locker(&env::predecessor_account_id()) {
+            Some(silo_id) => silo_id,
+            None => return self.account_id_from_token_address(token.clone()),
+        };
+        let token_account_id = self.silo_account_id(&silo_id, token);
+        if self.token_silos.get(&token_account_id).is_none() {
+            self.token_silos.insert(&token_account_id, &silo_id);
+            let mut silo = self.silos.get(&silo_id).unwrap();
+            silo.token_count += 1;
+            self.silos.insert(&silo_id, &silo);
+        }
+        token_account_id
+    }
+
+    /// Engine the token is bridged from.
+    pub(crate) fn engine_of(&self, token_account_id: &AccountId) -> AccountId {
+        self.token_silos
+            .get(token_account_id)
+            .and_then(|silo_id| self.silos.get(&silo_id))
+            .map_or_else(|| self.aurora.clone(), |silo| silo.engine)
+    }
+
+    /// Locker of the engine the token is bridged from.
+    pub(crate) fn locker_of(&self, token_account_id: &AccountId) -> aurora_sdk::Address {
+        self.token_silos
+            .get(token_account_id)
+            .and_then(|silo_id| self.silos.get(&silo_id))
+            .map_or_else(|| self.locker.clone(), |silo| silo.locker)
+    }
+
+    /// Forget the silo of a removed token.
+    pub(crate) fn unroute_token(&mut self, token_account_id: &AccountId) {
+        let silo_id = match self.token_silos.remove(token_account_id) {
+            Some(silo_id) => silo_id,
+            None => return,
+        };
+        if let Some(mut silo) = self.silos.get(&silo_id) {
+            silo.token_count -= 1;
+            self.silos.insert(&silo_id, &silo);
+        }
+    }
+
+    fn silo_account_id(&self, silo_id: &str, token: &aurora_sdk::Address) -> AccountId {
+        format!(
+            "{}-{}.{}",
+            silo_id,
+            self.naming_scheme.label(token),
+            env::current_account_id()
+        )
+        .parse()
+        .unwrap_or_else(|_| env::panic_str(ERR_INVALID_ACCOUNT))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::aurora_sdk::Address;
+    use crate::test_utils;
+    use near_sdk::{AccountId, PromiseResult};
+
+    fn silo_engine() -> AccountId {
+        "silo.near".parse().unwrap()
+    }
+
+    fn silo_locker_account_id() -> AccountId {
+        format!("{}.{}", test_utils::locker().encode(), silo_engine())
+            .parse()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_deposit_and_withdraw_on_silo() {
+        let mut contract = test_utils::new_contract_with_binary();
+        contract.add_silo("s1".to_string(), silo_engine(), test_utils::locker());
+        let token = Address([1; 20]);
+        let silo_token = contract.silo_token_account_id("s1".to_string(), token.clone());
+        assert_eq!(
+            silo_token.as_str(),
+            format!("s1-{}.{}", token.encode(), test_utils::factory())
+        );
+
+        test_utils::set_context(silo_locker_account_id());
+        let _ = contract.on_deposit(
+            token.clone(),
+            "alice.near".parse().unwrap(),
+            10,
+            None,
+            None,
+            None,
+        );
+        assert!(!test_utils::function_calls(&silo_token).is_empty());
+        assert!(test_utils::function_calls(&test_utils::token_account_id(&token)).is_empty());
+        assert_eq!(
+            contract.token_silo(silo_token.clone()),
+            Some("s1".to_string())
+        );
+        assert_eq!(contract.silos()[0].1.token_count, 1);
+
+        test_utils::set_callback_context(vec![PromiseResult::Successful(Vec::new())]);
+        let _ = contract.on_token_deployed(token.clone(), silo_token.clone(), None, None);
+        assert_eq!(
+            contract.token_account_id(token.clone()),
+            test_utils::token_account_id(&token)
+        );
+
+        test_utils::set_context(silo_token);
+        let _ = contract.on_withdraw(
+            Address([3; 20]),
+            10.into(),
+            Some("alice.near".parse().unwrap()),
+            None,
+            None,
+        );
+        assert!(test_utils::function_calls(&test_utils::aurora()).is_empty());
+        assert_eq!(test_utils::function_calls(&silo_engine())[0].method, "call");
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_SILO_HAS_TOKENS")]
+    fn test_remove_silo_with_tokens() {
+        let mut contract = test_utils::new_contract_with_binary();
+        contract.add_silo("s1".to_string(), silo_engine(), test_utils::locker());
+
+        test_utils::set_context(silo_locker_account_id());
+        let _ = contract.on_deposit(
+            Address([1; 20]),
+            "alice.near".parse().unwrap(),
+            10,
+            None,
+            None,
+            None,
+        );
+
+        test_utils::set_context(test_utils::factory());
+        contract.remove_silo("s1".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_INVALID_SILO")]
+    fn test_invalid_silo_id() {
+        let mut contract = test_utils::new_contract();
+        contract.add_silo("Silo-1".to_string(), silo_engine(), test_utils::locker());
+    }
+}
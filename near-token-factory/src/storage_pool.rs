@@ -0,0 +1,167 @@
+//! Payment of the storage registering the receivers of deposits on the tokens. By
+//! default the factory pays it from its own balance. It can be paid from a storage pool
+//! funded by anyone instead, or not at all, in which case receivers register
+//! themselves. Mints to receivers that are not registered fail, and are handled by
+//! `on_mint_complete`.
+use crate::{logging, Contract, ContractExt};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::U128;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{env, near_bindgen, require, AccountId, Balance, Promise};
+
+const ERR_STORAGE_POOL_TOO_LOW: &str =
+    "ERR_STORAGE_POOL_TOO_LOW: Storage pool doesn't cover the amount.";
+
+/// Who pays the storage registering the receiver of a deposit on the token.
+#[derive(
+    BorshSerialize, BorshDeserialize, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq,
+)]
+#[serde(crate = "near_sdk::serde")]
+pub enum StoragePayer {
+    /// The factory pays from its own balance.
+    Factory,
+    /// The storage pool pays. Receivers are not registered while the pool can't cover
+    /// the registration.
+    Pool,
+    /// Receivers are not registered, they must register themselves.
+    Receiver,
+}
+
+impl Default for StoragePayer {
+    fn default() -> Self {
+        Self::Factory
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Set who pays the storage registering the receivers of deposits. ONLY the `Owner`
+    /// role can call this method.
+    pub fn set_storage_payer(&mut self, payer: StoragePayer) {
+        self.assert_owner();
+        self.record_admin_action("set_storage_payer");
+
+        self.storage_payer = payer;
+    }
+
+    /// Who pays the storage registering the receivers of deposits.
+    pub fn storage_payer(&self) -> StoragePayer {
+        self.storage_payer
+    }
+
+    /// Add the attached deposit to the storage pool. Anyone can call this method.
+    /// Returns the balance of the pool.
+    #[payable]
+    pub fn fund_storage_pool(&mut self) -> U128 {
+        self.storage_pool += env::attached_deposit();
+        self.storage_pool.into()
+    }
+
+    /// Balance of the storage pool.
+    pub fn storage_pool_balance(&self) -> U128 {
+        self.storage_pool.into()
+    }
+
+    /// Transfer `amount` of the storage pool to `to`. ONLY the `Owner` role can call
+    /// this method.
+    pub fn withdraw_storage_pool(&mut self, amount: U128, to: AccountId) -> Promise {
+        self.assert_owner();
+        self.record_admin_action("withdraw_storage_pool");
+        require!(amount.0 <= self.storage_pool, ERR_STORAGE_POOL_TOO_LOW);
+
+        self.storage_pool -= amount.0;
+        Promise::new(to).transfer(amount.0)
+    }
+}
+
+impl Contract {
+    /// Deposit attached to the registration of the receiver of a deposit, or `None` if
+    /// the receiver is not registered by the factory. Under the `Pool` payer, it is
+    /// taken from the storage pool. The token refunds the registration of accounts that
+    /// are already registered to the factory balance, not to the pool.
+    pub(crate) fn take_receiver_storage_deposit(&mut self) -> Option<Balance> {
+        let amount = self.gas_config.receiver_storage_deposit.0;
+        match self.storage_payer {
+            StoragePayer::Factory => Some(amount),
+            StoragePayer::Pool if self.storage_pool >= amount => {
+                self.storage_pool -= amount;
+                Some(amount)
+            }
+            StoragePayer::Pool => {
+                self.log_at(
+                    logging::LogLevel::Errors,
+                    "Storage pool doesn't cover the registration of a receiver",
+                );
+                None
+            }
+            StoragePayer::Receiver => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StoragePayer;
+    use crate::aurora_sdk::Address;
+    use crate::test_utils;
+    use near_sdk::json_types::U128;
+    use near_sdk::testing_env;
+
+    fn deposit(contract: &mut crate::Contract, token: &Address) -> Vec<(String, u128)> {
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(
+            token.clone(),
+            "alice.near".parse().unwrap(),
+            10,
+            None,
+            None,
+            None,
+        );
+        test_utils::function_calls(&test_utils::token_account_id(token))
+            .into_iter()
+            .map(|call| (call.method, call.deposit))
+            .collect()
+    }
+
+    #[test]
+    fn test_pool_pays_registration() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        test_utils::deploy_token(&mut contract, &token);
+        contract.set_storage_payer(StoragePayer::Pool);
+        contract.set_receiver_storage_deposit(U128(7));
+        testing_env!(test_utils::context(test_utils::factory())
+            .attached_deposit(10)
+            .build());
+        assert_eq!(contract.fund_storage_pool(), U128(10));
+
+        assert_eq!(
+            deposit(&mut contract, &token),
+            [
+                ("storage_deposit".to_string(), 7),
+                ("deposit".to_string(), 0)
+            ]
+        );
+        assert_eq!(contract.storage_pool_balance(), U128(3));
+        // The pool no longer covers the registration.
+        assert_eq!(deposit(&mut contract, &token), [("deposit".to_string(), 0)]);
+        assert_eq!(contract.storage_pool_balance(), U128(3));
+    }
+
+    #[test]
+    fn test_receiver_pays_registration() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        test_utils::deploy_token(&mut contract, &token);
+        contract.set_storage_payer(StoragePayer::Receiver);
+
+        assert_eq!(deposit(&mut contract, &token), [("deposit".to_string(), 0)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_STORAGE_POOL_TOO_LOW")]
+    fn test_withdraw_storage_pool_above_balance() {
+        let mut contract = test_utils::new_contract();
+        let _ = contract.withdraw_storage_pool(U128(1), "alice.near".parse().unwrap());
+    }
+}
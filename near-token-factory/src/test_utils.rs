@@ -0,0 +1,174 @@
+//! Helpers shared by the unit tests of the factory.
+use crate::aurora_sdk::Address;
+use crate::fees::FeeConfig;
+use crate::{binary_hash, Contract};
+use near_sdk::mock::VmAction;
+use near_sdk::test_utils::{get_created_receipts, VMContextBuilder};
+use near_sdk::{testing_env, AccountId, Balance, Gas, PromiseResult, RuntimeFeesConfig, VMConfig};
+
+pub fn factory() -> AccountId {
+    "factory.near".parse().unwrap()
+}
+
+pub fn aurora() -> AccountId {
+    "aurora".parse().unwrap()
+}
+
+pub fn locker() -> Address {
+    Address([0x0a; 20])
+}
+
+pub fn locker_account_id() -> AccountId {
+    format!("{}.{}", locker().encode(), aurora())
+        .parse()
+        .unwrap()
+}
+
+pub fn token_account_id(address: &Address) -> AccountId {
+    format!("{}.{}", address.encode(), factory())
+        .parse()
+        .unwrap()
+}
+
+/// Context where `predecessor` calls the factory.
+pub fn context(predecessor: AccountId) -> VMContextBuilder {
+    let mut builder = VMContextBuilder::new();
+    builder
+        .current_account_id(factory())
+        .signer_account_id(predecessor.clone())
+        .predecessor_account_id(predecessor);
+    builder
+}
+
+pub fn set_context(predecessor: AccountId) {
+    testing_env!(context(predecessor).build());
+}
+
+/// Context of a callback executed by the factory with the given promise results.
+pub fn set_callback_context(promise_results: Vec<PromiseResult>) {
+    testing_env!(
+        context(factory()).build(),
+        VMConfig::test(),
+        RuntimeFeesConfig::test(),
+        Default::default(),
+        promise_results
+    );
+}
+
+/// Run the `on_token_deployed` callback of a successful deploy of `token`. The factory
+/// itself is the predecessor after this call.
+pub fn confirm_deploy(contract: &mut Contract, token: &Address) {
+    set_callback_context(vec![PromiseResult::Successful(Vec::new())]);
+    let _ = contract.on_token_deployed(token.clone(), token_account_id(token), None, None);
+}
+
+/// Create `token` with `create_token_with_registrations` and run the callback of its
+/// successful deploy in the same block. The factory itself is the predecessor after
+/// this call.
+pub fn deploy_token(contract: &mut Contract, token: &Address) {
+    let block_height = near_sdk::env::block_height();
+    let _ = contract.create_token_with_registrations(token.clone(), Vec::new());
+    testing_env!(
+        context(factory()).block_index(block_height).build(),
+        VMConfig::test(),
+        RuntimeFeesConfig::test(),
+        Default::default(),
+        vec![PromiseResult::Successful(Vec::new())]
+    );
+    let _ = contract.on_token_deployed(token.clone(), token_account_id(token), None, None);
+}
+
+/// Deploy a fresh factory. The factory itself is the predecessor after this call.
+pub fn new_contract() -> Contract {
+    set_context(factory());
+    Contract::new(aurora(), locker(), None)
+}
+
+/// Deploy a fresh factory with a token binary set. The factory itself is the
+/// predecessor after this call, and the logs of the setup are cleared.
+pub fn new_contract_with_binary() -> Contract {
+    let mut contract = new_contract();
+    contract.set_token_binary(vec![0, 97, 115, 109].into());
+    set_context(factory());
+    contract
+}
+
+/// Let the timelock of the changes proposed so far expire, keeping the predecessor.
+pub fn skip_timelock(contract: &Contract) {
+    let block_height = near_sdk::env::block_height() + contract.timelock_delay().0;
+    testing_env!(context(near_sdk::env::predecessor_account_id())
+        .block_index(block_height)
+        .build());
+}
+
+/// Replace the token binary through the timelock, keeping the predecessor.
+pub fn replace_token_binary(contract: &mut Contract, binary: Vec<u8>) {
+    contract.propose_token_binary(binary_hash(&binary).into());
+    skip_timelock(contract);
+    contract.confirm_token_binary(binary.into());
+}
+
+/// Point the factory to `locker` through the timelock, keeping the predecessor.
+pub fn set_locker(contract: &mut Contract, locker: Address) {
+    contract.propose_locker(locker);
+    skip_timelock(contract);
+    contract.confirm_locker();
+}
+
+/// Set the default fees through the timelock, keeping the predecessor.
+pub fn set_default_fees(contract: &mut Contract, fees: FeeConfig) {
+    contract.propose_default_fees(fees);
+    skip_timelock(contract);
+    contract.confirm_default_fees();
+}
+
+/// Set the fees of `token` through the timelock, keeping the predecessor.
+pub fn set_token_fees(contract: &mut Contract, token: &Address, fees: Option<FeeConfig>) {
+    contract.propose_token_fees(token.clone(), fees);
+    skip_timelock(contract);
+    contract.confirm_token_fees(token.clone());
+}
+
+/// Function call action scheduled by the contract.
+pub struct FunctionCall {
+    pub method: String,
+    pub args: Vec<u8>,
+    pub gas: Gas,
+    pub deposit: Balance,
+}
+
+/// Function calls scheduled towards `receiver_id` in the current context, in order.
+pub fn function_calls(receiver_id: &AccountId) -> Vec<FunctionCall> {
+    get_created_receipts()
+        .into_iter()
+        .filter(|receipt| &receipt.receiver_id == receiver_id)
+        .flat_map(|receipt| receipt.actions)
+        .filter_map(|action| match action {
+            VmAction::FunctionCall {
+                function_name,
+                args,
+                gas,
+                deposit,
+            } => Some(FunctionCall {
+                method: function_name,
+                args,
+                gas,
+                deposit,
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Amounts transferred towards `receiver_id` in the current context, in order.
+pub fn transfers(receiver_id: &AccountId) -> Vec<Balance> {
+    get_created_receipts()
+        .into_iter()
+        .filter(|receipt| &receipt.receiver_id == receiver_id)
+        .flat_map(|receipt| receipt.actions)
+        .filter_map(|action| match action {
+            VmAction::Transfer { deposit } => Some(deposit),
+            _ => None,
+        })
+        .collect()
+}
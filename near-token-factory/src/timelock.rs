@@ -0,0 +1,614 @@
+//! Configuration changes that only take effect after a timelock. Changes are proposed
+//! first, and they can be confirmed once the delay has passed, so they can be observed
+//! on chain before they take effect. The locker, the token binary, the binary of the
+//! deployer, the fees and the timelock delay itself can ONLY be changed this way,
+//! except for the first token binary. At most one change per target is pending.
+use crate::fees::{self, FeeConfig};
+use crate::roles::Role;
+use crate::{aurora_sdk, binary_hash, format_locker_account_id, Contract, ContractExt};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{Base58CryptoHash, Base64VecU8, U64};
+use near_sdk::serde::Serialize;
+use near_sdk::{env, near_bindgen, require, AccountId, BlockHeight, CryptoHash};
+
+/// Default number of blocks between proposing and confirming a change.
+pub const DEFAULT_TIMELOCK_DELAY: BlockHeight = 43_200;
+/// Minimum number of blocks between proposing and confirming a change.
+pub const MIN_TIMELOCK_DELAY: BlockHeight = 3_600;
+
+const ERR_NO_PENDING_CHANGE: &str = "ERR_NO_PENDING_CHANGE: There is no pending change.";
+const ERR_TIMELOCK_NOT_EXPIRED: &str =
+    "ERR_TIMELOCK_NOT_EXPIRED: Pending change can't be confirmed yet.";
+const ERR_PROPOSED_BINARY_MISMATCH: &str =
+    "ERR_PROPOSED_BINARY_MISMATCH: Binary doesn't match the hash of the proposed binary.";
+const ERR_TIMELOCK_DELAY_TOO_SHORT: &str =
+    "ERR_TIMELOCK_DELAY_TOO_SHORT: Timelock delay is shorter than the minimum delay.";
+
+/// Change waiting for its timelock to expire.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub enum ProposedChange {
+    Locker(aurora_sdk::Address),
+    /// Sha256 hash of the proposed token binary.
+    TokenBinary(CryptoHash),
+    DefaultFees(FeeConfig),
+    /// Fees of a token, or `None` to use the default fees again.
+    TokenFees(aurora_sdk::Address, Option<FeeConfig>),
+    TimelockDelay(BlockHeight),
+    /// Sha256 hash of the proposed binary of the deployer.
+    DeployerBinary(CryptoHash),
+}
+
+impl ProposedChange {
+    fn target(&self) -> String {
+        match self {
+            ProposedChange::Locker(_) => "locker".to_string(),
+            ProposedChange::TokenBinary(_) => "token_binary".to_string(),
+            ProposedChange::DefaultFees(_) => "default_fees".to_string(),
+            ProposedChange::TokenFees(token, _) => token_fees_target(token),
+            ProposedChange::TimelockDelay(_) => "timelock_delay".to_string(),
+            ProposedChange::DeployerBinary(_) => "deployer_binary".to_string(),
+        }
+    }
+
+    fn summary(&self) -> String {
+        match self {
+            ProposedChange::Locker(locker) => locker.to_string(),
+            ProposedChange::TokenBinary(hash) | ProposedChange::DeployerBinary(hash) => {
+                String::from(&Base58CryptoHash::from(*hash))
+            }
+            ProposedChange::DefaultFees(fees) | ProposedChange::TokenFees(_, Some(fees)) => {
+                format!(
+                    "deposit {} bps, withdraw {} bps, min fee {}",
+                    fees.deposit_bps, fees.withdraw_bps, fees.min_fee.0
+                )
+            }
+            ProposedChange::TokenFees(_, None) => "default fees".to_string(),
+            ProposedChange::TimelockDelay(delay) => format!("{} blocks", delay),
+        }
+    }
+}
+
+/// Target of the pending change of the fees of `token`.
+fn token_fees_target(token: &aurora_sdk::Address) -> String {
+    format!("token_fees:{}", token)
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PendingChangeRecord {
+    pub change: ProposedChange,
+    pub confirmable_at: BlockHeight,
+}
+
+/// View of a change waiting for its timelock to expire.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PendingChange {
+    /// Configuration field that is changed.
+    pub target: String,
+    /// Summary of the new value.
+    pub new_value: String,
+    /// First block at which the change can be confirmed.
+    pub confirmable_at: U64,
+}
+
+/// State of the rotation of the locker.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct LockerRotation {
+    /// Current locker.
+    pub locker: aurora_sdk::Address,
+    /// Proposed locker, if any.
+    pub pending_locker: Option<aurora_sdk::Address>,
+    /// First block at which the proposed locker can be confirmed.
+    pub confirmable_at: Option<U64>,
+    /// Previous locker, while it is still accepted after a rotation.
+    pub previous_locker: Option<aurora_sdk::Address>,
+    /// Last block at which the previous locker is accepted.
+    pub previous_locker_accepted_until: Option<U64>,
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Propose a new locker address. It can be confirmed with `confirm_locker` after
+    /// the timelock delay. A previous proposal for the locker is replaced. ONLY the
+    /// `Owner` role can call this method.
+    pub fn propose_locker(&mut self, locker: aurora_sdk::Address) {
+        self.assert_owner();
+        self.record_admin_action("propose_locker");
+
+        self.propose_change(ProposedChange::Locker(locker));
+    }
+
+    /// Apply the proposed locker address once the timelock expired. The previous
+    /// locker is still accepted during the locker grace period, so deposits it already
+    /// sent are not rejected. ONLY the `Owner` role can call this method.
+    pub fn confirm_locker(&mut self) {
+        self.assert_owner();
+        self.record_admin_action("confirm_locker");
+
+        if let ProposedChange::Locker(locker) = self.take_confirmable_change("locker") {
+            let previous_locker = self.locker.clone();
+            self.update_locker(locker);
+            self.retired_locker = (self.locker_grace_period > 0).then(|| {
+                (
+                    previous_locker,
+                    env::block_height() + self.locker_grace_period,
+                )
+            });
+        }
+    }
+
+    /// Propose the token binary with the sha256 hash `hash`. It can be installed with
+    /// `confirm_token_binary` after the timelock delay. A previous proposal for the
+    /// binary is replaced. ONLY the `Owner` and `TokenUpgrader` roles can call this
+    /// method.
+    pub fn propose_token_binary(&mut self, hash: Base58CryptoHash) {
+        self.assert_role(Role::TokenUpgrader);
+        self.record_admin_action("propose_token_binary");
+        self.assert_upgrades_not_frozen();
+
+        self.propose_change(ProposedChange::TokenBinary(hash.into()));
+    }
+
+    /// Install `binary` as the token binary once the timelock of the proposed binary
+    /// expired. `binary` MUST match the proposed hash. Binaries too large for a single
+    /// call are installed with `finalize_binary_upload` instead. ONLY the `Owner` and
+    /// `TokenUpgrader` roles can call this method.
+    pub fn confirm_token_binary(&mut self, binary: Base64VecU8) {
+        self.assert_role(Role::TokenUpgrader);
+        self.record_admin_action("confirm_token_binary");
+        self.assert_upgrades_not_frozen();
+
+        self.install_proposed_token_binary(binary.into());
+    }
+
+    /// Propose the binary of the deployer with the sha256 hash `hash`. The deployer can
+    /// be created with `create_deployer` after the timelock delay. A previous proposal
+    /// for the binary is replaced. ONLY the `Owner` role can call this method.
+    pub fn propose_deployer_binary(&mut self, hash: Base58CryptoHash) {
+        self.assert_owner();
+        self.record_admin_action("propose_deployer_binary");
+
+        self.propose_change(ProposedChange::DeployerBinary(hash.into()));
+    }
+
+    /// Propose new default fees. They can be applied with `confirm_default_fees` after
+    /// the timelock delay. A previous proposal for the fees is replaced. ONLY the
+    /// `Owner` role can call this method.
+    pub fn propose_default_fees(&mut self, fees: FeeConfig) {
+        self.assert_owner();
+        self.record_admin_action("propose_default_fees");
+        fees::assert_valid_fees(&fees);
+
+        self.propose_change(ProposedChange::DefaultFees(fees));
+    }
+
+    /// Apply the proposed default fees once the timelock expired. ONLY the `Owner`
+    /// role can call this method.
+    pub fn confirm_default_fees(&mut self) {
+        self.assert_owner();
+        self.record_admin_action("confirm_default_fees");
+
+        if let ProposedChange::DefaultFees(fees) = self.take_confirmable_change("default_fees") {
+            self.default_fees = fees;
+        }
+    }
+
+    /// Propose the fees of a token, or the default fees again if `fees` is `None`. They
+    /// can be applied with `confirm_token_fees` after the timelock delay. A previous
+    /// proposal for the fees of the token is replaced. ONLY the `Owner` role can call
+    /// this method.
+    pub fn propose_token_fees(
+        &mut self,
+        token_address: aurora_sdk::Address,
+        fees: Option<FeeConfig>,
+    ) {
+        self.assert_owner();
+        self.record_admin_action("propose_token_fees");
+        if let Some(fees) = &fees {
+            fees::assert_valid_fees(fees);
+        }
+
+        self.propose_change(ProposedChange::TokenFees(token_address, fees));
+    }
+
+    /// Apply the proposed fees of a token once the timelock expired. ONLY the `Owner`
+    /// role can call this method.
+    pub fn confirm_token_fees(&mut self, token_address: aurora_sdk::Address) {
+        self.assert_owner();
+        self.record_admin_action("confirm_token_fees");
+
+        if let ProposedChange::TokenFees(token_address, fees) =
+            self.take_confirmable_change(&token_fees_target(&token_address))
+        {
+            let token_account_id = self.account_id_from_token_address(token_address);
+            match fees {
+                Some(fees) => {
+                    self.token_fees.insert(&token_account_id, &fees);
+                }
+                None => {
+                    self.token_fees.remove(&token_account_id);
+                }
+            }
+        }
+    }
+
+    /// Set the number of blocks the previous locker is still accepted after
+    /// `confirm_locker`. Zero disables the grace period. It only applies to rotations
+    /// confirmed afterwards. ONLY the `Owner` role can call this method.
+    pub fn set_locker_grace_period(&mut self, blocks: U64) {
+        self.assert_owner();
+        self.record_admin_action("set_locker_grace_period");
+
+        self.locker_grace_period = blocks.0;
+    }
+
+    /// Number of blocks the previous locker is still accepted after `confirm_locker`.
+    pub fn locker_grace_period(&self) -> U64 {
+        self.locker_grace_period.into()
+    }
+
+    /// Current, proposed and previous lockers.
+    pub fn locker_rotation(&self) -> LockerRotation {
+        let pending = self
+            .pending_changes
+            .iter()
+            .find_map(|record| match &record.change {
+                ProposedChange::Locker(locker) => Some((locker.clone(), record.confirmable_at)),
+                _ => None,
+            });
+        let previous = self
+            .retired_locker
+            .clone()
+            .filter(|(_, accepted_until)| env::block_height() <= *accepted_until);
+        LockerRotation {
+            locker: self.locker.clone(),
+            pending_locker: pending.as_ref().map(|(locker, _)| locker.clone()),
+            confirmable_at: pending.map(|(_, confirmable_at)| confirmable_at.into()),
+            previous_locker: previous.as_ref().map(|(locker, _)| locker.clone()),
+            previous_locker_accepted_until: previous
+                .map(|(_, accepted_until)| accepted_until.into()),
+        }
+    }
+
+    /// Discard the pending change of `target`, i.e. `locker`, `token_binary`,
+    /// `default_fees`, `token_fees:{token_address}`, `timelock_delay` or
+    /// `deployer_binary`. ONLY the `Owner` role can call this method.
+    pub fn cancel_pending_change(&mut self, target: String) {
+        self.assert_owner();
+        self.record_admin_action("cancel_pending_change");
+
+        let len = self.pending_changes.len();
+        self.pending_changes
+            .retain(|record| record.change.target() != target);
+        require!(self.pending_changes.len() < len, ERR_NO_PENDING_CHANGE);
+    }
+
+    /// Propose the number of blocks between proposing and confirming a change, at
+    /// least `MIN_TIMELOCK_DELAY`. It can be applied with `confirm_timelock_delay`
+    /// after the current delay. A previous proposal for the delay is replaced. ONLY the
+    /// `Owner` role can call this method.
+    pub fn propose_timelock_delay(&mut self, delay: U64) {
+        self.assert_owner();
+        self.record_admin_action("propose_timelock_delay");
+        require!(delay.0 >= MIN_TIMELOCK_DELAY, ERR_TIMELOCK_DELAY_TOO_SHORT);
+
+        self.propose_change(ProposedChange::TimelockDelay(delay.0));
+    }
+
+    /// Apply the proposed timelock delay once the timelock expired. It only applies to
+    /// changes proposed afterwards. ONLY the `Owner` role can call this method.
+    pub fn confirm_timelock_delay(&mut self) {
+        self.assert_owner();
+        self.record_admin_action("confirm_timelock_delay");
+
+        if let ProposedChange::TimelockDelay(delay) = self.take_confirmable_change("timelock_delay")
+        {
+            self.timelock_delay = delay;
+        }
+    }
+
+    /// Number of blocks between proposing and confirming a change.
+    pub fn timelock_delay(&self) -> U64 {
+        self.timelock_delay.into()
+    }
+
+    /// Changes waiting for their timelock to expire.
+    pub fn pending_changes(&self) -> Vec<PendingChange> {
+        self.pending_changes
+            .iter()
+            .map(|record| PendingChange {
+                target: record.change.target(),
+                new_value: record.change.summary(),
+                confirmable_at: record.confirmable_at.into(),
+            })
+            .collect()
+    }
+}
+
+impl Contract {
+    /// Whether `account_id` is the current locker, or the previous locker during the
+    /// grace period of a rotation.
+    pub(crate) fn is_locker(&self, account_id: &AccountId) -> bool {
+        if account_id == &self.locker_account_id() {
+            return true;
+        }
+        matches!(
+            &self.retired_locker,
+            Some((locker, accepted_until)) if env::block_height() <= *accepted_until
+                && format_locker_account_id(&self.locker_account_format, locker, &self.aurora)
+                    .as_ref() == Some(account_id)
+        )
+    }
+
+    /// Install `binary` as the token binary, panicking unless it is the proposed binary
+    /// and its timelock expired.
+    pub(crate) fn install_proposed_token_binary(&mut self, binary: Vec<u8>) {
+        if let ProposedChange::TokenBinary(hash) = self.take_confirmable_change("token_binary") {
+            require!(binary_hash(&binary) == hash, ERR_PROPOSED_BINARY_MISMATCH);
+            self.install_token_binary(binary);
+        }
+    }
+
+    /// Consume the proposed binary of the deployer, panicking unless `binary` matches it
+    /// and its timelock expired.
+    pub(crate) fn take_proposed_deployer_binary(&mut self, binary: &[u8]) {
+        if let ProposedChange::DeployerBinary(hash) =
+            self.take_confirmable_change("deployer_binary")
+        {
+            require!(binary_hash(binary) == hash, ERR_PROPOSED_BINARY_MISMATCH);
+        }
+    }
+
+    fn propose_change(&mut self, change: ProposedChange) {
+        let target = change.target();
+        self.pending_changes
+            .retain(|record| record.change.target() != target);
+        self.pending_changes.push(PendingChangeRecord {
+            change,
+            confirmable_at: env::block_height() + self.timelock_delay,
+        });
+    }
+
+    fn take_confirmable_change(&mut self, target: &str) -> ProposedChange {
+        let index = self
+            .pending_changes
+            .iter()
+            .position(|record| record.change.target() == target)
+            .unwrap_or_else(|| env::panic_str(ERR_NO_PENDING_CHANGE));
+        require!(
+            self.pending_changes[index].confirmable_at <= env::block_height(),
+            ERR_TIMELOCK_NOT_EXPIRED
+        );
+        self.pending_changes.remove(index).change
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LockerRotation, PendingChange, DEFAULT_TIMELOCK_DELAY, MIN_TIMELOCK_DELAY};
+    use crate::aurora_sdk::Address;
+    use crate::fees::FeeConfig;
+    use crate::{binary_hash, test_utils};
+    use near_sdk::json_types::U128;
+    use near_sdk::testing_env;
+
+    fn set_block(block_height: u64) {
+        testing_env!(test_utils::context(test_utils::factory())
+            .block_index(block_height)
+            .build());
+    }
+
+    #[test]
+    fn test_proposed_locker_is_pending() {
+        let mut contract = test_utils::new_contract();
+        let locker = Address([0x0b; 20]);
+
+        set_block(100);
+        contract.propose_locker(locker.clone());
+
+        assert_eq!(
+            contract.pending_changes(),
+            vec![PendingChange {
+                target: "locker".to_string(),
+                new_value: locker.to_string(),
+                confirmable_at: (100 + DEFAULT_TIMELOCK_DELAY).into(),
+            }]
+        );
+
+        set_block(100 + DEFAULT_TIMELOCK_DELAY);
+        contract.confirm_locker();
+        assert!(contract.pending_changes().is_empty());
+        assert_eq!(
+            contract.locker_account_id().as_str(),
+            format!("{}.{}", locker.encode(), test_utils::aurora())
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_TIMELOCK_NOT_EXPIRED")]
+    fn test_confirm_before_timelock_expires() {
+        let mut contract = test_utils::new_contract();
+
+        set_block(100);
+        contract.propose_locker(Address([0x0b; 20]));
+
+        set_block(99 + DEFAULT_TIMELOCK_DELAY);
+        contract.confirm_locker();
+    }
+
+    #[test]
+    fn test_previous_locker_accepted_during_grace_period() {
+        let mut contract = test_utils::new_contract();
+        let locker = Address([0x0b; 20]);
+        contract.set_locker_grace_period(10.into());
+
+        set_block(100);
+        contract.propose_locker(locker.clone());
+        assert_eq!(
+            contract.locker_rotation(),
+            LockerRotation {
+                locker: test_utils::locker(),
+                pending_locker: Some(locker.clone()),
+                confirmable_at: Some((100 + DEFAULT_TIMELOCK_DELAY).into()),
+                previous_locker: None,
+                previous_locker_accepted_until: None,
+            }
+        );
+
+        let confirmed_at = 100 + DEFAULT_TIMELOCK_DELAY;
+        set_block(confirmed_at);
+        contract.confirm_locker();
+        assert_eq!(
+            contract.locker_rotation().previous_locker_accepted_until,
+            Some((confirmed_at + 10).into())
+        );
+
+        set_block(confirmed_at + 10);
+        assert!(contract.can_call("create_token".to_string(), test_utils::locker_account_id()));
+        set_block(confirmed_at + 11);
+        assert!(!contract.can_call("create_token".to_string(), test_utils::locker_account_id()));
+        assert_eq!(contract.locker_rotation().previous_locker, None);
+    }
+
+    #[test]
+    fn test_timelocked_changes_have_no_direct_setter() {
+        let contract = test_utils::new_contract();
+        let methods = contract.interface().methods;
+
+        for action in [
+            "set_locker",
+            "set_default_fees",
+            "set_token_fees",
+            "set_timelock_delay",
+        ] {
+            assert!(!contract.can_call(action.to_string(), test_utils::factory()));
+            assert!(!methods.iter().any(|method| method.name == action));
+        }
+    }
+
+    #[test]
+    fn test_cancel_pending_change() {
+        let mut contract = test_utils::new_contract();
+        contract.propose_locker(Address([0x0b; 20]));
+
+        contract.cancel_pending_change("locker".to_string());
+
+        assert!(contract.pending_changes().is_empty());
+        assert_eq!(
+            contract.locker_account_id(),
+            test_utils::locker_account_id()
+        );
+    }
+
+    #[test]
+    fn test_proposed_token_binary() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let binary = vec![0, 97, 115, 109, 2];
+
+        set_block(100);
+        contract.propose_token_binary(binary_hash(&binary).into());
+        assert_eq!(contract.pending_changes()[0].target, "token_binary");
+
+        set_block(100 + DEFAULT_TIMELOCK_DELAY);
+        contract.confirm_token_binary(binary.clone().into());
+        assert_eq!(
+            contract.token_binary_hash(),
+            Some(binary_hash(&binary).into())
+        );
+        assert!(contract.pending_changes().is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_PROPOSED_BINARY_MISMATCH")]
+    fn test_confirm_other_token_binary() {
+        let mut contract = test_utils::new_contract_with_binary();
+
+        set_block(100);
+        contract.propose_token_binary(binary_hash(&[0, 97, 115, 109, 2]).into());
+
+        set_block(100 + DEFAULT_TIMELOCK_DELAY);
+        contract.confirm_token_binary(vec![0, 97, 115, 109, 3].into());
+    }
+
+    #[test]
+    fn test_proposed_default_fees() {
+        let mut contract = test_utils::new_contract();
+        let fees = FeeConfig {
+            deposit_bps: 10,
+            withdraw_bps: 20,
+            min_fee: U128(1),
+        };
+
+        set_block(100);
+        contract.propose_default_fees(fees);
+        contract.propose_locker(Address([0x0b; 20]));
+        assert_eq!(
+            contract.pending_changes()[0],
+            PendingChange {
+                target: "default_fees".to_string(),
+                new_value: "deposit 10 bps, withdraw 20 bps, min fee 1".to_string(),
+                confirmable_at: (100 + DEFAULT_TIMELOCK_DELAY).into(),
+            }
+        );
+
+        set_block(100 + DEFAULT_TIMELOCK_DELAY);
+        contract.confirm_default_fees();
+        assert_eq!(contract.default_fees(), fees);
+        // Other pending changes are kept.
+        assert_eq!(contract.pending_changes()[0].target, "locker");
+    }
+
+    #[test]
+    fn test_proposed_token_fees() {
+        let mut contract = test_utils::new_contract();
+        let token = Address([1; 20]);
+        let fees = FeeConfig {
+            deposit_bps: 10,
+            withdraw_bps: 20,
+            min_fee: U128(1),
+        };
+
+        set_block(100);
+        contract.propose_token_fees(token.clone(), Some(fees));
+        assert_eq!(
+            contract.pending_changes()[0].target,
+            format!("token_fees:{}", token)
+        );
+        assert_eq!(contract.token_fees(token.clone()), contract.default_fees());
+
+        set_block(100 + DEFAULT_TIMELOCK_DELAY);
+        contract.confirm_token_fees(token.clone());
+        assert_eq!(contract.token_fees(token), fees);
+    }
+
+    #[test]
+    fn test_proposed_timelock_delay() {
+        let mut contract = test_utils::new_contract();
+
+        set_block(100);
+        contract.propose_timelock_delay(MIN_TIMELOCK_DELAY.into());
+        assert_eq!(contract.timelock_delay().0, DEFAULT_TIMELOCK_DELAY);
+
+        set_block(100 + DEFAULT_TIMELOCK_DELAY);
+        contract.confirm_timelock_delay();
+        assert_eq!(contract.timelock_delay().0, MIN_TIMELOCK_DELAY);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_TIMELOCK_NOT_EXPIRED")]
+    fn test_timelock_delay_change_is_timelocked() {
+        let mut contract = test_utils::new_contract();
+
+        set_block(100);
+        contract.propose_timelock_delay(MIN_TIMELOCK_DELAY.into());
+        contract.confirm_timelock_delay();
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_TIMELOCK_DELAY_TOO_SHORT")]
+    fn test_timelock_delay_below_minimum() {
+        let mut contract = test_utils::new_contract();
+        contract.propose_timelock_delay(0.into());
+    }
+}
@@ -0,0 +1,134 @@
+//! Optional allowlist of the ERC-20 tokens that can be bridged, for curated deployments
+//! of the connector. While the allowlist mode is enabled, `create_token` and the
+//! deposits reject tokens that are not in the allowlist. Tokens already deployed are
+//! not affected otherwise, e.g. they can still be withdrawn.
+use crate::{aurora_sdk, Contract, ContractExt};
+use near_sdk::{near_bindgen, require};
+
+pub(crate) const ERR_TOKEN_NOT_ALLOWED: &str =
+    "ERR_TOKEN_NOT_ALLOWED: Token is not in the allowlist of bridged tokens.";
+
+#[near_bindgen]
+impl Contract {
+    /// Enable or disable the allowlist mode. The allowlist is kept while the mode is
+    /// disabled. ONLY the `Owner` role can call this method.
+    pub fn set_token_allowlist_enabled(&mut self, enabled: bool) {
+        self.assert_owner();
+        self.record_admin_action("set_token_allowlist_enabled");
+
+        self.token_allowlist_enabled = enabled;
+    }
+
+    /// Whether only the tokens in the allowlist can be bridged.
+    pub fn is_token_allowlist_enabled(&self) -> bool {
+        self.token_allowlist_enabled
+    }
+
+    /// Add the token to the allowlist. ONLY the `Owner` role can call this method.
+    pub fn add_allowed_token(&mut self, token_address: aurora_sdk::Address) {
+        self.assert_owner();
+        self.record_admin_action("add_allowed_token");
+
+        self.allowed_tokens.insert(&token_address);
+    }
+
+    /// Remove the token from the allowlist. ONLY the `Owner` role can call this method.
+    pub fn remove_allowed_token(&mut self, token_address: aurora_sdk::Address) {
+        self.assert_owner();
+        self.record_admin_action("remove_allowed_token");
+
+        self.allowed_tokens.remove(&token_address);
+    }
+
+    /// Whether the token can be bridged, either because it is in the allowlist or
+    /// because the allowlist mode is disabled.
+    pub fn is_token_allowed(&self, token_address: aurora_sdk::Address) -> bool {
+        !self.token_allowlist_enabled || self.allowed_tokens.contains(&token_address)
+    }
+
+    /// Tokens in the allowlist, starting at `from_index`.
+    pub fn get_allowed_tokens(&self, from_index: u64, limit: u64) -> Vec<aurora_sdk::Address> {
+        self.allowed_tokens
+            .iter()
+            .skip(from_index as usize)
+            .take(limit as usize)
+            .collect()
+    }
+
+    /// Number of tokens in the allowlist.
+    pub fn allowed_tokens_count(&self) -> u64 {
+        self.allowed_tokens.len()
+    }
+}
+
+impl Contract {
+    /// Panic if the allowlist mode is enabled and the token is not in the allowlist.
+    pub(crate) fn assert_token_allowed(&self, token_address: &aurora_sdk::Address) {
+        require!(
+            !self.token_allowlist_enabled || self.allowed_tokens.contains(token_address),
+            ERR_TOKEN_NOT_ALLOWED
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::aurora_sdk::Address;
+    use crate::test_utils;
+
+    #[test]
+    fn test_allowed_tokens() {
+        let mut contract = test_utils::new_contract();
+        let token = Address([1; 20]);
+        assert!(contract.is_token_allowed(token.clone()));
+
+        contract.set_token_allowlist_enabled(true);
+        assert!(!contract.is_token_allowed(token.clone()));
+        contract.add_allowed_token(token.clone());
+        contract.add_allowed_token(Address([2; 20]));
+        assert!(contract.is_token_allowed(token.clone()));
+        assert_eq!(contract.allowed_tokens_count(), 2);
+        assert_eq!(contract.get_allowed_tokens(1, 10), vec![Address([2; 20])]);
+
+        contract.remove_allowed_token(token.clone());
+        assert!(!contract.is_token_allowed(token));
+    }
+
+    #[test]
+    fn test_allowed_token_can_be_deposited() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        contract.set_token_allowlist_enabled(true);
+        contract.add_allowed_token(token.clone());
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(token, "alice.near".parse().unwrap(), 10, None, None, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_TOKEN_NOT_ALLOWED")]
+    fn test_deposit_of_token_not_allowed() {
+        let mut contract = test_utils::new_contract_with_binary();
+        contract.set_token_allowlist_enabled(true);
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(
+            Address([1; 20]),
+            "alice.near".parse().unwrap(),
+            10,
+            None,
+            None,
+            None,
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_TOKEN_NOT_ALLOWED")]
+    fn test_create_token_not_allowed() {
+        let mut contract = test_utils::new_contract_with_binary();
+        contract.set_token_allowlist_enabled(true);
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.create_token(Address([1; 20]), None);
+    }
+}
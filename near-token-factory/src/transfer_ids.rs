@@ -0,0 +1,259 @@
+//! Replay protection of deposits. The depositor of `on_deposit` can attach a transfer
+//! id, and a deposit whose id was already processed for the same depositor is ignored
+//! instead of minted again. The locker uses a nonce it increments on every deposit.
+//!
+//! Processed ids are kept in a log in processing order. Entries older than
+//! `PROCESSED_TRANSFER_RETENTION` can be pruned by anyone with
+//! `prune_processed_transfers`. Pruning raises the floor of the depositor to the pruned
+//! id, and ids at or below the floor stay processed, so ids SHOULD increase.
+use crate::{logging, Contract, ContractExt};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::U64;
+use near_sdk::{env, near_bindgen, AccountId};
+
+/// Time a processed id is kept before it can be pruned, in nanoseconds.
+const PROCESSED_TRANSFER_RETENTION: u64 = 7 * 24 * 3_600_000_000_000;
+/// Maximum number of entries removed by a call of `prune_processed_transfers`.
+const MAX_PRUNED_PER_CALL: u32 = 100;
+
+/// Entry of the log of processed ids.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ProcessedTransfer {
+    pub depositor_id: AccountId,
+    pub transfer_id: u64,
+    /// Block timestamp at which the deposit was processed.
+    pub processed_at: u64,
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Whether the deposit with `transfer_id` of `depositor_id` was processed.
+    /// `depositor_id` defaults to the locker.
+    pub fn is_transfer_processed(&self, transfer_id: U64, depositor_id: Option<AccountId>) -> bool {
+        let depositor_id = depositor_id.unwrap_or_else(|| self.locker_account_id());
+        self.transfer_processed(&depositor_id, transfer_id.0)
+    }
+
+    /// Remove up to `limit` entries processed more than `PROCESSED_TRANSFER_RETENTION`
+    /// ago, oldest first, and return the number removed. At most `MAX_PRUNED_PER_CALL`
+    /// entries are removed, so the cost of a call is bounded.
+    pub fn prune_processed_transfers(&mut self, limit: u32) -> u32 {
+        let cutoff = env::block_timestamp().saturating_sub(PROCESSED_TRANSFER_RETENTION);
+        let mut pruned = 0;
+        while pruned < limit.min(MAX_PRUNED_PER_CALL)
+            && self.transfer_log_head < self.transfer_log_tail
+        {
+            let entry = self.transfer_log.get(&self.transfer_log_head).unwrap();
+            if entry.processed_at > cutoff {
+                break;
+            }
+            self.transfer_log.remove(&self.transfer_log_head);
+            self.transfer_log_head += 1;
+            self.processed_transfers
+                .remove(&(entry.depositor_id.clone(), entry.transfer_id));
+            let floor = self.transfer_id_floors.get(&entry.depositor_id);
+            if floor.map_or(true, |floor| floor < entry.transfer_id) {
+                self.transfer_id_floors
+                    .insert(&entry.depositor_id, &entry.transfer_id);
+            }
+            pruned += 1;
+        }
+        pruned
+    }
+}
+
+impl Contract {
+    pub(crate) fn transfer_processed(&self, depositor_id: &AccountId, transfer_id: u64) -> bool {
+        self.transfer_id_floors
+            .get(depositor_id)
+            .map_or(false, |floor| transfer_id <= floor)
+            || self
+                .processed_transfers
+                .contains_key(&(depositor_id.clone(), transfer_id))
+    }
+
+    /// Record `transfer_id` of the caller as processed. Return `false`, and log it, if
+    /// it was already processed, in which case the deposit MUST be ignored.
+    pub(crate) fn mark_transfer_processed(&mut self, transfer_id: u64) -> bool {
+        let depositor_id = env::predecessor_account_id();
+        if self.transfer_processed(&depositor_id, transfer_id) {
+            self.log_at(
+                logging::LogLevel::Errors,
+                &format!(
+                    "Transfer {} of {} was already processed",
+                    transfer_id, depositor_id
+                ),
+            );
+            return false;
+        }
+
+        self.processed_transfers
+            .insert(&(depositor_id.clone(), transfer_id), &());
+        self.transfer_log.insert(
+            &self.transfer_log_tail,
+            &ProcessedTransfer {
+                depositor_id,
+                transfer_id,
+                processed_at: env::block_timestamp(),
+            },
+        );
+        self.transfer_log_tail += 1;
+        true
+    }
+
+    /// Undo `mark_transfer_processed` for a deposit that was refunded. The entry of the
+    /// log is kept, so pruning it still raises the floor of the depositor.
+    pub(crate) fn unmark_transfer_processed(&mut self, depositor_id: &AccountId, transfer_id: u64) {
+        self.processed_transfers
+            .remove(&(depositor_id.clone(), transfer_id));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PROCESSED_TRANSFER_RETENTION;
+    use crate::aurora_sdk::Address;
+    use crate::test_utils;
+    use near_sdk::json_types::U64;
+    use near_sdk::{AccountId, PromiseOrValue, PromiseResult};
+
+    fn deposit(contract: &mut crate::Contract, transfer_id: u64) -> PromiseOrValue<()> {
+        test_utils::set_context(test_utils::locker_account_id());
+        contract.on_deposit(
+            Address([1; 20]),
+            "alice.near".parse().unwrap(),
+            10,
+            None,
+            None,
+            Some(transfer_id),
+        )
+    }
+
+    #[test]
+    fn test_replayed_deposit_is_ignored() {
+        let mut contract = test_utils::new_contract_with_binary();
+        test_utils::deploy_token(&mut contract, &Address([1; 20]));
+        assert!(matches!(
+            deposit(&mut contract, 1),
+            PromiseOrValue::Promise(_)
+        ));
+        assert!(contract.is_transfer_processed(U64(1), None));
+
+        assert!(matches!(
+            deposit(&mut contract, 1),
+            PromiseOrValue::Value(())
+        ));
+        let account_id = test_utils::token_account_id(&Address([1; 20]));
+        assert!(test_utils::function_calls(&account_id).is_empty());
+        assert!(!contract.is_transfer_processed(U64(2), None));
+    }
+
+    #[test]
+    fn test_dry_run_deposit_keeps_transfer_id() {
+        let mut contract = test_utils::new_contract_with_binary();
+        test_utils::deploy_token(&mut contract, &Address([1; 20]));
+        contract.set_dry_run(true);
+        assert!(matches!(
+            deposit(&mut contract, 1),
+            PromiseOrValue::Value(())
+        ));
+        assert!(!contract.is_transfer_processed(U64(1), None));
+
+        test_utils::set_context(test_utils::factory());
+        contract.set_dry_run(false);
+        assert!(matches!(
+            deposit(&mut contract, 1),
+            PromiseOrValue::Promise(_)
+        ));
+        assert!(contract.is_transfer_processed(U64(1), None));
+    }
+
+    fn deposit_batch(contract: &mut crate::Contract, transfer_ids: Vec<u64>) -> PromiseOrValue<()> {
+        let deposits: Vec<(AccountId, u128)> = transfer_ids
+            .iter()
+            .map(|_| ("alice.near".parse().unwrap(), 10))
+            .collect();
+        test_utils::set_context(test_utils::locker_account_id());
+        contract.on_deposit_batch(Address([1; 20]), deposits, Some(transfer_ids))
+    }
+
+    #[test]
+    fn test_replayed_batch_entries_are_ignored() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        test_utils::deploy_token(&mut contract, &token);
+        let _ = deposit_batch(&mut contract, vec![1, 2]);
+        assert_eq!(contract.mirrored_supply(token.clone()).0, 20);
+        assert!(contract.is_transfer_processed(U64(1), None));
+        assert!(contract.is_transfer_processed(U64(2), None));
+
+        let _ = deposit_batch(&mut contract, vec![2, 3, 3]);
+        assert_eq!(contract.mirrored_supply(token.clone()).0, 30);
+        let deposits = test_utils::function_calls(&test_utils::token_account_id(&token))
+            .into_iter()
+            .filter(|call| call.method == "deposit")
+            .count();
+        assert_eq!(deposits, 1);
+        assert!(contract.is_transfer_processed(U64(3), None));
+
+        assert!(matches!(
+            deposit_batch(&mut contract, vec![1, 3]),
+            PromiseOrValue::Value(())
+        ));
+        assert_eq!(contract.mirrored_supply(token).0, 30);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_INVALID_BATCH_SIZE")]
+    fn test_batch_transfer_ids_of_other_length() {
+        let mut contract = test_utils::new_contract_with_binary();
+        test_utils::deploy_token(&mut contract, &Address([1; 20]));
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit_batch(
+            Address([1; 20]),
+            vec![("alice.near".parse().unwrap(), 10)],
+            Some(vec![1, 2]),
+        );
+    }
+
+    #[test]
+    fn test_failed_batch_deploy_releases_transfer_ids() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        let _ = deposit_batch(&mut contract, vec![1, 2]);
+        assert!(contract.is_transfer_processed(U64(1), None));
+        let calls = test_utils::function_calls(&test_utils::factory());
+        let args: near_sdk::serde_json::Value =
+            near_sdk::serde_json::from_slice(&calls[0].args).unwrap();
+        let deposit = near_sdk::serde_json::from_value(args["deposit"].clone()).unwrap();
+
+        test_utils::set_callback_context(vec![PromiseResult::Failed]);
+        let _ = contract.on_token_deployed(
+            token.clone(),
+            test_utils::token_account_id(&token),
+            Some(1),
+            Some(deposit),
+        );
+        assert!(!contract.is_transfer_processed(U64(1), None));
+        assert!(!contract.is_transfer_processed(U64(2), None));
+    }
+
+    #[test]
+    fn test_prune_keeps_pruned_ids_processed() {
+        let mut contract = test_utils::new_contract_with_binary();
+        test_utils::deploy_token(&mut contract, &Address([1; 20]));
+        let _ = deposit(&mut contract, 1);
+        let _ = deposit(&mut contract, 2);
+        assert_eq!(contract.prune_processed_transfers(10), 0);
+
+        near_sdk::testing_env!(test_utils::context(test_utils::locker_account_id())
+            .block_timestamp(PROCESSED_TRANSFER_RETENTION + 1)
+            .build());
+        assert_eq!(contract.prune_processed_transfers(1), 1);
+        assert_eq!(contract.prune_processed_transfers(10), 1);
+        assert_eq!(contract.prune_processed_transfers(10), 0);
+        assert!(contract.is_transfer_processed(U64(1), None));
+        assert!(contract.is_transfer_processed(U64(2), None));
+        assert!(!contract.is_transfer_processed(U64(3), None));
+    }
+}
@@ -0,0 +1,118 @@
+//! Handling of withdraws from token accounts that were never deployed by the factory.
+use crate::{aurora_sdk, events, Contract, ContractExt};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::U128;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{near_bindgen, AccountId};
+
+const ERR_UNKNOWN_TOKEN: &str = "ERR_UNKNOWN_TOKEN: Token was not deployed by the factory.";
+
+/// What happens when `on_withdraw` is called by a token that is not registered.
+#[derive(
+    BorshSerialize, BorshDeserialize, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq,
+)]
+#[serde(crate = "near_sdk::serde")]
+pub enum UnknownTokenPolicy {
+    /// The call panics.
+    Reject,
+    /// `UnknownTokenWithdraw` is emitted and no tokens are unlocked on Aurora.
+    LogAndIgnore,
+    /// The tokens are unlocked on Aurora as for a registered token.
+    Allow,
+}
+
+impl Default for UnknownTokenPolicy {
+    fn default() -> Self {
+        Self::Allow
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Set how withdraws from unregistered tokens are handled. ONLY the `Owner` role
+    /// can call this method.
+    pub fn set_unknown_token_policy(&mut self, policy: UnknownTokenPolicy) {
+        self.assert_owner();
+        self.record_admin_action("set_unknown_token_policy");
+
+        self.unknown_token_policy = policy;
+    }
+
+    /// Policy applied to withdraws from unregistered tokens.
+    pub fn unknown_token_policy(&self) -> UnknownTokenPolicy {
+        self.unknown_token_policy
+    }
+}
+
+impl Contract {
+    /// Apply the unknown token policy to a withdraw from `token_account_id`. Returns
+    /// whether the tokens should be unlocked on Aurora.
+    pub(crate) fn check_withdraw_token(
+        &self,
+        token_account_id: &AccountId,
+        receiver_id: &aurora_sdk::Address,
+        amount: U128,
+    ) -> bool {
+        if self.tokens.get(token_account_id).is_some() {
+            return true;
+        }
+
+        match self.unknown_token_policy {
+            UnknownTokenPolicy::Reject => near_sdk::env::panic_str(ERR_UNKNOWN_TOKEN),
+            UnknownTokenPolicy::LogAndIgnore => {
+                self.emit_event(events::ConnectorEvent::UnknownTokenWithdraw {
+                    token_account_id: token_account_id.clone(),
+                    receiver_id: receiver_id.to_string(),
+                    amount,
+                });
+                false
+            }
+            UnknownTokenPolicy::Allow => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::UnknownTokenPolicy;
+    use crate::{aurora_sdk::Address, test_utils};
+    use near_sdk::PromiseOrValue;
+
+    fn withdraw_from_unknown_token(policy: UnknownTokenPolicy) -> bool {
+        let mut contract = test_utils::new_contract();
+        contract.set_unknown_token_policy(policy);
+
+        test_utils::set_context(test_utils::token_account_id(&Address([1; 20])));
+        let unlocked = matches!(
+            contract.on_withdraw(Address([3; 20]), 10.into(), None, None, None),
+            PromiseOrValue::Promise(_)
+        );
+        assert_eq!(
+            test_utils::function_calls(&test_utils::aurora()).is_empty(),
+            !unlocked
+        );
+        unlocked
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_UNKNOWN_TOKEN")]
+    fn test_reject_unknown_token() {
+        withdraw_from_unknown_token(UnknownTokenPolicy::Reject);
+    }
+
+    #[test]
+    fn test_log_and_ignore_unknown_token() {
+        assert!(!withdraw_from_unknown_token(
+            UnknownTokenPolicy::LogAndIgnore
+        ));
+
+        let logs = near_sdk::test_utils::get_logs();
+        assert_eq!(logs.len(), 1);
+        assert!(logs[0].contains(r#""event":"unknown_token_withdraw""#));
+    }
+
+    #[test]
+    fn test_allow_unknown_token() {
+        assert!(withdraw_from_unknown_token(UnknownTokenPolicy::Allow));
+    }
+}
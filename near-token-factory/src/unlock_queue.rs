@@ -0,0 +1,209 @@
+//! Withdraws whose unlock on Aurora failed, kept to be retried instead of minting the
+//! burnt tokens back, see `FailedUnlockPolicy`.
+use crate::{
+    aurora_sdk, events, logging, Contract, ContractExt, PAUSE_WITHDRAWALS, WITHDRAW_AURORA_CALL_GAS,
+};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::U128;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{env, near_bindgen, AccountId, Gas, Promise, PromiseResult};
+
+const UNLOCK_RETRIED_CALLBACK_GAS: Gas = Gas(5_000_000_000_000);
+
+const ERR_NO_QUEUED_UNLOCK: &str = "ERR_NO_QUEUED_UNLOCK: There is no queued unlock with this id.";
+
+/// What happens with a withdraw whose unlock on Aurora failed.
+#[derive(
+    BorshSerialize, BorshDeserialize, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq,
+)]
+#[serde(crate = "near_sdk::serde")]
+pub enum FailedUnlockPolicy {
+    /// The burnt tokens are minted back to the account that withdrew them.
+    MintBack,
+    /// The unlock is queued, and anyone can retry it with `retry_withdraw`.
+    Queue,
+}
+
+impl Default for FailedUnlockPolicy {
+    fn default() -> Self {
+        Self::MintBack
+    }
+}
+
+/// Unlock of a withdraw that failed on Aurora. The tokens are already burnt and the
+/// withdraw is still recorded in the accounting.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct QueuedUnlock {
+    pub token_account_id: AccountId,
+    /// Account that burnt the tokens.
+    pub sender_id: AccountId,
+    /// Aurora address receiving the unlocked tokens.
+    pub receiver_id: aurora_sdk::Address,
+    pub amount: U128,
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Set how withdraws whose unlock on Aurora failed are handled. ONLY the `Owner`
+    /// role can call this method.
+    pub fn set_failed_unlock_policy(&mut self, policy: FailedUnlockPolicy) {
+        self.assert_owner();
+        self.record_admin_action("set_failed_unlock_policy");
+
+        self.failed_unlock_policy = policy;
+    }
+
+    /// Policy applied to withdraws whose unlock on Aurora failed.
+    pub fn failed_unlock_policy(&self) -> FailedUnlockPolicy {
+        self.failed_unlock_policy
+    }
+
+    /// Queued unlock with the given id, if any.
+    pub fn queued_unlock(&self, id: u64) -> Option<QueuedUnlock> {
+        self.queued_unlocks.get(&id)
+    }
+
+    /// Paginated list of queued unlocks and their ids.
+    pub fn queued_unlocks(&self, from_index: u64, limit: u64) -> Vec<(u64, QueuedUnlock)> {
+        self.queued_unlocks
+            .iter()
+            .skip(from_index as usize)
+            .take(limit as usize)
+            .collect()
+    }
+
+    /// Call the locker again to unlock the queued withdraw `id`. The unlock is removed
+    /// from the queue while it is in flight, and queued again with the same id if it
+    /// fails. Anyone can call this method, e.g. the user or a keeper, but not while
+    /// withdrawals are paused.
+    pub fn retry_withdraw(&mut self, id: u64) -> Promise {
+        self.assert_not_paused(PAUSE_WITHDRAWALS);
+        let unlock = self
+            .queued_unlocks
+            .remove(&id)
+            .unwrap_or_else(|| env::panic_str(ERR_NO_QUEUED_UNLOCK));
+
+        let token_id = self.address_from_token_account_id(unlock.token_account_id.clone());
+        let input = self.encode_unlock(
+            &unlock.token_account_id,
+            &token_id,
+            &unlock.receiver_id,
+            unlock.amount.0,
+        );
+        aurora_sdk::aurora::ext_aurora::ext(self.engine_of(&unlock.token_account_id))
+            .with_static_gas(WITHDRAW_AURORA_CALL_GAS)
+            .call(self.unlock_call_args(token_id, input))
+            .then(
+                Contract::ext(env::current_account_id())
+                    .with_static_gas(UNLOCK_RETRIED_CALLBACK_GAS)
+                    .with_unused_gas_weight(0)
+                    .on_withdraw_retried(id, unlock),
+            )
+    }
+
+    /// Callback invoked after `retry_withdraw` called the locker. Returns whether the
+    /// tokens were unlocked. Otherwise the unlock is queued again with the same id.
+    /// This is a callback function that can be only executed from the contract itself.
+    #[private]
+    pub fn on_withdraw_retried(&mut self, id: u64, unlock: QueuedUnlock) -> bool {
+        let unlocked = match env::promise_result(0) {
+            PromiseResult::Successful(output) => matches!(
+                aurora_sdk::SubmitResult::try_from_slice(&output),
+                Ok(result) if result.status.is_ok()
+            ),
+            _ => false,
+        };
+
+        if !unlocked {
+            self.log_at(
+                logging::LogLevel::Errors,
+                &format!("Retry of the queued unlock {} failed", id),
+            );
+            self.queued_unlocks.insert(&id, &unlock);
+        }
+        unlocked
+    }
+}
+
+impl Contract {
+    /// Queue the unlock of a withdraw that failed on Aurora and emit `UnlockQueued`.
+    pub(crate) fn queue_unlock(&mut self, unlock: QueuedUnlock) {
+        let id = self.next_unlock_id;
+        self.next_unlock_id += 1;
+        self.record_failed_withdraw(&unlock.token_account_id);
+
+        self.emit_event(events::ConnectorEvent::UnlockQueued {
+            id: id.into(),
+            token: self
+                .address_from_token_account_id(unlock.token_account_id.clone())
+                .to_string(),
+            receiver_id: unlock.receiver_id.to_string(),
+            amount: unlock.amount,
+        });
+        self.queued_unlocks.insert(&id, &unlock);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FailedUnlockPolicy, QueuedUnlock};
+    use crate::aurora_sdk::Address;
+    use crate::test_utils;
+    use near_sdk::PromiseResult;
+
+    #[test]
+    fn test_failed_unlock_is_queued_and_retried() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        let token_account_id = test_utils::token_account_id(&token);
+        contract.set_failed_unlock_policy(FailedUnlockPolicy::Queue);
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(
+            token.clone(),
+            "alice.near".parse().unwrap(),
+            10,
+            None,
+            None,
+            None,
+        );
+
+        test_utils::set_callback_context(vec![PromiseResult::Failed]);
+        let outcome = contract.on_withdraw_complete(
+            token_account_id.clone(),
+            "alice.near".parse().unwrap(),
+            10.into(),
+            Some(Address([3; 20])),
+        );
+        assert!(!outcome.success);
+        // The tokens are not minted back.
+        assert!(test_utils::function_calls(&token_account_id).is_empty());
+        let unlock = QueuedUnlock {
+            token_account_id,
+            sender_id: "alice.near".parse().unwrap(),
+            receiver_id: Address([3; 20]),
+            amount: 10.into(),
+        };
+        assert_eq!(contract.queued_unlocks(0, 10), vec![(0, unlock.clone())]);
+
+        test_utils::set_context("keeper.near".parse().unwrap());
+        let _ = contract.retry_withdraw(0);
+        assert!(contract.queued_unlock(0).is_none());
+        assert_eq!(
+            test_utils::function_calls(&test_utils::aurora())[0].method,
+            "call"
+        );
+
+        // The retry fails as well, so the unlock is queued again.
+        test_utils::set_callback_context(vec![PromiseResult::Failed]);
+        assert!(!contract.on_withdraw_retried(0, unlock.clone()));
+        assert_eq!(contract.queued_unlock(0), Some(unlock));
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_NO_QUEUED_UNLOCK")]
+    fn test_retry_unknown_unlock() {
+        let mut contract = test_utils::new_contract();
+        let _ = contract.retry_withdraw(0);
+    }
+}
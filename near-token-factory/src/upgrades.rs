@@ -0,0 +1,389 @@
+//! Upgrade of deployed tokens to the current token binary.
+use crate::roles::Role;
+use crate::{aurora_sdk, events, ext, logging, Contract, ContractExt, ERR_TOKEN_NOT_DEPLOYED};
+use near_sdk::{env, near_bindgen, require, AccountId, Gas, Promise, PromiseResult};
+
+const ERR_TOKEN_UP_TO_DATE: &str =
+    "ERR_TOKEN_UP_TO_DATE: Token already runs the current binary version.";
+const ERR_UPGRADES_FROZEN: &str = "ERR_UPGRADES_FROZEN: Binary changes and upgrades are frozen.";
+
+/// Gas for `upgrade_contract` of the token, including the deployment of the binary and
+/// the `migrate` call.
+const TOKEN_UPGRADE_GAS: Gas = Gas(50_000_000_000_000);
+pub(crate) const TOKEN_UPGRADED_CALLBACK_GAS: Gas = Gas(5_000_000_000_000);
+
+#[near_bindgen]
+impl Contract {
+    /// Block changes of the token binary, `upgrade_token` and `upgrade_tokens`, e.g. during
+    /// sensitive periods. Bridging is not affected, see `pause` for that. ONLY the
+    /// `Owner` role can call this method.
+    pub fn freeze_upgrades(&mut self) {
+        self.assert_owner();
+        self.record_admin_action("freeze_upgrades");
+
+        self.upgrades_frozen = true;
+    }
+
+    /// Revert `freeze_upgrades`. ONLY the `Owner` role can call this method.
+    pub fn unfreeze_upgrades(&mut self) {
+        self.assert_owner();
+        self.record_admin_action("unfreeze_upgrades");
+
+        self.upgrades_frozen = false;
+    }
+
+    /// Enable or disable lazy upgrades: `on_deposit` upgrades a token running an older
+    /// version before minting, like `upgrade_token`, so tokens converge to the current
+    /// binary as they are used. The mint waits for the upgrade, and runs on the previous
+    /// binary if the upgrade fails. The upgrade is skipped if upgrades are frozen, if the
+    /// token opted out, or if the deposit didn't attach `TOKEN_UPGRADE_GAS` and the
+    /// upgrade callback gas on top of `required_deposit_gas`. ONLY the `Owner` role can
+    /// call this method.
+    pub fn set_lazy_upgrades(&mut self, enabled: bool) {
+        self.assert_owner();
+        self.record_admin_action("set_lazy_upgrades");
+
+        self.lazy_upgrades = enabled;
+    }
+
+    /// Whether deposits upgrade tokens running an older version, see `set_lazy_upgrades`.
+    pub fn lazy_upgrades(&self) -> bool {
+        self.lazy_upgrades
+    }
+
+    /// Exclude the token from lazy upgrades, or include it again. It can still be
+    /// upgraded with `upgrade_token`. ONLY the `Owner` role can call this method.
+    pub fn set_lazy_upgrade_opt_out(&mut self, token_address: aurora_sdk::Address, opt_out: bool) {
+        self.assert_owner();
+        self.record_admin_action("set_lazy_upgrade_opt_out");
+
+        let token_account_id = self.account_id_from_token_address(token_address);
+        if opt_out {
+            self.lazy_upgrade_opt_outs.insert(&token_account_id);
+        } else {
+            self.lazy_upgrade_opt_outs.remove(&token_account_id);
+        }
+    }
+
+    /// Whether the token is excluded from lazy upgrades.
+    pub fn is_lazy_upgrade_opted_out(&self, token_address: aurora_sdk::Address) -> bool {
+        self.lazy_upgrade_opt_outs
+            .contains(&self.account_id_from_token_address(token_address))
+    }
+
+    /// Deploy the current binary to a token running an older version. The factory can't
+    /// deploy to an existing account, so the token deploys the binary itself through
+    /// `upgrade_contract`, and migrates its state. The version of the token is updated
+    /// right away, so the token is not upgraded twice, and rolled back by
+    /// `on_token_upgraded` if the upgrade fails. ONLY the `Owner` and `TokenUpgrader`
+    /// roles can call this method.
+    pub fn upgrade_token(&mut self, token_address: aurora_sdk::Address) -> Promise {
+        self.assert_role(Role::TokenUpgrader);
+        self.record_admin_action("upgrade_token");
+        self.assert_upgrades_not_frozen();
+
+        let token_account_id = self.account_id_from_token_address(token_address);
+        let version = self
+            .tokens
+            .get(&token_account_id)
+            .unwrap_or_else(|| env::panic_str(ERR_TOKEN_NOT_DEPLOYED));
+        require!(version < self.token_binary_version, ERR_TOKEN_UP_TO_DATE);
+        self.assert_not_detached(&token_account_id);
+
+        self.upgrade_token_account(token_account_id, self.get_token_binary())
+    }
+
+    /// Upgrade up to `limit` tokens running an older version, like `upgrade_token`.
+    /// Detached tokens are skipped. Returns the number of upgrades scheduled. ONLY the
+    /// `Owner` and `TokenUpgrader` roles can call this method.
+    pub fn upgrade_tokens(&mut self, limit: u64) -> u64 {
+        self.assert_role(Role::TokenUpgrader);
+        self.record_admin_action("upgrade_tokens");
+        self.assert_upgrades_not_frozen();
+
+        let stale: Vec<AccountId> = self
+            .tokens
+            .iter()
+            .filter(|(token_account_id, version)| {
+                *version < self.token_binary_version
+                    && !self.detached_tokens.contains(token_account_id)
+            })
+            .map(|(token_account_id, _)| token_account_id)
+            .take(limit as usize)
+            .collect();
+        if stale.is_empty() {
+            return 0;
+        }
+
+        let binary = self.get_token_binary();
+        for token_account_id in stale.iter() {
+            self.upgrade_token_account(token_account_id.clone(), binary.clone());
+        }
+        stale.len() as u64
+    }
+
+    /// Callback invoked after a token is upgraded from the binary `previous_version`.
+    /// If the upgrade failed, the version of the token is rolled back to
+    /// `previous_version`. Returns whether the upgrade succeeded. This is a callback
+    /// function that can be only executed from the contract itself.
+    #[private]
+    pub fn on_token_upgraded(
+        &mut self,
+        token_account_id: AccountId,
+        previous_version: u32,
+    ) -> bool {
+        if matches!(env::promise_result(0), PromiseResult::Successful(_)) {
+            self.emit_event(events::ConnectorEvent::TokenUpgraded {
+                token_account_id: token_account_id.clone(),
+                token_binary_version: self.tokens.get(&token_account_id).unwrap_or_default(),
+            });
+            return true;
+        }
+
+        self.log_at(
+            logging::LogLevel::Errors,
+            &format!("Upgrade of {} failed", token_account_id),
+        );
+        self.set_token_version(&token_account_id, previous_version);
+        false
+    }
+}
+
+impl Contract {
+    pub(crate) fn assert_upgrades_not_frozen(&self) {
+        require!(!self.upgrades_frozen, ERR_UPGRADES_FROZEN);
+    }
+
+    /// Upgrade of a registered token scheduled by a deposit under lazy upgrades, if the
+    /// token runs an older version and the gas left covers the upgrade on top of
+    /// `reserved_gas`. The mint of the deposit is chained after the returned promise.
+    pub(crate) fn lazy_upgrade(
+        &mut self,
+        token_account_id: &AccountId,
+        reserved_gas: Gas,
+    ) -> Option<Promise> {
+        if !self.lazy_upgrades
+            || self.upgrades_frozen
+            || self.lazy_upgrade_opt_outs.contains(token_account_id)
+            || self.detached_tokens.contains(token_account_id)
+            || self.tokens.get(token_account_id)? >= self.token_binary_version
+        {
+            return None;
+        }
+        let required_gas = TOKEN_UPGRADE_GAS.0 + TOKEN_UPGRADED_CALLBACK_GAS.0 + reserved_gas.0;
+        if env::prepaid_gas().0.saturating_sub(env::used_gas().0) < required_gas {
+            self.log_at(
+                logging::LogLevel::Debug,
+                &format!("Not enough gas to upgrade {}", token_account_id),
+            );
+            return None;
+        }
+        let binary = self.token_binary.get()?;
+        self.log_at(
+            logging::LogLevel::Info,
+            &format!("Upgrading {}", token_account_id),
+        );
+        Some(self.upgrade_token_account(token_account_id.clone(), binary))
+    }
+
+    fn upgrade_token_account(&mut self, token_account_id: AccountId, binary: Vec<u8>) -> Promise {
+        let previous_version = self
+            .set_token_version(&token_account_id, self.token_binary_version)
+            .unwrap_or_else(|| env::panic_str(ERR_TOKEN_NOT_DEPLOYED));
+
+        ext::ext_near_token::ext(token_account_id.clone())
+            .with_static_gas(TOKEN_UPGRADE_GAS)
+            .upgrade_contract(binary.into())
+            .then(
+                Contract::ext(env::current_account_id())
+                    .with_static_gas(TOKEN_UPGRADED_CALLBACK_GAS)
+                    .with_unused_gas_weight(0)
+                    .on_token_upgraded(token_account_id, previous_version),
+            )
+    }
+
+    /// Record `version` as the binary version of a registered token, keeping the
+    /// version counts in sync. Returns the previous version, or `None` if the token is
+    /// not registered, in which case nothing changes.
+    fn set_token_version(&mut self, token_account_id: &AccountId, version: u32) -> Option<u32> {
+        let previous = self.tokens.get(token_account_id)?;
+        self.tokens.insert(token_account_id, &version);
+        let count = self.version_counts.get(&previous).unwrap_or(0);
+        self.version_counts
+            .insert(&previous, &count.saturating_sub(1));
+        self.add_to_version_count(version, 1);
+        self.token_spec_versions
+            .insert(token_account_id, &self.spec_version(version));
+        Some(previous)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::aurora_sdk::Address;
+    use crate::{binary_hash, test_utils};
+    use near_sdk::json_types::Base64VecU8;
+    use near_sdk::PromiseResult;
+
+    const NEW_BINARY: [u8; 5] = [0, 97, 115, 109, 2];
+
+    #[test]
+    fn test_upgrade_token() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        let token_account_id = test_utils::token_account_id(&token);
+        test_utils::deploy_token(&mut contract, &token);
+        test_utils::replace_token_binary(&mut contract, NEW_BINARY.to_vec());
+
+        test_utils::set_context(test_utils::factory());
+        let _ = contract.upgrade_token(token);
+
+        let calls = test_utils::function_calls(&token_account_id);
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].method, "upgrade_contract");
+        let args: near_sdk::serde_json::Value =
+            near_sdk::serde_json::from_slice(&calls[0].args).unwrap();
+        let binary: Base64VecU8 = near_sdk::serde_json::from_value(args["binary"].clone()).unwrap();
+        assert_eq!(Vec::<u8>::from(binary), NEW_BINARY);
+        assert_eq!(contract.tokens.get(&token_account_id), Some(2));
+
+        test_utils::set_callback_context(vec![PromiseResult::Successful(Vec::new())]);
+        assert!(contract.on_token_upgraded(token_account_id.clone(), 1));
+        assert_eq!(contract.tokens.get(&token_account_id), Some(2));
+        assert_eq!(contract.version_distribution(), [(2, 1)]);
+    }
+
+    /// Methods called on the token by a deposit of `Address([1; 20])`, deployed before
+    /// the binary is bumped, with lazy upgrades enabled.
+    fn lazy_upgrade_calls(opt_out: bool) -> Vec<String> {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        test_utils::deploy_token(&mut contract, &token);
+        test_utils::replace_token_binary(&mut contract, NEW_BINARY.to_vec());
+        contract.set_lazy_upgrades(true);
+        contract.set_lazy_upgrade_opt_out(token.clone(), opt_out);
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(
+            token.clone(),
+            "alice.near".parse().unwrap(),
+            10,
+            None,
+            None,
+            None,
+        );
+        test_utils::function_calls(&test_utils::token_account_id(&token))
+            .into_iter()
+            .map(|call| call.method)
+            .collect()
+    }
+
+    #[test]
+    fn test_lazy_upgrade_on_deposit() {
+        assert_eq!(
+            lazy_upgrade_calls(false),
+            ["upgrade_contract", "storage_deposit", "deposit"]
+        );
+        assert_eq!(lazy_upgrade_calls(true), ["storage_deposit", "deposit"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_TOKEN_UP_TO_DATE")]
+    fn test_upgrade_current_token() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        test_utils::deploy_token(&mut contract, &token);
+
+        test_utils::set_context(test_utils::factory());
+        let _ = contract.upgrade_token(token);
+    }
+
+    #[test]
+    fn test_failed_upgrade_rolls_back_version() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        let token_account_id = test_utils::token_account_id(&token);
+        test_utils::deploy_token(&mut contract, &token);
+        test_utils::replace_token_binary(&mut contract, NEW_BINARY.to_vec());
+
+        test_utils::set_context(test_utils::factory());
+        let _ = contract.upgrade_token(token);
+
+        test_utils::set_callback_context(vec![PromiseResult::Failed]);
+        assert!(!contract.on_token_upgraded(token_account_id.clone(), 1));
+        assert_eq!(contract.tokens.get(&token_account_id), Some(1));
+        assert_eq!(contract.version_distribution(), [(1, 1)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_UPGRADES_FROZEN")]
+    fn test_frozen_upgrades_reject_binary_change() {
+        let mut contract = test_utils::new_contract_with_binary();
+        contract.freeze_upgrades();
+        assert!(contract.modes().upgrades_frozen);
+
+        contract.propose_token_binary(binary_hash(&NEW_BINARY).into());
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_UPGRADES_FROZEN")]
+    fn test_frozen_upgrades_reject_token_upgrade() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        test_utils::deploy_token(&mut contract, &token);
+        test_utils::replace_token_binary(&mut contract, NEW_BINARY.to_vec());
+        contract.freeze_upgrades();
+
+        let _ = contract.upgrade_token(token);
+    }
+
+    #[test]
+    fn test_frozen_upgrades_allow_bridging() {
+        let mut contract = test_utils::new_contract_with_binary();
+        let token = Address([1; 20]);
+        contract.freeze_upgrades();
+
+        test_utils::set_context(test_utils::locker_account_id());
+        let _ = contract.on_deposit(
+            token.clone(),
+            "alice.near".parse().unwrap(),
+            10,
+            None,
+            None,
+            None,
+        );
+        test_utils::confirm_deploy(&mut contract, &token);
+        test_utils::set_context(test_utils::token_account_id(&token));
+        let _ = contract.on_withdraw(Address([3; 20]), 5.into(), None, None, None);
+
+        assert_eq!(contract.mirrored_supply(token).0, 5);
+
+        test_utils::set_context(test_utils::factory());
+        contract.unfreeze_upgrades();
+        assert!(!contract.modes().upgrades_frozen);
+        test_utils::replace_token_binary(&mut contract, NEW_BINARY.to_vec());
+    }
+
+    #[test]
+    fn test_upgrade_tokens() {
+        let mut contract = test_utils::new_contract_with_binary();
+        for i in 1..=3 {
+            test_utils::deploy_token(&mut contract, &Address([i; 20]));
+        }
+        test_utils::replace_token_binary(&mut contract, NEW_BINARY.to_vec());
+        test_utils::deploy_token(&mut contract, &Address([4; 20]));
+
+        test_utils::set_context(test_utils::factory());
+        assert_eq!(contract.upgrade_tokens(2), 2);
+        let upgraded = (1..=4)
+            .filter(|i| {
+                !test_utils::function_calls(&test_utils::token_account_id(&Address([*i; 20])))
+                    .is_empty()
+            })
+            .count();
+        assert_eq!(upgraded, 2);
+
+        // Tokens being upgraded are not scheduled again.
+        assert_eq!(contract.upgrade_tokens(5), 1);
+    }
+}
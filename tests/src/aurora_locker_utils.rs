@@ -1,6 +1,72 @@
+use crate::aurora_engine_utils::AuroraEngine;
 use aurora_engine_types::types::Address;
+use borsh::BorshSerialize;
+
+const MOCK_LOCKER_BALANCE: u128 = 10_000_000_000_000_000_000_000_000;
 
 pub struct AuroraLocker {
     pub address: Address,
     pub abi: ethabi::Contract,
 }
+
+/// Stand-in for the NEAR representative of the locker. It is the `{address}.{engine}`
+/// sub-account the factory expects calls of the locker from, so deposits can be
+/// issued without going through the EVM.
+pub struct MockLocker {
+    pub address: Address,
+    pub inner: workspaces::Account,
+}
+
+/// Borsh arguments of the factory `on_deposit`.
+#[derive(BorshSerialize)]
+struct OnDepositArgs {
+    token: [u8; 20],
+    receiver_id: String,
+    amount: u128,
+    origin_tx_hash: Option<[u8; 32]>,
+    // `None` is encoded as a single zero byte, whatever the type of the metadata.
+    metadata: Option<()>,
+    transfer_id: Option<u64>,
+}
+
+impl MockLocker {
+    pub async fn create(engine: &AuroraEngine, address: Address) -> anyhow::Result<Self> {
+        let inner = engine
+            .inner
+            .as_account()
+            .create_subaccount(&address.encode())
+            .initial_balance(MOCK_LOCKER_BALANCE)
+            .transact()
+            .await?
+            .into_result()?;
+        Ok(Self { address, inner })
+    }
+
+    /// Call `on_deposit` on the factory, like the locker does after `amount` of
+    /// `token` was locked on Aurora.
+    pub async fn deposit(
+        &self,
+        factory: &workspaces::AccountId,
+        token: Address,
+        receiver_id: &workspaces::AccountId,
+        amount: u128,
+        transfer_id: Option<u64>,
+    ) -> anyhow::Result<workspaces::result::ExecutionFinalResult> {
+        let args = OnDepositArgs {
+            token: token.raw().0,
+            receiver_id: receiver_id.to_string(),
+            amount,
+            origin_tx_hash: None,
+            metadata: None,
+            transfer_id,
+        };
+        let outcome = self
+            .inner
+            .call(factory, "on_deposit")
+            .args(args.try_to_vec()?)
+            .max_gas()
+            .transact()
+            .await?;
+        Ok(outcome)
+    }
+}
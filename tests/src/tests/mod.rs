@@ -1,5 +1,7 @@
 use crate::{
     aurora_engine_utils::{self, erc20, erc20::ERC20DeployedAt, repo::AuroraEngineRepo},
+    aurora_locker_utils::MockLocker,
+    token_factory_utils::TokenFactory,
     wnear_utils::Wnear,
 };
 use aurora_engine::parameters::{CallArgs, FunctionCallArgsV2, SubmitResult};
@@ -145,10 +147,141 @@ async fn test_deploy_token_factory() {
     // In reality we would deploy the locker contract and get its address,
     // but that is not needed for this test. We can choose any address we like.
     let locker_address = Address::decode("000000000000000000000000000000000000000a").unwrap();
-    let locker_id = format!("{}.{}", locker_address.encode(), engine.inner.id().as_str())
-        .parse()
+    let _factory = TokenFactory::deploy(&worker, &engine, locker_address)
+        .await
+        .unwrap();
+}
+
+/// Sandbox with the engine, the factory, and a mock locker, plus a user on NEAR.
+struct Bridge {
+    engine: aurora_engine_utils::AuroraEngine,
+    factory: TokenFactory,
+    locker: MockLocker,
+    user: workspaces::Account,
+}
+
+impl Bridge {
+    async fn deploy() -> anyhow::Result<Self> {
+        let worker = workspaces::sandbox().await?;
+        let engine = aurora_engine_utils::deploy_latest(&worker).await?;
+        let locker_address = Address::decode("000000000000000000000000000000000000000a").unwrap();
+        let locker = MockLocker::create(&engine, locker_address).await?;
+        let factory = TokenFactory::deploy(&worker, &engine, locker_address).await?;
+        let user = worker.dev_create_account().await?;
+        Ok(Self {
+            engine,
+            factory,
+            locker,
+            user,
+        })
+    }
+
+    async fn deposit(&self, token: Address, amount: u128) -> anyhow::Result<()> {
+        self.locker
+            .deposit(self.factory.inner.id(), token, self.user.id(), amount, None)
+            .await?
+            .into_result()?;
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn test_deposit_and_withdraw_round_trip() {
+    let bridge = Bridge::deploy().await.unwrap();
+    // Calls to an address without code succeed, so the unlock of the withdraw
+    // succeeds on the engine.
+    let token = Address::decode("000000000000000000000000000000000000000b").unwrap();
+    let receiver = Address::decode("000000000000000000000000000000000000000c").unwrap();
+
+    // The first deposit deploys the token and mints it.
+    bridge.deposit(token, 100).await.unwrap();
+    let balance = bridge.factory.ft_balance_of(token, bridge.user.id()).await;
+    assert_eq!(balance.unwrap(), 100);
+
+    // Deposits of a deployed token only mint it.
+    bridge.deposit(token, 50).await.unwrap();
+    let balance = bridge.factory.ft_balance_of(token, bridge.user.id()).await;
+    assert_eq!(balance.unwrap(), 150);
+
+    let outcome = bridge
+        .factory
+        .withdraw(&bridge.user, token, receiver, 120)
+        .await
+        .unwrap();
+    assert_eq!(outcome["success"], true);
+    let balance = bridge.factory.ft_balance_of(token, bridge.user.id()).await;
+    assert_eq!(balance.unwrap(), 30);
+}
+
+#[tokio::test]
+async fn test_replayed_deposit_is_not_minted() {
+    let bridge = Bridge::deploy().await.unwrap();
+    let token = Address::decode("000000000000000000000000000000000000000b").unwrap();
+
+    for _ in 0..2 {
+        bridge
+            .locker
+            .deposit(
+                bridge.factory.inner.id(),
+                token,
+                bridge.user.id(),
+                100,
+                Some(7),
+            )
+            .await
+            .unwrap()
+            .into_result()
+            .unwrap();
+    }
+    let balance = bridge.factory.ft_balance_of(token, bridge.user.id()).await;
+    assert_eq!(balance.unwrap(), 100);
+}
+
+#[tokio::test]
+async fn test_failed_unlock_mints_back() {
+    let bridge = Bridge::deploy().await.unwrap();
+    // The ERC-20 has no `withdraw` function, so the unlock reverts on the engine.
+    let constructor = erc20::Constructor::load().await.unwrap();
+    let token = bridge
+        .engine
+        .deploy_evm_contract(constructor.deploy_code("TEST", "AAA"))
+        .await
+        .unwrap();
+    let receiver = Address::decode("000000000000000000000000000000000000000c").unwrap();
+
+    bridge.deposit(token, 100).await.unwrap();
+    let outcome = bridge
+        .factory
+        .withdraw(&bridge.user, token, receiver, 60)
+        .await
+        .unwrap();
+    assert_eq!(outcome["success"], false);
+    let balance = bridge.factory.ft_balance_of(token, bridge.user.id()).await;
+    assert_eq!(balance.unwrap(), 100);
+}
+
+#[tokio::test]
+async fn test_out_of_gas_mint_is_not_minted() {
+    let bridge = Bridge::deploy().await.unwrap();
+    let token = Address::decode("000000000000000000000000000000000000000b").unwrap();
+    bridge.deposit(token, 100).await.unwrap();
+
+    // The `deposit` call of the token runs out of gas, so `on_mint_complete` emits
+    // `mint_failed` and nothing is minted.
+    bridge
+        .factory
+        .set_gas_config(5_000_000_000_000, 1_000_000_000)
+        .await
         .unwrap();
-    let _factory = crate::token_factory_utils::TokenFactory::deploy(&worker, &locker_id)
+    let outcome = bridge
+        .locker
+        .deposit(bridge.factory.inner.id(), token, bridge.user.id(), 50, None)
         .await
         .unwrap();
+    assert!(outcome
+        .logs()
+        .iter()
+        .any(|log| log.contains("\"mint_failed\"")));
+    let balance = bridge.factory.ft_balance_of(token, bridge.user.id()).await;
+    assert_eq!(balance.unwrap(), 100);
 }
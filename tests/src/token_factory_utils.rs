@@ -1,3 +1,5 @@
+use crate::aurora_engine_utils::AuroraEngine;
+use aurora_engine_types::types::Address;
 use std::path::{Path, PathBuf};
 use tokio::process::Command;
 use workspaces::{network::Sandbox, Worker};
@@ -12,7 +14,8 @@ pub struct TokenFactory {
 impl TokenFactory {
     pub async fn deploy(
         worker: &Worker<Sandbox>,
-        locker: &workspaces::AccountId,
+        engine: &AuroraEngine,
+        locker: Address,
     ) -> anyhow::Result<Self> {
         // Compile and deploy factory contract
         let wasm = Self::compile_factory().await?;
@@ -28,7 +31,9 @@ impl TokenFactory {
         contract
             .call("new")
             .args_json(serde_json::json!({
-                "locker": locker,
+                "aurora": engine.inner.id(),
+                "locker": locker.as_bytes(),
+                "owner": null,
             }))
             .max_gas()
             .transact()
@@ -53,6 +58,67 @@ impl TokenFactory {
         Ok(Self { inner: contract })
     }
 
+    /// Account id of the NEP-141 representative of `token`.
+    pub fn token_account_id(&self, token: Address) -> workspaces::AccountId {
+        format!("{}.{}", token.encode(), self.inner.id().as_str())
+            .parse()
+            .unwrap()
+    }
+
+    /// Balance of `account_id` on the NEP-141 representative of `token`.
+    pub async fn ft_balance_of(
+        &self,
+        token: Address,
+        account_id: &workspaces::AccountId,
+    ) -> anyhow::Result<u128> {
+        let outcome = self
+            .inner
+            .as_account()
+            .call(&self.token_account_id(token), "ft_balance_of")
+            .args_json(serde_json::json!({ "account_id": account_id }))
+            .transact()
+            .await?;
+        let result: String = outcome.json()?;
+        Ok(result.parse()?)
+    }
+
+    /// Set the gas attached to the `new` and `deposit` calls of the tokens.
+    pub async fn set_gas_config(&self, deploy_gas: u64, deposit_gas: u64) -> anyhow::Result<()> {
+        self.inner
+            .call("set_gas_config")
+            .args_json(serde_json::json!({
+                "deploy_gas": deploy_gas.to_string(),
+                "deposit_gas": deposit_gas.to_string(),
+            }))
+            .transact()
+            .await?
+            .into_result()?;
+        Ok(())
+    }
+
+    /// Withdraw `amount` of the representative of `token` held by `account` to
+    /// `receiver` on Aurora, and return the `WithdrawOutcome` of the factory.
+    pub async fn withdraw(
+        &self,
+        account: &workspaces::Account,
+        token: Address,
+        receiver: Address,
+        amount: u128,
+    ) -> anyhow::Result<serde_json::Value> {
+        let outcome = account
+            .call(&self.token_account_id(token), "withdraw")
+            .args_json(serde_json::json!({
+                "receiver_id": receiver.as_bytes(),
+                "amount": amount.to_string(),
+                "memo": null,
+            }))
+            .max_gas()
+            .transact()
+            .await?
+            .into_result()?;
+        Ok(outcome.json()?)
+    }
+
     pub async fn compile_factory() -> anyhow::Result<Vec<u8>> {
         let root_path = Path::new(ROOT_PATH);
         add_wasm_target(root_path).await?;